@@ -6,21 +6,15 @@
 #![no_std]
 #![no_main]
 
+use esp32_c3_buddy_like::Board;
 use esp_backtrace as _;
-use hal::{
-    delay::Delay,
-    gpio::{Io, Level, Output},
-    prelude::*,
-};
 use esp_println::println;
+use hal::{delay::Delay, prelude::*};
 
 #[entry]
 fn main() -> ! {
-    let peripherals = hal::init(hal::Config::default());
-
-    // Set GPIO8 as an output, and set its state high initially.
-    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
-    let mut led = Output::new(io.pins.gpio8, Level::High);
+    // GPIO8 is set up as an output by the board, with its state high initially.
+    let mut led = Board::init().led;
 
     let delay = Delay::new();
 
@@ -0,0 +1,177 @@
+#![no_std]
+#![no_main]
+
+use esp32_c3_buddy_like::{
+    app::{App, Chat, Input, Mac, Received, BROADCAST},
+    button::Button,
+    pairing::{PairedPeers, PairingStore, LMK_LEN},
+    peers::{Peer, PeerStore, PeerTable},
+    protocol::{
+        reliable::{Event, Reliable, ReliableConfig},
+        Frame,
+    },
+    Board, VisibleArea,
+};
+use esp_backtrace as _;
+use esp_println::println;
+use esp_storage::FlashStorage;
+use esp_wifi::{
+    esp_now::{EspNow, PeerInfo},
+    init, EspWifiInitFor,
+};
+use hal::{prelude::*, time};
+
+// the channel of a gateway's access point, e.g.
+// `ESP_NOW_CHANNEL=6 cargo espflash flash --release --example ...`
+const CHANNEL: Option<&str> = option_env!("ESP_NOW_CHANNEL");
+
+#[entry]
+fn main() -> ! {
+    esp_alloc::heap_allocator!(72 * 1024);
+    let board = Board::init();
+
+    let mut rng = board.rng;
+    let init = init(
+        EspWifiInitFor::Wifi,
+        board.timg0.timer0,
+        rng,
+        board.radio_clk,
+    )
+    .unwrap();
+
+    let wifi = board.wifi;
+    let mut esp_now = EspNow::new(&init, wifi).unwrap();
+
+    println!("esp-now version {}", esp_now.get_version().unwrap());
+    if let Some(channel) = CHANNEL.and_then(|c| c.parse().ok()) {
+        esp_now.set_channel(channel).unwrap();
+    }
+
+    // pair with the esp-now-receiver example, the long press picks the peer
+    // here
+    let paired = PairingStore::new(FlashStorage::new())
+        .load()
+        .unwrap_or_else(|e| {
+            println!("Could not load the paired peers: {:?}", e);
+            PairedPeers::default()
+        });
+    let mut peer_store = PeerStore::new(FlashStorage::new());
+    let mut peers = peer_store.load().unwrap_or_else(|e| {
+        println!("Could not load the peer table: {:?}", e);
+        PeerTable::default()
+    });
+    for peer in paired.peers() {
+        register(&esp_now, peer.mac, Some(peer.lmk));
+        let evicted = peers.set_paired(peer.mac, true, true);
+        unregister(&esp_now, evicted);
+    }
+    let mut app = Chat::with_peers(peers);
+    if app.selected().is_none() {
+        println!("No paired peers, pair with the esp-now-receiver example first");
+    }
+
+    let mut display = VisibleArea::new(board.display);
+    let button_pin = board.button;
+    let mut button = Button::default();
+
+    app.render(&mut display).unwrap();
+    display.flush().unwrap();
+
+    let mut reliable = Reliable::new(ReliableConfig::default(), rng.random());
+
+    loop {
+        let now = time::now().duration_since_epoch().to_millis();
+
+        button.poll(now, button_pin.is_low());
+
+        let r = esp_now.receive();
+        let received = r.as_ref().and_then(|r| {
+            let src = r.info.src_address;
+            // chat only lists paired peers, the others would just take up
+            // the peer table and the flash
+            // broadcasts are never encrypted, so anyone could claim to be a
+            // paired board in one
+            if !paired.contains(&src) || r.info.dst_address == BROADCAST {
+                return None;
+            }
+            let evicted = app.peers_mut().seen(now, src, r.info.rx_control.rssi as i8);
+            unregister(&esp_now, evicted);
+            reliable.receive(now, src, r.info.dst_address, r.get_data())?;
+            Some(Received {
+                src,
+                dst: r.info.dst_address,
+                data: r.get_data(),
+            })
+        });
+        let input = Input {
+            button: button.pop(),
+            received,
+        };
+
+        let effects = app.update(now, input);
+
+        if let Some(outgoing) = effects.send {
+            // retried until the peer acknowledges it
+            if let Ok(frame) = Frame::decode(&outgoing.data) {
+                if let Err(e) = reliable.send(now, outgoing.dst, frame.message) {
+                    println!("Could not send: {:?}", e);
+                }
+            }
+        }
+        while let Some(outgoing) = reliable.poll_transmit(now) {
+            send(&mut esp_now, outgoing.dst, &outgoing.data);
+        }
+        while let Some(event) = reliable.pop_event() {
+            if let Event::Failed { dst, .. } = event {
+                println!("Message to {:02x?} was not acknowledged", dst);
+            }
+        }
+
+        if app.take_peers_changed(now) {
+            if let Err(e) = peer_store.save(app.peers()) {
+                println!("Could not save the peer table: {:?}", e);
+            }
+        }
+
+        if effects.redraw {
+            app.render(&mut display).unwrap();
+            display.flush().unwrap();
+        }
+    }
+}
+
+/// Send `data` to `dst`. A peer removed while frames for it were pending
+/// only costs those frames.
+fn send(esp_now: &mut EspNow, dst: Mac, data: &[u8]) {
+    match esp_now.send(&dst, data) {
+        Ok(waiter) => {
+            let _ = waiter.wait();
+        }
+        Err(e) => println!("Could not send to {:02x?}: {:?}", dst, e),
+    }
+}
+
+/// Add `mac` as a peer, or update it; encrypted with `lmk` if given.
+fn register(esp_now: &EspNow, mac: Mac, lmk: Option<[u8; LMK_LEN]>) {
+    let peer = PeerInfo {
+        peer_address: mac,
+        lmk,
+        channel: None,
+        encrypt: lmk.is_some(),
+    };
+    let result = if esp_now.peer_exists(&mac) {
+        esp_now.modify_peer(peer)
+    } else {
+        esp_now.add_peer(peer)
+    };
+    if let Err(e) = result {
+        println!("Could not register peer {:02x?}: {:?}", mac, e);
+    }
+}
+
+/// Drop a peer pushed out of the peer table, unless it is paired.
+fn unregister(esp_now: &EspNow, evicted: Option<Peer>) {
+    if let Some(peer) = evicted.filter(|p| !p.paired) {
+        let _ = esp_now.remove_peer(&peer.mac);
+    }
+}
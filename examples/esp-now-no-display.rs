@@ -1,86 +1,264 @@
 #![no_std]
 #![no_main]
-use core::fmt::Write as FmtWrite;
+use esp32_c3_buddy_like::{
+    app::{receiver::GREETING, Mac, BROADCAST},
+    button::{Button, ButtonEvent},
+    pairing::{PairedPeers, Pairing, PairingEvent, PairingStore, Stage, LMK_LEN, MAX_PAIRED},
+    protocol::{
+        fragment,
+        mesh::{MeshConfig, Router},
+        reliable::{Reliable, ReliableConfig},
+        BlobKind, Frame, Message,
+    },
+    screens::BITMAP_LEN,
+    Board,
+};
 use esp_backtrace as _;
 use esp_println::println;
+use esp_storage::FlashStorage;
 use esp_wifi::{
-    esp_now::{PeerInfo, BROADCAST_ADDRESS},
-    init, EspWifiInitFor,
+    esp_now::{EspNow, PeerInfo},
+    init,
+    wifi::get_sta_mac,
+    EspWifiInitFor,
 };
 use hal::{
     prelude::*,
-    rng::Rng,
     time::{self, Duration},
-    timer::timg::TimerGroup,
 };
 
+// the channel of a gateway's access point, e.g.
+// `ESP_NOW_CHANNEL=6 cargo espflash flash --release --example ...`
+const CHANNEL: Option<&str> = option_env!("ESP_NOW_CHANNEL");
+
 #[entry]
 fn main() -> ! {
     esp_alloc::heap_allocator!(72 * 1024);
-    let peripherals = hal::init({
-        let mut config = hal::Config::default();
-        config.cpu_clock = CpuClock::max();
-        config
-    });
+    let board = Board::init();
 
-    let timg0 = TimerGroup::new(peripherals.TIMG0);
-
-    let mut rng = Rng::new(peripherals.RNG);
+    let mut rng = board.rng;
 
     let init = init(
         EspWifiInitFor::Wifi,
-        timg0.timer0,
+        board.timg0.timer0,
         rng,
-        peripherals.RADIO_CLK,
+        board.radio_clk,
     )
     .unwrap();
 
-    let wifi = peripherals.WIFI;
-    let mut esp_now = esp_wifi::esp_now::EspNow::new(&init, wifi).unwrap();
+    let wifi = board.wifi;
+    let mut esp_now = EspNow::new(&init, wifi).unwrap();
 
     println!("esp-now version {}", esp_now.get_version().unwrap());
+    if let Some(channel) = CHANNEL.and_then(|c| c.parse().ok()) {
+        esp_now.set_channel(channel).unwrap();
+    }
+
+    let mut own = [0u8; 6];
+    get_sta_mac(&mut own);
+
+    // only paired peers are talked to, see the README on pairing
+    let mut pairing_store = PairingStore::new(FlashStorage::new());
+    let mut paired = pairing_store.load().unwrap_or_else(|e| {
+        println!("Could not load the paired peers: {:?}", e);
+        PairedPeers::default()
+    });
+    for peer in paired.peers() {
+        println!("Paired with {:02x?}", peer.mac);
+        register(&esp_now, peer.mac, Some(peer.lmk));
+    }
+
+    let button_pin = board.button;
+    let mut button = Button::default();
 
     let mut next_send_time = time::now() + Duration::secs(5);
+    let mut reliable = Reliable::new(ReliableConfig::default(), rng.random());
+    let mut router = Router::new(own, MeshConfig::default(), rng.random());
+    let mut pairing = Pairing::new(own);
+    let mut stage = Stage::Idle;
+    let mut transfer = 0u16;
+    let mut bitmap = [0u8; BITMAP_LEN];
 
     loop {
-        let r = esp_now.receive();
-        if let Some(r) = r {
-            println!("Received {:?}", r);
-
-            if r.info.dst_address == BROADCAST_ADDRESS {
-                if !esp_now.peer_exists(&r.info.src_address) {
-                    esp_now
-                        .add_peer(PeerInfo {
-                            peer_address: r.info.src_address,
-                            lmk: None,
-                            channel: None,
-                            encrypt: false,
-                        })
-                        .unwrap();
+        let now = time::now().duration_since_epoch().to_millis();
+
+        // a long press pairs, a click accepts the code, a double click stops
+        button.poll(now, button_pin.is_low());
+        while let Some(event) = button.pop() {
+            match event {
+                ButtonEvent::LongPress(_) => {
+                    let mut secret = [0; 32];
+                    let mut nonce = [0; 16];
+                    rng.read(&mut secret);
+                    rng.read(&mut nonce);
+                    pairing.start(now, secret, nonce);
+                }
+                ButtonEvent::Click => {
+                    pairing.confirm(now);
                 }
-                let status = esp_now
-                    .send(&r.info.src_address, b"Hello Peer")
-                    .unwrap()
-                    .wait();
-                println!("Send hello to peer status: {:?}", status);
+                ButtonEvent::DoubleClick => pairing.cancel(),
+                _ => {}
+            }
+        }
+
+        if let Some(r) = esp_now.receive() {
+            let src = r.info.src_address;
+            let frame = Frame::decode(r.get_data()).ok();
+            match frame.map(|f| f.message) {
+                Some(message) if pairing.receive(now, src, &message) => {}
+                Some(Message::Mesh(mesh)) => {
+                    // relayed for anyone, but only taken from paired boards
+                    // that prove it is them
+                    let delivered = router.receive(now, mesh);
+                    if let Some((delivered, data)) = delivered.and_then(|d| {
+                        let key = paired.get(&d.origin)?.mesh_key();
+                        Some((d, d.authenticated(&key)?))
+                    }) {
+                        println!(
+                            "Received {:?} from {:02x?} after {} hops",
+                            Frame::decode(data),
+                            delivered.origin,
+                            delivered.hops
+                        );
+                    }
+                }
+                // broadcasts are never encrypted, so anyone could claim to
+                // be a paired board in one
+                _ if paired.contains(&src) && r.info.dst_address != BROADCAST => {
+                    if let Some(frame) =
+                        reliable.receive(now, src, r.info.dst_address, r.get_data())
+                    {
+                        println!("Received {:?} from {:02x?}", frame, src);
+                    }
+                }
+                _ => {}
             }
         }
 
         if time::now() >= next_send_time {
             next_send_time = time::now() + Duration::secs(5);
-            println!("Send");
-            let message: [u8; 1] = 42_u8.to_be_bytes();
             let random_number: u32 = rng.random() % 128;
-            let mut counter_string: heapless::String<4> = heapless::String::new();
-            match write!(counter_string, "{}", random_number) {
-                Ok(_) => (),
-                Err(e) => println!("Error writing: {:?}", e),
+            let peers: heapless::Vec<Mac, MAX_PAIRED> =
+                paired.peers().iter().map(|p| p.mac).collect();
+            // to each peer, encrypted with its key
+            for &peer in &peers {
+                match reliable.send(now, peer, Message::Counter(random_number)) {
+                    Ok(seq) => println!("Send counter {} to {:02x?}", seq, peer),
+                    Err(e) => println!("Could not send counter: {:?}", e),
+                }
+            }
+            // and to the boards out of range, one by one so they can tell
+            // it comes from here
+            for peer in paired.peers() {
+                let message = Message::Counter(random_number);
+                if let Err(e) = router.send_authenticated(now, peer.mac, message, &peer.mesh_key())
+                {
+                    println!("Could not flood: {:?}", e);
+                }
+            }
+
+            // diagonal stripes for the display of one peer, moving each time
+            if let Some(&peer) = peers.get(transfer as usize % peers.len().max(1)) {
+                for (i, byte) in bitmap.iter_mut().enumerate() {
+                    let (x, y) = (i % 16, i / 16);
+                    *byte = if (x * 8 + y + random_number as usize) % 32 < 16 {
+                        0xff
+                    } else {
+                        0
+                    };
+                }
+                transfer = transfer.wrapping_add(1);
+                for fragment in fragment::split(transfer, BlobKind::Bitmap, &bitmap).unwrap() {
+                    if let Err(e) = reliable.send(now, peer, Message::Fragment(fragment)) {
+                        println!("Could not send bitmap: {:?}", e);
+                        break;
+                    }
+                }
             }
-            let status = esp_now
-                .send(&BROADCAST_ADDRESS, counter_string.as_bytes())
-                .unwrap()
-                .wait();
-            println!("Send broadcast status: {:?}", status)
         }
+
+        while let Some(outgoing) = reliable.poll_transmit(now) {
+            send(&mut esp_now, outgoing.dst, &outgoing.data);
+        }
+        while let Some(outgoing) = router.poll_transmit(now) {
+            send(&mut esp_now, outgoing.dst, &outgoing.data);
+        }
+        while let Some(outgoing) = pairing.poll_transmit(now) {
+            // in the clear: the peer has no key yet, or lost the one it had;
+            // the paired peers get theirs back once pairing ends
+            register(&esp_now, outgoing.dst, None);
+            send(&mut esp_now, outgoing.dst, &outgoing.data);
+        }
+        while let Some(event) = reliable.pop_event() {
+            println!("{:?}", event);
+        }
+
+        while let Some(event) = pairing.pop_event() {
+            match event {
+                PairingEvent::Paired(peer) => {
+                    println!("Paired with {:02x?}", peer.mac);
+                    if let Some(dropped) = paired.add(peer) {
+                        let _ = esp_now.remove_peer(&dropped.mac);
+                    }
+                    if let Err(e) = pairing_store.save(&paired) {
+                        println!("Could not save the paired peers: {:?}", e);
+                    }
+                }
+                PairingEvent::Failed(e) => println!("Pairing failed: {:?}", e),
+            }
+            // encrypted from now on, or again
+            for peer in paired.peers() {
+                register(&esp_now, peer.mac, Some(peer.lmk));
+            }
+            if let PairingEvent::Paired(peer) = event {
+                if let Err(e) = reliable.send(now, peer.mac, Message::Text(GREETING)) {
+                    println!("Could not greet peer: {:?}", e);
+                }
+            }
+        }
+
+        if pairing.stage() != stage {
+            stage = pairing.stage();
+            match stage {
+                Stage::Idle => {}
+                Stage::Searching => println!("Pairing, hold the button of the other board"),
+                Stage::Comparing { peer, code } => println!(
+                    "Pairing with {:02x?}, code {:04}: click if it matches",
+                    peer, code
+                ),
+                Stage::Confirmed { code, .. } => {
+                    println!("Code {:04} confirmed, waiting for the peer", code)
+                }
+            }
+        }
+    }
+}
+
+/// Send `data` to `dst`. A peer removed while frames for it were pending
+/// only costs those frames.
+fn send(esp_now: &mut EspNow, dst: Mac, data: &[u8]) {
+    match esp_now.send(&dst, data) {
+        Ok(waiter) => {
+            let _ = waiter.wait();
+        }
+        Err(e) => println!("Could not send to {:02x?}: {:?}", dst, e),
+    }
+}
+
+/// Add `mac` as a peer, or update it; encrypted with `lmk` if given.
+fn register(esp_now: &EspNow, mac: Mac, lmk: Option<[u8; LMK_LEN]>) {
+    let peer = PeerInfo {
+        peer_address: mac,
+        lmk,
+        channel: None,
+        encrypt: lmk.is_some(),
+    };
+    let result = if esp_now.peer_exists(&mac) {
+        esp_now.modify_peer(peer)
+    } else {
+        esp_now.add_peer(peer)
+    };
+    if let Err(e) = result {
+        println!("Could not register peer {:02x?}: {:?}", mac, e);
     }
 }
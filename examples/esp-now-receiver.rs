@@ -1,143 +1,302 @@
 #![no_std]
 #![no_main]
 
-use core::fmt::Write as FmtWrite;
-use core::str;
-use embedded_graphics::{
-    mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
-    pixelcolor::BinaryColor,
-    prelude::*,
-    text::{Baseline, Text},
+use embassy_executor::Spawner;
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Ticker};
+use esp32_c3_buddy_like::{
+    app::{App, Input, Mac, Received, Receiver, BROADCAST},
+    button::ButtonEvent,
+    pairing::{PairedPeers, Pairing, PairingEvent, PairingStore, Stage, LMK_LEN},
+    peers::{Peer, PeerStore, PeerTable},
+    protocol::{
+        mesh::{MeshConfig, Router},
+        reliable::{Reliable, ReliableConfig},
+        Frame, Message,
+    },
+    runtime::{self, now, ButtonEvents, HeardFrames, SharedDisplay},
+    screens, Board, VisibleArea,
 };
 use esp_backtrace as _;
 use esp_println::println;
+use esp_storage::FlashStorage;
 use esp_wifi::{
-    esp_now::{PeerInfo, BROADCAST_ADDRESS},
-    init, EspWifiInitFor,
+    esp_now::{EspNow, EspNowManager, PeerInfo},
+    init,
+    wifi::get_sta_mac,
+    EspWifiInitFor, EspWifiInitialization,
 };
-use hal::{gpio::Io, i2c, prelude::*, rng::Rng, timer::timg::TimerGroup};
-use sh1106::{prelude::*, Builder};
+// `esp_hal_embassy::main` expects the HAL under its own name
+use hal as esp_hal;
+use static_cell::StaticCell;
 
-fn bytes_to_ascii_string(bytes: &[u8; 256]) -> Result<&str, str::Utf8Error> {
-    // Find the actual length by looking for null terminator or non-ASCII bytes
-    let len = bytes
-        .iter()
-        .position(|&b| b == 0 || b > 127)
-        .unwrap_or(bytes.len());
+// the channel of a gateway's access point, e.g.
+// `ESP_NOW_CHANNEL=6 cargo espflash flash --release --example ...`
+const CHANNEL: Option<&str> = option_env!("ESP_NOW_CHANNEL");
 
-    // Take the slice up to the determined length
-    let valid_bytes = &bytes[..len];
+/// How often the retries and relays of the protocols are looked after.
+const TICK_MS: u64 = 10;
 
-    // Convert to str, this is safe because we've verified ASCII-only content
-    str::from_utf8(valid_bytes)
+static BUTTON: ButtonEvents = Channel::new();
+static HEARD: HeardFrames = Channel::new();
+
+/// A `&'static mut` to `$val`, for what the tasks share or keep for good.
+macro_rules! mk_static {
+    ($t:ty, $val:expr $(,)?) => {{
+        static STATIC_CELL: StaticCell<$t> = StaticCell::new();
+        STATIC_CELL.uninit().write($val)
+    }};
 }
 
-#[entry]
-fn main() -> ! {
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) {
     esp_alloc::heap_allocator!(72 * 1024);
-    let peripherals = hal::init({
-        let mut config = hal::Config::default();
-        config.cpu_clock = CpuClock::max();
-        config
-    });
-
-    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let board = Board::init();
+    // Wi-Fi takes the timer of the other group
+    esp_hal_embassy::init(board.timg1.timer0);
 
-    let init = init(
-        EspWifiInitFor::Wifi,
-        timg0.timer0,
-        Rng::new(peripherals.RNG),
-        peripherals.RADIO_CLK,
-    )
-    .unwrap();
+    let mut rng = board.rng;
+    let init = mk_static!(
+        EspWifiInitialization,
+        init(
+            EspWifiInitFor::Wifi,
+            board.timg0.timer0,
+            rng,
+            board.radio_clk,
+        )
+        .unwrap(),
+    );
 
-    let wifi = peripherals.WIFI;
-    let mut esp_now = esp_wifi::esp_now::EspNow::new(&init, wifi).unwrap();
+    let wifi = board.wifi;
+    let (esp_now, mut sender, receiver) = EspNow::new(init, wifi).unwrap().split();
 
     println!("esp-now version {}", esp_now.get_version().unwrap());
+    if let Some(channel) = CHANNEL.and_then(|c| c.parse().ok()) {
+        esp_now.set_channel(channel).unwrap();
+    }
+
+    let mut own = [0u8; 6];
+    get_sta_mac(&mut own);
 
-    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
+    let mut pairing_store = PairingStore::new(FlashStorage::new());
+    let mut paired = pairing_store.load().unwrap_or_else(|e| {
+        println!("Could not load the paired peers: {:?}", e);
+        PairedPeers::default()
+    });
+    let mut peer_store = PeerStore::new(FlashStorage::new());
+    let peers = peer_store.load().unwrap_or_else(|e| {
+        println!("Could not load the peer table: {:?}", e);
+        PeerTable::default()
+    });
+    let mut app = Receiver::with_peers(peers);
 
-    let sda = io.pins.gpio5;
-    let scl = io.pins.gpio6;
+    for peer in paired.peers() {
+        println!("Paired with {:02x?}", peer.mac);
+        register(&esp_now, peer.mac, Some(peer.lmk));
+        let evicted = app.peers_mut().set_paired(peer.mac, true, true);
+        unregister(&esp_now, evicted);
+    }
 
-    let i2c = i2c::I2c::new(peripherals.I2C0, sda, scl, 400u32.kHz());
+    let oled = VisibleArea::new(board.display);
+    let display = mk_static!(SharedDisplay, SharedDisplay::new(&oled));
+    spawner.must_spawn(runtime::display_task(display, oled.into_inner()));
+    spawner.must_spawn(runtime::button_task(board.button, &BUTTON));
+    spawner.must_spawn(runtime::radio_task(receiver, &HEARD));
 
-    // // positions on the screen
-    // // the zero point on the screen is (28, 12)
-    let starting_point = Point::new(28, 12);
+    display.draw(|display| app.render(display)).await.unwrap();
 
-    let mut display: GraphicsMode<_> = Builder::new().connect_i2c(i2c).into();
-    match display.init() {
-        Ok(_) => (),
-        Err(e) => println!("Error initializing display: {:?}", e),
-    }
-    display.flush().unwrap();
-
-    let text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_6X10)
-        .text_color(BinaryColor::On)
-        .build();
-
-    display.clear();
-    Text::with_baseline(
-        "ESP-NOW receiver:",
-        starting_point,
-        text_style,
-        Baseline::Top,
-    )
-    .draw(&mut display)
-    .unwrap();
-    display.flush().unwrap();
+    let mut reliable = Reliable::new(ReliableConfig::default(), rng.random());
+    let mut router = Router::new(own, MeshConfig::default(), rng.random());
+    let mut pairing = Pairing::new(own);
+    let mut stage = Stage::Idle;
+    let mut ticker = Ticker::every(Duration::from_millis(TICK_MS));
 
     loop {
-        let r = esp_now.receive();
-        if let Some(r) = r {
-            let message = match bytes_to_ascii_string(&r.data) {
-                Ok(s) => s,
-                Err(e) => {
-                    println!("Error decoding message: {:?}", e);
-                    continue;
+        // whatever comes first; the tick keeps the retries going
+        let (button, r) = match select3(BUTTON.receive(), HEARD.receive(), ticker.next()).await {
+            Either3::First(event) => (Some(event), None),
+            Either3::Second(heard) => (None, Some(heard)),
+            Either3::Third(()) => (None, None),
+        };
+        let now = now();
+
+        // a long press pairs; while pairing, a click accepts the code and a
+        // double click stops, otherwise the app gets them
+        let mut app_button = None;
+        if let Some(event) = button {
+            match event {
+                ButtonEvent::LongPress(_) => {
+                    let mut secret = [0; 32];
+                    let mut nonce = [0; 16];
+                    rng.read(&mut secret);
+                    rng.read(&mut nonce);
+                    pairing.start(now, secret, nonce);
                 }
-            };
+                ButtonEvent::Click if pairing.is_active() => {
+                    pairing.confirm(now);
+                }
+                ButtonEvent::DoubleClick if pairing.is_active() => pairing.cancel(),
+                event => app_button = Some(event),
+            }
+        }
+
+        // what the app gets: frames from paired peers, directly or through
+        // the mesh
+        let mut via_mesh = false;
+        let received = r.as_ref().and_then(|r| {
+            let src = r.src;
+            let evicted = app.peers_mut().seen(now, src, r.rssi);
+            unregister(&esp_now, evicted);
+            if let Ok(frame) = Frame::decode(&r.data) {
+                if pairing.receive(now, src, &frame.message) {
+                    return None;
+                }
+                if let Message::Mesh(mesh) = frame.message {
+                    // relayed for anyone, but only taken from paired boards
+                    // that prove it is them
+                    let delivered = router.receive(now, mesh)?;
+                    let peer = paired.get(&delivered.origin)?;
+                    let data = delivered.authenticated(&peer.mesh_key())?;
+                    via_mesh = true;
+                    return Some(Received {
+                        src: delivered.origin,
+                        dst: delivered.dst,
+                        data,
+                    });
+                }
+            }
+            // broadcasts are never encrypted, so anyone could claim to be a
+            // paired board in one
+            if !paired.contains(&src) || r.dst == BROADCAST {
+                return None;
+            }
+            // acknowledge what asks for it, and drop repeats
+            reliable.receive(now, src, r.dst, &r.data)?;
+            Some(r.received())
+        });
+        let input = Input {
+            button: app_button,
+            received,
+        };
+
+        let effects = app.update(now, input);
+
+        if let Some(message) = app.message().filter(|_| received.is_some()) {
             println!("Received message: {}", message);
+        }
 
-            if r.info.dst_address == BROADCAST_ADDRESS {
-                if !esp_now.peer_exists(&r.info.src_address) {
-                    esp_now
-                        .add_peer(PeerInfo {
-                            peer_address: r.info.src_address,
-                            lmk: None,
-                            channel: None,
-                            encrypt: false,
-                        })
-                        .unwrap();
+        if via_mesh {
+            // the origin is out of range, answer through the mesh
+            if let Some(outgoing) = effects.send {
+                let frame = Frame::decode(&outgoing.data).ok();
+                if let (Some(frame), Some(peer)) = (frame, paired.get(&outgoing.dst)) {
+                    let key = peer.mesh_key();
+                    let _ = router.send_authenticated(now, outgoing.dst, frame.message, &key);
+                }
+            }
+        } else {
+            if let Some(peer) = effects.add_peer {
+                if !esp_now.peer_exists(&peer) {
+                    register(&esp_now, peer, paired.get(&peer).map(|p| p.lmk));
                 }
-                let status = esp_now
-                    .send(&r.info.src_address, b"Hello Peer")
-                    .unwrap()
-                    .wait();
+            }
+            if let Some(outgoing) = effects.send {
+                let status = sender.send_async(&outgoing.dst, &outgoing.data).await;
                 println!("Send hello to peer status: {:?}", status);
             }
-            display.clear();
-            Text::with_baseline("Received:", starting_point, text_style, Baseline::Top)
-                .draw(&mut display)
+        }
+        while let Some(outgoing) = router.poll_transmit(now) {
+            let _ = sender.send_async(&outgoing.dst, &outgoing.data).await;
+        }
+        while let Some(outgoing) = reliable.poll_transmit(now) {
+            let _ = sender.send_async(&outgoing.dst, &outgoing.data).await;
+        }
+        while let Some(outgoing) = pairing.poll_transmit(now) {
+            // in the clear: the peer has no key yet, or lost the one it had;
+            // the paired peers get theirs back once pairing ends
+            register(&esp_now, outgoing.dst, None);
+            let _ = sender.send_async(&outgoing.dst, &outgoing.data).await;
+        }
+
+        let mut redraw = effects.redraw;
+        while let Some(event) = pairing.pop_event() {
+            let text = match event {
+                PairingEvent::Paired(peer) => {
+                    println!("Paired with {:02x?}", peer.mac);
+                    if let Some(dropped) = paired.add(peer) {
+                        let _ = esp_now.remove_peer(&dropped.mac);
+                        app.peers_mut().set_paired(dropped.mac, false, false);
+                    }
+                    let evicted = app.peers_mut().set_paired(peer.mac, true, true);
+                    unregister(&esp_now, evicted);
+                    if let Err(e) = pairing_store.save(&paired) {
+                        println!("Could not save the paired peers: {:?}", e);
+                    }
+                    "Paired!"
+                }
+                PairingEvent::Failed(e) => {
+                    println!("Pairing failed: {:?}", e);
+                    "Pairing\nfailed"
+                }
+            };
+            // encrypted from now on, or again
+            for peer in paired.peers() {
+                register(&esp_now, peer.mac, Some(peer.lmk));
+            }
+            display
+                .draw(|display| screens::text(display, text, 0))
+                .await
                 .unwrap();
-            let mut counter_string: heapless::String<256> = heapless::String::new();
-            match write!(counter_string, "{}", message) {
-                Ok(_) => (),
-                Err(e) => println!("Error writing ip: {:?}", e),
+            redraw = false;
+        }
+
+        if pairing.stage() != stage {
+            stage = pairing.stage();
+            if let Stage::Comparing { peer, code } = stage {
+                println!("Pairing with {:02x?}, code {:04}", peer, code);
             }
-            Text::with_baseline(
-                &counter_string,
-                Point::new(starting_point.x, starting_point.y + 10),
-                text_style,
-                Baseline::Top,
-            )
-            .draw(&mut display)
-            .unwrap();
-
-            display.flush().unwrap();
+            if stage != Stage::Idle {
+                display
+                    .draw(|display| screens::pairing(display, stage))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        if app.take_peers_changed(now) {
+            if let Err(e) = peer_store.save(app.peers()) {
+                println!("Could not save the peer table: {:?}", e);
+            }
+        }
+
+        if redraw && !pairing.is_active() {
+            display.draw(|display| app.render(display)).await.unwrap();
         }
     }
 }
+
+/// Add `mac` as a peer, or update it; encrypted with `lmk` if given.
+fn register(esp_now: &EspNowManager, mac: Mac, lmk: Option<[u8; LMK_LEN]>) {
+    let peer = PeerInfo {
+        peer_address: mac,
+        lmk,
+        channel: None,
+        encrypt: lmk.is_some(),
+    };
+    let result = if esp_now.peer_exists(&mac) {
+        esp_now.modify_peer(peer)
+    } else {
+        esp_now.add_peer(peer)
+    };
+    if let Err(e) = result {
+        println!("Could not register peer {:02x?}: {:?}", mac, e);
+    }
+}
+
+/// Drop a peer pushed out of the peer table, unless it is paired.
+fn unregister(esp_now: &EspNowManager, evicted: Option<Peer>) {
+    if let Some(peer) = evicted.filter(|p| !p.paired) {
+        let _ = esp_now.remove_peer(&peer.mac);
+    }
+}
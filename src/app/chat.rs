@@ -0,0 +1,262 @@
+//! Chat with the paired peers over ESP-NOW
+//!
+//! The history shows the latest messages, each stamped with the name the
+//! sender has in the [`PeerTable`]. With the single button:
+//!
+//! | button       | history                   | composing                  |
+//! |--------------|---------------------------|----------------------------|
+//! | click        | scroll back one message   | next character on the wheel |
+//! | double click | start a message           | take the character         |
+//! | long press   | talk to the next peer     | send, or cancel when empty |
+//!
+//! The [`WHEEL`] holds the letters, a space, digits, some punctuation and
+//! `<`, which deletes the last character.
+
+use heapless::{Deque, String};
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use super::{App, Effects, Input, Mac, Outgoing};
+use crate::button::ButtonEvent;
+use crate::peers::{Peer, PeerTable, MAX_NAME_LEN};
+use crate::protocol::{Frame, Message, MAX_MESSAGE_LEN};
+use crate::screens;
+
+/// Messages kept in the history.
+pub const HISTORY_LEN: usize = 16;
+
+/// Longest message that can be composed.
+pub const MAX_DRAFT_LEN: usize = 48;
+
+/// The characters of the composer, in wheel order: the most used letters
+/// come first, so they take the fewest clicks.
+pub const WHEEL: &[u8] = b"ETAOINSHRDLUCMFWYPVBGKQJXZ 0123456789.,?!<";
+
+/// Deletes the last character instead of adding one.
+pub const DELETE: char = '<';
+
+/// One entry of the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// The sender's name, or the receiver's for messages sent from here.
+    pub peer: String<MAX_NAME_LEN>,
+    /// Sent from this board.
+    pub outgoing: bool,
+    pub text: String<MAX_MESSAGE_LEN>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// The history, `scroll` messages back from the latest.
+    History { scroll: usize },
+    /// Writing a message, `wheel` is the position on [`WHEEL`].
+    Compose {
+        draft: String<MAX_DRAFT_LEN>,
+        wheel: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct Chat {
+    peers: PeerTable,
+    /// Who messages go to.
+    selected: Option<Mac>,
+    history: Deque<ChatMessage, HISTORY_LEN>,
+    mode: Mode,
+    seq: u16,
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Self::with_peers(PeerTable::new())
+    }
+}
+
+impl Chat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start with the peers loaded from flash, talking to the first paired
+    /// one.
+    pub fn with_peers(peers: PeerTable) -> Self {
+        let selected = peers.peers().iter().find(|p| p.paired).map(|p| p.mac);
+        Chat {
+            peers,
+            selected,
+            history: Deque::new(),
+            mode: Mode::History { scroll: 0 },
+            seq: 0,
+        }
+    }
+
+    pub fn peers(&self) -> &PeerTable {
+        &self.peers
+    }
+
+    /// The firmware records every frame it hears here, and which peers are
+    /// paired.
+    pub fn peers_mut(&mut self) -> &mut PeerTable {
+        &mut self.peers
+    }
+
+    /// Whether the peer table should be saved at `now_ms`, see
+    /// [`PeerTable::take_changed`].
+    pub fn take_peers_changed(&mut self, now_ms: u64) -> bool {
+        self.peers.take_changed(now_ms)
+    }
+
+    /// The peer messages go to.
+    pub fn selected(&self) -> Option<Mac> {
+        self.selected
+    }
+
+    /// The history, oldest first.
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = &ChatMessage> {
+        self.history.iter()
+    }
+
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    /// Name of `mac` in the peer table, or the default one.
+    fn name(&self, mac: &Mac) -> String<MAX_NAME_LEN> {
+        match self.peers.get(mac) {
+            Some(peer) => peer.name.clone(),
+            None => Peer::new(*mac).name,
+        }
+    }
+
+    fn push(&mut self, message: ChatMessage) {
+        if self.history.is_full() {
+            self.history.pop_front();
+        }
+        let _ = self.history.push_back(message);
+    }
+
+    /// Talk to the paired peer after the selected one.
+    fn next_peer(&mut self) {
+        let mut paired = self
+            .peers
+            .peers()
+            .iter()
+            .filter(|p| p.paired)
+            .map(|p| p.mac);
+        let first = paired.clone().next();
+        self.selected = match self.selected {
+            Some(mac) => paired.by_ref().find(|&m| m == mac).and(paired.next()),
+            None => None,
+        }
+        .or(first);
+    }
+
+    fn send(&mut self, text: &str) -> Option<Outgoing> {
+        let dst = self.selected?;
+        self.seq = self.seq.wrapping_add(1);
+        // a draft always fits
+        let data = Frame::new(self.seq, Message::Text(text)).to_vec().unwrap();
+        self.push(ChatMessage {
+            peer: self.name(&dst),
+            outgoing: true,
+            text: String::try_from(text).unwrap(),
+        });
+        Some(Outgoing { dst, data })
+    }
+
+    fn received(&mut self, src: Mac, data: &[u8]) -> bool {
+        let Ok(Frame {
+            message: Message::Text(text),
+            ..
+        }) = Frame::decode(data)
+        else {
+            return false;
+        };
+        // decoding keeps it to MAX_MESSAGE_LEN, anything longer is dropped
+        // rather than trusted
+        let Ok(text) = String::try_from(text) else {
+            return false;
+        };
+        self.push(ChatMessage {
+            peer: self.name(&src),
+            outgoing: false,
+            text,
+        });
+        true
+    }
+}
+
+impl App for Chat {
+    fn update(&mut self, _now_ms: u64, input: Input<'_>) -> Effects {
+        let mut effects = Effects::default();
+        if let Some(received) = input.received {
+            if self.received(received.src, received.data) {
+                if let Mode::History { scroll } = &mut self.mode {
+                    // back to the latest
+                    *scroll = 0;
+                    effects.redraw = true;
+                }
+            }
+        }
+
+        let Some(event) = input.button else {
+            return effects;
+        };
+        match (&mut self.mode, event) {
+            (Mode::History { scroll }, ButtonEvent::Click) => {
+                *scroll = if *scroll + 1 < self.history.len() {
+                    *scroll + 1
+                } else {
+                    0
+                };
+            }
+            (Mode::History { .. }, ButtonEvent::DoubleClick) => {
+                self.mode = Mode::Compose {
+                    draft: String::new(),
+                    wheel: 0,
+                };
+            }
+            (Mode::History { .. }, ButtonEvent::LongPress(_)) => self.next_peer(),
+            (Mode::Compose { wheel, .. }, ButtonEvent::Click) => {
+                *wheel = (*wheel + 1) % WHEEL.len();
+            }
+            (Mode::Compose { draft, wheel }, ButtonEvent::DoubleClick) => {
+                match WHEEL[*wheel] as char {
+                    DELETE => {
+                        draft.pop();
+                    }
+                    // full drafts stay as they are
+                    c => {
+                        let _ = draft.push(c);
+                    }
+                }
+            }
+            (Mode::Compose { draft, .. }, ButtonEvent::LongPress(_)) => {
+                let draft = core::mem::take(draft);
+                self.mode = Mode::History { scroll: 0 };
+                if !draft.is_empty() {
+                    effects.send = self.send(&draft);
+                }
+            }
+            _ => return effects,
+        }
+        effects.redraw = true;
+        effects
+    }
+
+    fn render<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let to = self.selected.map(|mac| self.name(&mac));
+        match &self.mode {
+            Mode::History { scroll } => {
+                let newest_first = self.history.iter().rev().skip(*scroll);
+                screens::chat_history(display, to.as_deref(), newest_first)
+            }
+            Mode::Compose { draft, wheel } => {
+                screens::chat_compose(display, to.as_deref(), draft, *wheel)
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Counts button presses
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use super::{App, Effects, Input};
+use crate::{button::ButtonEvent, screens};
+
+#[derive(Debug, Default)]
+pub struct Counter {
+    count: u32,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+impl App for Counter {
+    fn update(&mut self, _now_ms: u64, input: Input<'_>) -> Effects {
+        if input.button == Some(ButtonEvent::Pressed) {
+            self.count += 1;
+            return Effects::redraw();
+        }
+        Effects::default()
+    }
+
+    fn render<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        screens::counter(display, self.count)
+    }
+}
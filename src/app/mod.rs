@@ -0,0 +1,84 @@
+//! Hardware independent application logic
+//!
+//! Each app is a state machine: the firmware feeds it the current time and
+//! whatever happened since the last call, carries out the returned
+//! [`Effects`], and asks it to [`App::render`] itself when needed. Nothing in
+//! here touches a peripheral, so all of it runs on the host in `cargo host-test`.
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use crate::button::ButtonEvent;
+
+pub mod chat;
+pub mod counter;
+pub mod receiver;
+pub mod snow;
+
+pub use chat::Chat;
+pub use counter::Counter;
+pub use receiver::Receiver;
+pub use snow::Snow;
+
+/// A MAC address, as used by ESP-NOW.
+pub type Mac = [u8; 6];
+
+/// Destination address of ESP-NOW broadcasts.
+pub const BROADCAST: Mac = [0xff; 6];
+
+/// Largest payload of a single ESP-NOW frame.
+pub const MAX_PAYLOAD: usize = 250;
+
+/// What happened since the previous [`App::update`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Input<'a> {
+    /// Something happened with the button.
+    pub button: Option<ButtonEvent>,
+    /// An ESP-NOW frame that arrived.
+    pub received: Option<Received<'a>>,
+}
+
+/// An incoming ESP-NOW frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Received<'a> {
+    pub src: Mac,
+    pub dst: Mac,
+    pub data: &'a [u8],
+}
+
+/// An ESP-NOW frame the app wants to send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outgoing {
+    pub dst: Mac,
+    pub data: heapless::Vec<u8, MAX_PAYLOAD>,
+}
+
+/// What the firmware has to do after an [`App::update`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Effects {
+    /// The screen content changed and should be rendered and flushed.
+    pub redraw: bool,
+    /// Register this address as an ESP-NOW peer, if it is not one already.
+    pub add_peer: Option<Mac>,
+    /// Send this frame.
+    pub send: Option<Outgoing>,
+}
+
+impl Effects {
+    /// Only ask for a redraw.
+    pub fn redraw() -> Self {
+        Effects {
+            redraw: true,
+            ..Default::default()
+        }
+    }
+}
+
+pub trait App {
+    /// Advance the state machine to `now_ms` (milliseconds since boot).
+    fn update(&mut self, now_ms: u64, input: Input<'_>) -> Effects;
+
+    /// Draw the current state, in visible area coordinates.
+    fn render<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>;
+}
@@ -0,0 +1,249 @@
+//! Shows the last message or bitmap received over ESP-NOW and greets
+//! broadcasting peers
+//!
+//! A message too long for the screen scrolls by itself. A double click
+//! switches to the list of known peers, where a click scrolls down.
+
+use core::fmt::Write;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use super::{App, Effects, Input, Mac, Outgoing, BROADCAST};
+use crate::button::ButtonEvent;
+use crate::peers::PeerTable;
+use crate::protocol::{fragment::Reassembler, BlobKind, Frame, Message, MAX_MESSAGE_LEN};
+use crate::screens::{self, BITMAP_LEN, PEER_ROWS};
+
+/// Sent back as [`Message::Text`] to every device we receive a broadcast from.
+pub const GREETING: &str = "Hello Peer";
+
+/// How long a bitmap may take between two of its fragments.
+pub const FRAGMENT_TIMEOUT_MS: u64 = 2_000;
+
+/// What the receiver shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    /// The last message or bitmap.
+    Received,
+    /// The peer table, scrolled down to `first`.
+    Peers { first: usize },
+}
+
+#[derive(Debug)]
+pub struct Receiver {
+    message: Option<heapless::String<MAX_MESSAGE_LEN>>,
+    /// When the message arrived, it scrolls from then on.
+    message_at_ms: u64,
+    /// Where the message had scrolled to when it was drawn.
+    shown_position: u64,
+    /// Replaces the message while it is the last thing received.
+    bitmap: Option<[u8; BITMAP_LEN]>,
+    reassembler: Reassembler,
+    /// Frames that could not be decoded.
+    rejected: u32,
+    seq: u16,
+    peers: PeerTable,
+    /// The table may have changed since the peer list was drawn.
+    peers_touched: bool,
+    /// Peers online when the list was drawn.
+    shown_online: usize,
+    view: View,
+    now_ms: u64,
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::with_peers(PeerTable::new())
+    }
+}
+
+impl Receiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start with the peers loaded from flash.
+    pub fn with_peers(peers: PeerTable) -> Self {
+        Receiver {
+            message: None,
+            message_at_ms: 0,
+            shown_position: 0,
+            bitmap: None,
+            reassembler: Reassembler::new(FRAGMENT_TIMEOUT_MS),
+            rejected: 0,
+            seq: 0,
+            peers,
+            peers_touched: false,
+            shown_online: 0,
+            view: View::Received,
+            now_ms: 0,
+        }
+    }
+
+    /// The peers shown in the list.
+    pub fn peers(&self) -> &PeerTable {
+        &self.peers
+    }
+
+    /// The firmware records every frame it hears here, before deciding
+    /// whether to listen to it, and which peers are paired.
+    pub fn peers_mut(&mut self) -> &mut PeerTable {
+        self.peers_touched = true;
+        &mut self.peers
+    }
+
+    /// Whether the peer table should be saved at `now_ms`, see
+    /// [`PeerTable::take_changed`].
+    pub fn take_peers_changed(&mut self, now_ms: u64) -> bool {
+        self.peers.take_changed(now_ms)
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    /// The last message received, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// The last bitmap received, unless a message came after it.
+    pub fn bitmap(&self) -> Option<&[u8; BITMAP_LEN]> {
+        self.bitmap.as_ref()
+    }
+
+    /// Number of received payloads that were not valid frames.
+    pub fn rejected(&self) -> u32 {
+        self.rejected
+    }
+
+    fn reply(&mut self, dst: Mac, message: Message<'_>) -> Outgoing {
+        self.seq = self.seq.wrapping_add(1);
+        Outgoing {
+            dst,
+            // replies are short
+            data: Frame::new(self.seq, message).to_vec().unwrap(),
+        }
+    }
+}
+
+impl App for Receiver {
+    fn update(&mut self, now_ms: u64, input: Input<'_>) -> Effects {
+        self.now_ms = now_ms;
+        let mut effects = self.received(now_ms, input);
+
+        let before = self.view;
+        match (input.button, self.view) {
+            (Some(ButtonEvent::DoubleClick), View::Received) => {
+                self.view = View::Peers { first: 0 }
+            }
+            (Some(ButtonEvent::DoubleClick), View::Peers { .. }) => self.view = View::Received,
+            (Some(ButtonEvent::Click), View::Peers { first }) => {
+                // back to the top after the last page
+                let first = first + 1;
+                self.view = View::Peers {
+                    first: if first + PEER_ROWS > self.peers.peers().len() {
+                        0
+                    } else {
+                        first
+                    },
+                };
+            }
+            _ => {}
+        }
+
+        if let View::Peers { .. } = self.view {
+            // the list hides what arrived, but changes on its own over time
+            let online = self.peers.online_count(now_ms);
+            effects.redraw = self.peers_touched || online != self.shown_online;
+            self.peers_touched = false;
+            self.shown_online = online;
+        }
+        if let (View::Received, None, Some(message)) = (self.view, &self.bitmap, self.message()) {
+            let position = screens::message_box(message)
+                .at(now_ms.saturating_sub(self.message_at_ms))
+                .position();
+            effects.redraw |= position != self.shown_position;
+            self.shown_position = position;
+        }
+        effects.redraw |= self.view != before;
+        effects
+    }
+
+    fn render<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        match (self.view, &self.bitmap) {
+            (View::Peers { first }, _) => {
+                screens::peers(display, self.now_ms, self.peers.peers(), first)
+            }
+            (View::Received, Some(bitmap)) => screens::bitmap(display, bitmap),
+            (View::Received, None) => screens::esp_now_receiver(
+                display,
+                self.message(),
+                self.now_ms.saturating_sub(self.message_at_ms),
+            ),
+        }
+    }
+}
+
+impl Receiver {
+    /// Takes what arrived, whatever is shown.
+    fn received(&mut self, now_ms: u64, input: Input<'_>) -> Effects {
+        let Some(received) = input.received else {
+            return Effects::default();
+        };
+        let Ok(frame) = Frame::decode(received.data) else {
+            self.rejected += 1;
+            return Effects::default();
+        };
+
+        let mut message = heapless::String::new();
+        // every message fits, a text is at most MAX_MESSAGE_LEN bytes
+        let _ = match frame.message {
+            Message::Text(text) => message.write_str(text),
+            Message::Counter(value) => write!(message, "{}", value),
+            Message::SensorReading(reading) => write!(message, "{}", reading),
+            Message::Ping(_)
+            | Message::Pong(_)
+            | Message::Command(_)
+            | Message::Ack { .. }
+            | Message::Fragment(_)
+            | Message::PairCommit { .. }
+            | Message::PairReveal { .. }
+            | Message::PairConfirm { .. }
+            | Message::Mesh(_) => Ok(()),
+        };
+
+        let mut effects = if !message.is_empty() {
+            self.message = Some(message);
+            self.message_at_ms = now_ms;
+            self.bitmap = None;
+            Effects::redraw()
+        } else if let Message::Fragment(fragment) = frame.message {
+            match self.reassembler.receive(now_ms, received.src, fragment) {
+                Ok(Some(blob)) if blob.kind == BlobKind::Bitmap => match blob.data.try_into() {
+                    Ok(bitmap) => {
+                        self.bitmap = Some(bitmap);
+                        Effects::redraw()
+                    }
+                    Err(_) => Effects::default(),
+                },
+                // other blobs are of no use here, and a broken transfer
+                // leaves the screen as it is
+                _ => Effects::default(),
+            }
+        } else {
+            Effects::default()
+        };
+        if let Message::Ping(value) = frame.message {
+            effects.add_peer = Some(received.src);
+            effects.send = Some(self.reply(received.src, Message::Pong(value)));
+        } else if received.dst == BROADCAST {
+            effects.add_peer = Some(received.src);
+            effects.send = Some(self.reply(received.src, Message::Text(GREETING)));
+        }
+        effects
+    }
+}
@@ -0,0 +1,103 @@
+//! Snowflakes falling at a 45 degree angle
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_4X6, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::{Baseline, Text},
+};
+
+use super::{App, Effects, Input};
+
+/// Number of snowflakes on the screen.
+pub const FLAKES: usize = 10;
+
+/// How often the flakes move.
+pub const STEP_MS: u64 = 100;
+
+const SNOW_STYLE: MonoTextStyle<'static, BinaryColor> =
+    MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+
+#[derive(Debug)]
+pub struct Snow {
+    size: Size,
+    flakes: [Point; FLAKES],
+    rng: u32,
+    last_step_ms: Option<u64>,
+}
+
+impl Snow {
+    /// Snow for a screen of `size`, `seed` is typically a hardware random number.
+    pub fn new(seed: u32, size: Size) -> Self {
+        Snow {
+            size,
+            flakes: [Point::zero(); FLAKES],
+            // xorshift gets stuck at zero
+            rng: seed | 1,
+            last_step_ms: None,
+        }
+    }
+
+    pub fn flakes(&self) -> &[Point] {
+        &self.flakes
+    }
+
+    fn random(&mut self) -> u32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    fn random_x(&mut self) -> i32 {
+        (self.random() % self.size.width) as i32
+    }
+
+    fn step(&mut self) {
+        for i in 0..FLAKES {
+            // Randomly generate new snowflakes at the top
+            if self.random() % 20 == 0 {
+                self.flakes[i] = Point::new(self.random_x(), 0);
+                continue;
+            }
+
+            // Adjust for 45-degree tilt
+            let mut flake = self.flakes[i] + Point::new(-1, 1);
+
+            // Check bounds and reset if needed
+            if flake.y > self.size.height as i32 {
+                flake = Point::new(self.random_x(), 0);
+            }
+            if flake.x < 0 {
+                // Wrap around if it goes off the left edge
+                flake.x = self.size.width as i32;
+            }
+            self.flakes[i] = flake;
+        }
+    }
+}
+
+impl App for Snow {
+    fn update(&mut self, now_ms: u64, _input: Input<'_>) -> Effects {
+        match self.last_step_ms {
+            Some(last) if now_ms.saturating_sub(last) < STEP_MS => Effects::default(),
+            _ => {
+                self.last_step_ms = Some(now_ms);
+                self.step();
+                Effects::redraw()
+            }
+        }
+    }
+
+    fn render<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        display.clear(BinaryColor::Off)?;
+        for &flake in &self.flakes {
+            Text::with_baseline("*", flake, SNOW_STYLE, Baseline::Top).draw(display)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Peripheral setup for the esp32-c3 buddy-like board
+//!
+//! The following wiring is assumed:
+//! - OLED (SH1106, I2C) => SDA GPIO5, SCL GPIO6
+//! - LED => GPIO8
+//! - Button => GPIO9 (active low, also the BOOT strapping pin)
+//! - Console => the USB-Serial-JTAG port, shared with `esp_println`
+//!
+//! The I2C driver is set up for async use: the blocking binaries flush the
+//! display as before, the async ones await the transfers, which run from the
+//! I2C interrupt. The ESP32-C3 has no DMA for I2C.
+
+use embedded_hal::i2c::I2c as BlockingI2c;
+use esp_println::println;
+use hal::{
+    gpio::{Input, Io, Level, Output, Pull},
+    i2c::Error as I2cError,
+    i2c::I2c,
+    interrupt::InterruptHandler,
+    peripherals::{I2C0, RADIO_CLK, TIMG0, TIMG1, WIFI},
+    prelude::*,
+    rng::Rng,
+    time,
+    timer::timg::TimerGroup,
+    usb_serial_jtag::UsbSerialJtag,
+    Async, Blocking,
+};
+
+use crate::framebuffer::{AsyncPageWriter, BufferedDisplay, PageWriter, WIDTH};
+
+/// I2C bus clock used for the OLED.
+pub const I2C_FREQUENCY_KHZ: u32 = 400;
+
+/// I2C address of the OLED.
+pub const DISPLAY_ADDRESS: u8 = 0x3c;
+
+/// The OLED behind a framebuffer that sends only what changed.
+pub type Display = BufferedDisplay<Sh1106>;
+
+/// The SH1106 on I2C0, written to blocking or not.
+pub struct Sh1106 {
+    i2c: I2c<'static, I2C0, Async>,
+}
+
+impl Sh1106 {
+    /// The controller has 132 columns, the 128 of the panel start at 2.
+    const COLUMN_OFFSET: u8 = 2;
+
+    /// Set the controller up for a 128x64 panel and switch it on: clock,
+    /// multiplex 64, no offset, charge pump on, remapped segments and COM
+    /// scan, alternative COM pins, contrast, precharge and VCOMH.
+    const INIT: [u8; 23] = [
+        0xae, 0xd5, 0x80, 0xa8, 0x3f, 0xd3, 0x00, 0x40, 0xad, 0x8b, 0xa1, 0xc8, 0xda, 0x12, 0x81,
+        0x80, 0xd9, 0xf1, 0xdb, 0x40, 0xa4, 0xa6, 0xaf,
+    ];
+
+    pub fn new(i2c: I2c<'static, I2C0, Async>) -> Self {
+        Sh1106 { i2c }
+    }
+
+    pub fn init(&mut self) -> Result<(), I2cError> {
+        let mut buf = [0; 1 + Self::INIT.len()];
+        buf[1..].copy_from_slice(&Self::INIT);
+        BlockingI2c::write(&mut self.i2c, DISPLAY_ADDRESS, &buf)
+    }
+
+    /// The commands that make the next data go to `column` of `page`.
+    fn address(page: u8, column: u8) -> [u8; 4] {
+        let column = column + Self::COLUMN_OFFSET;
+        [0x00, 0xb0 | page, column & 0xf, 0x10 | (column >> 4)]
+    }
+
+    /// `data` prefixed to be written to the display RAM; `len` bytes of it.
+    fn data(data: &[u8]) -> ([u8; 1 + WIDTH], usize) {
+        let mut buf = [0x40; 1 + WIDTH];
+        buf[1..=data.len()].copy_from_slice(data);
+        (buf, 1 + data.len())
+    }
+}
+
+impl PageWriter for Sh1106 {
+    type Error = I2cError;
+
+    fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), I2cError> {
+        BlockingI2c::write(&mut self.i2c, DISPLAY_ADDRESS, &Self::address(page, column))?;
+        let (buf, len) = Self::data(data);
+        BlockingI2c::write(&mut self.i2c, DISPLAY_ADDRESS, &buf[..len])
+    }
+}
+
+impl AsyncPageWriter for Sh1106 {
+    type Error = I2cError;
+
+    async fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.i2c
+            .write(DISPLAY_ADDRESS, &Self::address(page, column))
+            .await?;
+        let (buf, len) = Self::data(data);
+        self.i2c.write(DISPLAY_ADDRESS, &buf[..len]).await
+    }
+}
+
+/// Microseconds since boot, for the [`FrameStats`](crate::framebuffer::FrameStats).
+fn now_us() -> u64 {
+    time::now().duration_since_epoch().to_micros()
+}
+
+/// Everything the examples need from the board, already configured.
+pub struct Board {
+    pub display: Display,
+    pub button: Input<'static>,
+    pub led: Output<'static>,
+    pub usb_serial: UsbSerialJtag<'static, Async>,
+    pub rng: Rng,
+    pub timg0: TimerGroup<'static, TIMG0, Blocking>,
+    pub timg1: TimerGroup<'static, TIMG1, Blocking>,
+    pub wifi: WIFI,
+    pub radio_clk: RADIO_CLK,
+}
+
+impl Board {
+    /// Initialize the chip at maximum CPU clock and set up the board peripherals.
+    pub fn init() -> Self {
+        Self::init_inner(None)
+    }
+
+    /// Same as [`Board::init`], but installs `handler` as the GPIO interrupt
+    /// handler before the pins are handed out.
+    pub fn init_with_gpio_handler(handler: InterruptHandler) -> Self {
+        Self::init_inner(Some(handler))
+    }
+
+    fn init_inner(gpio_handler: Option<InterruptHandler>) -> Self {
+        let peripherals = hal::init({
+            let mut config = hal::Config::default();
+            config.cpu_clock = CpuClock::max();
+            config
+        });
+
+        let mut io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
+        if let Some(handler) = gpio_handler {
+            io.set_interrupt_handler(handler);
+        }
+
+        let i2c = I2c::new_async(
+            peripherals.I2C0,
+            io.pins.gpio5,
+            io.pins.gpio6,
+            I2C_FREQUENCY_KHZ.kHz(),
+        );
+
+        let mut display = Display::new(Sh1106::new(i2c), now_us);
+        match display.writer_mut().init() {
+            Ok(_) => (),
+            Err(e) => println!("Error initializing display: {:?}", e),
+        }
+        match display.flush() {
+            Ok(_) => (),
+            Err(e) => println!("Error flushing display: {:?}", e),
+        }
+
+        let button = Input::new(io.pins.gpio9, Pull::Up);
+        let led = Output::new(io.pins.gpio8, Level::High);
+
+        Board {
+            display,
+            button,
+            led,
+            usb_serial: UsbSerialJtag::new_async(peripherals.USB_DEVICE),
+            rng: Rng::new(peripherals.RNG),
+            timg0: TimerGroup::new(peripherals.TIMG0),
+            timg1: TimerGroup::new(peripherals.TIMG1),
+            wifi: peripherals.WIFI,
+            radio_clk: peripherals.RADIO_CLK,
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Debounced push button with click, double-click and long-press detection
+//!
+//! [`Button`] only sees timestamps and levels, so it works from a polling loop
+//! ([`Button::poll`]) as well as from a GPIO edge interrupt
+//! ([`Button::on_edge`] in the handler, [`Button::update`] in the main loop).
+//! Between edges, [`Button::next_deadline`] tells when `update` is due, so an
+//! async task can sleep until the next edge or that time.
+
+use heapless::Deque;
+
+/// Something the user did with the button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    /// The button went down (debounced).
+    Pressed,
+    /// The button went up (debounced).
+    Released,
+    /// A short press, reported once the double-click window has passed.
+    Click,
+    /// Two short presses within the double-click window.
+    DoubleClick,
+    /// The button has been held for this many milliseconds. Reported once per
+    /// press, while the button is still down; the release is not a click.
+    LongPress(u32),
+}
+
+/// Timings of the button driver, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonConfig {
+    /// The level has to be stable this long before a change is accepted.
+    pub debounce_ms: u32,
+    /// A second click within this time after a release is a double-click.
+    pub double_click_ms: u32,
+    /// Holding the button this long is a long press.
+    pub long_press_ms: u32,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        ButtonConfig {
+            debounce_ms: 20,
+            double_click_ms: 300,
+            long_press_ms: 800,
+        }
+    }
+}
+
+/// Number of events that can be queued before the oldest ones are dropped.
+pub const EVENT_QUEUE_LEN: usize = 16;
+
+#[derive(Debug)]
+pub struct Button {
+    config: ButtonConfig,
+    /// Last level seen, and when it was seen first.
+    raw_down: bool,
+    raw_since_ms: u64,
+    /// Accepted (debounced) level.
+    down: bool,
+    pressed_at_ms: u64,
+    long_press_reported: bool,
+    /// Release time of a click that may still become a double-click.
+    pending_click_ms: Option<u64>,
+    events: Deque<ButtonEvent, EVENT_QUEUE_LEN>,
+}
+
+impl Default for Button {
+    fn default() -> Self {
+        Self::new(ButtonConfig::default())
+    }
+}
+
+impl Button {
+    pub fn new(config: ButtonConfig) -> Self {
+        Button {
+            config,
+            raw_down: false,
+            raw_since_ms: 0,
+            down: false,
+            pressed_at_ms: 0,
+            long_press_reported: false,
+            pending_click_ms: None,
+            events: Deque::new(),
+        }
+    }
+
+    pub fn config(&self) -> ButtonConfig {
+        self.config
+    }
+
+    /// Whether the button is down, after debouncing.
+    pub fn is_down(&self) -> bool {
+        self.down
+    }
+
+    /// Polling path: sample the level of the button and process timers.
+    pub fn poll(&mut self, now_ms: u64, down: bool) {
+        self.on_edge(now_ms, down);
+        self.update(now_ms);
+    }
+
+    /// Interrupt path: record the level right after a GPIO edge.
+    ///
+    /// This only stores the level, call [`Button::update`] regularly to turn
+    /// it into events.
+    pub fn on_edge(&mut self, now_ms: u64, down: bool) {
+        if down != self.raw_down {
+            self.raw_down = down;
+            self.raw_since_ms = now_ms;
+        }
+    }
+
+    /// Debounce the recorded level and emit the events that are due at `now_ms`.
+    pub fn update(&mut self, now_ms: u64) {
+        let stable_ms = now_ms.saturating_sub(self.raw_since_ms);
+        if self.raw_down != self.down && stable_ms >= self.config.debounce_ms as u64 {
+            // the change happened when the level started to be stable
+            let at_ms = self.raw_since_ms;
+            if self.down {
+                // the button may have been held long enough before it went up
+                self.check_long_press(at_ms);
+            }
+            self.down = self.raw_down;
+            if self.down {
+                self.pressed(at_ms);
+            } else {
+                self.released(at_ms);
+            }
+        }
+
+        if self.down {
+            self.check_long_press(now_ms);
+        }
+
+        if let Some(released_ms) = self.pending_click_ms {
+            if !self.down
+                && now_ms.saturating_sub(released_ms) >= self.config.double_click_ms as u64
+            {
+                self.pending_click_ms = None;
+                self.push(ButtonEvent::Click);
+            }
+        }
+    }
+
+    /// When [`Button::update`] has something to do without a new edge:
+    /// accept a level, report a long press or a click. `None` if only an edge
+    /// can change anything.
+    pub fn next_deadline(&self) -> Option<u64> {
+        let debounced = (self.raw_down != self.down)
+            .then(|| self.raw_since_ms + self.config.debounce_ms as u64);
+        let long_press = (self.down && !self.long_press_reported)
+            .then(|| self.pressed_at_ms + self.config.long_press_ms as u64);
+        let click = self
+            .pending_click_ms
+            .filter(|_| !self.down)
+            .map(|released_ms| released_ms + self.config.double_click_ms as u64);
+        [debounced, long_press, click].into_iter().flatten().min()
+    }
+
+    /// The oldest event that has not been taken yet.
+    pub fn pop(&mut self) -> Option<ButtonEvent> {
+        self.events.pop_front()
+    }
+
+    fn check_long_press(&mut self, now_ms: u64) {
+        if self.long_press_reported {
+            return;
+        }
+
+        let held_ms = now_ms.saturating_sub(self.pressed_at_ms);
+        if held_ms >= self.config.long_press_ms as u64 {
+            self.long_press_reported = true;
+            // a click right before the long press is not going to be a double-click
+            if self.pending_click_ms.take().is_some() {
+                self.push(ButtonEvent::Click);
+            }
+            self.push(ButtonEvent::LongPress(held_ms as u32));
+        }
+    }
+
+    fn pressed(&mut self, at_ms: u64) {
+        self.pressed_at_ms = at_ms;
+        self.long_press_reported = false;
+        self.push(ButtonEvent::Pressed);
+    }
+
+    fn released(&mut self, at_ms: u64) {
+        self.push(ButtonEvent::Released);
+        if self.long_press_reported {
+            return;
+        }
+
+        match self.pending_click_ms.take() {
+            Some(previous)
+                if at_ms.saturating_sub(previous) <= self.config.double_click_ms as u64 =>
+            {
+                self.push(ButtonEvent::DoubleClick);
+            }
+            Some(_) => {
+                // too late for a double-click, report both clicks separately
+                self.push(ButtonEvent::Click);
+                self.pending_click_ms = Some(at_ms);
+            }
+            None if self.config.double_click_ms == 0 => self.push(ButtonEvent::Click),
+            None => self.pending_click_ms = Some(at_ms),
+        }
+    }
+
+    fn push(&mut self, event: ButtonEvent) {
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        // there is room now
+        let _ = self.events.push_back(event);
+    }
+}
@@ -0,0 +1,248 @@
+//! Wi-Fi credentials persisted in flash
+//!
+//! The credentials live in the `config` partition from `partitions.csv`, as a
+//! single record:
+//!
+//! | bytes | content                                         |
+//! |-------|-------------------------------------------------|
+//! | 4     | magic `BCFG`                                    |
+//! | 1     | format version                                  |
+//! | 1     | number of networks                              |
+//! | ...   | per network: ssid len, ssid, password len, password |
+//! | 4     | CRC-32 of everything before it, little endian   |
+//!
+//! A freshly erased partition reads as an empty configuration.
+
+use embedded_storage::Storage;
+use heapless::{String, Vec};
+
+use crate::crc::crc32;
+
+/// Offset of the `config` partition, keep in sync with `partitions.csv`.
+pub const CONFIG_PARTITION_OFFSET: u32 = 0x3f_0000;
+
+/// Size of the `config` partition, keep in sync with `partitions.csv`.
+pub const CONFIG_PARTITION_SIZE: u32 = 0x1_0000;
+
+/// Number of networks that can be stored.
+pub const MAX_NETWORKS: usize = 4;
+
+pub const MAX_SSID_LEN: usize = 32;
+pub const MAX_PASSWORD_LEN: usize = 64;
+
+const MAGIC: &[u8; 4] = b"BCFG";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 4;
+
+/// Largest encoded record.
+pub const MAX_RECORD_LEN: usize =
+    HEADER_LEN + MAX_NETWORKS * (2 + MAX_SSID_LEN + MAX_PASSWORD_LEN) + CRC_LEN;
+
+/// SSID and password of one network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub ssid: String<MAX_SSID_LEN>,
+    pub password: String<MAX_PASSWORD_LEN>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialsError {
+    SsidTooLong,
+    PasswordTooLong,
+    EmptySsid,
+}
+
+/// The stored networks, most recently added first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WifiConfig {
+    networks: Vec<Credentials, MAX_NETWORKS>,
+}
+
+impl WifiConfig {
+    pub fn networks(&self) -> &[Credentials] {
+        &self.networks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// Store the credentials for `ssid`, replacing the ones stored before.
+    ///
+    /// The network becomes the first one; when the configuration is full the
+    /// network that was added longest ago is dropped.
+    pub fn add(&mut self, ssid: &str, password: &str) -> Result<(), CredentialsError> {
+        if ssid.is_empty() {
+            return Err(CredentialsError::EmptySsid);
+        }
+        let credentials = Credentials {
+            ssid: ssid.try_into().map_err(|_| CredentialsError::SsidTooLong)?,
+            password: password
+                .try_into()
+                .map_err(|_| CredentialsError::PasswordTooLong)?,
+        };
+
+        self.remove(ssid);
+        if self.networks.is_full() {
+            self.networks.pop();
+        }
+        // there is room now
+        let _ = self.networks.insert(0, credentials);
+        Ok(())
+    }
+
+    /// Forget `ssid`, returns whether it was stored.
+    pub fn remove(&mut self, ssid: &str) -> bool {
+        match self.networks.iter().position(|n| n.ssid == ssid) {
+            Some(index) => {
+                self.networks.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pick the network to connect to from the result of a scan, given as
+    /// `(ssid, signal strength)` pairs.
+    ///
+    /// The strongest stored network that is visible wins. If none of them is
+    /// visible (e.g. a hidden SSID) the most recently added one is returned.
+    pub fn select<'a, 'b>(
+        &'a self,
+        visible: impl IntoIterator<Item = (&'b str, i8)>,
+    ) -> Option<&'a Credentials> {
+        visible
+            .into_iter()
+            .filter_map(|(ssid, rssi)| {
+                self.networks
+                    .iter()
+                    .find(|n| n.ssid == ssid)
+                    .map(|n| (n, rssi))
+            })
+            .max_by_key(|&(_, rssi)| rssi)
+            .map(|(n, _)| n)
+            .or_else(|| self.networks.first())
+    }
+
+    /// Serialize into `buf`, returns the number of bytes used.
+    pub fn encode(&self, buf: &mut [u8; MAX_RECORD_LEN]) -> usize {
+        buf[..4].copy_from_slice(MAGIC);
+        buf[4] = VERSION;
+        buf[5] = self.networks.len() as u8;
+
+        let mut len = HEADER_LEN;
+        for network in &self.networks {
+            for field in [network.ssid.as_bytes(), network.password.as_bytes()] {
+                buf[len] = field.len() as u8;
+                buf[len + 1..][..field.len()].copy_from_slice(field);
+                len += 1 + field.len();
+            }
+        }
+
+        let crc = crc32(&buf[..len]);
+        buf[len..][..CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        len + CRC_LEN
+    }
+
+    /// Parse a record written by [`WifiConfig::encode`].
+    ///
+    /// Erased flash decodes to an empty configuration, anything else that is
+    /// not a valid record is [`Corrupt`](DecodeError::Corrupt).
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        if data.len() < HEADER_LEN + CRC_LEN {
+            return Err(DecodeError::Corrupt);
+        }
+        if data[..4] == [0xff; 4] {
+            return Ok(Self::default());
+        }
+        if &data[..4] != MAGIC {
+            return Err(DecodeError::Corrupt);
+        }
+        if data[4] != VERSION {
+            return Err(DecodeError::UnsupportedVersion(data[4]));
+        }
+
+        let mut config = WifiConfig::default();
+        let mut pos = HEADER_LEN;
+        let field = |pos: &mut usize| -> Result<&str, DecodeError> {
+            let len = *data.get(*pos).ok_or(DecodeError::Corrupt)? as usize;
+            let bytes = data
+                .get(*pos + 1..*pos + 1 + len)
+                .ok_or(DecodeError::Corrupt)?;
+            *pos += 1 + len;
+            core::str::from_utf8(bytes).map_err(|_| DecodeError::Corrupt)
+        };
+
+        for _ in 0..data[5] {
+            let ssid = field(&mut pos)?;
+            let password = field(&mut pos)?;
+            let credentials = Credentials {
+                ssid: ssid.try_into().map_err(|_| DecodeError::Corrupt)?,
+                password: password.try_into().map_err(|_| DecodeError::Corrupt)?,
+            };
+            config
+                .networks
+                .push(credentials)
+                .map_err(|_| DecodeError::Corrupt)?;
+        }
+
+        let stored_crc = data.get(pos..pos + CRC_LEN).ok_or(DecodeError::Corrupt)?;
+        if crc32(&data[..pos]).to_le_bytes() != stored_crc {
+            return Err(DecodeError::Corrupt);
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Bad magic, truncated record or wrong checksum.
+    Corrupt,
+    /// Written by a newer firmware.
+    UnsupportedVersion(u8),
+}
+
+#[derive(Debug)]
+pub enum StoreError<E> {
+    Storage(E),
+    Decode(DecodeError),
+}
+
+/// Loads and saves a [`WifiConfig`] at a fixed offset of a flash storage.
+pub struct ConfigStore<S> {
+    storage: S,
+    offset: u32,
+}
+
+impl<S: Storage> ConfigStore<S> {
+    /// A store in the `config` partition.
+    pub fn new(storage: S) -> Self {
+        Self::with_offset(storage, CONFIG_PARTITION_OFFSET)
+    }
+
+    pub fn with_offset(storage: S, offset: u32) -> Self {
+        ConfigStore { storage, offset }
+    }
+
+    pub fn load(&mut self) -> Result<WifiConfig, StoreError<S::Error>> {
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        self.storage
+            .read(self.offset, &mut buf)
+            .map_err(StoreError::Storage)?;
+        WifiConfig::decode(&buf).map_err(StoreError::Decode)
+    }
+
+    pub fn save(&mut self, config: &WifiConfig) -> Result<(), StoreError<S::Error>> {
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        let len = config.encode(&mut buf);
+        self.storage
+            .write(self.offset, &buf[..len])
+            .map_err(StoreError::Storage)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
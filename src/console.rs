@@ -0,0 +1,589 @@
+//! Interactive command console on the USB-Serial-JTAG port
+//!
+//! ```text
+//! $ espflash monitor
+//! buddy> wifi set "My Network" secret
+//! saved, used after the next reboot
+//! buddy> peers
+//! ff:ff:ff:ff:ff:ff  channel -  plain
+//! ```
+//!
+//! [`LineEditor`] echoes what is typed and handles backspace, `Ctrl-C`,
+//! `Ctrl-U` and recalling the previous line with the up arrow. The finished
+//! line goes to a [`Registry`], which looks up the [`Command`] and runs it
+//! against the firmware. [`builtins`] are the commands every [`Device`]
+//! supports; a registry can hold any other commands as well.
+//!
+//! Log output from `esp_println` shares the port and simply appears between
+//! the prompts.
+
+use core::fmt::{self, Write};
+
+use crate::app::Mac;
+use crate::config::WifiConfig;
+use crate::gateway::{Collector, GatewayStatus};
+
+pub const PROMPT: &str = "buddy> ";
+
+/// Longest line accepted, longer input is refused with a bell.
+pub const MAX_LINE_LEN: usize = 128;
+
+/// A console command, run on a `D`.
+pub struct Command<D: ?Sized> {
+    /// One or more words, e.g. `"wifi set"`.
+    pub name: &'static str,
+    /// The arguments, shown by `help` and on a [`Error::Usage`].
+    pub args: &'static str,
+    pub help: &'static str,
+    pub run: fn(&mut D, Args<'_>, &mut dyn Write) -> Result<(), Error>,
+}
+
+/// Why a [`Command`] failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The arguments are wrong, the registry prints the usage.
+    Usage,
+    Write(fmt::Error),
+}
+
+impl From<fmt::Error> for Error {
+    fn from(e: fmt::Error) -> Self {
+        Error::Write(e)
+    }
+}
+
+/// The words of a command line after the command name.
+///
+/// Words are separated by whitespace; double quotes group words with spaces,
+/// `"My Network"`.
+#[derive(Debug, Clone, Copy)]
+pub struct Args<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(line: &'a str) -> Self {
+        Args {
+            rest: line.trim_start(),
+        }
+    }
+
+    /// Everything not consumed yet, verbatim but trimmed.
+    pub fn rest(&self) -> &'a str {
+        self.rest.trim_end()
+    }
+
+    /// The next word, or a usage error if there is none.
+    pub fn word(&mut self) -> Result<&'a str, Error> {
+        self.next().ok_or(Error::Usage)
+    }
+
+    /// A usage error if there are words left.
+    pub fn end(mut self) -> Result<(), Error> {
+        match self.next() {
+            Some(_) => Err(Error::Usage),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (word, rest) = match self.rest.strip_prefix('"') {
+            // an unterminated quote runs to the end of the line
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => self
+                .rest
+                .split_once(char::is_whitespace)
+                .unwrap_or((self.rest, "")),
+        };
+        self.rest = rest.trim_start();
+        Some(word)
+    }
+}
+
+/// The commands a console knows.
+pub struct Registry<'a, D: ?Sized> {
+    commands: &'a [Command<D>],
+}
+
+impl<'a, D: ?Sized> Registry<'a, D> {
+    pub const fn new(commands: &'a [Command<D>]) -> Self {
+        Registry { commands }
+    }
+
+    pub fn commands(&self) -> &'a [Command<D>] {
+        self.commands
+    }
+
+    /// The command `line` starts with and its arguments. The longest name
+    /// wins, so `wifi` and `wifi set` can both exist.
+    pub fn find<'l>(&self, line: &'l str) -> Option<(&'a Command<D>, Args<'l>)> {
+        self.commands
+            .iter()
+            .filter_map(|command| {
+                let mut args = Args::new(line);
+                command
+                    .name
+                    .split(' ')
+                    .all(|word| args.next() == Some(word))
+                    .then_some((command, args))
+            })
+            .max_by_key(|(command, _)| command.name.len())
+    }
+
+    /// Run `line`, writing the output and any complaints to `out`.
+    ///
+    /// `help` is always there and lists the commands.
+    pub fn dispatch(&self, line: &str, device: &mut D, out: &mut dyn Write) -> fmt::Result {
+        let mut words = Args::new(line);
+        let first = match words.next() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        if first == "help" || first == "?" {
+            return self.write_help(out);
+        }
+
+        let Some((command, args)) = self.find(line) else {
+            // `wifi` alone, when there is only `wifi set`
+            let mut usages = self
+                .commands
+                .iter()
+                .filter(|c| c.name.split(' ').next() == Some(first))
+                .peekable();
+            if usages.peek().is_none() {
+                return writeln!(out, "unknown command '{}', try 'help'", first);
+            }
+            for command in usages {
+                write_usage(out, command)?;
+            }
+            return Ok(());
+        };
+
+        match (command.run)(device, args, out) {
+            Ok(()) => Ok(()),
+            Err(Error::Usage) => write_usage(out, command),
+            Err(Error::Write(e)) => Err(e),
+        }
+    }
+
+    pub fn write_help(&self, out: &mut dyn Write) -> fmt::Result {
+        for command in self.commands {
+            let mut width = command.name.len();
+            out.write_str(command.name)?;
+            if !command.args.is_empty() {
+                write!(out, " {}", command.args)?;
+                width += 1 + command.args.len();
+            }
+            writeln!(
+                out,
+                "{:1$}  {2}",
+                "",
+                22usize.saturating_sub(width),
+                command.help
+            )?;
+        }
+        writeln!(out, "help{:20}this text", "")
+    }
+}
+
+fn write_usage<D: ?Sized>(out: &mut dyn Write, command: &Command<D>) -> fmt::Result {
+    if command.args.is_empty() {
+        writeln!(out, "usage: {}", command.name)
+    } else {
+        writeln!(out, "usage: {} {}", command.name, command.args)
+    }
+}
+
+/// An ESP-NOW peer, as registered with the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    pub mac: Mac,
+    /// `None` means the current channel.
+    pub channel: Option<u8>,
+    pub encrypted: bool,
+}
+
+/// What the [`builtins`] need from the firmware.
+pub trait Device {
+    fn wifi_config(&mut self) -> &mut WifiConfig;
+
+    /// Write the Wi-Fi configuration to flash, `false` if that failed.
+    fn save_wifi_config(&mut self) -> bool;
+
+    /// Report every ESP-NOW peer to `found`.
+    fn peers(&mut self, found: &mut dyn FnMut(&Peer));
+
+    fn set_led(&mut self, on: bool);
+
+    fn draw_text(&mut self, text: &str);
+
+    fn gateway(&mut self) -> GatewayStatus;
+
+    /// Forward ESP-NOW frames to `collector` from now on, or stop for
+    /// `None`, and store that in flash. `false` if storing failed.
+    fn set_collector(&mut self, collector: Option<Collector>) -> bool;
+}
+
+/// The commands of the firmware console.
+pub fn builtins<D: Device + ?Sized>() -> [Command<D>; 9] {
+    [
+        Command {
+            name: "wifi set",
+            args: "<ssid> [<password>]",
+            help: "store a network",
+            run: wifi_set,
+        },
+        Command {
+            name: "wifi forget",
+            args: "<ssid>",
+            help: "remove a stored network",
+            run: wifi_forget,
+        },
+        Command {
+            name: "wifi list",
+            args: "",
+            help: "show the stored networks",
+            run: wifi_list,
+        },
+        Command {
+            name: "peers",
+            args: "",
+            help: "show the ESP-NOW peers",
+            run: peers,
+        },
+        Command {
+            name: "text",
+            args: "<msg>",
+            help: "show <msg> on the display",
+            run: text,
+        },
+        Command {
+            name: "led",
+            args: "on|off",
+            help: "switch the LED",
+            run: led,
+        },
+        Command {
+            name: "gateway",
+            args: "",
+            help: "show where ESP-NOW frames go",
+            run: gateway,
+        },
+        Command {
+            name: "gateway set",
+            args: "udp|tcp://<ip>:<port>",
+            help: "forward ESP-NOW frames",
+            run: gateway_set,
+        },
+        Command {
+            name: "gateway off",
+            args: "",
+            help: "stop forwarding",
+            run: gateway_off,
+        },
+    ]
+}
+
+fn wifi_set<D: Device + ?Sized>(
+    device: &mut D,
+    mut args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let ssid = args.word()?;
+    let password = args.next().unwrap_or("");
+    args.end()?;
+
+    if let Err(e) = device.wifi_config().add(ssid, password) {
+        writeln!(out, "invalid credentials: {:?}", e)?;
+        return Ok(());
+    }
+    save(device, out)
+}
+
+fn wifi_forget<D: Device + ?Sized>(
+    device: &mut D,
+    mut args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let ssid = args.word()?;
+    args.end()?;
+
+    if !device.wifi_config().remove(ssid) {
+        writeln!(out, "'{}' is not stored", ssid)?;
+        return Ok(());
+    }
+    save(device, out)
+}
+
+fn save<D: Device + ?Sized>(device: &mut D, out: &mut dyn Write) -> Result<(), Error> {
+    if device.save_wifi_config() {
+        writeln!(out, "saved, used after the next reboot")?;
+    } else {
+        writeln!(out, "error saving the configuration")?;
+    }
+    Ok(())
+}
+
+fn wifi_list<D: Device + ?Sized>(
+    device: &mut D,
+    args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    args.end()?;
+    let networks = device.wifi_config().networks();
+    if networks.is_empty() {
+        writeln!(out, "no networks stored")?;
+    }
+    for network in networks {
+        writeln!(out, "{}", network.ssid)?;
+    }
+    Ok(())
+}
+
+fn peers<D: Device + ?Sized>(
+    device: &mut D,
+    args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    args.end()?;
+    let mut count = 0;
+    let mut result = Ok(());
+    device.peers(&mut |peer| {
+        count += 1;
+        if result.is_ok() {
+            result = write_peer(out, peer);
+        }
+    });
+    result?;
+    if count == 0 {
+        writeln!(out, "no peers")?;
+    }
+    Ok(())
+}
+
+fn write_peer(out: &mut dyn Write, peer: &Peer) -> fmt::Result {
+    let m = peer.mac;
+    write!(
+        out,
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}  channel ",
+        m[0], m[1], m[2], m[3], m[4], m[5]
+    )?;
+    match peer.channel {
+        Some(channel) => write!(out, "{:<2}", channel)?,
+        None => out.write_str("- ")?,
+    }
+    writeln!(
+        out,
+        "  {}",
+        if peer.encrypted { "encrypted" } else { "plain" }
+    )
+}
+
+fn text<D: Device + ?Sized>(
+    device: &mut D,
+    args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    match args.rest() {
+        "" => Err(Error::Usage),
+        text => {
+            device.draw_text(text);
+            writeln!(out, "ok")?;
+            Ok(())
+        }
+    }
+}
+
+fn led<D: Device + ?Sized>(
+    device: &mut D,
+    mut args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let on = match args.word()? {
+        "on" => true,
+        "off" => false,
+        _ => return Err(Error::Usage),
+    };
+    args.end()?;
+    device.set_led(on);
+    writeln!(out, "ok")?;
+    Ok(())
+}
+
+fn gateway<D: Device + ?Sized>(
+    device: &mut D,
+    args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    args.end()?;
+    let status = device.gateway();
+    match status.collector {
+        Some(collector) => writeln!(out, "forwarding to {}", collector)?,
+        None => writeln!(out, "not forwarding")?,
+    }
+    match status.channel {
+        Some(channel) => writeln!(out, "channel {}", channel)?,
+        None => writeln!(out, "channel unknown, not connected")?,
+    }
+    writeln!(
+        out,
+        "{} forwarded, {} dropped",
+        status.forwarded, status.dropped
+    )?;
+    Ok(())
+}
+
+fn gateway_set<D: Device + ?Sized>(
+    device: &mut D,
+    mut args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let collector = args.word()?;
+    args.end()?;
+
+    match Collector::parse(collector) {
+        Ok(collector) => save_collector(device, Some(collector), out),
+        Err(e) => {
+            writeln!(out, "invalid collector: {:?}", e)?;
+            Ok(())
+        }
+    }
+}
+
+fn gateway_off<D: Device + ?Sized>(
+    device: &mut D,
+    args: Args<'_>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    args.end()?;
+    save_collector(device, None, out)
+}
+
+fn save_collector<D: Device + ?Sized>(
+    device: &mut D,
+    collector: Option<Collector>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    if device.set_collector(collector) {
+        writeln!(out, "saved")?;
+    } else {
+        writeln!(out, "error saving the collector")?;
+    }
+    Ok(())
+}
+
+const CTRL_C: u8 = 0x03;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After ESC.
+    Started,
+    /// After ESC `[`, parameters until the final byte.
+    Csi,
+}
+
+/// Line editing for a terminal that does not echo on its own.
+///
+/// Echo is written to the `echo` argument of [`LineEditor::push`], errors
+/// writing it are ignored since there is nobody to tell.
+#[derive(Debug)]
+pub struct LineEditor {
+    line: heapless::String<MAX_LINE_LEN>,
+    /// The previous non-empty line, for the up arrow.
+    previous: heapless::String<MAX_LINE_LEN>,
+    complete: bool,
+    after_cr: bool,
+    escape: Escape,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor {
+            line: heapless::String::new(),
+            previous: heapless::String::new(),
+            complete: false,
+            after_cr: false,
+            escape: Escape::None,
+        }
+    }
+
+    /// Add a byte, returns the line once Enter is pressed.
+    pub fn push(&mut self, byte: u8, echo: &mut dyn Write) -> Option<&str> {
+        if self.complete {
+            if !self.line.is_empty() {
+                self.previous = self.line.clone();
+            }
+            self.line.clear();
+            self.complete = false;
+        }
+
+        let after_cr = core::mem::replace(&mut self.after_cr, false);
+        match (self.escape, byte) {
+            (Escape::None, b'\n') if after_cr => (),
+            (Escape::None, ESC) => self.escape = Escape::Started,
+            (Escape::Started, b'[') => self.escape = Escape::Csi,
+            (Escape::Started, _) => self.escape = Escape::None,
+            // the up arrow
+            (Escape::Csi, b'A') => {
+                self.escape = Escape::None;
+                self.erase(echo);
+                self.line = self.previous.clone();
+                let _ = echo.write_str(&self.line);
+            }
+            // other keys are ignored, the final byte ends the sequence
+            (Escape::Csi, 0x40..=0x7e) => self.escape = Escape::None,
+            (Escape::Csi, _) => (),
+            (Escape::None, b'\r' | b'\n') => {
+                let _ = echo.write_str("\r\n");
+                self.after_cr = byte == b'\r';
+                self.complete = true;
+                return Some(&self.line);
+            }
+            (Escape::None, CTRL_C) => {
+                let _ = echo.write_str("^C\r\n");
+                self.line.clear();
+                self.complete = true;
+                return Some(&self.line);
+            }
+            (Escape::None, CTRL_U) => self.erase(echo),
+            (Escape::None, 0x08 | 0x7f) => {
+                if self.line.pop().is_some() {
+                    let _ = echo.write_str("\x08 \x08");
+                }
+            }
+            (Escape::None, b' '..=b'~') => {
+                if self.line.push(byte as char).is_ok() {
+                    let _ = echo.write_char(byte as char);
+                } else {
+                    let _ = echo.write_char('\x07');
+                }
+            }
+            (Escape::None, _) => (),
+        }
+        None
+    }
+
+    /// Remove the current line from the screen and the buffer.
+    fn erase(&mut self, echo: &mut dyn Write) {
+        for _ in 0..self.line.len() {
+            let _ = echo.write_str("\x08 \x08");
+        }
+        self.line.clear();
+    }
+}
@@ -0,0 +1,95 @@
+//! Drawing helpers for the small OLED on the board
+//!
+//! The SH1106 controller has a 128x64 framebuffer, but only a 72x40 window of it
+//! is actually visible on the glass, starting at (28, 12).
+
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+use crate::framebuffer::{BufferedDisplay, Flushed, PageWriter};
+
+/// Top left corner of the visible glass, in framebuffer coordinates.
+pub const VISIBLE_OFFSET: Point = Point::new(28, 12);
+
+/// Size of the visible glass.
+pub const VISIBLE_SIZE: Size = Size::new(72, 40);
+
+/// A [`DrawTarget`] that only exposes the visible part of the display.
+///
+/// Coordinates are translated so that (0, 0) is the top left corner of the
+/// glass, and anything drawn outside of it is clipped.
+pub struct VisibleArea<D> {
+    parent: D,
+    area: Rectangle,
+}
+
+impl<D: DrawTarget> VisibleArea<D> {
+    /// Wrap `parent` using the visible area of the buddy-like board.
+    pub fn new(parent: D) -> Self {
+        Self::with_area(parent, Rectangle::new(VISIBLE_OFFSET, VISIBLE_SIZE))
+    }
+
+    /// Wrap `parent`, exposing only `area` (given in `parent` coordinates).
+    pub fn with_area(parent: D, area: Rectangle) -> Self {
+        VisibleArea { parent, area }
+    }
+
+    /// The exposed area, in `parent` coordinates.
+    pub fn area(&self) -> Rectangle {
+        self.area
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.parent
+    }
+
+    pub fn inner_mut(&mut self) -> &mut D {
+        &mut self.parent
+    }
+
+    pub fn into_inner(self) -> D {
+        self.parent
+    }
+}
+
+impl<W: PageWriter> VisibleArea<BufferedDisplay<W>> {
+    /// Write out what changed in the framebuffer to the display.
+    pub fn flush(&mut self) -> Result<Flushed, W::Error> {
+        self.parent.flush()
+    }
+}
+
+impl<D: DrawTarget> DrawTarget for VisibleArea<D> {
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounds = self.bounding_box();
+        let offset = self.area.top_left;
+
+        self.parent.draw_iter(
+            pixels
+                .into_iter()
+                .filter(|Pixel(pos, _)| bounds.contains(*pos))
+                .map(|Pixel(pos, color)| Pixel(pos + offset, color)),
+        )
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+
+        self.parent
+            .fill_solid(&area.translate(self.area.top_left), color)
+    }
+}
+
+impl<D> OriginDimensions for VisibleArea<D> {
+    fn size(&self) -> Size {
+        self.area.size
+    }
+}
@@ -0,0 +1,534 @@
+//! Forwarding ESP-NOW frames to a collector on the network
+//!
+//! A board connected to Wi-Fi keeps listening to ESP-NOW and hands every
+//! frame it hears to a [`Gateway`], which turns it into one line of JSON:
+//!
+//! ```text
+//! {"ts_ms":81234,"src":"34:85:18:01:02:03","dst":"ff:ff:ff:ff:ff:ff","rssi":-61,"channel":6,"data":"0100070568690a"}
+//! ```
+//!
+//! `ts_ms` is the gateway's time since boot, `data` the payload in hex,
+//! undecoded; [`parse_json`] reads it back, for `tools/buddy-tap`. The
+//! firmware sends the lines to the configured [`Collector`], one UDP datagram
+//! per line or all of them on one TCP connection, which [`TcpUplink`] keeps
+//! open. Lines wait in a small queue while the collector cannot be reached;
+//! when it is full the oldest are dropped.
+//!
+//! ESP-NOW is sent on the channel the radio is tuned to, and while the
+//! station is connected that is the channel of the access point. The battery
+//! nodes have to use the same one, see `ESP_NOW_CHANNEL` in the README.
+//!
+//! The collector is stored in flash after the peer table:
+//!
+//! | bytes | content                                          |
+//! |-------|--------------------------------------------------|
+//! | 4     | magic `BGWY`                                     |
+//! | 1     | format version                                   |
+//! | 1     | transport: 0 none, 1 UDP, 2 TCP                  |
+//! | 4     | IPv4 address                                     |
+//! | 2     | port, little endian                              |
+//! | 4     | CRC-32 of everything before it, little endian    |
+
+use core::fmt::{self, Write};
+
+use embedded_storage::Storage;
+use heapless::{Deque, String};
+
+use crate::app::{Mac, MAX_PAYLOAD};
+use crate::config::{DecodeError, StoreError};
+use crate::crc::crc32;
+use crate::peers::PEERS_OFFSET;
+
+/// Offset of the collector record in flash, the sector after the peer table.
+pub const COLLECTOR_OFFSET: u32 = PEERS_OFFSET + 0x1000;
+
+/// Longest JSON line, newline included: a full payload is 500 hex digits,
+/// the other fields take at most 119 bytes.
+pub const MAX_LINE_LEN: usize = 640;
+
+/// Lines waiting for the collector.
+pub const MAX_QUEUED_LINES: usize = 8;
+
+/// Give up on a TCP connection attempt after this long.
+pub const CONNECT_TIMEOUT_MS: u64 = 5_000;
+
+/// Wait this long before connecting again after a failure.
+pub const RETRY_MS: u64 = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// Where the lines go, written `udp://192.168.1.10:9000` or `tcp://...`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Collector {
+    pub transport: Transport,
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// Neither `udp://` nor `tcp://`.
+    Transport,
+    /// Not four numbers up to 255; host names are not resolved.
+    Address,
+    /// Missing, zero or above 65535.
+    Port,
+}
+
+impl Collector {
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let (transport, rest) = if let Some(rest) = s.strip_prefix("udp://") {
+            (Transport::Udp, rest)
+        } else if let Some(rest) = s.strip_prefix("tcp://") {
+            (Transport::Tcp, rest)
+        } else {
+            return Err(ParseError::Transport);
+        };
+        let (address, port) = rest.split_once(':').ok_or(ParseError::Port)?;
+
+        let mut ip = [0; 4];
+        let mut octets = address.split('.');
+        for octet in &mut ip {
+            *octet = octets
+                .next()
+                .and_then(|o| o.parse().ok())
+                .ok_or(ParseError::Address)?;
+        }
+        if octets.next().is_some() {
+            return Err(ParseError::Address);
+        }
+        let port = port
+            .parse()
+            .ok()
+            .filter(|&p| p != 0)
+            .ok_or(ParseError::Port)?;
+
+        Ok(Collector {
+            transport,
+            ip,
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Collector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.transport {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+        };
+        let [a, b, c, d] = self.ip;
+        write!(f, "{}://{}.{}.{}.{}:{}", scheme, a, b, c, d, self.port)
+    }
+}
+
+/// An ESP-NOW frame as heard by the gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Forwarded<'a> {
+    /// Milliseconds since the gateway booted.
+    pub ts_ms: u64,
+    pub src: Mac,
+    pub dst: Mac,
+    pub rssi: i8,
+    pub channel: u8,
+    pub data: &'a [u8],
+}
+
+/// Write `frame` as a JSON object, without the newline.
+pub fn write_json(out: &mut dyn Write, frame: &Forwarded<'_>) -> fmt::Result {
+    write!(out, "{{\"ts_ms\":{},\"src\":\"", frame.ts_ms)?;
+    write_mac(out, &frame.src)?;
+    out.write_str("\",\"dst\":\"")?;
+    write_mac(out, &frame.dst)?;
+    write!(
+        out,
+        "\",\"rssi\":{},\"channel\":{},\"data\":\"",
+        frame.rssi, frame.channel
+    )?;
+    for byte in frame.data {
+        write!(out, "{:02x}", byte)?;
+    }
+    out.write_str("\"}")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineError {
+    /// Not a flat JSON object of strings and integers.
+    Syntax,
+    /// The field is not there.
+    Missing(&'static str),
+    /// The field has the wrong type or is out of range.
+    Invalid(&'static str),
+}
+
+/// Parse a line written by [`write_json`], decoding the payload into `data`.
+///
+/// The fields may come in any order and unknown ones are skipped, so the
+/// format can grow. Strings with escapes are not supported, the gateway
+/// never writes any.
+pub fn parse_json<'a>(
+    line: &str,
+    data: &'a mut [u8; MAX_PAYLOAD],
+) -> Result<Forwarded<'a>, LineError> {
+    let mut ts_ms = None;
+    let mut src = None;
+    let mut dst = None;
+    let mut rssi = None;
+    let mut channel = None;
+    let mut len = None;
+
+    let body = line
+        .trim()
+        .strip_prefix('{')
+        .and_then(|l| l.strip_suffix('}'))
+        .ok_or(LineError::Syntax)?;
+    let mut rest = body.trim_start();
+    while !rest.is_empty() {
+        let (key, after) = json_string(rest)?;
+        let after = after
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or(LineError::Syntax)?
+            .trim_start();
+        let (value, after) = json_value(after)?;
+        match key {
+            "ts_ms" => ts_ms = Some(number(value, "ts_ms")?),
+            "src" => src = Some(parse_mac(value).ok_or(LineError::Invalid("src"))?),
+            "dst" => dst = Some(parse_mac(value).ok_or(LineError::Invalid("dst"))?),
+            "rssi" => rssi = Some(number(value, "rssi")?),
+            "channel" => channel = Some(number(value, "channel")?),
+            "data" => len = Some(parse_hex(value, data).ok_or(LineError::Invalid("data"))?),
+            _ => (),
+        }
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+            if rest.is_empty() {
+                return Err(LineError::Syntax);
+            }
+        } else if !rest.is_empty() {
+            return Err(LineError::Syntax);
+        }
+    }
+
+    let len = len.ok_or(LineError::Missing("data"))?;
+    Ok(Forwarded {
+        ts_ms: ts_ms.ok_or(LineError::Missing("ts_ms"))?,
+        src: src.ok_or(LineError::Missing("src"))?,
+        dst: dst.ok_or(LineError::Missing("dst"))?,
+        rssi: rssi.ok_or(LineError::Missing("rssi"))?,
+        channel: channel.ok_or(LineError::Missing("channel"))?,
+        data: &data[..len],
+    })
+}
+
+/// A value as found in the line: a string with its quotes, or a number.
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+    String(&'a str),
+    Number(&'a str),
+}
+
+/// The string `s` starts with, without quotes, and what follows it.
+fn json_string(s: &str) -> Result<(&str, &str), LineError> {
+    let s = s.strip_prefix('"').ok_or(LineError::Syntax)?;
+    let (string, rest) = s.split_once('"').ok_or(LineError::Syntax)?;
+    if string.contains('\\') {
+        return Err(LineError::Syntax);
+    }
+    Ok((string, rest))
+}
+
+fn json_value(s: &str) -> Result<(Value<'_>, &str), LineError> {
+    if s.starts_with('"') {
+        let (string, rest) = json_string(s)?;
+        return Ok((Value::String(string), rest));
+    }
+    let end = s
+        .find(|c: char| !(c == '-' || c.is_ascii_digit()))
+        .unwrap_or(s.len());
+    if end == 0 {
+        return Err(LineError::Syntax);
+    }
+    Ok((Value::Number(&s[..end]), &s[end..]))
+}
+
+fn number<T: core::str::FromStr>(value: Value<'_>, field: &'static str) -> Result<T, LineError> {
+    match value {
+        Value::Number(n) => n.parse().map_err(|_| LineError::Invalid(field)),
+        Value::String(_) => Err(LineError::Invalid(field)),
+    }
+}
+
+fn parse_mac(value: Value<'_>) -> Option<Mac> {
+    let Value::String(s) = value else {
+        return None;
+    };
+    let mut mac = [0; 6];
+    let mut octets = s.split(':');
+    for byte in &mut mac {
+        let octet = octets.next().filter(|o| o.len() == 2)?;
+        *byte = u8::from_str_radix(octet, 16).ok()?;
+    }
+    octets.next().is_none().then_some(mac)
+}
+
+/// Decode hex digits into `out`, returns the number of bytes.
+fn parse_hex(value: Value<'_>, out: &mut [u8]) -> Option<usize> {
+    let Value::String(s) = value else {
+        return None;
+    };
+    if s.len() % 2 != 0 || s.len() / 2 > out.len() {
+        return None;
+    }
+    for (byte, pair) in out.iter_mut().zip(s.as_bytes().chunks(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(s.len() / 2)
+}
+
+fn write_mac(out: &mut dyn Write, mac: &Mac) -> fmt::Result {
+    let m = mac;
+    write!(
+        out,
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        m[0], m[1], m[2], m[3], m[4], m[5]
+    )
+}
+
+/// The lines on their way to the collector.
+#[derive(Debug, Default)]
+pub struct Gateway {
+    queue: Deque<String<MAX_LINE_LEN>, MAX_QUEUED_LINES>,
+    forwarded: u32,
+    dropped: u32,
+}
+
+impl Gateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `frame` as a line, pushing out the oldest if the queue is full.
+    /// Payloads longer than an ESP-NOW frame are dropped.
+    pub fn forward(&mut self, frame: &Forwarded<'_>) {
+        if frame.data.len() > MAX_PAYLOAD {
+            self.dropped += 1;
+            return;
+        }
+        let mut line = String::new();
+        // fits, see MAX_LINE_LEN
+        let _ = write_json(&mut line, frame);
+        let _ = line.push('\n');
+        if self.queue.is_full() {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        // there is room now
+        let _ = self.queue.push_back(line);
+    }
+
+    /// The oldest line, with its newline. It stays queued until
+    /// [`Gateway::sent`].
+    pub fn next_line(&self) -> Option<&str> {
+        self.queue.front().map(|line| line.as_str())
+    }
+
+    /// The collector took the line [`Gateway::next_line`] returned.
+    pub fn sent(&mut self) {
+        if self.queue.pop_front().is_some() {
+            self.forwarded += 1;
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Lines sent so far.
+    pub fn forwarded(&self) -> u32 {
+        self.forwarded
+    }
+
+    /// Frames lost because the queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+/// What the firmware has to do with the TCP socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UplinkCommand {
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UplinkState {
+    Closed { retry_at_ms: u64 },
+    Opening { since_ms: u64 },
+    Connected,
+}
+
+/// Keeps the TCP connection to a [`Collector`] open, retrying every
+/// [`RETRY_MS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpUplink {
+    state: UplinkState,
+}
+
+impl Default for TcpUplink {
+    fn default() -> Self {
+        TcpUplink {
+            state: UplinkState::Closed { retry_at_ms: 0 },
+        }
+    }
+}
+
+impl TcpUplink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lines can be written.
+    pub fn is_connected(&self) -> bool {
+        self.state == UplinkState::Connected
+    }
+
+    /// Advance to `now_ms`, given whether the socket is connected.
+    pub fn update(&mut self, now_ms: u64, connected: bool) -> Option<UplinkCommand> {
+        match self.state {
+            UplinkState::Closed { retry_at_ms } if now_ms >= retry_at_ms => {
+                self.state = UplinkState::Opening { since_ms: now_ms };
+                Some(UplinkCommand::Open)
+            }
+            UplinkState::Closed { .. } => None,
+            UplinkState::Opening { .. } if connected => {
+                self.state = UplinkState::Connected;
+                None
+            }
+            UplinkState::Opening { since_ms } if now_ms - since_ms >= CONNECT_TIMEOUT_MS => {
+                Some(self.failed(now_ms))
+            }
+            UplinkState::Opening { .. } => None,
+            UplinkState::Connected if !connected => Some(self.failed(now_ms)),
+            UplinkState::Connected => None,
+        }
+    }
+
+    /// Writing failed, or the attempt to connect did. Returns
+    /// [`UplinkCommand::Close`].
+    pub fn failed(&mut self, now_ms: u64) -> UplinkCommand {
+        self.state = UplinkState::Closed {
+            retry_at_ms: now_ms + RETRY_MS,
+        };
+        UplinkCommand::Close
+    }
+}
+
+/// What the `gateway` console command shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GatewayStatus {
+    pub collector: Option<Collector>,
+    /// The channel ESP-NOW is heard on, `None` before the station connects.
+    pub channel: Option<u8>,
+    pub forwarded: u32,
+    pub dropped: u32,
+}
+
+const MAGIC: &[u8; 4] = b"BGWY";
+const VERSION: u8 = 1;
+const CRC_LEN: usize = 4;
+
+/// Length of the encoded record.
+pub const COLLECTOR_RECORD_LEN: usize = 12 + CRC_LEN;
+
+/// Serialize the configured collector, or that there is none.
+pub fn encode(collector: Option<Collector>) -> [u8; COLLECTOR_RECORD_LEN] {
+    let mut buf = [0; COLLECTOR_RECORD_LEN];
+    buf[..4].copy_from_slice(MAGIC);
+    buf[4] = VERSION;
+    if let Some(collector) = collector {
+        buf[5] = match collector.transport {
+            Transport::Udp => 1,
+            Transport::Tcp => 2,
+        };
+        buf[6..10].copy_from_slice(&collector.ip);
+        buf[10..12].copy_from_slice(&collector.port.to_le_bytes());
+    }
+    let crc = crc32(&buf[..12]);
+    buf[12..].copy_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+/// Parse a record written by [`encode`]; erased flash decodes to no
+/// collector.
+pub fn decode(data: &[u8]) -> Result<Option<Collector>, DecodeError> {
+    if data.len() < COLLECTOR_RECORD_LEN {
+        return Err(DecodeError::Corrupt);
+    }
+    if data[..4] == [0xff; 4] {
+        return Ok(None);
+    }
+    if &data[..4] != MAGIC {
+        return Err(DecodeError::Corrupt);
+    }
+    if data[4] != VERSION {
+        return Err(DecodeError::UnsupportedVersion(data[4]));
+    }
+    if crc32(&data[..12]).to_le_bytes() != data[12..COLLECTOR_RECORD_LEN] {
+        return Err(DecodeError::Corrupt);
+    }
+
+    let transport = match data[5] {
+        0 => return Ok(None),
+        1 => Transport::Udp,
+        2 => Transport::Tcp,
+        _ => return Err(DecodeError::Corrupt),
+    };
+    Ok(Some(Collector {
+        transport,
+        ip: data[6..10].try_into().unwrap(),
+        port: u16::from_le_bytes([data[10], data[11]]),
+    }))
+}
+
+/// Loads and saves the [`Collector`] at a fixed offset of a flash storage.
+pub struct CollectorStore<S> {
+    storage: S,
+    offset: u32,
+}
+
+impl<S: Storage> CollectorStore<S> {
+    /// A store at [`COLLECTOR_OFFSET`].
+    pub fn new(storage: S) -> Self {
+        Self::with_offset(storage, COLLECTOR_OFFSET)
+    }
+
+    pub fn with_offset(storage: S, offset: u32) -> Self {
+        CollectorStore { storage, offset }
+    }
+
+    pub fn load(&mut self) -> Result<Option<Collector>, StoreError<S::Error>> {
+        let mut buf = [0u8; COLLECTOR_RECORD_LEN];
+        self.storage
+            .read(self.offset, &mut buf)
+            .map_err(StoreError::Storage)?;
+        decode(&buf).map_err(StoreError::Decode)
+    }
+
+    pub fn save(&mut self, collector: Option<Collector>) -> Result<(), StoreError<S::Error>> {
+        self.storage
+            .write(self.offset, &encode(collector))
+            .map_err(StoreError::Storage)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
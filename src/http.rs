@@ -0,0 +1,256 @@
+//! Just enough HTTP/1.1 for the small web pages served by the firmware
+//!
+//! Requests are parsed from a buffer holding the whole request, responses are
+//! written into anything implementing [`core::fmt::Write`]. Every response
+//! closes the connection, so there is no keep-alive or chunked encoding.
+
+use core::fmt::{self, Write};
+
+pub const HTTP_PORT: u16 = 80;
+
+/// Longest request, head and body, that is received; longer ones are
+/// [`ParseError::Invalid`].
+pub const MAX_REQUEST_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Other,
+}
+
+/// A request that has been received completely.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// The path without the query string.
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    headers: &'a str,
+    pub body: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// More data is needed, keep reading.
+    Incomplete,
+    /// Not something we understand, answer with `400 Bad Request`.
+    Invalid,
+}
+
+impl<'a> Request<'a> {
+    /// Parse the request at the start of `buf`.
+    ///
+    /// Returns [`ParseError::Incomplete`] until the headers and the whole body
+    /// (as announced by `Content-Length`) are in the buffer, and
+    /// [`ParseError::Invalid`] if the body would not fit into
+    /// [`MAX_REQUEST_LEN`].
+    pub fn parse(buf: &'a [u8]) -> Result<Self, ParseError> {
+        let head_len = buf
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or(ParseError::Incomplete)?;
+        let head = core::str::from_utf8(&buf[..head_len]).map_err(|_| ParseError::Invalid)?;
+
+        let (request_line, headers) = head.split_once("\r\n").unwrap_or((head, ""));
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::Invalid);
+        };
+        if !version.starts_with("HTTP/1.") || !target.starts_with('/') {
+            return Err(ParseError::Invalid);
+        }
+
+        let method = match method {
+            "GET" => Method::Get,
+            "POST" => Method::Post,
+            _ => Method::Other,
+        };
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+
+        let mut request = Request {
+            method,
+            path,
+            query,
+            headers,
+            body: &[],
+        };
+
+        let body_len = match request.header("Content-Length") {
+            Some(len) => len.parse().map_err(|_| ParseError::Invalid)?,
+            None => 0,
+        };
+        let body_start = head_len + 4;
+        let body_end = body_start
+            .checked_add(body_len)
+            .filter(|&end| end <= MAX_REQUEST_LEN)
+            .ok_or(ParseError::Invalid)?;
+        request.body = buf
+            .get(body_start..body_end)
+            .ok_or(ParseError::Incomplete)?;
+
+        Ok(request)
+    }
+
+    /// The value of the first header called `name` (case insensitive).
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.split("\r\n").find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then_some(value.trim())
+        })
+    }
+}
+
+/// The raw (still URL encoded) value of `name` in an
+/// `application/x-www-form-urlencoded` body or a query string.
+pub fn form_field<'a>(form: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    form.split(|&b| b == b'&').find_map(|pair| {
+        let mut kv = pair.splitn(2, |&b| b == b'=');
+        let key = kv.next()?;
+        (key == name.as_bytes()).then(|| kv.next().unwrap_or_default())
+    })
+}
+
+/// Decode `+` and `%XX` escapes of a form value.
+///
+/// `None` if the escapes are malformed, the result is not UTF-8 or does not
+/// fit into `N` bytes.
+pub fn url_decode<const N: usize>(raw: &[u8]) -> Option<heapless::String<N>> {
+    let mut bytes: heapless::Vec<u8, N> = heapless::Vec::new();
+    let mut i = 0;
+    while i < raw.len() {
+        let b = match raw[i] {
+            b'+' => b' ',
+            b'%' => {
+                let hex = core::str::from_utf8(raw.get(i + 1..i + 3)?).ok()?;
+                i += 2;
+                u8::from_str_radix(hex, 16).ok()?
+            }
+            b => b,
+        };
+        bytes.push(b).ok()?;
+        i += 1;
+    }
+    heapless::String::from_utf8(bytes).ok()
+}
+
+/// Escapes text for use in HTML content and attribute values.
+pub struct Html<'a>(pub &'a str);
+
+impl fmt::Display for Html<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&#39;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Found,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::Found => 302,
+            Status::BadRequest => 400,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Found => "Found",
+            Status::BadRequest => "Bad Request",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+        }
+    }
+}
+
+/// Write a complete response with `body`.
+pub fn write_response<W: Write>(
+    w: &mut W,
+    status: Status,
+    content_type: &str,
+    body: &str,
+) -> fmt::Result {
+    write_head(w, status, &[("Content-Type", content_type)], body.len())?;
+    w.write_str(body)
+}
+
+/// Write a complete response with the body produced by `body`.
+///
+/// `body` is called twice, first to find the `Content-Length`, so larger pages
+/// can be streamed without buffering them.
+pub fn write_response_with<W, F>(
+    w: &mut W,
+    status: Status,
+    content_type: &str,
+    body: F,
+) -> fmt::Result
+where
+    W: Write,
+    F: Fn(&mut dyn Write) -> fmt::Result,
+{
+    let mut length = Length(0);
+    body(&mut length)?;
+    write_head(w, status, &[("Content-Type", content_type)], length.0)?;
+    body(w)
+}
+
+/// Counts the bytes written to it.
+struct Length(usize);
+
+impl Write for Length {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// Redirect the client to `location`.
+pub fn write_redirect<W: Write>(w: &mut W, location: &str) -> fmt::Result {
+    write_head(w, Status::Found, &[("Location", location)], 0)
+}
+
+/// Status line and headers, including the empty line that ends them.
+pub fn write_head<W: Write>(
+    w: &mut W,
+    status: Status,
+    headers: &[(&str, &str)],
+    content_length: usize,
+) -> fmt::Result {
+    write!(w, "HTTP/1.1 {} {}\r\n", status.code(), status.reason())?;
+    for (name, value) in headers {
+        write!(w, "{}: {}\r\n", name, value)?;
+    }
+    write!(
+        w,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        content_length
+    )
+}
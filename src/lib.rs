@@ -0,0 +1,33 @@
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod app;
+#[cfg(feature = "board")]
+pub mod board;
+pub mod button;
+pub mod config;
+pub mod console;
+pub mod crc;
+pub mod display;
+pub mod framebuffer;
+pub mod gateway;
+pub mod http;
+pub mod pairing;
+pub mod peers;
+pub mod protocol;
+pub mod provisioning;
+#[cfg(feature = "board")]
+pub mod runtime;
+pub mod screens;
+pub mod shell;
+#[cfg(feature = "std")]
+pub mod sim;
+pub mod status;
+pub mod supervisor;
+pub mod widgets;
+
+#[cfg(feature = "board")]
+pub use board::Board;
+pub use display::VisibleArea;
@@ -1,201 +1,977 @@
 #![no_std]
 #![no_main]
 
-use embedded_graphics::{
-    mono_font::{ascii::FONT_4X6, ascii::FONT_6X10, MonoTextStyleBuilder},
-    pixelcolor::BinaryColor,
-    prelude::*,
-    text::{Baseline, Text},
+use core::{cell::Cell, fmt::Write as _};
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{with_timeout, Duration, Ticker, Timer};
+use embedded_io::{Read, ReadReady, Write};
+use esp32_c3_buddy_like::{
+    board::Display,
+    button::ButtonEvent,
+    config::{ConfigStore, WifiConfig},
+    console::{self, LineEditor, Peer, Registry},
+    gateway::{
+        Collector, CollectorStore, Forwarded, Gateway, GatewayStatus, TcpUplink, Transport,
+        UplinkCommand,
+    },
+    http::{self, ParseError, Request, Status, HTTP_PORT},
+    provisioning::{
+        dhcp::{self, DhcpServer},
+        dns,
+        portal::{self, Outcome},
+        AP_IP, AP_SSID, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, DNS_PORT,
+    },
+    runtime::{self, now, ButtonEvents, HeardFrames, SharedDisplay},
+    screens::{self, WifiStatus},
+    shell::{self, CrLf, LineBuffer, SHELL_PORT},
+    status::{self, DeviceInfo},
+    supervisor::{Command, Link, SupervisorConfig, WifiState, WifiSupervisor},
+    widgets::PAGE_MS,
+    Board, VisibleArea,
 };
-
-use core::fmt::Write as FmtWrite;
 use esp_backtrace as _;
 use esp_println::println;
+use esp_storage::FlashStorage;
 use esp_wifi::{
+    esp_now::{enable_esp_now_with_wifi, EspNow, EspNowManager},
     init,
     wifi::{
-        utils::create_network_interface, AccessPointInfo, ClientConfiguration, Configuration,
-        WifiError, WifiStaDevice,
+        get_sta_mac, ipv4,
+        utils::{create_ap_sta_network_interface, create_network_interface, ApStaInterface},
+        AccessPointConfiguration, AccessPointInfo, ClientConfiguration, Configuration,
+        WifiController, WifiDeviceMode, WifiError, WifiStaDevice,
     },
-    wifi_interface::WifiStack,
-    EspWifiInitFor,
+    wifi_interface::{IoError, Socket, UdpSocket, WifiStack},
+    EspWifiInitFor, EspWifiInitialization,
 };
+// `esp_hal_embassy::main` expects the HAL under its own name
+use hal as esp_hal;
 use hal::{
-    delay::Delay,
-    gpio::Io,
-    i2c,
-    prelude::*,
-    rng::Rng,
-    time::{self},
-    timer::timg::TimerGroup,
+    gpio::{Level, Output},
+    peripherals::WIFI,
+    reset::software_reset,
+    usb_serial_jtag::UsbSerialJtag,
+    Async,
 };
-use smoltcp::iface::SocketStorage;
+use smoltcp::{iface::SocketStorage, socket::udp::PacketMetadata, wire::IpAddress};
+use static_cell::StaticCell;
+
+// Stored in flash on the first boot if no network is configured yet,
+// e.g. `SSID=... PASSWORD=... cargo espflash flash --release`
+const SEED_SSID: Option<&str> = option_env!("SSID");
+const SEED_PASSWORD: Option<&str> = option_env!("PASSWORD");
+// the same for the gateway, e.g. `COLLECTOR=udp://192.168.1.10:9000`
+const SEED_COLLECTOR: Option<&str> = option_env!("COLLECTOR");
+
+/// Local port the gateway sends its UDP datagrams from.
+const FORWARD_PORT: u16 = 50_000;
+
+/// How often the network stack is polled, it cannot wake a task by itself.
+const POLL_MS: u64 = 10;
+
+/// Longest text [`Show::Text`] keeps.
+const TEXT_LEN: usize = 64;
+
+static BUTTON: ButtonEvents = Channel::new();
+static HEARD: HeardFrames = Channel::new();
+/// Switches the LED, see [`led_task`].
+static LED: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+/// What the display shows next, see [`render_task`].
+static SHOW: Signal<CriticalSectionRawMutex, Show> = Signal::new();
+/// A collector set on the console, for the Wi-Fi task.
+static NEW_COLLECTOR: Signal<CriticalSectionRawMutex, Option<Collector>> = Signal::new();
+/// The gateway as the Wi-Fi task saw it last, for the console.
+static GATEWAY_STATUS: Mutex<CriticalSectionRawMutex, Cell<GatewayStatus>> =
+    Mutex::new(Cell::new(GatewayStatus {
+        collector: None,
+        channel: None,
+        forwarded: 0,
+        dropped: 0,
+    }));
+
+/// A `&'static mut` to `$val`, for what the tasks share or keep for good.
+macro_rules! mk_static {
+    ($t:ty, $val:expr $(,)?) => {{
+        static STATIC_CELL: StaticCell<$t> = StaticCell::new();
+        STATIC_CELL.uninit().write($val)
+    }};
+}
 
-use sh1106::{prelude::*, Builder};
-const SSID: &str = "SSID"; // env!("SSID");
-const PASSWORD: &str = "PASSWORD"; // env!("PASSWORD");
+/// What [`render_task`] draws.
+enum Show {
+    Wifi(WifiStatus),
+    /// Until the Wi-Fi status changes.
+    Text(heapless::String<TEXT_LEN>),
+}
 
-#[entry]
-fn main() -> ! {
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) {
     esp_alloc::heap_allocator!(72 * 1024);
 
-    let peripherals = hal::init(hal::Config::default());
-
-    let timer = TimerGroup::new(peripherals.TIMG1).timer0;
+    let board = Board::init();
+    // Wi-Fi takes the timer of the other group
+    esp_hal_embassy::init(board.timg0.timer0);
+    // holding the button at power-up starts the provisioning access point
+    let provisioning = board.button.is_low();
+    let mut display = VisibleArea::new(board.display);
 
-    let delay = Delay::new();
-
-    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
-
-    let sda = io.pins.gpio5;
-    let scl = io.pins.gpio6;
+    screens::wifi_status(&mut display, WifiStatus::Starting).unwrap();
+    display.flush().unwrap();
 
-    let i2c = i2c::I2c::new(peripherals.I2C0, sda, scl, 100u32.kHz());
+    let init = mk_static!(
+        EspWifiInitialization,
+        init(
+            EspWifiInitFor::Wifi,
+            board.timg1.timer0,
+            board.rng,
+            board.radio_clk,
+        )
+        .map_err(|e| println!("Failed to initialize wifi {:?}", e))
+        .unwrap(),
+    );
+
+    let mut config_store = ConfigStore::new(FlashStorage::new());
+    let mut wifi_config = match config_store.load() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Error loading Wi-Fi config: {:?}", e);
+            WifiConfig::default()
+        }
+    };
+    if let (true, Some(ssid), Some(password)) = (wifi_config.is_empty(), SEED_SSID, SEED_PASSWORD) {
+        println!("Storing Wi-Fi credentials for {}", ssid);
+        match wifi_config.add(ssid, password) {
+            Ok(_) => {
+                if let Err(e) = config_store.save(&wifi_config) {
+                    println!("Error saving Wi-Fi config: {:?}", e);
+                }
+            }
+            Err(e) => println!("Invalid Wi-Fi credentials: {:?}", e),
+        }
+    }
 
-    let mut display: GraphicsMode<_> = Builder::new().connect_i2c(i2c).into();
-    match display.init() {
-        Ok(_) => (),
-        Err(e) => println!("Error initializing display: {:?}", e),
+    let mut collector_store = CollectorStore::new(FlashStorage::new());
+    let mut collector = collector_store.load().unwrap_or_else(|e| {
+        println!("Error loading the collector: {:?}", e);
+        None
+    });
+    if let (None, Some(seed)) = (collector, SEED_COLLECTOR) {
+        match Collector::parse(seed) {
+            Ok(seed) => {
+                println!("Storing collector {}", seed);
+                collector = Some(seed);
+                if let Err(e) = collector_store.save(collector) {
+                    println!("Error saving the collector: {:?}", e);
+                }
+            }
+            Err(e) => println!("Invalid collector {}: {:?}", seed, e),
+        }
     }
-    display.flush().unwrap();
 
-    let text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_6X10)
-        .text_color(BinaryColor::On)
-        .build();
-
-    // with the 6x10 font, IP address is too long to fit on the screen
-    let ip_text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_4X6)
-        .text_color(BinaryColor::On)
-        .build();
-
-    // positions on the screen
-    // the zero point on the screen is (28, 12)
-    let starting_point = Point::new(28, 12);
-    let ip_point = starting_point + Point::new(0, 36);
-
-    display.clear();
-    Text::with_baseline("WiFi example", starting_point, text_style, Baseline::Top)
-        .draw(&mut display)
-        .unwrap();
+    if provisioning {
+        provision(init, board.wifi, &mut display, config_store, wifi_config).await;
+    }
 
-    display.flush().unwrap();
+    let (wifi, esp_now_token) = enable_esp_now_with_wifi(board.wifi);
+    let (iface, device, mut controller, sockets) = create_network_interface(
+        init,
+        wifi,
+        WifiStaDevice,
+        mk_static!([SocketStorage<'static>; 6], Default::default()),
+    )
+    .unwrap();
 
-    let rng = Rng::new(peripherals.RNG);
+    let wifi_stack = WifiStack::new(iface, device, sockets, now);
+    let (esp_now, _, receiver) = EspNow::new_with_wifi(init, esp_now_token).unwrap().split();
 
-    let init = init(EspWifiInitFor::Wifi, timer, rng, peripherals.RADIO_CLK)
-        .map_err(|e| println!("Failed to initialize wifi {:?}", e))
-        .unwrap();
+    // the real credentials are only known after the scan
+    let res = controller.set_configuration(&Configuration::Client(Default::default()));
+    println!("wifi_set_configuration returned {:?}", res);
 
-    let wifi = peripherals.WIFI;
-    let mut socket_set_entries: [SocketStorage; 5] = Default::default();
-    let (iface, device, mut controller, sockets) =
-        create_network_interface(&init, wifi, WifiStaDevice, &mut socket_set_entries).unwrap();
+    controller.start().await.unwrap();
+    println!("is wifi started: {:?}", controller.is_started());
 
-    let now = || time::now().duration_since_epoch().to_millis();
+    println!("Start Wifi Scan");
+    let res: Result<(heapless::Vec<AccessPointInfo, 10>, usize), WifiError> =
+        controller.scan_n().await;
+    let access_points = res.map(|(res, _count)| res).unwrap_or_default();
+    for ap in &access_points {
+        println!("{:?}", ap);
+    }
 
-    let wifi_stack = WifiStack::new(iface, device, sockets, now);
+    let visible = access_points
+        .iter()
+        .map(|ap| (ap.ssid.as_str(), ap.signal_strength));
+    let network = wifi_config.select(visible).cloned();
+
+    // the console, the display and the gateway's radio work without a network
+    GATEWAY_STATUS.lock(|status| {
+        status.set(GatewayStatus {
+            collector,
+            ..status.get()
+        })
+    });
+    let shared = mk_static!(SharedDisplay, SharedDisplay::new(&display));
+    spawner.must_spawn(runtime::display_task(shared, display.into_inner()));
+    spawner.must_spawn(render_task(shared));
+    spawner.must_spawn(runtime::button_task(board.button, &BUTTON));
+    spawner.must_spawn(led_task(board.led));
+    spawner.must_spawn(runtime::radio_task(receiver, &HEARD));
+    spawner.must_spawn(console_task(
+        board.usb_serial,
+        wifi_config,
+        config_store,
+        collector_store,
+        esp_now,
+    ));
+
+    let Some(network) = network else {
+        println!("No Wi-Fi network configured");
+        SHOW.signal(Show::Wifi(WifiStatus::NotConfigured));
+        return;
+    };
 
+    // ESP-NOW follows the station onto the channel of the access point
+    let channel = access_points
+        .iter()
+        .find(|ap| ap.ssid == network.ssid)
+        .map(|ap| ap.channel);
+    println!("Using network {} on channel {:?}", network.ssid, channel);
     let client_config = Configuration::Client(ClientConfiguration {
-        ssid: SSID.try_into().unwrap(),
-        password: PASSWORD.try_into().unwrap(),
+        ssid: network.ssid.clone(),
+        password: network.password.clone(),
+        channel,
         ..Default::default()
     });
     let res = controller.set_configuration(&client_config);
     println!("wifi_set_configuration returned {:?}", res);
 
-    controller.start().unwrap();
-    println!("is wifi started: {:?}", controller.is_started());
+    println!("{:?}", controller.get_capabilities());
 
-    println!("Start Wifi Scan");
-    let res: Result<(heapless::Vec<AccessPointInfo, 10>, usize), WifiError> = controller.scan_n();
-    if let Ok((res, _count)) = res {
-        for ap in res {
-            println!("{:?}", ap);
+    spawner.must_spawn(wifi_task(controller, wifi_stack, collector, channel));
+}
+
+/// Draw what is asked for in [`SHOW`], and the next page of text that does
+/// not fit on one.
+#[embassy_executor::task]
+async fn render_task(display: &'static SharedDisplay) {
+    let mut show = SHOW.wait().await;
+    let mut shown_at = now();
+    loop {
+        let elapsed = now() - shown_at;
+        match &show {
+            Show::Wifi(status) => {
+                display
+                    .draw(|display| screens::wifi_status(display, *status))
+                    .await
+            }
+            Show::Text(text) => {
+                display
+                    .draw(|display| screens::text(display, text, elapsed))
+                    .await
+            }
         }
+        .unwrap();
+
+        let paged = match &show {
+            Show::Text(text) => screens::text_box(text).layout().overflows(),
+            Show::Wifi(_) => false,
+        };
+        if !paged {
+            show = SHOW.wait().await;
+        } else {
+            let next_page = Timer::after(Duration::from_millis(PAGE_MS - elapsed % PAGE_MS));
+            match select(SHOW.wait(), next_page).await {
+                Either::First(next) => show = next,
+                Either::Second(()) => continue,
+            }
+        }
+        shown_at = now();
     }
+}
 
-    println!("{:?}", controller.get_capabilities());
-    println!("wifi_connect {:?}", controller.connect());
-
-    // wait to get connected
-    println!("Wait to get connected");
-    display.clear();
-    Text::with_baseline(
-        "WiFi example\nconnecting...",
-        starting_point,
-        text_style,
-        Baseline::Top,
+#[embassy_executor::task]
+async fn led_task(mut led: Output<'static>) {
+    loop {
+        let on = LED.wait().await;
+        // the LED is wired active low
+        led.set_level(if on { Level::Low } else { Level::High });
+    }
+}
+
+/// Keep the station connected and serve the network: HTTP, the debug shell
+/// and the gateway's uplink to the collector.
+#[embassy_executor::task]
+async fn wifi_task(
+    mut controller: WifiController<'static>,
+    wifi_stack: WifiStack<'static, WifiStaDevice>,
+    mut collector: Option<Collector>,
+    channel: Option<u8>,
+) {
+    // the sockets live as long as the stack
+    let mut socket = wifi_stack.get_socket(
+        mk_static!([u8; 1536], [0; 1536]),
+        mk_static!([u8; 1536], [0; 1536]),
+    );
+    let mut http_request = HttpRequest::new();
+    let mut shell_socket = wifi_stack.get_socket(
+        mk_static!([u8; 256], [0; 256]),
+        mk_static!([u8; 1024], [0; 1024]),
+    );
+    let mut shell_session = ShellSession::new();
+    let mut udp_socket = wifi_stack.get_udp_socket(
+        mk_static!([PacketMetadata; 1], [PacketMetadata::EMPTY; 1]),
+        mk_static!([u8; 64], [0; 64]),
+        mk_static!([PacketMetadata; 4], [PacketMetadata::EMPTY; 4]),
+        mk_static!([u8; 1536], [0; 1536]),
+    );
+    udp_socket.bind(FORWARD_PORT).unwrap();
+    let mut tcp_socket = wifi_stack.get_socket(
+        mk_static!([u8; 64], [0; 64]),
+        mk_static!([u8; 1536], [0; 1536]),
+    );
+    let mut gateway = Gateway::new();
+    let mut uplink = TcpUplink::new();
+
+    let mut mac = [0u8; 6];
+    get_sta_mac(&mut mac);
+
+    let supervisor_config = SupervisorConfig::default();
+    // the supervisor notices a hanging attempt too, this only lets the task
+    // go on
+    let connect_timeout = Duration::from_millis(supervisor_config.connect_timeout_ms as u64);
+    let mut supervisor = WifiSupervisor::new(supervisor_config);
+    let mut command = supervisor.start(now());
+    let mut shown = None;
+    let mut ticker = Ticker::every(Duration::from_millis(POLL_MS));
+
+    loop {
+        match command {
+            Some(Command::Connect) => {
+                let res = with_timeout(connect_timeout, controller.connect()).await;
+                println!("wifi_connect {:?}", res);
+            }
+            Some(Command::Disconnect) => {
+                let res = with_timeout(connect_timeout, controller.disconnect()).await;
+                println!("wifi_disconnect {:?}", res);
+            }
+            None => (),
+        }
+
+        wifi_stack.work();
+
+        let connected = match controller.is_connected() {
+            Ok(connected) => connected,
+            // the supervisor notices that on its own
+            Err(WifiError::Disconnected) => false,
+            Err(err) => {
+                println!("{:?}", err);
+                false
+            }
+        };
+        let ip = match wifi_stack.get_ip_info() {
+            Ok(info) if wifi_stack.is_iface_up() => Some(info.ip.octets()),
+            _ => None,
+        };
+        command = supervisor.update(now(), Link { connected, ip });
+
+        // a click tries again after the supervisor gave up
+        while let Ok(event) = BUTTON.try_receive() {
+            if event == ButtonEvent::Click {
+                command = command.or(supervisor.start(now()));
+            }
+        }
+
+        let status = supervisor.status(now());
+        if shown != Some(status) {
+            println!("{:?}", supervisor.state());
+            SHOW.signal(Show::Wifi(status));
+            shown = Some(status);
+        }
+
+        // every frame heard goes to the collector, if there is one
+        while let Ok(heard) = HEARD.try_receive() {
+            if collector.is_some() {
+                gateway.forward(&Forwarded {
+                    ts_ms: heard.at_ms,
+                    src: heard.src,
+                    dst: heard.dst,
+                    rssi: heard.rssi,
+                    channel: heard.channel,
+                    data: &heard.data,
+                });
+            }
+        }
+        if let Some(new) = NEW_COLLECTOR.try_take() {
+            collector = new;
+            // connect to the new one
+            tcp_socket.disconnect();
+            uplink = TcpUplink::new();
+        }
+
+        let up = matches!(supervisor.state(), WifiState::Up { .. });
+        if let (true, Some(collector)) = (up, collector) {
+            send_lines(
+                &mut gateway,
+                collector,
+                &mut udp_socket,
+                &mut tcp_socket,
+                &mut uplink,
+            );
+        }
+        GATEWAY_STATUS.lock(|status| {
+            status.set(GatewayStatus {
+                collector,
+                channel: channel.filter(|_| up),
+                forwarded: gateway.forwarded(),
+                dropped: gateway.dropped(),
+            })
+        });
+
+        if let WifiState::Up { ip } = supervisor.state() {
+            let info = device_info(mac, ip);
+            let outcome = serve_http(&mut socket, &mut http_request, |request, mut response| {
+                status::handle(request, &info, &mut response)
+            });
+            if let Some(status::Outcome::DrawText(text)) = outcome {
+                show_text(&text);
+            }
+
+            serve_shell(&mut shell_socket, &mut shell_session, &mut controller, info).await;
+        }
+
+        ticker.next().await;
+    }
+}
+
+/// Run the serial console on `usb_serial`.
+#[embassy_executor::task]
+async fn console_task(
+    mut usb_serial: UsbSerialJtag<'static, Async>,
+    mut wifi_config: WifiConfig,
+    mut config_store: ConfigStore<FlashStorage>,
+    mut collector_store: CollectorStore<FlashStorage>,
+    esp_now: EspNowManager<'static>,
+) {
+    let mut editor = LineEditor::new();
+    let _ = write!(CrLf(&mut usb_serial), "{}", console::PROMPT);
+
+    let mut buf = [0u8; 64];
+    loop {
+        // reading the port cannot fail
+        let Ok(len) = embedded_io_async::Read::read(&mut usb_serial, &mut buf).await;
+        for &byte in &buf[..len] {
+            let Some(line) = editor.push(byte, &mut usb_serial) else {
+                continue;
+            };
+            let mut device = ConsoleDevice {
+                wifi_config: &mut wifi_config,
+                config_store: &mut config_store,
+                esp_now: &esp_now,
+                collector_store: &mut collector_store,
+            };
+            let mut out = CrLf(&mut usb_serial);
+            let commands = console::builtins();
+            let _ = Registry::new(&commands).dispatch(line, &mut device, &mut out);
+            let _ = out.write_str(console::PROMPT);
+        }
+    }
+}
+
+fn device_info(mac: [u8; 6], ip: [u8; 4]) -> DeviceInfo {
+    DeviceInfo {
+        mac,
+        ip: Some(ip),
+        uptime_ms: now(),
+        free_heap: esp_alloc::HEAP.free(),
+        rssi: rssi(),
+    }
+}
+
+/// Signal strength of the access point the station is connected to, in dBm.
+fn rssi() -> Option<i8> {
+    let mut rssi = 0;
+    // SAFETY: the driver only writes to the integer it gets
+    let err = unsafe { esp_wifi_sys::include::esp_wifi_sta_get_rssi(&mut rssi) };
+    (err == 0).then_some(rssi as i8)
+}
+
+/// Serve the captive portal on the provisioning access point until a network
+/// is chosen, then store it and restart.
+async fn provision(
+    init: &EspWifiInitialization,
+    wifi: WIFI,
+    display: &mut VisibleArea<Display>,
+    mut config_store: ConfigStore<FlashStorage>,
+    mut wifi_config: WifiConfig,
+) -> ! {
+    println!("Starting provisioning access point {}", AP_SSID);
+
+    let mut ap_socket_set_entries: [SocketStorage; 5] = Default::default();
+    let mut sta_socket_set_entries: [SocketStorage; 1] = Default::default();
+    let ApStaInterface {
+        ap_interface,
+        ap_device,
+        mut controller,
+        ap_socket_set,
+        ..
+    } = create_ap_sta_network_interface(
+        init,
+        wifi,
+        &mut ap_socket_set_entries,
+        &mut sta_socket_set_entries,
     )
-    .draw(&mut display)
     .unwrap();
+
+    let ap_config = AccessPointConfiguration {
+        ssid: AP_SSID.try_into().unwrap(),
+        ..Default::default()
+    };
+    let res = controller.set_configuration(&Configuration::Mixed(Default::default(), ap_config));
+    println!("wifi_set_configuration returned {:?}", res);
+    controller.start().await.unwrap();
+
+    // the station half is only used to find the networks offered in the form
+    let res: Result<(heapless::Vec<AccessPointInfo, 10>, usize), WifiError> =
+        controller.scan_n().await;
+    let access_points = res.map(|(res, _count)| res).unwrap_or_default();
+    let networks: heapless::Vec<(&str, i8), 10> = access_points
+        .iter()
+        .map(|ap| (ap.ssid.as_str(), ap.signal_strength))
+        .collect();
+
+    let mut http_rx_buffer = [0u8; 1536];
+    let mut http_tx_buffer = [0u8; 1536];
+    let mut dhcp_rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut dhcp_rx_buffer = [0u8; 1536];
+    let mut dhcp_tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut dhcp_tx_buffer = [0u8; 1536];
+    let mut dns_rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut dns_rx_buffer = [0u8; 1536];
+    let mut dns_tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut dns_tx_buffer = [0u8; 1536];
+
+    let mut wifi_stack = WifiStack::new(ap_interface, ap_device, ap_socket_set, now);
+    let ip = ipv4::Ipv4Addr::from(AP_IP);
+    wifi_stack
+        .set_iface_configuration(&ipv4::Configuration::Client(
+            ipv4::ClientConfiguration::Fixed(ipv4::ClientSettings {
+                ip,
+                subnet: ipv4::Subnet {
+                    gateway: ip,
+                    mask: ipv4::Mask(24),
+                },
+                dns: None,
+                secondary_dns: None,
+            }),
+        ))
+        .unwrap();
+
+    screens::provisioning(display, AP_SSID, AP_IP).unwrap();
     match display.flush() {
         Ok(_) => (),
         Err(e) => println!("Error flushing display: {:?}", e),
-    };
+    }
+
+    let mut http_socket = wifi_stack.get_socket(&mut http_rx_buffer, &mut http_tx_buffer);
+    let mut dhcp_socket = wifi_stack.get_udp_socket(
+        &mut dhcp_rx_meta,
+        &mut dhcp_rx_buffer,
+        &mut dhcp_tx_meta,
+        &mut dhcp_tx_buffer,
+    );
+    dhcp_socket.bind(DHCP_SERVER_PORT).unwrap();
+    let mut dns_socket = wifi_stack.get_udp_socket(
+        &mut dns_rx_meta,
+        &mut dns_rx_buffer,
+        &mut dns_tx_meta,
+        &mut dns_tx_buffer,
+    );
+    dns_socket.bind(DNS_PORT).unwrap();
+
+    let mut dhcp_server = DhcpServer::new(AP_IP);
+    let mut http_request = HttpRequest::new();
 
     loop {
-        let res = controller.is_connected();
-        match res {
-            Ok(connected) => {
-                if connected {
-                    break;
+        let mut packet = [0u8; 576];
+        if let Ok((len, _, _)) = dhcp_socket.receive(&mut packet) {
+            let mut reply = [0u8; dhcp::REPLY_LEN];
+            if let Some(len) = dhcp_server.handle(&packet[..len], &mut reply) {
+                let broadcast = IpAddress::v4(255, 255, 255, 255);
+                if let Err(e) = dhcp_socket.send(broadcast, DHCP_CLIENT_PORT, &reply[..len]) {
+                    println!("Error sending DHCP reply: {:?}", e);
                 }
             }
-            Err(err) => {
-                println!("{:?}", err);
-                loop {}
+        }
+
+        if let Ok((len, addr, port)) = dns_socket.receive(&mut packet) {
+            let mut reply = [0u8; dns::MAX_MESSAGE_LEN];
+            if let Some(len) = dns::answer(&packet[..len], AP_IP, &mut reply) {
+                if let Err(e) = dns_socket.send(addr, port, &reply[..len]) {
+                    println!("Error sending DNS reply: {:?}", e);
+                }
             }
         }
+
+        let outcome = serve_http(
+            &mut http_socket,
+            &mut http_request,
+            |request, mut response| portal::handle(request, &networks, &mut response),
+        );
+        if let Some(Outcome::Save(credentials)) = outcome {
+            println!("Storing Wi-Fi credentials for {}", credentials.ssid);
+            match wifi_config.add(&credentials.ssid, &credentials.password) {
+                Ok(_) => {
+                    if let Err(e) = config_store.save(&wifi_config) {
+                        println!("Error saving Wi-Fi config: {:?}", e);
+                    }
+                }
+                Err(e) => println!("Invalid Wi-Fi credentials: {:?}", e),
+            }
+
+            // give the browser a moment to get the page before the AP goes away
+            let until = now() + 1000;
+            while now() < until {
+                wifi_stack.work();
+                Timer::after_millis(POLL_MS).await;
+            }
+            software_reset();
+        }
+
+        Timer::after_millis(POLL_MS).await;
     }
-    println!("{:?}", controller.is_connected());
+}
 
-    // wait for getting an ip address
-    println!("Waiting for ip...");
+/// Send the lines the gateway queued to `collector`, over `udp` or over
+/// `tcp` as far as it is connected.
+fn send_lines<MODE: WifiDeviceMode>(
+    gateway: &mut Gateway,
+    collector: Collector,
+    udp: &mut UdpSocket<'_, '_, MODE>,
+    tcp: &mut Socket<'_, '_, MODE>,
+    uplink: &mut TcpUplink,
+) {
+    let [a, b, c, d] = collector.ip;
+    let addr = IpAddress::v4(a, b, c, d);
+    match collector.transport {
+        Transport::Udp => {
+            while let Some(line) = gateway.next_line() {
+                if let Err(e) = udp.send(addr, collector.port, line.as_bytes()) {
+                    println!("Error forwarding to {}: {:?}", collector, e);
+                    return;
+                }
+                gateway.sent();
+            }
+        }
+        Transport::Tcp => {
+            match uplink.update(now(), tcp.is_connected()) {
+                Some(UplinkCommand::Open) => {
+                    if let Err(e) = tcp.open(addr, collector.port) {
+                        println!("Error connecting to {}: {:?}", collector, e);
+                        uplink.failed(now());
+                    }
+                }
+                Some(UplinkCommand::Close) => {
+                    println!("Lost the connection to {}", collector);
+                    tcp.disconnect();
+                }
+                None => (),
+            }
+            if !uplink.is_connected() {
+                return;
+            }
+            while let Some(line) = gateway.next_line() {
+                if let Err(e) = tcp.write_all(line.as_bytes()) {
+                    println!("Error forwarding to {}: {:?}", collector, e);
+                    uplink.failed(now());
+                    tcp.disconnect();
+                    return;
+                }
+                gateway.sent();
+            }
+            let _ = tcp.flush();
+        }
+    }
+}
 
-    loop {
-        wifi_stack.work();
+/// The request received so far on an HTTP socket, see [`serve_http`].
+struct HttpRequest {
+    buf: [u8; http::MAX_REQUEST_LEN],
+    len: usize,
+}
+
+impl HttpRequest {
+    fn new() -> Self {
+        HttpRequest {
+            buf: [0; http::MAX_REQUEST_LEN],
+            len: 0,
+        }
+    }
+}
+
+/// Keep `socket` listening on port 80 and answer a complete request with
+/// `handler`. Returns what the handler returned once the response is sent.
+fn serve_http<MODE, T, F>(
+    socket: &mut Socket<'_, '_, MODE>,
+    request: &mut HttpRequest,
+    handler: F,
+) -> Option<T>
+where
+    MODE: WifiDeviceMode,
+    F: FnOnce(&Request<'_>, &mut dyn core::fmt::Write) -> Result<T, core::fmt::Error>,
+{
+    if !socket.is_open() {
+        socket.listen_unblocking(HTTP_PORT).unwrap();
+        request.len = 0;
+    }
+    if !socket.is_connected() || !matches!(socket.read_ready(), Ok(true)) {
+        return None;
+    }
+    match socket.read(&mut request.buf[request.len..]) {
+        Ok(len) => request.len += len,
+        Err(e) => {
+            println!("Error reading request: {:?}", e);
+            socket.close();
+            return None;
+        }
+    }
+
+    let mut response = SocketWriter(&mut *socket);
+    let result = match Request::parse(&request.buf[..request.len]) {
+        Err(ParseError::Incomplete) if request.len < request.buf.len() => return None,
+        Ok(parsed) => handler(&parsed, &mut response).ok(),
+        Err(_) => {
+            let _ = http::write_response(&mut response, Status::BadRequest, "text/plain", "");
+            None
+        }
+    };
+    if let Err(e) = socket.flush() {
+        println!("Error sending response: {:?}", e);
+    }
+    socket.close();
+    result
+}
 
-        if wifi_stack.is_iface_up() {
-            println!("got ip {:?}", wifi_stack.get_ip_info());
-
-            let mut ip_addr: heapless::String<256> = heapless::String::new();
-            let bytes = wifi_stack.get_ip_info().unwrap().ip.octets();
-            match write!(
-                ip_addr,
-                "{}.{}.{}.{}",
-                bytes[0], bytes[1], bytes[2], bytes[3]
-            ) {
-                Ok(_) => (),
-                Err(e) => println!("Error writing ip: {:?}", e),
-            }
-            // .unwrap();
-            display.clear();
-            Text::with_baseline(
-                "WiFi example\nConnected.\nIP:",
-                starting_point,
-                text_style,
-                Baseline::Top,
-            )
-            .draw(&mut display)
-            .unwrap();
-            Text::new(&ip_addr, ip_point, ip_text_style)
-                .draw(&mut display)
-                .unwrap();
-            match display.flush() {
-                Ok(_) => (),
-                Err(e) => println!("Error flushing display: {:?}", e),
+/// [`core::fmt::Write`] for a TCP socket, to write HTTP responses straight into it.
+struct SocketWriter<'a, S>(&'a mut S);
+
+impl<S: Write> core::fmt::Write for SocketWriter<'_, S> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+/// What the debug shell gets to use, see [`serve_shell`].
+struct ShellDevice {
+    info: DeviceInfo,
+    /// Scanned before a line asking for it runs, the shell cannot wait.
+    networks: Result<heapless::Vec<AccessPointInfo, 10>, WifiError>,
+}
+
+impl shell::Device for ShellDevice {
+    fn info(&mut self) -> DeviceInfo {
+        self.info
+    }
+
+    fn heap(&mut self) -> (usize, usize) {
+        (esp_alloc::HEAP.used(), esp_alloc::HEAP.free())
+    }
+
+    fn scan(&mut self, found: &mut dyn FnMut(&str, i8)) -> bool {
+        match &self.networks {
+            Ok(access_points) => {
+                for ap in access_points {
+                    found(&ap.ssid, ap.signal_strength);
+                }
+                true
             }
+            Err(e) => {
+                println!("Error scanning: {:?}", e);
+                false
+            }
+        }
+    }
+
+    fn set_led(&mut self, on: bool) {
+        LED.signal(on);
+    }
+
+    fn draw_text(&mut self, text: &str) {
+        show_text(text);
+    }
+}
+
+/// Show `text` until the Wi-Fi status changes, as much of it as fits into
+/// [`Show::Text`].
+fn show_text(text: &str) {
+    let mut shown = heapless::String::new();
+    for c in text.chars() {
+        if shown.push(c).is_err() {
             break;
         }
     }
+    SHOW.signal(Show::Text(shown));
+}
+
+/// What the serial console gets to use.
+struct ConsoleDevice<'a, 'd> {
+    wifi_config: &'a mut WifiConfig,
+    config_store: &'a mut ConfigStore<FlashStorage>,
+    esp_now: &'a EspNowManager<'d>,
+    collector_store: &'a mut CollectorStore<FlashStorage>,
+}
+
+impl console::Device for ConsoleDevice<'_, '_> {
+    fn wifi_config(&mut self) -> &mut WifiConfig {
+        self.wifi_config
+    }
+
+    fn save_wifi_config(&mut self) -> bool {
+        match self.config_store.save(self.wifi_config) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error saving Wi-Fi config: {:?}", e);
+                false
+            }
+        }
+    }
 
-    println!("Start busy loop on main");
+    fn peers(&mut self, found: &mut dyn FnMut(&Peer)) {
+        let mut from_head = true;
+        while let Ok(peer) = self.esp_now.fetch_peer(from_head) {
+            found(&Peer {
+                mac: peer.peer_address,
+                channel: peer.channel,
+                encrypted: peer.encrypt,
+            });
+            from_head = false;
+        }
+    }
 
-    let mut rx_buffer = [0u8; 1536];
-    let mut tx_buffer = [0u8; 1536];
-    let _socket = wifi_stack.get_socket(&mut rx_buffer, &mut tx_buffer);
+    fn set_led(&mut self, on: bool) {
+        LED.signal(on);
+    }
+
+    fn draw_text(&mut self, text: &str) {
+        show_text(text);
+    }
 
-    loop {}
+    fn gateway(&mut self) -> GatewayStatus {
+        GATEWAY_STATUS.lock(Cell::get)
+    }
+
+    fn set_collector(&mut self, collector: Option<Collector>) -> bool {
+        GATEWAY_STATUS.lock(|status| {
+            status.set(GatewayStatus {
+                collector,
+                ..status.get()
+            })
+        });
+        NEW_COLLECTOR.signal(collector);
+        match self.collector_store.save(collector) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Error saving the collector: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
+/// The debug shell connection, see [`serve_shell`].
+struct ShellSession {
+    line: LineBuffer,
+    greeted: bool,
+}
+
+impl ShellSession {
+    fn new() -> Self {
+        ShellSession {
+            line: LineBuffer::new(),
+            greeted: false,
+        }
+    }
+}
+
+/// Keep `socket` listening on the shell port and run the lines a client sends.
+async fn serve_shell<MODE: WifiDeviceMode>(
+    socket: &mut Socket<'_, '_, MODE>,
+    session: &mut ShellSession,
+    controller: &mut WifiController<'_>,
+    info: DeviceInfo,
+) {
+    if !socket.is_open() {
+        socket.listen_unblocking(SHELL_PORT).unwrap();
+        *session = ShellSession::new();
+    }
+    let ready = match socket.read_ready() {
+        Ok(ready) => ready,
+        // the client hung up
+        Err(IoError::SocketClosed) => {
+            socket.close();
+            return;
+        }
+        // nobody connected yet
+        Err(_) => return,
+    };
+
+    let mut out = CrLf(SocketWriter(&mut *socket));
+    if !session.greeted {
+        let _ = write!(out, "buddy debug shell, try 'help'\n{}", shell::PROMPT);
+        session.greeted = true;
+    }
+    if !ready {
+        let _ = socket.flush();
+        return;
+    }
+
+    let mut buf = [0u8; 64];
+    let len = match socket.read(&mut buf) {
+        Ok(len) => len,
+        Err(e) => {
+            println!("Error reading shell input: {:?}", e);
+            socket.close();
+            return;
+        }
+    };
+    for &byte in &buf[..len] {
+        let Some(line) = session.line.push(byte) else {
+            continue;
+        };
+        let networks = match shell::parse(line) {
+            Ok(Some(shell::Command::WifiScan)) => controller.scan_n().await.map(|(res, _)| res),
+            _ => Ok(heapless::Vec::new()),
+        };
+        let mut device = ShellDevice { info, networks };
+        let mut out = CrLf(SocketWriter(&mut *socket));
+        match shell::run_line(line, &mut device, &mut out) {
+            Ok(shell::Outcome::Continue) => {
+                let _ = out.write_str(shell::PROMPT);
+            }
+            Ok(shell::Outcome::Reboot) => {
+                let _ = socket.flush();
+                socket.close();
+                // give the reply a moment to leave
+                let closed_at = now();
+                while now() - closed_at < 500 {
+                    socket.work();
+                    Timer::after_millis(POLL_MS).await;
+                }
+                software_reset();
+            }
+            Ok(shell::Outcome::Close) | Err(_) => {
+                let _ = socket.flush();
+                socket.close();
+                return;
+            }
+        }
+    }
+    let _ = socket.flush();
 }
@@ -0,0 +1,605 @@
+//! Pairing two boards for encrypted ESP-NOW
+//!
+//! Both users long-press the button. The boards swap X25519 public keys and
+//! nonces, and each shows a 4-digit code computed from all of them. When the
+//! codes match nobody sits in between, and once both users confirm, each board
+//! stores the other with a local master key (LMK) derived from the shared
+//! secret and registers it as an encrypted ESP-NOW peer.
+//!
+//! | step | what                                                             |
+//! |------|------------------------------------------------------------------|
+//! | 1    | broadcast [`Message::PairCommit`]: public key, `H(key, nonce)`   |
+//! | 2    | on the peer's commit, send it [`Message::PairReveal`]: the nonce |
+//! | 3    | on the peer's nonce, check its commit and show the code          |
+//! | 4    | once the user accepts, send [`Message::PairConfirm`]             |
+//!
+//! Nobody reveals a nonce before the other side has committed to its own, so
+//! a man in the middle cannot pick values that make both codes match. Every
+//! hash is SHA-256 over a label and fixed size fields.
+//!
+//! Like the rest of the protocol, [`Pairing`] only decides what is sent: the
+//! firmware hands it the received messages and sends what
+//! [`Pairing::poll_transmit`] returns. Every message is repeated until the next
+//! step, so lost frames only slow it down. The first board heard from is the
+//! peer, other boards pairing nearby at the same time are ignored.
+
+use embedded_storage::Storage;
+use heapless::{Deque, Vec};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::app::{Mac, Outgoing, BROADCAST};
+use crate::config::{DecodeError, StoreError, CONFIG_PARTITION_OFFSET};
+use crate::crc::crc32;
+use crate::protocol::{mesh::MeshKey, Frame, Message};
+
+/// Offset of the paired peers in flash, the sector after the Wi-Fi
+/// credentials in the `config` partition.
+pub const PAIRING_OFFSET: u32 = CONFIG_PARTITION_OFFSET + 0x1000;
+
+/// How long to look for a peer and wait for the users.
+pub const PAIRING_TIMEOUT_MS: u64 = 30_000;
+
+/// How often the current message is repeated.
+const RESEND_MS: u64 = 300;
+
+/// How long to keep answering the peer after both confirmed, in case our
+/// confirmation got lost. The peer is reported as paired afterwards.
+const LINGER_MS: u64 = 2_000;
+
+/// Length of an ESP-NOW local master key.
+pub const LMK_LEN: usize = 16;
+
+/// Number of peers that can be paired, as many as ESP-NOW takes encrypted
+/// peers.
+pub const MAX_PAIRED: usize = 6;
+
+/// A peer both users agreed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairedPeer {
+    pub mac: Mac,
+    pub lmk: [u8; LMK_LEN],
+}
+
+impl PairedPeer {
+    /// The key the mesh messages between the two boards are authenticated
+    /// with, see [`mesh`](crate::protocol::mesh).
+    pub fn mesh_key(&self) -> MeshKey {
+        hash(b"buddy mesh key", &[&self.lmk])
+    }
+}
+
+/// Where a pairing stands, for the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Idle,
+    /// Waiting for another board in pairing mode.
+    Searching,
+    /// Showing `code`, waiting for the user to accept it.
+    Comparing {
+        peer: Mac,
+        code: u16,
+    },
+    /// The user accepted `code`, waiting for the peer.
+    Confirmed {
+        peer: Mac,
+        code: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingEvent {
+    Paired(PairedPeer),
+    Failed(PairingError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingError {
+    /// No peer, or no confirmation within [`PAIRING_TIMEOUT_MS`].
+    TimedOut,
+    /// The user rejected the code or stopped pairing.
+    Cancelled,
+    /// The peer's nonce or confirmation did not check out.
+    BadPeer,
+}
+
+struct Keys {
+    code: u16,
+    lmk: [u8; LMK_LEN],
+    /// Proves to the peer that we hold the same key.
+    confirm_key: [u8; 16],
+}
+
+struct Peer {
+    mac: Mac,
+    public_key: [u8; 32],
+    commitment: [u8; 32],
+    /// Known once the peer revealed its nonce.
+    keys: Option<Keys>,
+}
+
+struct Session {
+    deadline_ms: u64,
+    next_send_ms: u64,
+    secret: StaticSecret,
+    public_key: [u8; 32],
+    nonce: [u8; 16],
+    peer: Option<Peer>,
+    /// By our user.
+    confirmed: bool,
+    peer_confirmed: bool,
+    /// When both sides had confirmed.
+    done_ms: Option<u64>,
+}
+
+/// The pairing state of one board.
+pub struct Pairing {
+    own: Mac,
+    session: Option<Session>,
+    seq: u16,
+    outgoing: Deque<Outgoing, 3>,
+    events: Deque<PairingEvent, 4>,
+}
+
+impl Pairing {
+    /// `own` is the station MAC of this board.
+    pub fn new(own: Mac) -> Self {
+        Pairing {
+            own,
+            session: None,
+            seq: 0,
+            outgoing: Deque::new(),
+            events: Deque::new(),
+        }
+    }
+
+    /// Enter pairing mode, or start over. `secret` and `nonce` have to be
+    /// fresh random bytes, e.g. from the hardware random number generator.
+    pub fn start(&mut self, now_ms: u64, secret: [u8; 32], nonce: [u8; 16]) {
+        let secret = StaticSecret::from(secret);
+        let public_key = PublicKey::from(&secret).to_bytes();
+        self.outgoing.clear();
+        self.session = Some(Session {
+            deadline_ms: now_ms + PAIRING_TIMEOUT_MS,
+            next_send_ms: now_ms,
+            secret,
+            public_key,
+            nonce,
+            peer: None,
+            confirmed: false,
+            peer_confirmed: false,
+            done_ms: None,
+        });
+    }
+
+    /// Leave pairing mode without pairing.
+    pub fn cancel(&mut self) {
+        if self.session.is_some() {
+            self.fail(PairingError::Cancelled);
+        }
+    }
+
+    /// The user accepted the code. Returns `false` if there is none to
+    /// accept.
+    pub fn confirm(&mut self, now_ms: u64) -> bool {
+        let Some(session) = &mut self.session else {
+            return false;
+        };
+        let has_code = session.peer.as_ref().is_some_and(|p| p.keys.is_some());
+        if !has_code || session.confirmed {
+            return false;
+        }
+        session.confirmed = true;
+        session.next_send_ms = now_ms;
+        if session.peer_confirmed {
+            session.done_ms = Some(now_ms);
+        }
+        true
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.session.is_some()
+    }
+
+    pub fn stage(&self) -> Stage {
+        let Some(session) = &self.session else {
+            return Stage::Idle;
+        };
+        match &session.peer {
+            Some(Peer {
+                mac,
+                keys: Some(keys),
+                ..
+            }) if session.confirmed => Stage::Confirmed {
+                peer: *mac,
+                code: keys.code,
+            },
+            Some(Peer {
+                mac,
+                keys: Some(keys),
+                ..
+            }) => Stage::Comparing {
+                peer: *mac,
+                code: keys.code,
+            },
+            _ => Stage::Searching,
+        }
+    }
+
+    /// Take a received message from `src`. Returns whether it was a pairing
+    /// message; those are used up here, whether pairing or not.
+    pub fn receive(&mut self, now_ms: u64, src: Mac, message: &Message<'_>) -> bool {
+        let own = self.own;
+        let result = match *message {
+            Message::PairCommit {
+                public_key,
+                commitment,
+            } => {
+                if let Some(session) = &mut self.session {
+                    if session.peer.is_none() && src != own {
+                        session.peer = Some(Peer {
+                            mac: src,
+                            public_key,
+                            commitment,
+                            keys: None,
+                        });
+                        session.next_send_ms = now_ms;
+                    }
+                }
+                Ok(())
+            }
+            Message::PairReveal { nonce } => match self.session_with(src) {
+                Some(session) => session.revealed(own, now_ms, nonce),
+                None => Ok(()),
+            },
+            Message::PairConfirm { tag } => match self.session_with(src) {
+                Some(session) => session.peer_confirmed(now_ms, &tag),
+                None => Ok(()),
+            },
+            _ => return false,
+        };
+        if let Err(e) = result {
+            self.fail(e);
+        }
+        true
+    }
+
+    /// The session, if `src` is its peer.
+    fn session_with(&mut self, src: Mac) -> Option<&mut Session> {
+        self.session
+            .as_mut()
+            .filter(|s| s.peer.as_ref().is_some_and(|p| p.mac == src))
+    }
+
+    /// The next frame to send now. Call until it returns `None`.
+    pub fn poll_transmit(&mut self, now_ms: u64) -> Option<Outgoing> {
+        if let Some(outgoing) = self.outgoing.pop_front() {
+            return Some(outgoing);
+        }
+        let session = self.session.as_mut()?;
+
+        if let Some(done_ms) = session.done_ms {
+            if now_ms >= done_ms + LINGER_MS {
+                // done is only set once the keys are known
+                let peer = session.peer.as_ref().unwrap();
+                let paired = PairedPeer {
+                    mac: peer.mac,
+                    lmk: peer.keys.as_ref().unwrap().lmk,
+                };
+                self.session = None;
+                self.push_event(PairingEvent::Paired(paired));
+                return None;
+            }
+        } else if now_ms >= session.deadline_ms {
+            self.fail(PairingError::TimedOut);
+            return None;
+        }
+
+        if now_ms < session.next_send_ms {
+            return None;
+        }
+        session.next_send_ms = now_ms + RESEND_MS;
+
+        let mut messages: Vec<(Mac, Message<'static>), 3> = Vec::new();
+        match &session.peer {
+            Some(Peer { keys: Some(_), .. }) => {}
+            // until the peer revealed it may still be missing our commit
+            _ => {
+                let _ = messages.push((
+                    BROADCAST,
+                    Message::PairCommit {
+                        public_key: session.public_key,
+                        commitment: commitment(&session.public_key, &session.nonce),
+                    },
+                ));
+            }
+        }
+        if let Some(peer) = &session.peer {
+            // the peer's confirmation shows it has our nonce
+            if !session.peer_confirmed {
+                let _ = messages.push((
+                    peer.mac,
+                    Message::PairReveal {
+                        nonce: session.nonce,
+                    },
+                ));
+            }
+            if let (true, Some(keys)) = (session.confirmed, &peer.keys) {
+                let _ = messages.push((
+                    peer.mac,
+                    Message::PairConfirm {
+                        tag: confirm_tag(&keys.confirm_key, &self.own),
+                    },
+                ));
+            }
+        }
+
+        for (dst, message) in messages {
+            self.seq = self.seq.wrapping_add(1);
+            // all pairing messages fit into a frame, and there is room for
+            // all of them
+            let data = Frame::new(self.seq, message).to_vec().unwrap();
+            let _ = self.outgoing.push_back(Outgoing { dst, data });
+        }
+        self.outgoing.pop_front()
+    }
+
+    /// Finished pairings, oldest first.
+    pub fn pop_event(&mut self) -> Option<PairingEvent> {
+        self.events.pop_front()
+    }
+
+    fn fail(&mut self, error: PairingError) {
+        self.session = None;
+        self.outgoing.clear();
+        self.push_event(PairingEvent::Failed(error));
+    }
+
+    fn push_event(&mut self, event: PairingEvent) {
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        // there is room now
+        let _ = self.events.push_back(event);
+    }
+}
+
+impl Session {
+    fn revealed(&mut self, own: Mac, now_ms: u64, nonce: [u8; 16]) -> Result<(), PairingError> {
+        // `session_with` found the peer
+        let peer = self.peer.as_mut().unwrap();
+        if peer.keys.is_some() {
+            return Ok(());
+        }
+        if commitment(&peer.public_key, &nonce) != peer.commitment {
+            return Err(PairingError::BadPeer);
+        }
+
+        let shared = self
+            .secret
+            .diffie_hellman(&PublicKey::from(peer.public_key));
+        if !shared.was_contributory() {
+            return Err(PairingError::BadPeer);
+        }
+        // the same order on both sides
+        let ours = (own, &self.public_key, &self.nonce);
+        let theirs = (peer.mac, &peer.public_key, &nonce);
+        let (first, second) = if own < peer.mac {
+            (ours, theirs)
+        } else {
+            (theirs, ours)
+        };
+        let [mac_1, mac_2] = [first.0, second.0];
+        let transcript: [&[u8]; 6] = [&mac_1, first.1, first.2, &mac_2, second.1, second.2];
+
+        let code = hash(b"buddy pair code", &transcript);
+        let mut keyed = [shared.as_bytes().as_slice(); 7];
+        keyed[1..].copy_from_slice(&transcript);
+        let key = hash(b"buddy pair key", &keyed);
+        peer.keys = Some(Keys {
+            code: (u32::from_le_bytes(code[..4].try_into().unwrap()) % 10_000) as u16,
+            lmk: key[..LMK_LEN].try_into().unwrap(),
+            confirm_key: key[LMK_LEN..].try_into().unwrap(),
+        });
+        self.next_send_ms = now_ms;
+        Ok(())
+    }
+
+    fn peer_confirmed(&mut self, now_ms: u64, tag: &[u8; 16]) -> Result<(), PairingError> {
+        // `session_with` found the peer
+        let peer = self.peer.as_ref().unwrap();
+        let Some(keys) = &peer.keys else {
+            // the peer's reveal is still on its way
+            return Ok(());
+        };
+        let expected = confirm_tag(&keys.confirm_key, &peer.mac);
+        if tag
+            .iter()
+            .zip(expected)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            != 0
+        {
+            return Err(PairingError::BadPeer);
+        }
+
+        self.peer_confirmed = true;
+        if self.confirmed {
+            self.done_ms.get_or_insert(now_ms);
+            // answer right away, the peer may be waiting for us
+            self.next_send_ms = now_ms;
+        }
+        Ok(())
+    }
+}
+
+fn hash(label: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(label);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn commitment(public_key: &[u8; 32], nonce: &[u8; 16]) -> [u8; 32] {
+    hash(b"buddy pair commit", &[public_key, nonce])
+}
+
+fn confirm_tag(confirm_key: &[u8; 16], mac: &Mac) -> [u8; 16] {
+    hash(b"buddy pair confirm", &[confirm_key, mac])[..16]
+        .try_into()
+        .unwrap()
+}
+
+const MAGIC: &[u8; 4] = b"BPAR";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 4;
+const ENTRY_LEN: usize = 6 + LMK_LEN;
+
+/// Largest encoded record.
+pub const MAX_PAIRED_RECORD_LEN: usize = HEADER_LEN + MAX_PAIRED * ENTRY_LEN + CRC_LEN;
+
+/// The paired peers, most recently paired first.
+///
+/// Stored like the [`WifiConfig`](crate::config::WifiConfig): magic `BPAR`,
+/// format version, number of peers, per peer the MAC and the LMK, and a
+/// CRC-32.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PairedPeers {
+    peers: Vec<PairedPeer, MAX_PAIRED>,
+}
+
+impl PairedPeers {
+    pub fn peers(&self) -> &[PairedPeer] {
+        &self.peers
+    }
+
+    pub fn get(&self, mac: &Mac) -> Option<&PairedPeer> {
+        self.peers.iter().find(|p| p.mac == *mac)
+    }
+
+    /// Whether frames from `mac` should be listened to.
+    pub fn contains(&self, mac: &Mac) -> bool {
+        self.get(mac).is_some()
+    }
+
+    /// Store `peer`, replacing an earlier pairing with the same board.
+    ///
+    /// When full, the peer paired longest ago is dropped and returned, so it
+    /// can be unregistered.
+    pub fn add(&mut self, peer: PairedPeer) -> Option<PairedPeer> {
+        self.remove(&peer.mac);
+        let dropped = if self.peers.is_full() {
+            self.peers.pop()
+        } else {
+            None
+        };
+        // there is room now
+        let _ = self.peers.insert(0, peer);
+        dropped
+    }
+
+    /// Forget `mac`, returns whether it was paired.
+    pub fn remove(&mut self, mac: &Mac) -> bool {
+        match self.peers.iter().position(|p| p.mac == *mac) {
+            Some(index) => {
+                self.peers.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Serialize into `buf`, returns the number of bytes used.
+    pub fn encode(&self, buf: &mut [u8; MAX_PAIRED_RECORD_LEN]) -> usize {
+        buf[..4].copy_from_slice(MAGIC);
+        buf[4] = VERSION;
+        buf[5] = self.peers.len() as u8;
+
+        let mut len = HEADER_LEN;
+        for peer in &self.peers {
+            buf[len..][..6].copy_from_slice(&peer.mac);
+            buf[len + 6..][..LMK_LEN].copy_from_slice(&peer.lmk);
+            len += ENTRY_LEN;
+        }
+
+        let crc = crc32(&buf[..len]);
+        buf[len..][..CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        len + CRC_LEN
+    }
+
+    /// Parse a record written by [`PairedPeers::encode`]; erased flash
+    /// decodes to no peers.
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        if data.len() < HEADER_LEN + CRC_LEN {
+            return Err(DecodeError::Corrupt);
+        }
+        if data[..4] == [0xff; 4] {
+            return Ok(Self::default());
+        }
+        if &data[..4] != MAGIC {
+            return Err(DecodeError::Corrupt);
+        }
+        if data[4] != VERSION {
+            return Err(DecodeError::UnsupportedVersion(data[4]));
+        }
+
+        let count = data[5] as usize;
+        if count > MAX_PAIRED {
+            return Err(DecodeError::Corrupt);
+        }
+        let len = HEADER_LEN + count * ENTRY_LEN;
+        let stored_crc = data.get(len..len + CRC_LEN).ok_or(DecodeError::Corrupt)?;
+        if crc32(&data[..len]).to_le_bytes() != stored_crc {
+            return Err(DecodeError::Corrupt);
+        }
+
+        let mut peers = PairedPeers::default();
+        for entry in data[HEADER_LEN..len].chunks_exact(ENTRY_LEN) {
+            // at most MAX_PAIRED, checked above
+            let _ = peers.peers.push(PairedPeer {
+                mac: entry[..6].try_into().unwrap(),
+                lmk: entry[6..].try_into().unwrap(),
+            });
+        }
+        Ok(peers)
+    }
+}
+
+/// Loads and saves the [`PairedPeers`] at a fixed offset of a flash storage.
+pub struct PairingStore<S> {
+    storage: S,
+    offset: u32,
+}
+
+impl<S: Storage> PairingStore<S> {
+    /// A store at [`PAIRING_OFFSET`].
+    pub fn new(storage: S) -> Self {
+        Self::with_offset(storage, PAIRING_OFFSET)
+    }
+
+    pub fn with_offset(storage: S, offset: u32) -> Self {
+        PairingStore { storage, offset }
+    }
+
+    pub fn load(&mut self) -> Result<PairedPeers, StoreError<S::Error>> {
+        let mut buf = [0u8; MAX_PAIRED_RECORD_LEN];
+        self.storage
+            .read(self.offset, &mut buf)
+            .map_err(StoreError::Storage)?;
+        PairedPeers::decode(&buf).map_err(StoreError::Decode)
+    }
+
+    pub fn save(&mut self, peers: &PairedPeers) -> Result<(), StoreError<S::Error>> {
+        let mut buf = [0u8; MAX_PAIRED_RECORD_LEN];
+        let len = peers.encode(&mut buf);
+        self.storage
+            .write(self.offset, &buf[..len])
+            .map_err(StoreError::Storage)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
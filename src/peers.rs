@@ -0,0 +1,343 @@
+//! The ESP-NOW peers a board knows about
+//!
+//! The [`PeerTable`] keeps, for each board heard from, a friendly name, when
+//! it was last heard, the signal strength of its last frame and whether it is
+//! paired. It holds [`MAX_KNOWN_PEERS`] boards; a new one pushes out the one
+//! heard from longest ago, and the firmware unregisters it from ESP-NOW, which
+//! takes only 20 peers.
+//!
+//! The table is persisted in flash after the paired peers:
+//!
+//! | bytes | content                                          |
+//! |-------|--------------------------------------------------|
+//! | 4     | magic `BPER`                                     |
+//! | 1     | format version                                   |
+//! | 1     | number of peers                                  |
+//! | ...   | per peer: MAC, flags, name len, name (padded)    |
+//! | 4     | CRC-32 of everything before it, little endian    |
+//!
+//! Peers are stored most recently heard first, so the eviction order survives
+//! a reboot. Timestamps and RSSI are not stored: they only mean something
+//! until the next reboot.
+//!
+//! Pairing, renaming or removing a peer is saved right away. Every board in
+//! radio range, or one making up MACs, adds peers and reorders the table
+//! though, so that is saved at most every [`SAVE_INTERVAL_MS`]; a reboot
+//! loses what changed since.
+
+use core::fmt::Write;
+
+use embedded_storage::Storage;
+use heapless::{String, Vec};
+
+use crate::app::Mac;
+use crate::config::{DecodeError, StoreError};
+use crate::crc::crc32;
+use crate::pairing::PAIRING_OFFSET;
+
+/// Offset of the peer table in flash, the sector after the paired peers.
+pub const PEERS_OFFSET: u32 = PAIRING_OFFSET + 0x1000;
+
+/// Number of peers in the table. ESP-NOW takes 20, this leaves room for the
+/// broadcast address and a board being paired.
+pub const MAX_KNOWN_PEERS: usize = 16;
+
+pub const MAX_NAME_LEN: usize = 12;
+
+/// New peers and the order they were heard in are saved at most this often.
+pub const SAVE_INTERVAL_MS: u64 = 30 * 60_000;
+
+/// A peer heard from within this long is online.
+pub const ONLINE_MS: u64 = 30_000;
+
+/// One board in the [`PeerTable`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub mac: Mac,
+    pub name: String<MAX_NAME_LEN>,
+    /// When the last frame arrived, `None` if not since boot.
+    pub last_seen_ms: Option<u64>,
+    /// Signal strength of the last frame, in dBm.
+    pub rssi: Option<i8>,
+    /// Both users agreed on it, see [`pairing`](crate::pairing).
+    pub paired: bool,
+    /// Registered with ESP-NOW with a key.
+    pub encrypted: bool,
+}
+
+impl Peer {
+    /// A peer never heard from, named after the end of its MAC, e.g.
+    /// `buddy-a1b2`.
+    pub fn new(mac: Mac) -> Self {
+        let mut name = String::new();
+        // 10 characters, fits
+        let _ = write!(name, "buddy-{:02x}{:02x}", mac[4], mac[5]);
+        Peer {
+            mac,
+            name,
+            last_seen_ms: None,
+            rssi: None,
+            paired: false,
+            encrypted: false,
+        }
+    }
+
+    /// Whether a frame arrived within [`ONLINE_MS`] before `now_ms`.
+    pub fn is_online(&self, now_ms: u64) -> bool {
+        self.last_seen_ms
+            .is_some_and(|seen| now_ms.saturating_sub(seen) < ONLINE_MS)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameError {
+    UnknownPeer,
+    TooLong,
+    Empty,
+}
+
+const MAGIC: &[u8; 4] = b"BPER";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 4;
+const ENTRY_LEN: usize = 6 + 1 + 1 + MAX_NAME_LEN;
+
+const PAIRED: u8 = 1 << 0;
+const ENCRYPTED: u8 = 1 << 1;
+
+/// Largest encoded record.
+pub const MAX_PEERS_RECORD_LEN: usize = HEADER_LEN + MAX_KNOWN_PEERS * ENTRY_LEN + CRC_LEN;
+
+/// The known peers, most recently heard first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerTable {
+    peers: Vec<Peer, MAX_KNOWN_PEERS>,
+    /// A peer was paired, renamed or removed since it was saved.
+    changed: bool,
+    /// Peers were added, evicted or heard from in another order since it was
+    /// saved.
+    moved: bool,
+    /// When [`PeerTable::take_changed`] last asked for a save, 0 is boot.
+    saved_at_ms: u64,
+}
+
+impl PeerTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    pub fn get(&self, mac: &Mac) -> Option<&Peer> {
+        self.peers.iter().find(|p| p.mac == *mac)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// The peers heard from within [`ONLINE_MS`].
+    pub fn online(&self, now_ms: u64) -> impl Iterator<Item = &Peer> {
+        self.peers.iter().filter(move |p| p.is_online(now_ms))
+    }
+
+    pub fn online_count(&self, now_ms: u64) -> usize {
+        self.online(now_ms).count()
+    }
+
+    /// A frame from `mac` arrived with `rssi`. Adds the peer if it is new.
+    ///
+    /// Returns the peer evicted to make room, so it can be unregistered.
+    pub fn seen(&mut self, now_ms: u64, mac: Mac, rssi: i8) -> Option<Peer> {
+        let (peer, evicted) = self.entry(mac);
+        peer.last_seen_ms = Some(now_ms);
+        peer.rssi = Some(rssi);
+        evicted
+    }
+
+    /// Record whether `mac` is paired and registered with a key. Adds the
+    /// peer if it is new, returning the one evicted like [`PeerTable::seen`].
+    pub fn set_paired(&mut self, mac: Mac, paired: bool, encrypted: bool) -> Option<Peer> {
+        let (peer, evicted) = self.entry(mac);
+        if (peer.paired, peer.encrypted) != (paired, encrypted) {
+            peer.paired = paired;
+            peer.encrypted = encrypted;
+            self.changed = true;
+        }
+        evicted
+    }
+
+    pub fn rename(&mut self, mac: &Mac, name: &str) -> Result<(), RenameError> {
+        if name.is_empty() {
+            return Err(RenameError::Empty);
+        }
+        let name = String::try_from(name).map_err(|_| RenameError::TooLong)?;
+        let peer = self
+            .peers
+            .iter_mut()
+            .find(|p| p.mac == *mac)
+            .ok_or(RenameError::UnknownPeer)?;
+        if peer.name != name {
+            peer.name = name;
+            self.changed = true;
+        }
+        Ok(())
+    }
+
+    /// Forget `mac`, returns it if it was known.
+    pub fn remove(&mut self, mac: &Mac) -> Option<Peer> {
+        let index = self.peers.iter().position(|p| p.mac == *mac)?;
+        self.changed = true;
+        Some(self.peers.remove(index))
+    }
+
+    /// Whether the table should be saved at `now_ms`: right away if peers
+    /// were removed, renamed or paired, and if they were only added, evicted
+    /// or heard from in another order, [`SAVE_INTERVAL_MS`] after the last
+    /// save.
+    pub fn take_changed(&mut self, now_ms: u64) -> bool {
+        let due = self.changed
+            || self.moved && now_ms.saturating_sub(self.saved_at_ms) >= SAVE_INTERVAL_MS;
+        if due {
+            self.changed = false;
+            self.moved = false;
+            self.saved_at_ms = now_ms;
+        }
+        due
+    }
+
+    /// Move `mac` to the front, adding it if needed.
+    ///
+    /// When full, the unpaired peer heard from longest ago makes room; paired
+    /// peers only go when there is nothing else.
+    fn entry(&mut self, mac: Mac) -> (&mut Peer, Option<Peer>) {
+        let mut evicted = None;
+        let peer = match self.peers.iter().position(|p| p.mac == mac) {
+            Some(index) => {
+                self.moved |= index > 0;
+                self.peers.remove(index)
+            }
+            None => {
+                if self.peers.is_full() {
+                    let index = self
+                        .peers
+                        .iter()
+                        .rposition(|p| !p.paired)
+                        .unwrap_or(self.peers.len() - 1);
+                    evicted = Some(self.peers.remove(index));
+                }
+                self.moved = true;
+                Peer::new(mac)
+            }
+        };
+        // there is room now
+        let _ = self.peers.insert(0, peer);
+        (&mut self.peers[0], evicted)
+    }
+
+    /// Serialize into `buf`, returns the number of bytes used.
+    pub fn encode(&self, buf: &mut [u8; MAX_PEERS_RECORD_LEN]) -> usize {
+        buf[..4].copy_from_slice(MAGIC);
+        buf[4] = VERSION;
+        buf[5] = self.peers.len() as u8;
+
+        let mut len = HEADER_LEN;
+        for peer in &self.peers {
+            let entry = &mut buf[len..len + ENTRY_LEN];
+            entry[..6].copy_from_slice(&peer.mac);
+            entry[6] =
+                if peer.paired { PAIRED } else { 0 } | if peer.encrypted { ENCRYPTED } else { 0 };
+            entry[7] = peer.name.len() as u8;
+            entry[8..].fill(0);
+            entry[8..][..peer.name.len()].copy_from_slice(peer.name.as_bytes());
+            len += ENTRY_LEN;
+        }
+
+        let crc = crc32(&buf[..len]);
+        buf[len..][..CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        len + CRC_LEN
+    }
+
+    /// Parse a record written by [`PeerTable::encode`]; erased flash decodes
+    /// to an empty table. None of the peers has been heard from yet.
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        if data.len() < HEADER_LEN + CRC_LEN {
+            return Err(DecodeError::Corrupt);
+        }
+        if data[..4] == [0xff; 4] {
+            return Ok(Self::default());
+        }
+        if &data[..4] != MAGIC {
+            return Err(DecodeError::Corrupt);
+        }
+        if data[4] != VERSION {
+            return Err(DecodeError::UnsupportedVersion(data[4]));
+        }
+
+        let count = data[5] as usize;
+        if count > MAX_KNOWN_PEERS {
+            return Err(DecodeError::Corrupt);
+        }
+        let len = HEADER_LEN + count * ENTRY_LEN;
+        let stored_crc = data.get(len..len + CRC_LEN).ok_or(DecodeError::Corrupt)?;
+        if crc32(&data[..len]).to_le_bytes() != stored_crc {
+            return Err(DecodeError::Corrupt);
+        }
+
+        let mut table = PeerTable::default();
+        for entry in data[HEADER_LEN..len].chunks_exact(ENTRY_LEN) {
+            let name_len = entry[7] as usize;
+            let name = entry[8..]
+                .get(..name_len)
+                .and_then(|name| core::str::from_utf8(name).ok())
+                .ok_or(DecodeError::Corrupt)?;
+            let mut peer = Peer::new(entry[..6].try_into().unwrap());
+            // at most MAX_NAME_LEN bytes, checked above
+            peer.name = String::try_from(name).unwrap();
+            peer.paired = entry[6] & PAIRED != 0;
+            peer.encrypted = entry[6] & ENCRYPTED != 0;
+            // at most MAX_KNOWN_PEERS, checked above
+            let _ = table.peers.push(peer);
+        }
+        Ok(table)
+    }
+}
+
+/// Loads and saves the [`PeerTable`] at a fixed offset of a flash storage.
+pub struct PeerStore<S> {
+    storage: S,
+    offset: u32,
+}
+
+impl<S: Storage> PeerStore<S> {
+    /// A store at [`PEERS_OFFSET`].
+    pub fn new(storage: S) -> Self {
+        Self::with_offset(storage, PEERS_OFFSET)
+    }
+
+    pub fn with_offset(storage: S, offset: u32) -> Self {
+        PeerStore { storage, offset }
+    }
+
+    pub fn load(&mut self) -> Result<PeerTable, StoreError<S::Error>> {
+        let mut buf = [0u8; MAX_PEERS_RECORD_LEN];
+        self.storage
+            .read(self.offset, &mut buf)
+            .map_err(StoreError::Storage)?;
+        PeerTable::decode(&buf).map_err(StoreError::Decode)
+    }
+
+    pub fn save(&mut self, table: &PeerTable) -> Result<(), StoreError<S::Error>> {
+        let mut buf = [0u8; MAX_PEERS_RECORD_LEN];
+        let len = table.encode(&mut buf);
+        self.storage
+            .write(self.offset, &buf[..len])
+            .map_err(StoreError::Storage)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
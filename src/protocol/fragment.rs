@@ -0,0 +1,233 @@
+//! Blobs larger than one frame
+//!
+//! [`split`] cuts a buffer, e.g. a bitmap for the display or a config file,
+//! into [`Fragment`]s that each fit into a [`Message::Fragment`](super::Message).
+//! The [`Reassembler`] on the other side puts them back together, in
+//! whatever order they arrive. It works in a fixed pool of slots, so a sender
+//! cannot make it use more memory, and drops blobs that stop arriving halfway.
+//!
+//! Fragments that get lost are not asked for again; send them with
+//! [`Reliable`](super::reliable::Reliable) for that. A fragment repeated after
+//! its blob was complete starts a new blob that never finishes and times out.
+
+use super::{
+    fragment_count, BlobKind, EncodeError, Fragment, FRAGMENT_DATA_LEN, MAX_BLOB_LEN, MAX_FRAGMENTS,
+};
+use crate::app::Mac;
+
+/// Cut `data` into fragments of the blob `transfer`.
+pub fn split(transfer: u16, kind: BlobKind, data: &[u8]) -> Result<Fragments<'_>, EncodeError> {
+    if data.len() > MAX_BLOB_LEN {
+        return Err(EncodeError::TooLong);
+    }
+    Ok(Fragments {
+        transfer,
+        kind,
+        data,
+        index: 0,
+    })
+}
+
+/// The fragments of one blob, see [`split`].
+#[derive(Debug, Clone)]
+pub struct Fragments<'a> {
+    transfer: u16,
+    kind: BlobKind,
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Fragment<'a>;
+
+    fn next(&mut self) -> Option<Fragment<'a>> {
+        if self.index >= fragment_count(self.data.len()) {
+            return None;
+        }
+        let start = self.index * FRAGMENT_DATA_LEN;
+        let end = (start + FRAGMENT_DATA_LEN).min(self.data.len());
+        let fragment = Fragment {
+            transfer: self.transfer,
+            kind: self.kind,
+            // at most MAX_FRAGMENTS, checked in `split`
+            index: self.index as u8,
+            blob_len: self.data.len() as u16,
+            data: &self.data[start..end],
+        };
+        self.index += 1;
+        Some(fragment)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = fragment_count(self.data.len()).saturating_sub(self.index);
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Fragments<'_> {}
+
+/// A blob put back together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blob<'a> {
+    pub src: Mac,
+    pub transfer: u16,
+    pub kind: BlobKind,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReassemblyError {
+    /// The blob is larger than a slot.
+    TooLarge,
+    /// Every slot is busy with another blob.
+    PoolFull,
+    /// The fragment does not fit the blob it belongs to.
+    Mismatch,
+}
+
+/// Words of the bitmap of received fragments.
+const RECEIVED_WORDS: usize = MAX_FRAGMENTS / 32;
+
+#[derive(Debug, Clone, Copy)]
+struct Slot<const LEN: usize> {
+    in_use: bool,
+    src: Mac,
+    transfer: u16,
+    kind: BlobKind,
+    len: usize,
+    /// Bit `n` is set once fragment `n` arrived.
+    received: [u32; RECEIVED_WORDS],
+    missing: usize,
+    last_heard_ms: u64,
+    data: [u8; LEN],
+}
+
+impl<const LEN: usize> Slot<LEN> {
+    const EMPTY: Self = Slot {
+        in_use: false,
+        src: [0; 6],
+        transfer: 0,
+        kind: BlobKind::Bitmap,
+        len: 0,
+        received: [0; RECEIVED_WORDS],
+        missing: 0,
+        last_heard_ms: 0,
+        data: [0; LEN],
+    };
+}
+
+/// Reassembles up to `SLOTS` blobs of at most `LEN` bytes at a time.
+///
+/// The defaults fit one 128x64 bitmap for the display, and a second blob
+/// arriving at the same time.
+#[derive(Debug, Clone)]
+pub struct Reassembler<const SLOTS: usize = 2, const LEN: usize = 1024> {
+    timeout_ms: u64,
+    slots: [Slot<LEN>; SLOTS],
+    expired: u32,
+}
+
+impl<const SLOTS: usize, const LEN: usize> Reassembler<SLOTS, LEN> {
+    /// Blobs that go `timeout_ms` without a new fragment are dropped.
+    pub fn new(timeout_ms: u64) -> Self {
+        Reassembler {
+            timeout_ms,
+            slots: [Slot::EMPTY; SLOTS],
+            expired: 0,
+        }
+    }
+
+    /// Take a received fragment from `src`. Returns the blob once its last
+    /// fragment is in; repeated fragments are ignored.
+    pub fn receive(
+        &mut self,
+        now_ms: u64,
+        src: Mac,
+        fragment: Fragment<'_>,
+    ) -> Result<Option<Blob<'_>>, ReassemblyError> {
+        self.expire(now_ms);
+        if !fragment.is_valid() {
+            return Err(ReassemblyError::Mismatch);
+        }
+        let len = fragment.blob_len as usize;
+        if len > LEN {
+            return Err(ReassemblyError::TooLarge);
+        }
+
+        let found = self
+            .slots
+            .iter()
+            .position(|s| s.in_use && s.src == src && s.transfer == fragment.transfer);
+        let index = match found {
+            Some(index) => {
+                let slot = &self.slots[index];
+                if slot.kind != fragment.kind || slot.len != len {
+                    return Err(ReassemblyError::Mismatch);
+                }
+                index
+            }
+            None => {
+                let index = self
+                    .slots
+                    .iter()
+                    .position(|s| !s.in_use)
+                    .ok_or(ReassemblyError::PoolFull)?;
+                let slot = &mut self.slots[index];
+                slot.in_use = true;
+                slot.src = src;
+                slot.transfer = fragment.transfer;
+                slot.kind = fragment.kind;
+                slot.len = len;
+                slot.received = [0; RECEIVED_WORDS];
+                slot.missing = fragment.count();
+                index
+            }
+        };
+
+        let slot = &mut self.slots[index];
+        slot.last_heard_ms = now_ms;
+        let n = fragment.index as usize;
+        let (word, bit) = (n / 32, 1 << (n % 32));
+        if slot.received[word] & bit != 0 {
+            return Ok(None);
+        }
+        slot.received[word] |= bit;
+        slot.missing -= 1;
+        let start = n * FRAGMENT_DATA_LEN;
+        slot.data[start..start + fragment.data.len()].copy_from_slice(fragment.data);
+
+        if slot.missing > 0 {
+            return Ok(None);
+        }
+        slot.in_use = false;
+        Ok(Some(Blob {
+            src: slot.src,
+            transfer: slot.transfer,
+            kind: slot.kind,
+            data: &slot.data[..slot.len],
+        }))
+    }
+
+    /// Drop the blobs that timed out by `now_ms`. Returns how many.
+    pub fn expire(&mut self, now_ms: u64) -> usize {
+        let mut dropped = 0;
+        for slot in self.slots.iter_mut().filter(|s| s.in_use) {
+            if now_ms.saturating_sub(slot.last_heard_ms) >= self.timeout_ms {
+                slot.in_use = false;
+                dropped += 1;
+            }
+        }
+        self.expired += dropped as u32;
+        dropped
+    }
+
+    /// Blobs started but not complete yet.
+    pub fn in_progress(&self) -> usize {
+        self.slots.iter().filter(|s| s.in_use).count()
+    }
+
+    /// Blobs dropped so far because they timed out.
+    pub fn expired(&self) -> u32 {
+        self.expired
+    }
+}
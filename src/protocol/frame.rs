@@ -0,0 +1,565 @@
+//! Binary frame format
+//!
+//! All integers are little endian.
+//!
+//! | bytes | content                                        |
+//! |-------|------------------------------------------------|
+//! | 2     | magic `BD`                                     |
+//! | 1     | format version                                 |
+//! | 1     | message type, bit 7 set asks for an ACK        |
+//! | 2     | sequence number                                |
+//! | 1     | payload length                                 |
+//! | ...   | payload, depending on the message type         |
+//! | 4     | CRC-32 of everything before it                 |
+//!
+//! | type | message                    | payload                       |
+//! |------|----------------------------|-------------------------------|
+//! | 1    | [`Message::Text`]          | UTF-8                         |
+//! | 2    | [`Message::Counter`]       | `u32`                         |
+//! | 3    | [`Message::Ping`]          | `u32`, echoed in the pong     |
+//! | 4    | [`Message::Pong`]          | `u32` from the ping           |
+//! | 5    | [`Message::SensorReading`] | sensor `u8`, value `i32`      |
+//! | 6    | [`Message::Command`]       | command `u8`, argument `u8`   |
+//! | 7    | [`Message::Ack`]           | seq `u16`, bit 0: broadcast   |
+//! | 8    | [`Message::Fragment`]      | see below                     |
+//! | 9    | [`Message::PairCommit`]    | public key, commitment        |
+//! | 10   | [`Message::PairReveal`]    | 16 byte nonce                 |
+//! | 11   | [`Message::PairConfirm`]   | 16 byte tag                   |
+//! | 12   | [`Message::Mesh`]          | see below                     |
+//!
+//! A fragment carries part of a larger blob, see
+//! [`fragment`](super::fragment):
+//!
+//! | bytes | content                                          |
+//! |-------|--------------------------------------------------|
+//! | 2     | transfer id                                      |
+//! | 1     | blob kind                                        |
+//! | 1     | fragment index                                   |
+//! | 2     | blob length                                      |
+//! | ...   | [`FRAGMENT_DATA_LEN`] blob bytes, less in the last |
+//!
+//! A mesh message carries a whole frame across several boards, see
+//! [`mesh`](super::mesh):
+//!
+//! | bytes | content                                          |
+//! |-------|--------------------------------------------------|
+//! | 6     | origin MAC                                       |
+//! | 2     | message id                                       |
+//! | 1     | TTL, hops left                                   |
+//! | 1     | hops so far                                      |
+//! | 6     | destination MAC, `ff:ff:ff:ff:ff:ff` for all     |
+//! | ...   | the frame, at most [`MAX_MESH_DATA_LEN`] bytes   |
+//! | 16    | optional: tag, see [`mesh`](super::mesh)         |
+
+use core::fmt;
+
+use crate::app::{Mac, MAX_PAYLOAD};
+use crate::crc::crc32;
+
+const MAGIC: &[u8; 2] = b"BD";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 7;
+const CRC_LEN: usize = 4;
+
+/// Largest encoded frame, what fits into one ESP-NOW payload.
+pub const MAX_FRAME_LEN: usize = MAX_PAYLOAD;
+
+/// Largest message payload, e.g. the bytes of a [`Message::Text`].
+pub const MAX_MESSAGE_LEN: usize = MAX_FRAME_LEN - HEADER_LEN - CRC_LEN;
+
+const TEXT: u8 = 1;
+const COUNTER: u8 = 2;
+const PING: u8 = 3;
+const PONG: u8 = 4;
+const SENSOR_READING: u8 = 5;
+const COMMAND: u8 = 6;
+const ACK: u8 = 7;
+const FRAGMENT: u8 = 8;
+const PAIR_COMMIT: u8 = 9;
+const PAIR_REVEAL: u8 = 10;
+const PAIR_CONFIRM: u8 = 11;
+const MESH: u8 = 12;
+
+const ACK_REQUESTED: u8 = 0x80;
+
+const FRAGMENT_HEADER_LEN: usize = 6;
+
+/// Blob bytes in every fragment but the last.
+pub const FRAGMENT_DATA_LEN: usize = MAX_MESSAGE_LEN - FRAGMENT_HEADER_LEN;
+
+/// Most fragments of one blob, as many as the index can count.
+pub const MAX_FRAGMENTS: usize = 256;
+
+/// Largest blob that can be sent in fragments.
+pub const MAX_BLOB_LEN: usize = MAX_FRAGMENTS * FRAGMENT_DATA_LEN;
+
+const MESH_HEADER_LEN: usize = 16;
+
+/// Largest frame a [`Message::Mesh`] carries.
+pub const MAX_MESH_DATA_LEN: usize = MAX_MESSAGE_LEN - MESH_HEADER_LEN;
+
+/// What a sensor measures; the unit of [`SensorReading::value`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sensor {
+    /// Thousandths of a degree Celsius.
+    Temperature,
+    /// Thousandths of a percent relative humidity.
+    Humidity,
+    /// Millivolts.
+    Voltage,
+    /// Anything else, the unit is up to the application. The id is any byte
+    /// not taken by the variants above, 3 and up.
+    Other(u8),
+}
+
+impl Sensor {
+    fn to_byte(self) -> u8 {
+        match self {
+            Sensor::Temperature => 0,
+            Sensor::Humidity => 1,
+            Sensor::Voltage => 2,
+            Sensor::Other(id) => id,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Sensor::Temperature,
+            1 => Sensor::Humidity,
+            2 => Sensor::Voltage,
+            id => Sensor::Other(id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorReading {
+    pub sensor: Sensor,
+    /// In the unit given by [`Sensor`].
+    pub value: i32,
+}
+
+impl fmt::Display for SensorReading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, unit) = match self.sensor {
+            Sensor::Temperature => ("temperature", " C"),
+            Sensor::Humidity => ("humidity", " %"),
+            Sensor::Voltage => ("voltage", " V"),
+            Sensor::Other(id) => return write!(f, "sensor {}: {}", id, self.value),
+        };
+        let sign = if self.value < 0 { "-" } else { "" };
+        let value = self.value.unsigned_abs();
+        write!(
+            f,
+            "{}: {}{}.{:03}{}",
+            name,
+            sign,
+            value / 1000,
+            value % 1000,
+            unit
+        )
+    }
+}
+
+/// What a fragmented blob contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobKind {
+    /// A 128x64 image for the display, one bit per pixel, row by row, the
+    /// leftmost pixel in the most significant bit.
+    Bitmap,
+    /// A configuration file.
+    Config,
+    /// Log output, as text.
+    Log,
+    /// Anything else. The id is any byte not taken by the variants above, 3
+    /// and up.
+    Other(u8),
+}
+
+impl BlobKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            BlobKind::Bitmap => 0,
+            BlobKind::Config => 1,
+            BlobKind::Log => 2,
+            BlobKind::Other(id) => id,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => BlobKind::Bitmap,
+            1 => BlobKind::Config,
+            2 => BlobKind::Log,
+            id => BlobKind::Other(id),
+        }
+    }
+}
+
+/// One piece of a blob too large for a single frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment<'a> {
+    /// Tells the blobs of one sender apart.
+    pub transfer: u16,
+    pub kind: BlobKind,
+    /// Which piece this is, counting from 0.
+    pub index: u8,
+    /// Length of the whole blob.
+    pub blob_len: u16,
+    /// [`FRAGMENT_DATA_LEN`] bytes starting at `index * FRAGMENT_DATA_LEN`,
+    /// fewer in the last fragment.
+    pub data: &'a [u8],
+}
+
+impl Fragment<'_> {
+    /// Number of fragments the blob is cut into; even an empty blob takes one.
+    pub fn count(&self) -> usize {
+        fragment_count(self.blob_len as usize)
+    }
+
+    /// Whether `index` and `data` fit the blob length, and the blob is at
+    /// most [`MAX_BLOB_LEN`] bytes.
+    pub fn is_valid(&self) -> bool {
+        let start = self.index as usize * FRAGMENT_DATA_LEN;
+        let expected = (self.blob_len as usize).saturating_sub(start);
+        self.blob_len as usize <= MAX_BLOB_LEN
+            && (self.index as usize) < self.count()
+            && self.data.len() == expected.min(FRAGMENT_DATA_LEN)
+    }
+}
+
+/// Number of fragments a blob of `len` bytes is cut into.
+pub fn fragment_count(len: usize) -> usize {
+    len.div_ceil(FRAGMENT_DATA_LEN).max(1)
+}
+
+/// Asks the receiver to do something.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Led(bool),
+    /// Make the device noticeable, e.g. by blinking.
+    Identify,
+    Reboot,
+}
+
+impl Command {
+    fn to_bytes(self) -> [u8; 2] {
+        match self {
+            Command::Led(on) => [0, on as u8],
+            Command::Identify => [1, 0],
+            Command::Reboot => [2, 0],
+        }
+    }
+
+    fn from_bytes(bytes: [u8; 2]) -> Option<Self> {
+        match bytes {
+            [0, on @ (0 | 1)] => Some(Command::Led(on == 1)),
+            [1, 0] => Some(Command::Identify),
+            [2, 0] => Some(Command::Reboot),
+            _ => None,
+        }
+    }
+}
+
+/// A frame on its way through the mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mesh<'a> {
+    /// The board that sent it first.
+    pub origin: Mac,
+    /// Counts up per origin, tells copies of one message apart from the next.
+    pub id: u16,
+    /// How many more boards may relay it.
+    pub ttl: u8,
+    /// How many boards relayed it so far.
+    pub hops: u8,
+    /// The board it is for, or [`BROADCAST`](crate::app::BROADCAST).
+    pub dst: Mac,
+    /// An encoded [`Frame`], at most [`MAX_MESH_DATA_LEN`] bytes.
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message<'a> {
+    /// At most [`MAX_MESSAGE_LEN`] bytes.
+    Text(&'a str),
+    Counter(u32),
+    /// Answered with a [`Message::Pong`] carrying the same value.
+    Ping(u32),
+    Pong(u32),
+    SensorReading(SensorReading),
+    Command(Command),
+    /// Confirms the frame `seq` that asked for an ACK, see
+    /// [`reliable`](super::reliable). `broadcast` tells which sequence
+    /// numbers of the sender are meant.
+    Ack {
+        seq: u16,
+        broadcast: bool,
+    },
+    Fragment(Fragment<'a>),
+    /// Offers to pair, see [`pairing`](crate::pairing): an X25519 public key
+    /// and the hash of it and the nonce sent later.
+    PairCommit {
+        public_key: [u8; 32],
+        commitment: [u8; 32],
+    },
+    /// The nonce behind the commitment, sent once the peer has committed too.
+    PairReveal {
+        nonce: [u8; 16],
+    },
+    /// The user accepted the code, proven with a tag only the peer can check.
+    PairConfirm {
+        tag: [u8; 16],
+    },
+    Mesh(Mesh<'a>),
+}
+
+/// One ESP-NOW payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Counts up per sender, so receivers can spot repeats and gaps.
+    pub seq: u16,
+    /// The receiver should answer with a [`Message::Ack`].
+    pub ack_requested: bool,
+    pub message: Message<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The text is longer than [`MAX_MESSAGE_LEN`], a blob longer than
+    /// [`MAX_BLOB_LEN`] or a mesh frame longer than [`MAX_MESH_DATA_LEN`].
+    TooLong,
+    /// The data of a fragment does not fit its index and blob length.
+    BadFragment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Shorter than header and checksum.
+    TooShort,
+    /// Not a frame of this protocol.
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The length field does not match the frame size, or is beyond
+    /// [`MAX_MESSAGE_LEN`].
+    BadLength,
+    BadChecksum,
+    /// A message type this version does not know.
+    UnknownType(u8),
+    /// The payload does not fit the message type.
+    BadPayload,
+}
+
+impl<'a> Frame<'a> {
+    pub fn new(seq: u16, message: Message<'a>) -> Self {
+        Frame {
+            seq,
+            ack_requested: false,
+            message,
+        }
+    }
+
+    /// Serialize into `buf`, returns the number of bytes used.
+    pub fn encode(&self, buf: &mut [u8; MAX_FRAME_LEN]) -> Result<usize, EncodeError> {
+        let (kind, payload_len) = {
+            let payload = &mut buf[HEADER_LEN..MAX_FRAME_LEN - CRC_LEN];
+            match self.message {
+                Message::Text(text) => {
+                    let text = text.as_bytes();
+                    if text.len() > MAX_MESSAGE_LEN {
+                        return Err(EncodeError::TooLong);
+                    }
+                    payload[..text.len()].copy_from_slice(text);
+                    (TEXT, text.len())
+                }
+                Message::Counter(value) => (COUNTER, put(payload, &value.to_le_bytes())),
+                Message::Ping(value) => (PING, put(payload, &value.to_le_bytes())),
+                Message::Pong(value) => (PONG, put(payload, &value.to_le_bytes())),
+                Message::SensorReading(reading) => {
+                    payload[0] = reading.sensor.to_byte();
+                    payload[1..5].copy_from_slice(&reading.value.to_le_bytes());
+                    (SENSOR_READING, 5)
+                }
+                Message::Command(command) => (COMMAND, put(payload, &command.to_bytes())),
+                Message::Ack { seq, broadcast } => {
+                    payload[..2].copy_from_slice(&seq.to_le_bytes());
+                    payload[2] = broadcast as u8;
+                    (ACK, 3)
+                }
+                Message::Fragment(fragment) => {
+                    if !fragment.is_valid() {
+                        return Err(EncodeError::BadFragment);
+                    }
+                    payload[..2].copy_from_slice(&fragment.transfer.to_le_bytes());
+                    payload[2] = fragment.kind.to_byte();
+                    payload[3] = fragment.index;
+                    payload[4..6].copy_from_slice(&fragment.blob_len.to_le_bytes());
+                    let len = FRAGMENT_HEADER_LEN + fragment.data.len();
+                    payload[FRAGMENT_HEADER_LEN..len].copy_from_slice(fragment.data);
+                    (FRAGMENT, len)
+                }
+                Message::PairCommit {
+                    public_key,
+                    commitment,
+                } => {
+                    payload[..32].copy_from_slice(&public_key);
+                    payload[32..64].copy_from_slice(&commitment);
+                    (PAIR_COMMIT, 64)
+                }
+                Message::PairReveal { nonce } => (PAIR_REVEAL, put(payload, &nonce)),
+                Message::PairConfirm { tag } => (PAIR_CONFIRM, put(payload, &tag)),
+                Message::Mesh(mesh) => {
+                    if mesh.data.len() > MAX_MESH_DATA_LEN {
+                        return Err(EncodeError::TooLong);
+                    }
+                    payload[..6].copy_from_slice(&mesh.origin);
+                    payload[6..8].copy_from_slice(&mesh.id.to_le_bytes());
+                    payload[8] = mesh.ttl;
+                    payload[9] = mesh.hops;
+                    payload[10..16].copy_from_slice(&mesh.dst);
+                    let len = MESH_HEADER_LEN + mesh.data.len();
+                    payload[MESH_HEADER_LEN..len].copy_from_slice(mesh.data);
+                    (MESH, len)
+                }
+            }
+        };
+
+        buf[..2].copy_from_slice(MAGIC);
+        buf[2] = VERSION;
+        buf[3] = if self.ack_requested {
+            kind | ACK_REQUESTED
+        } else {
+            kind
+        };
+        buf[4..6].copy_from_slice(&self.seq.to_le_bytes());
+        buf[6] = payload_len as u8;
+
+        let len = HEADER_LEN + payload_len;
+        let crc = crc32(&buf[..len]);
+        buf[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        Ok(len + CRC_LEN)
+    }
+
+    /// The encoded frame, ready to be sent.
+    pub fn to_vec(&self) -> Result<heapless::Vec<u8, MAX_FRAME_LEN>, EncodeError> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = self.encode(&mut buf)?;
+        // `len` is at most the capacity
+        Ok(heapless::Vec::from_slice(&buf[..len]).unwrap())
+    }
+
+    /// Length of the frame at the start of `bytes`, going by its header.
+    pub(crate) fn encoded_len(bytes: &[u8]) -> Option<usize> {
+        bytes
+            .get(6)
+            .map(|&payload_len| HEADER_LEN + payload_len as usize + CRC_LEN)
+    }
+
+    /// Parse a received payload.
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < HEADER_LEN + CRC_LEN {
+            return Err(DecodeError::TooShort);
+        }
+        if &bytes[..2] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        if bytes[2] != VERSION {
+            return Err(DecodeError::UnsupportedVersion(bytes[2]));
+        }
+        let payload_len = bytes[6] as usize;
+        // nothing longer is sent, see `encode`
+        if payload_len > MAX_MESSAGE_LEN || HEADER_LEN + payload_len + CRC_LEN != bytes.len() {
+            return Err(DecodeError::BadLength);
+        }
+        let (data, crc) = bytes.split_at(HEADER_LEN + payload_len);
+        if crc32(data) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(DecodeError::BadChecksum);
+        }
+
+        let seq = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let payload = &data[HEADER_LEN..];
+        let message = match bytes[3] & !ACK_REQUESTED {
+            TEXT => {
+                Message::Text(core::str::from_utf8(payload).map_err(|_| DecodeError::BadPayload)?)
+            }
+            COUNTER => Message::Counter(u32::from_le_bytes(array(payload)?)),
+            PING => Message::Ping(u32::from_le_bytes(array(payload)?)),
+            PONG => Message::Pong(u32::from_le_bytes(array(payload)?)),
+            SENSOR_READING => {
+                let [sensor, value @ ..] = array::<5>(payload)?;
+                Message::SensorReading(SensorReading {
+                    sensor: Sensor::from_byte(sensor),
+                    value: i32::from_le_bytes(value),
+                })
+            }
+            COMMAND => Message::Command(
+                Command::from_bytes(array(payload)?).ok_or(DecodeError::BadPayload)?,
+            ),
+            ACK => match array(payload)? {
+                [lo, hi, flags @ (0 | 1)] => Message::Ack {
+                    seq: u16::from_le_bytes([lo, hi]),
+                    broadcast: flags == 1,
+                },
+                _ => return Err(DecodeError::BadPayload),
+            },
+            FRAGMENT => {
+                if payload.len() < FRAGMENT_HEADER_LEN {
+                    return Err(DecodeError::BadPayload);
+                }
+                let fragment = Fragment {
+                    transfer: u16::from_le_bytes([payload[0], payload[1]]),
+                    kind: BlobKind::from_byte(payload[2]),
+                    index: payload[3],
+                    blob_len: u16::from_le_bytes([payload[4], payload[5]]),
+                    data: &payload[FRAGMENT_HEADER_LEN..],
+                };
+                if !fragment.is_valid() {
+                    return Err(DecodeError::BadPayload);
+                }
+                Message::Fragment(fragment)
+            }
+            PAIR_COMMIT => {
+                let bytes = array::<64>(payload)?;
+                Message::PairCommit {
+                    public_key: bytes[..32].try_into().unwrap(),
+                    commitment: bytes[32..].try_into().unwrap(),
+                }
+            }
+            PAIR_REVEAL => Message::PairReveal {
+                nonce: array(payload)?,
+            },
+            PAIR_CONFIRM => Message::PairConfirm {
+                tag: array(payload)?,
+            },
+            MESH => {
+                if payload.len() < MESH_HEADER_LEN {
+                    return Err(DecodeError::BadPayload);
+                }
+                Message::Mesh(Mesh {
+                    origin: payload[..6].try_into().unwrap(),
+                    id: u16::from_le_bytes([payload[6], payload[7]]),
+                    ttl: payload[8],
+                    hops: payload[9],
+                    dst: payload[10..16].try_into().unwrap(),
+                    data: &payload[MESH_HEADER_LEN..],
+                })
+            }
+            kind => return Err(DecodeError::UnknownType(kind)),
+        };
+        Ok(Frame {
+            seq,
+            ack_requested: bytes[3] & ACK_REQUESTED != 0,
+            message,
+        })
+    }
+}
+
+/// Copy `bytes` to the start of `payload`, returns their length.
+fn put(payload: &mut [u8], bytes: &[u8]) -> usize {
+    payload[..bytes.len()].copy_from_slice(bytes);
+    bytes.len()
+}
+
+/// A payload of exactly `N` bytes.
+fn array<const N: usize>(payload: &[u8]) -> Result<[u8; N], DecodeError> {
+    payload.try_into().map_err(|_| DecodeError::BadPayload)
+}
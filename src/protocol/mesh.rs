@@ -0,0 +1,321 @@
+//! Flooding frames across boards out of range of each other
+//!
+//! A frame sent with [`Router::send`] is wrapped in a [`Message::Mesh`] and
+//! broadcast. Every board that hears it for the first time hands it to the
+//! application if it is meant for this board, and broadcasts it again with
+//! the TTL one lower, until the TTL runs out. Boards recognize copies by the
+//! origin MAC and message id and drop them, so each message crosses every
+//! board at most once. Relays wait a random moment before sending, so
+//! neighbours that heard the same copy do not all transmit at once.
+//!
+//! Mesh frames are plain ESP-NOW broadcasts: they are not encrypted and a
+//! lost copy is only made up for by other paths. The origin is whatever the
+//! sender put there, so a message for a paired board is sent with
+//! [`Router::send_authenticated`]: a tag made with the [`MeshKey`] of the
+//! pair follows the frame, and the receiver checks it with
+//! [`Delivered::authenticated`] before it believes the origin. Broadcasts
+//! cannot carry a tag every paired board can check; they are relayed, but
+//! not taken by boards that only listen to paired ones. Like the rest of the
+//! protocol the [`Router`] only decides what is sent; the firmware hands it
+//! the received [`Mesh`] messages and sends what [`Router::poll_transmit`]
+//! returns. [`sim::MeshNetwork`](crate::sim) runs several on the host.
+
+use heapless::Deque;
+use sha2::{Digest, Sha256};
+
+use super::{EncodeError, Frame, Mesh, Message, MAX_FRAME_LEN, MAX_MESH_DATA_LEN};
+use crate::app::{Mac, Outgoing, BROADCAST};
+
+/// Messages remembered to recognize copies; the oldest is forgotten first.
+pub const SEEN_LEN: usize = 64;
+
+/// Frames waiting to be sent or relayed.
+pub const MAX_QUEUED: usize = 8;
+
+/// Bytes of the tag after the frame of an authenticated message.
+pub const TAG_LEN: usize = 16;
+
+/// Authenticates the mesh messages between two paired boards, see
+/// [`PairedPeer::mesh_key`](crate::pairing::PairedPeer::mesh_key).
+pub type MeshKey = [u8; 32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshConfig {
+    /// How many boards may relay a message on its way.
+    pub ttl: u8,
+    /// Relays wait up to this long before sending.
+    pub max_jitter_ms: u64,
+}
+
+impl Default for MeshConfig {
+    fn default() -> Self {
+        MeshConfig {
+            ttl: 4,
+            max_jitter_ms: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// [`MAX_QUEUED`] frames are waiting to be sent already.
+    QueueFull,
+    Encode(EncodeError),
+}
+
+impl From<EncodeError> for SendError {
+    fn from(e: EncodeError) -> Self {
+        SendError::Encode(e)
+    }
+}
+
+/// A message that reached this board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivered<'a> {
+    pub origin: Mac,
+    /// This board or [`BROADCAST`].
+    pub dst: Mac,
+    pub id: u16,
+    /// How many boards relayed it.
+    pub hops: u8,
+    /// The encoded [`Frame`] the origin sent.
+    pub data: &'a [u8],
+    /// Follows the frame if it was sent with [`Router::send_authenticated`].
+    pub tag: Option<&'a [u8; TAG_LEN]>,
+}
+
+impl<'a> Delivered<'a> {
+    /// The frame, if its tag was made with `key` for this origin,
+    /// destination and id. Anything else may come from any board.
+    pub fn authenticated(&self, key: &MeshKey) -> Option<&'a [u8]> {
+        let tag = self.tag?;
+        let expected = tag_of(key, self.origin, self.dst, self.id, self.data);
+        // in constant time, how long it takes tells a forger nothing
+        let diff = expected
+            .iter()
+            .zip(tag)
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+        (diff == 0).then_some(self.data)
+    }
+}
+
+/// The frame in the data of a [`Mesh`] message, and the tag after it if
+/// there is one.
+pub fn split_tag(data: &[u8]) -> (&[u8], Option<&[u8; TAG_LEN]>) {
+    match Frame::encoded_len(data) {
+        Some(len) if data.len() == len + TAG_LEN => {
+            let (frame, tag) = data.split_at(len);
+            (frame, tag.try_into().ok())
+        }
+        _ => (data, None),
+    }
+}
+
+/// HMAC-SHA256 of what a message says about itself and its frame, cut to
+/// [`TAG_LEN`] bytes. Hops and TTL change on the way and are left out.
+fn tag_of(key: &MeshKey, origin: Mac, dst: Mac, id: u16, frame: &[u8]) -> [u8; TAG_LEN] {
+    let pad = |byte: u8| {
+        let mut pad = [byte; 64];
+        for (p, k) in pad.iter_mut().zip(key) {
+            *p ^= k;
+        }
+        pad
+    };
+    let mut inner = Sha256::new();
+    inner.update(pad(0x36));
+    inner.update(origin);
+    inner.update(dst);
+    inner.update(id.to_le_bytes());
+    inner.update(frame);
+    let mut outer = Sha256::new();
+    outer.update(pad(0x5c));
+    outer.update(inner.finalize());
+    outer.finalize()[..TAG_LEN].try_into().unwrap()
+}
+
+#[derive(Debug)]
+struct Queued {
+    due_ms: u64,
+    data: heapless::Vec<u8, MAX_FRAME_LEN>,
+}
+
+/// The mesh state of one board.
+#[derive(Debug)]
+pub struct Router {
+    own: Mac,
+    config: MeshConfig,
+    next_id: u16,
+    /// Sequence number of the outer frames.
+    seq: u16,
+    rng: u32,
+    seen: Deque<(Mac, u16), SEEN_LEN>,
+    queue: Deque<Queued, MAX_QUEUED>,
+    relayed: u32,
+    duplicates: u32,
+    dropped: u32,
+}
+
+impl Router {
+    /// `seed` should differ between boots, e.g. a hardware random number, so
+    /// the ids after a restart are not taken for copies of earlier messages.
+    pub fn new(own: Mac, config: MeshConfig, seed: u32) -> Self {
+        Router {
+            own,
+            config,
+            next_id: seed as u16,
+            seq: (seed >> 16) as u16,
+            // xorshift gets stuck at zero
+            rng: seed | 1,
+            seen: Deque::new(),
+            queue: Deque::new(),
+            relayed: 0,
+            duplicates: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Flood `message` to `dst`, or to every board for [`BROADCAST`].
+    /// Returns its id.
+    ///
+    /// The message is sent as a [`Frame`] with the id as sequence number and
+    /// must fit into [`MAX_MESH_DATA_LEN`] bytes that way.
+    pub fn send(&mut self, now_ms: u64, dst: Mac, message: Message<'_>) -> Result<u16, SendError> {
+        self.send_tagged(now_ms, dst, message, None)
+    }
+
+    /// Like [`Router::send`], with a tag made with `key` after the frame,
+    /// so `dst` can tell the message really comes from this board. The tag
+    /// takes [`TAG_LEN`] of the [`MAX_MESH_DATA_LEN`] bytes.
+    pub fn send_authenticated(
+        &mut self,
+        now_ms: u64,
+        dst: Mac,
+        message: Message<'_>,
+        key: &MeshKey,
+    ) -> Result<u16, SendError> {
+        self.send_tagged(now_ms, dst, message, Some(key))
+    }
+
+    fn send_tagged(
+        &mut self,
+        now_ms: u64,
+        dst: Mac,
+        message: Message<'_>,
+        key: Option<&MeshKey>,
+    ) -> Result<u16, SendError> {
+        if self.queue.is_full() {
+            return Err(SendError::QueueFull);
+        }
+        let id = self.next_id;
+        let mut inner = Frame::new(id, message).to_vec()?;
+        if let Some(key) = key {
+            let tag = tag_of(key, self.own, dst, id, &inner);
+            inner
+                .extend_from_slice(&tag)
+                .map_err(|_| EncodeError::TooLong)?;
+        }
+        if inner.len() > MAX_MESH_DATA_LEN {
+            return Err(SendError::Encode(EncodeError::TooLong));
+        }
+        let mesh = Mesh {
+            origin: self.own,
+            id,
+            ttl: self.config.ttl,
+            hops: 0,
+            dst,
+            data: &inner,
+        };
+        self.queue(now_ms, mesh);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.remember(self.own, id);
+        Ok(id)
+    }
+
+    /// Take a [`Message::Mesh`] heard from a neighbour. Returns it if it is
+    /// new and meant for this board; relays it if it is new and meant for
+    /// another board too.
+    pub fn receive<'a>(&mut self, now_ms: u64, mesh: Mesh<'a>) -> Option<Delivered<'a>> {
+        if mesh.origin == self.own || self.seen.iter().any(|&s| s == (mesh.origin, mesh.id)) {
+            self.duplicates += 1;
+            return None;
+        }
+        self.remember(mesh.origin, mesh.id);
+
+        if mesh.dst != self.own && mesh.ttl > 0 {
+            let jitter = self.random() as u64 % (self.config.max_jitter_ms + 1);
+            let relay = Mesh {
+                ttl: mesh.ttl - 1,
+                hops: mesh.hops.saturating_add(1),
+                ..mesh
+            };
+            if self.queue.is_full() {
+                self.dropped += 1;
+            } else {
+                self.queue(now_ms + jitter, relay);
+                self.relayed += 1;
+            }
+        }
+
+        let (data, tag) = split_tag(mesh.data);
+        (mesh.dst == self.own || mesh.dst == BROADCAST).then_some(Delivered {
+            origin: mesh.origin,
+            dst: mesh.dst,
+            id: mesh.id,
+            hops: mesh.hops,
+            data,
+            tag,
+        })
+    }
+
+    /// The next frame to broadcast by `now_ms`, if any.
+    pub fn poll_transmit(&mut self, now_ms: u64) -> Option<Outgoing> {
+        let index = self.queue.iter().position(|q| q.due_ms <= now_ms)?;
+        // rotate the due frame to the front
+        for _ in 0..index {
+            let queued = self.queue.pop_front().unwrap();
+            // there is room, one was just taken out
+            let _ = self.queue.push_back(queued);
+        }
+        let queued = self.queue.pop_front().unwrap();
+        Some(Outgoing {
+            dst: BROADCAST,
+            data: queued.data,
+        })
+    }
+
+    /// Messages relayed for other boards so far.
+    pub fn relayed(&self) -> u32 {
+        self.relayed
+    }
+
+    /// Copies of messages seen before, including this board's own.
+    pub fn duplicates(&self) -> u32 {
+        self.duplicates
+    }
+
+    /// Messages not relayed because the queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    fn queue(&mut self, due_ms: u64, mesh: Mesh<'_>) {
+        self.seq = self.seq.wrapping_add(1);
+        // the data fits, checked by `send` or by decoding
+        let data = Frame::new(self.seq, Message::Mesh(mesh)).to_vec().unwrap();
+        let _ = self.queue.push_back(Queued { due_ms, data });
+    }
+
+    fn remember(&mut self, origin: Mac, id: u16) {
+        if self.seen.is_full() {
+            self.seen.pop_front();
+        }
+        let _ = self.seen.push_back((origin, id));
+    }
+
+    fn random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+}
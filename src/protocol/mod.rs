@@ -0,0 +1,18 @@
+//! The application protocol spoken over ESP-NOW
+//!
+//! Every ESP-NOW payload is one [`Frame`]: a small header, a typed
+//! [`Message`] and a checksum. Encoding and decoding work on byte slices
+//! only, so the same code runs in the firmware and on the host. Payloads too
+//! large for one frame travel in [`fragment`]s, frames for boards out of
+//! range through the [`mesh`].
+
+pub mod fragment;
+pub mod frame;
+pub mod mesh;
+pub mod reliable;
+
+pub use frame::{
+    fragment_count, BlobKind, Command, DecodeError, EncodeError, Fragment, Frame, Mesh, Message,
+    Sensor, SensorReading, FRAGMENT_DATA_LEN, MAX_BLOB_LEN, MAX_FRAGMENTS, MAX_FRAME_LEN,
+    MAX_MESH_DATA_LEN, MAX_MESSAGE_LEN,
+};
@@ -0,0 +1,392 @@
+//! Reliable delivery on top of ESP-NOW
+//!
+//! Frames sent with [`Reliable::send`] or [`Reliable::broadcast`] ask for a
+//! [`Message::Ack`] and go out again every
+//! [`ReliableConfig::ack_timeout_ms`] until it arrives, at most
+//! [`ReliableConfig::max_retries`] times. A broadcast waits for the ACKs of
+//! the peers it is meant for. Every destination has its own sequence numbers.
+//! The receiving side acknowledges every copy, but hands each frame to the
+//! application only once.
+//!
+//! Like the rest of the protocol this only decides what is sent, the firmware
+//! moves the bytes: [`Reliable::poll_transmit`] yields the frames to send,
+//! [`Reliable::receive`] takes the received ones and [`Reliable::pop_event`]
+//! tells how the sent frames fared.
+
+use heapless::{Deque, Vec};
+
+use super::{EncodeError, Frame, Message, MAX_FRAME_LEN};
+use crate::app::{Mac, Outgoing, BROADCAST};
+
+/// Frames waiting for their ACKs at the same time.
+pub const MAX_PENDING: usize = 8;
+
+/// Peers whose sequence numbers are tracked; the least recently heard from
+/// is forgotten first.
+pub const MAX_PEERS: usize = 16;
+
+/// How far back repeated sequence numbers are recognized.
+const WINDOW: u16 = 32;
+
+/// ACKs waiting to be sent; when more pile up the oldest is dropped and the
+/// sender repeats the frame.
+const MAX_ACKS: usize = 8;
+
+const MAX_EVENTS: usize = 2 * MAX_PENDING;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReliableConfig {
+    /// How long to wait for an ACK before sending again.
+    pub ack_timeout_ms: u64,
+    /// How often a frame is repeated before it counts as lost.
+    pub max_retries: u32,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        ReliableConfig {
+            ack_timeout_ms: 100,
+            max_retries: 5,
+        }
+    }
+}
+
+/// What became of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Acknowledged, for a broadcast by all of its peers.
+    Delivered { dst: Mac, seq: u16 },
+    /// Not acknowledged after all retries.
+    Failed { dst: Mac, seq: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// [`MAX_PENDING`] frames are waiting for their ACKs already.
+    QueueFull,
+    /// More than [`MAX_PEERS`] peers for a broadcast.
+    TooManyPeers,
+    Encode(EncodeError),
+}
+
+impl From<EncodeError> for SendError {
+    fn from(e: EncodeError) -> Self {
+        SendError::Encode(e)
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    dst: Mac,
+    seq: u16,
+    data: Vec<u8, MAX_FRAME_LEN>,
+    /// Peers that have not acknowledged yet.
+    awaiting: Vec<Mac, MAX_PEERS>,
+    /// Transmissions so far.
+    sent: u32,
+    due_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Ack {
+    dst: Mac,
+    seq: u16,
+    broadcast: bool,
+}
+
+/// The sequence numbers seen from one sender, unicast and broadcast apart.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    src: Mac,
+    broadcast: bool,
+    highest: u16,
+    /// Bit `n` stands for `highest - n`.
+    seen: u32,
+    last_heard_ms: u64,
+}
+
+impl Window {
+    /// Record `seq`, returns whether it is new.
+    fn accept(&mut self, seq: u16) -> bool {
+        let ahead = seq.wrapping_sub(self.highest);
+        let behind = self.highest.wrapping_sub(seq);
+        if ahead == 0 {
+            false
+        } else if ahead < 0x8000 {
+            self.seen = self.seen.checked_shl(ahead as u32).unwrap_or(0) | 1;
+            self.highest = seq;
+            true
+        } else if behind < WINDOW {
+            let bit = 1 << behind;
+            let new = self.seen & bit == 0;
+            self.seen |= bit;
+            new
+        } else {
+            // far behind, not a repeat of anything still in flight: the
+            // sender has restarted
+            self.highest = seq;
+            self.seen = 1;
+            true
+        }
+    }
+}
+
+/// The reliable delivery state of one node.
+#[derive(Debug)]
+pub struct Reliable {
+    config: ReliableConfig,
+    /// Next sequence number per destination.
+    next_seq: Vec<(Mac, u16), MAX_PEERS>,
+    /// For the first sequence number towards a destination.
+    rng: u32,
+    pending: Vec<Pending, MAX_PENDING>,
+    acks: Deque<Ack, MAX_ACKS>,
+    windows: Vec<Window, MAX_PEERS>,
+    events: Deque<Event, MAX_EVENTS>,
+}
+
+impl Reliable {
+    /// `seed` should differ between boots, e.g. a hardware random number, so
+    /// receivers do not take the first frames after a restart for repeats.
+    pub fn new(config: ReliableConfig, seed: u32) -> Self {
+        Reliable {
+            config,
+            next_seq: Vec::new(),
+            // xorshift gets stuck at zero
+            rng: seed | 1,
+            pending: Vec::new(),
+            acks: Deque::new(),
+            windows: Vec::new(),
+            events: Deque::new(),
+        }
+    }
+
+    /// Send `message` to `dst`, returns its sequence number.
+    pub fn send(&mut self, now_ms: u64, dst: Mac, message: Message<'_>) -> Result<u16, SendError> {
+        self.queue(now_ms, dst, message, &[dst])
+    }
+
+    /// Broadcast `message`, until each of `peers` has acknowledged it.
+    /// Returns its sequence number.
+    ///
+    /// Without any peers the frame is sent once and counts as delivered.
+    pub fn broadcast(
+        &mut self,
+        now_ms: u64,
+        message: Message<'_>,
+        peers: &[Mac],
+    ) -> Result<u16, SendError> {
+        self.queue(now_ms, BROADCAST, message, peers)
+    }
+
+    fn queue(
+        &mut self,
+        now_ms: u64,
+        dst: Mac,
+        message: Message<'_>,
+        peers: &[Mac],
+    ) -> Result<u16, SendError> {
+        if self.pending.is_full() {
+            return Err(SendError::QueueFull);
+        }
+        let awaiting = Vec::from_slice(peers).map_err(|_| SendError::TooManyPeers)?;
+
+        let seq = self.next_seq(dst);
+        let frame = Frame {
+            seq,
+            ack_requested: true,
+            message,
+        };
+        let data = frame.to_vec()?;
+        self.take_seq(dst);
+
+        // there is room, checked above
+        let _ = self.pending.push(Pending {
+            dst,
+            seq,
+            data,
+            awaiting,
+            sent: 0,
+            due_ms: now_ms,
+        });
+        Ok(seq)
+    }
+
+    /// The sequence number the next frame to `dst` gets.
+    fn next_seq(&mut self, dst: Mac) -> u16 {
+        if let Some((_, seq)) = self.next_seq.iter().find(|(mac, _)| *mac == dst) {
+            return *seq;
+        }
+        if self.next_seq.is_full() {
+            self.next_seq.remove(0);
+        }
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        let seq = self.rng as u16;
+        // there is room now
+        let _ = self.next_seq.push((dst, seq));
+        seq
+    }
+
+    fn take_seq(&mut self, dst: Mac) {
+        if let Some((_, seq)) = self.next_seq.iter_mut().find(|(mac, _)| *mac == dst) {
+            *seq = seq.wrapping_add(1);
+        }
+    }
+
+    /// Frames waiting for ACKs.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The next frame to send now, ACKs first. Call until it returns `None`.
+    pub fn poll_transmit(&mut self, now_ms: u64) -> Option<Outgoing> {
+        if let Some(ack) = self.acks.pop_front() {
+            let frame = Frame::new(
+                0,
+                Message::Ack {
+                    seq: ack.seq,
+                    broadcast: ack.broadcast,
+                },
+            );
+            return Some(Outgoing {
+                dst: ack.dst,
+                // an ACK always fits
+                data: frame.to_vec().unwrap(),
+            });
+        }
+
+        let max_sends = 1 + self.config.max_retries;
+        while let Some(index) = self.pending.iter().position(|p| p.due_ms <= now_ms) {
+            let pending = &mut self.pending[index];
+            if pending.sent >= max_sends {
+                let pending = self.pending.swap_remove(index);
+                self.push_event(Event::Failed {
+                    dst: pending.dst,
+                    seq: pending.seq,
+                });
+                continue;
+            }
+
+            pending.sent += 1;
+            pending.due_ms = now_ms + self.config.ack_timeout_ms;
+            let outgoing = Outgoing {
+                dst: pending.dst,
+                data: pending.data.clone(),
+            };
+            if pending.awaiting.is_empty() {
+                // a broadcast to nobody in particular
+                let pending = self.pending.swap_remove(index);
+                self.push_event(Event::Delivered {
+                    dst: pending.dst,
+                    seq: pending.seq,
+                });
+            }
+            return Some(outgoing);
+        }
+        None
+    }
+
+    /// Take a received ESP-NOW payload.
+    ///
+    /// Returns the frame if the application should see it: ACKs, invalid
+    /// frames and repeats of frames returned before are swallowed.
+    pub fn receive<'a>(
+        &mut self,
+        now_ms: u64,
+        src: Mac,
+        dst: Mac,
+        data: &'a [u8],
+    ) -> Option<Frame<'a>> {
+        let frame = Frame::decode(data).ok()?;
+        if let Message::Ack { seq, broadcast } = frame.message {
+            self.acknowledged(src, seq, broadcast);
+            return None;
+        }
+        if !frame.ack_requested {
+            return Some(frame);
+        }
+
+        let broadcast = dst == BROADCAST;
+        if self.acks.is_full() {
+            self.acks.pop_front();
+        }
+        // there is room now
+        let _ = self.acks.push_back(Ack {
+            dst: src,
+            seq: frame.seq,
+            broadcast,
+        });
+
+        self.is_new(now_ms, src, broadcast, frame.seq)
+            .then_some(frame)
+    }
+
+    /// Record `seq` from `src`, returns whether it is new.
+    fn is_new(&mut self, now_ms: u64, src: Mac, broadcast: bool, seq: u16) -> bool {
+        let found = self
+            .windows
+            .iter_mut()
+            .find(|w| w.src == src && w.broadcast == broadcast);
+        if let Some(window) = found {
+            window.last_heard_ms = now_ms;
+            return window.accept(seq);
+        }
+
+        if self.windows.is_full() {
+            let oldest = (0..self.windows.len())
+                .min_by_key(|&i| self.windows[i].last_heard_ms)
+                .unwrap();
+            self.windows.swap_remove(oldest);
+        }
+        // there is room now
+        let _ = self.windows.push(Window {
+            src,
+            broadcast,
+            highest: seq,
+            seen: 1,
+            last_heard_ms: now_ms,
+        });
+        true
+    }
+
+    fn acknowledged(&mut self, src: Mac, seq: u16, broadcast: bool) {
+        let found = self.pending.iter().position(|p| {
+            p.seq == seq
+                && if broadcast {
+                    p.dst == BROADCAST
+                } else {
+                    p.dst == src
+                }
+        });
+        let Some(index) = found else {
+            // late or repeated
+            return;
+        };
+
+        let pending = &mut self.pending[index];
+        pending.awaiting.retain(|peer| *peer != src);
+        if pending.awaiting.is_empty() {
+            let pending = self.pending.swap_remove(index);
+            self.push_event(Event::Delivered {
+                dst: pending.dst,
+                seq: pending.seq,
+            });
+        }
+    }
+
+    fn push_event(&mut self, event: Event) {
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        // there is room now
+        let _ = self.events.push_back(event);
+    }
+
+    /// Delivered and failed frames, oldest first.
+    pub fn pop_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+}
@@ -0,0 +1,184 @@
+//! A tiny DHCP server for the provisioning access point
+//!
+//! Hands out `x.x.x.2` to `x.x.x.(1 + MAX_LEASES)` of the access point's /24,
+//! with the access point as router and DNS server. Leases never expire, when
+//! the pool is full the least recently seen client loses its address.
+
+use heapless::Vec;
+
+use crate::app::Mac;
+
+/// Number of clients that get an address.
+pub const MAX_LEASES: usize = 8;
+
+/// Lease time announced to the clients, in seconds.
+pub const LEASE_SECS: u32 = 3600;
+
+/// Replies are padded to the minimal BOOTP message size.
+pub const REPLY_LEN: usize = 300;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_START: usize = 240;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+#[derive(Debug)]
+pub struct DhcpServer {
+    server_ip: [u8; 4],
+    /// Client and the last byte of its address, most recently seen last.
+    leases: Vec<(Mac, u8), MAX_LEASES>,
+}
+
+impl DhcpServer {
+    pub fn new(server_ip: [u8; 4]) -> Self {
+        DhcpServer {
+            server_ip,
+            leases: Vec::new(),
+        }
+    }
+
+    /// The address leased to `mac`, if any.
+    pub fn lease(&self, mac: &Mac) -> Option<[u8; 4]> {
+        self.leases
+            .iter()
+            .find(|(m, _)| m == mac)
+            .map(|&(_, host)| self.address(host))
+    }
+
+    /// Handle a message received on port 67.
+    ///
+    /// Returns the length of the reply written to `reply`, which has to be
+    /// broadcast to port 68. Messages that are not for us are ignored.
+    pub fn handle(&mut self, request: &[u8], reply: &mut [u8; REPLY_LEN]) -> Option<usize> {
+        if request.len() < OPTIONS_START
+            || request[0] != BOOTREQUEST
+            || request[1] != 1
+            || request[2] != 6
+            || request[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mac: Mac = request[28..34].try_into().unwrap();
+        let options = &request[OPTIONS_START..];
+
+        let (message_type, yiaddr) = match option(options, OPTION_MESSAGE_TYPE)? {
+            [DISCOVER] => (OFFER, self.assign(mac)),
+            [REQUEST] => {
+                if let Some(server_id) = option(options, OPTION_SERVER_ID) {
+                    if server_id != self.server_ip {
+                        // the client picked another server
+                        return None;
+                    }
+                }
+                let requested = match option(options, OPTION_REQUESTED_IP) {
+                    Some(ip) => ip,
+                    // renewing, the address is in ciaddr
+                    None => &request[12..16],
+                };
+                match self.lease(&mac) {
+                    Some(ip) if ip == requested => {
+                        self.assign(mac);
+                        (ACK, ip)
+                    }
+                    _ => (NAK, [0; 4]),
+                }
+            }
+            _ => return None,
+        };
+
+        reply.fill(0);
+        reply[0] = BOOTREPLY;
+        reply[1..3].copy_from_slice(&request[1..3]);
+        // xid, secs and flags
+        reply[4..12].copy_from_slice(&request[4..12]);
+        reply[16..20].copy_from_slice(&yiaddr);
+        reply[20..24].copy_from_slice(&self.server_ip);
+        // giaddr and chaddr
+        reply[24..44].copy_from_slice(&request[24..44]);
+        reply[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut options = Options {
+            buf: &mut reply[OPTIONS_START..],
+            len: 0,
+        };
+        options.push(OPTION_MESSAGE_TYPE, &[message_type]);
+        options.push(OPTION_SERVER_ID, &self.server_ip);
+        if message_type != NAK {
+            options.push(OPTION_LEASE_TIME, &LEASE_SECS.to_be_bytes());
+            options.push(OPTION_SUBNET_MASK, &[255, 255, 255, 0]);
+            options.push(OPTION_ROUTER, &self.server_ip);
+            options.push(OPTION_DNS, &self.server_ip);
+        }
+        options.buf[options.len] = OPTION_END;
+
+        Some(REPLY_LEN)
+    }
+
+    /// The address of `mac`, taking a new one (or the oldest) if needed.
+    fn assign(&mut self, mac: Mac) -> [u8; 4] {
+        let host = match self.leases.iter().position(|(m, _)| *m == mac) {
+            Some(index) => self.leases.remove(index).1,
+            None if self.leases.is_full() => self.leases.remove(0).1,
+            None => (2..)
+                .find(|host| self.leases.iter().all(|(_, h)| h != host))
+                .unwrap(),
+        };
+        // there is room now
+        let _ = self.leases.push((mac, host));
+        self.address(host)
+    }
+
+    fn address(&self, host: u8) -> [u8; 4] {
+        let [a, b, c, _] = self.server_ip;
+        [a, b, c, host]
+    }
+}
+
+/// The value of option `code`.
+fn option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    loop {
+        match *options.first()? {
+            OPTION_END => return None,
+            OPTION_PAD => options = &options[1..],
+            c => {
+                let len = *options.get(1)? as usize;
+                let value = options.get(2..2 + len)?;
+                if c == code {
+                    return Some(value);
+                }
+                options = &options[2 + len..];
+            }
+        }
+    }
+}
+
+struct Options<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Options<'_> {
+    fn push(&mut self, code: u8, value: &[u8]) {
+        self.buf[self.len] = code;
+        self.buf[self.len + 1] = value.len() as u8;
+        self.buf[self.len + 2..][..value.len()].copy_from_slice(value);
+        self.len += 2 + value.len();
+    }
+}
@@ -0,0 +1,71 @@
+//! Catch-all DNS responder: every name resolves to the access point
+
+/// Largest reply, the classic UDP DNS message limit.
+pub const MAX_MESSAGE_LEN: usize = 512;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const TTL_SECS: u32 = 60;
+
+/// Answer the standard query in `query` with `ip`.
+///
+/// Returns the length of the reply written to `reply`. Only queries with a
+/// single question are answered; questions for other types than `A` get an
+/// empty answer, so clients do not wait for IPv6 addresses.
+pub fn answer(query: &[u8], ip: [u8; 4], reply: &mut [u8; MAX_MESSAGE_LEN]) -> Option<usize> {
+    let header = query.get(..HEADER_LEN)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    let is_query = flags & 0x8000 == 0;
+    let opcode = (flags >> 11) & 0xf;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    if !is_query || opcode != 0 || questions != 1 {
+        return None;
+    }
+
+    // the name is a list of labels, ended by an empty one
+    let mut end = HEADER_LEN;
+    loop {
+        let len = *query.get(end)? as usize;
+        if len & 0xc0 != 0 {
+            // compression makes no sense in the first name
+            return None;
+        }
+        end += 1 + len;
+        if len == 0 {
+            break;
+        }
+    }
+    let qtype = u16::from_be_bytes(query.get(end..end + 2)?.try_into().unwrap());
+    let qclass = u16::from_be_bytes(query.get(end + 2..end + 4)?.try_into().unwrap());
+    let question = &query[HEADER_LEN..end + 4];
+    let answers = u16::from((qtype == TYPE_A || qtype == TYPE_ANY) && qclass == CLASS_IN);
+
+    let len = HEADER_LEN + question.len() + usize::from(answers) * 16;
+    if len > MAX_MESSAGE_LEN {
+        return None;
+    }
+
+    reply[..2].copy_from_slice(&header[..2]);
+    // response, recursion desired copied from the query, recursion available
+    let flags = 0x8080 | (flags & 0x0100);
+    reply[2..4].copy_from_slice(&flags.to_be_bytes());
+    reply[4..6].copy_from_slice(&1u16.to_be_bytes());
+    reply[6..8].copy_from_slice(&answers.to_be_bytes());
+    reply[8..12].fill(0);
+    reply[HEADER_LEN..][..question.len()].copy_from_slice(question);
+
+    if answers == 1 {
+        let answer = &mut reply[HEADER_LEN + question.len()..len];
+        // pointer to the name in the question
+        answer[..2].copy_from_slice(&(0xc000 | HEADER_LEN as u16).to_be_bytes());
+        answer[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        answer[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        answer[6..10].copy_from_slice(&TTL_SECS.to_be_bytes());
+        answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+        answer[12..16].copy_from_slice(&ip);
+    }
+
+    Some(len)
+}
@@ -0,0 +1,24 @@
+//! Wi-Fi provisioning over a captive portal
+//!
+//! Holding the button at power-up makes the firmware open the access point
+//! [`AP_SSID`] instead of connecting to a stored network. Clients get an
+//! address from [`dhcp::DhcpServer`], every DNS name resolves to [`AP_IP`]
+//! ([`dns::answer`]), so phones and laptops pop up the form served by
+//! [`portal::handle`] on their own. The chosen network is stored in the
+//! [`WifiConfig`](crate::config::WifiConfig) and the device restarts.
+//!
+//! Only the protocol handling lives here, the firmware moves the packets.
+
+pub mod dhcp;
+pub mod dns;
+pub mod portal;
+
+/// Name of the open provisioning access point.
+pub const AP_SSID: &str = "buddy-setup";
+
+/// Address of the device on the provisioning network (a /24).
+pub const AP_IP: [u8; 4] = [192, 168, 4, 1];
+
+pub const DNS_PORT: u16 = 53;
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
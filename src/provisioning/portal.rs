@@ -0,0 +1,128 @@
+//! The provisioning web page: a list of the scanned networks and a password field
+
+use core::fmt::{self, Write};
+
+use super::AP_IP;
+use crate::config::{Credentials, MAX_PASSWORD_LEN, MAX_SSID_LEN};
+use crate::http::{self, form_field, url_decode, Html, Method, Request, Status};
+
+/// What the firmware has to do after answering a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Nothing, keep serving.
+    Continue,
+    /// The user picked a network, store it and restart.
+    Save(Credentials),
+}
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:24em;margin:auto;padding:1em}\
+label{display:block;margin:.4em 0}input[type=text],input[type=password]{width:100%}";
+
+/// Answer `request` into `response`.
+///
+/// `networks` are the `(ssid, signal strength)` pairs of the scan, shown as
+/// choices; any other path than `/` and `/save` is redirected to the form so
+/// the captive portal detection of the client finds it.
+pub fn handle<W: Write>(
+    request: &Request<'_>,
+    networks: &[(&str, i8)],
+    response: &mut W,
+) -> Result<Outcome, fmt::Error> {
+    match (request.method, request.path) {
+        (Method::Get, "/") => {
+            http::write_response_with(response, Status::Ok, "text/html", |w| {
+                form_page(w, networks)
+            })?;
+            Ok(Outcome::Continue)
+        }
+        (Method::Post, "/save") => match credentials(request.body) {
+            Ok(credentials) => {
+                http::write_response_with(response, Status::Ok, "text/html", |w| {
+                    message_page(
+                        w,
+                        format_args!("Saved {}, restarting to connect.", Html(&credentials.ssid)),
+                    )
+                })?;
+                Ok(Outcome::Save(credentials))
+            }
+            Err(error) => {
+                http::write_response_with(response, Status::BadRequest, "text/html", |w| {
+                    message_page(w, format_args!("{} <a href=\"/\">Back</a>", error))
+                })?;
+                Ok(Outcome::Continue)
+            }
+        },
+        (Method::Get, _) => {
+            let mut location: heapless::String<24> = heapless::String::new();
+            write!(
+                location,
+                "http://{}.{}.{}.{}/",
+                AP_IP[0], AP_IP[1], AP_IP[2], AP_IP[3]
+            )?;
+            http::write_redirect(response, &location)?;
+            Ok(Outcome::Continue)
+        }
+        _ => {
+            http::write_response(response, Status::MethodNotAllowed, "text/plain", "")?;
+            Ok(Outcome::Continue)
+        }
+    }
+}
+
+/// The network chosen in the form: a radio button `ssid`, or the text field
+/// `other` for networks that did not show up in the scan.
+fn credentials(form: &[u8]) -> Result<Credentials, &'static str> {
+    let other = form_field(form, "other").unwrap_or_default();
+    let ssid = match other {
+        [] => form_field(form, "ssid").unwrap_or_default(),
+        other => other,
+    };
+    let ssid: heapless::String<MAX_SSID_LEN> = url_decode(ssid).ok_or("SSID too long.")?;
+    if ssid.is_empty() {
+        return Err("Pick a network.");
+    }
+    let password = form_field(form, "password").unwrap_or_default();
+    let password: heapless::String<MAX_PASSWORD_LEN> =
+        url_decode(password).ok_or("Password too long.")?;
+
+    Ok(Credentials { ssid, password })
+}
+
+fn form_page(w: &mut dyn Write, networks: &[(&str, i8)]) -> fmt::Result {
+    write!(
+        w,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\">\
+         <title>Wi-Fi setup</title><style>{}</style></head><body>\
+         <h1>Wi-Fi setup</h1><form method=\"post\" action=\"/save\">",
+        STYLE
+    )?;
+    for (i, (ssid, rssi)) in networks.iter().enumerate() {
+        write!(
+            w,
+            "<label><input type=\"radio\" name=\"ssid\" value=\"{ssid}\"{checked}> {ssid} \
+             ({rssi} dBm)</label>",
+            ssid = Html(ssid),
+            checked = if i == 0 { " checked" } else { "" },
+            rssi = rssi,
+        )?;
+    }
+    if networks.is_empty() {
+        w.write_str("<p>No networks found.</p>")?;
+    }
+    w.write_str(
+        "<label>Other network <input type=\"text\" name=\"other\" maxlength=\"32\"></label>\
+         <label>Password <input type=\"password\" name=\"password\" maxlength=\"64\"></label>\
+         <button type=\"submit\">Save</button></form></body></html>",
+    )
+}
+
+fn message_page(w: &mut dyn Write, message: fmt::Arguments<'_>) -> fmt::Result {
+    write!(
+        w,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\">\
+         <title>Wi-Fi setup</title><style>{}</style></head><body><p>{}</p></body></html>",
+        STYLE, message
+    )
+}
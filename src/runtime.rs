@@ -0,0 +1,165 @@
+//! Tasks shared by the async binaries
+//!
+//! The firmware and the ESP-NOW receiver run on the `esp-hal-embassy`
+//! executor, one task per job. The tasks here own a piece of hardware and
+//! only talk to the rest through channels and signals:
+//!
+//! - [`button_task`] sleeps until the next edge of the button, or until the
+//!   driver has a gesture to report, and sends the [`ButtonEvent`]s on.
+//! - [`display_task`] flushes the [`SharedDisplay`] whenever a task drew
+//!   into it. Tasks draw into a frame of their own, which is copied to the
+//!   display's before the transfer, so drawing never waits for the I2C bus
+//!   and the transfer does not block the other tasks.
+//! - [`radio_task`] copies every ESP-NOW frame out of the driver as it
+//!   arrives.
+//!
+//! What the binary does with the events stays in its own tasks.
+
+use core::cell::Cell;
+use embassy_futures::select::select;
+
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Instant, Timer};
+use esp_println::println;
+use esp_wifi::esp_now::EspNowReceiver;
+use hal::gpio::Input;
+
+use crate::{
+    app::{Mac, Received, MAX_PAYLOAD},
+    board::Display,
+    button::{Button, ButtonEvent, EVENT_QUEUE_LEN},
+    framebuffer::{FrameBuffer, FrameStats},
+    VisibleArea,
+};
+
+/// Frames the radio can hear before the task handling them catches up.
+pub const HEARD_QUEUE_LEN: usize = 8;
+
+pub type ButtonEvents = Channel<CriticalSectionRawMutex, ButtonEvent, EVENT_QUEUE_LEN>;
+
+pub type HeardFrames = Channel<CriticalSectionRawMutex, Heard, HEARD_QUEUE_LEN>;
+
+/// Milliseconds since boot, the clock all the tasks and state machines use.
+pub fn now() -> u64 {
+    Instant::now().as_millis()
+}
+
+/// Debounce the button on `pin` and send what it does to `events`.
+///
+/// Events nobody takes are dropped once the channel is full.
+#[embassy_executor::task]
+pub async fn button_task(mut pin: Input<'static>, events: &'static ButtonEvents) {
+    let mut button = Button::default();
+    loop {
+        button.poll(now(), pin.is_low());
+        while let Some(event) = button.pop() {
+            let _ = events.try_send(event);
+        }
+        match button.next_deadline() {
+            Some(at_ms) => {
+                let deadline = Timer::at(Instant::from_millis(at_ms));
+                select(pin.wait_for_any_edge(), deadline).await;
+            }
+            None => pin.wait_for_any_edge().await,
+        }
+    }
+}
+
+/// The frame the tasks draw into, sent by [`display_task`].
+pub struct SharedDisplay {
+    frame: Mutex<CriticalSectionRawMutex, VisibleArea<FrameBuffer>>,
+    dirty: Signal<CriticalSectionRawMutex, ()>,
+    stats: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<FrameStats>>,
+}
+
+impl SharedDisplay {
+    /// Start from what `display` shows; give the display to
+    /// [`display_task`] then.
+    pub fn new(display: &VisibleArea<Display>) -> Self {
+        let mut frame = FrameBuffer::new();
+        frame.copy_frame(display.inner().frame());
+        SharedDisplay {
+            frame: Mutex::new(VisibleArea::with_area(frame, display.area())),
+            dirty: Signal::new(),
+            stats: blocking_mutex::Mutex::new(Cell::new(display.inner().stats())),
+        }
+    }
+
+    /// Draw into the frame with `draw` and have it flushed.
+    pub async fn draw<R>(&self, draw: impl FnOnce(&mut VisibleArea<FrameBuffer>) -> R) -> R {
+        let result = draw(&mut *self.frame.lock().await);
+        self.dirty.signal(());
+        result
+    }
+
+    /// How the flushes went so far.
+    pub fn stats(&self) -> FrameStats {
+        self.stats.lock(Cell::get)
+    }
+}
+
+/// Send the frame of `shared` to `display` after every
+/// [`SharedDisplay::draw`]; draws that happen during a transfer go out with
+/// the next one.
+#[embassy_executor::task]
+pub async fn display_task(shared: &'static SharedDisplay, mut display: Display) {
+    loop {
+        shared.dirty.wait().await;
+        display
+            .frame_mut()
+            .copy_frame(shared.frame.lock().await.inner());
+        if let Err(e) = display.flush_async().await {
+            println!("Error flushing display: {:?}", e);
+        }
+        shared.stats.lock(|stats| stats.set(display.stats()));
+    }
+}
+
+/// An ESP-NOW frame as the radio heard it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heard {
+    /// When it arrived, see [`now`].
+    pub at_ms: u64,
+    pub src: Mac,
+    pub dst: Mac,
+    pub rssi: i8,
+    pub channel: u8,
+    pub data: heapless::Vec<u8, MAX_PAYLOAD>,
+}
+
+impl Heard {
+    /// The frame as the apps take it.
+    pub fn received(&self) -> Received<'_> {
+        Received {
+            src: self.src,
+            dst: self.dst,
+            data: &self.data,
+        }
+    }
+}
+
+/// Send every frame `receiver` gets to `heard`, waiting while it is full.
+#[embassy_executor::task]
+pub async fn radio_task(mut receiver: EspNowReceiver<'static>, heard: &'static HeardFrames) {
+    loop {
+        let r = receiver.receive_async().await;
+        let Ok(data) = heapless::Vec::from_slice(r.get_data()) else {
+            continue;
+        };
+        heard
+            .send(Heard {
+                at_ms: now(),
+                src: r.info.src_address,
+                dst: r.info.dst_address,
+                rssi: r.info.rx_control.rssi as i8,
+                channel: r.info.rx_control.channel as u8,
+                data,
+            })
+            .await;
+    }
+}
@@ -0,0 +1,390 @@
+//! The screens shown by the firmware and the examples
+//!
+//! All of them draw in visible area coordinates, so wrap the display in
+//! [`VisibleArea`](crate::display::VisibleArea) before handing it over.
+//! They are put together from [`widgets`](crate::widgets).
+
+use core::fmt::Write as FmtWrite;
+use embedded_graphics::{
+    image::{Image, ImageRaw},
+    mono_font::ascii::FONT_4X6,
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::Rectangle,
+};
+
+use crate::app::chat::{ChatMessage, MAX_DRAFT_LEN, WHEEL};
+use crate::display::VISIBLE_OFFSET;
+use crate::pairing::Stage;
+use crate::peers::{Peer, MAX_KNOWN_PEERS, MAX_NAME_LEN};
+use crate::protocol::MAX_MESSAGE_LEN;
+use crate::widgets::{
+    icons, screen, split_bottom, split_top, wrap, Align, BigNumber, Label, Overflow, ScrollingList,
+    StatusBar, TextBox, VAlign,
+};
+
+/// Rows of the peer list below its title.
+pub const PEER_ROWS: usize = 5;
+
+/// Rows of the chat history below the peer talked to.
+pub const CHAT_ROWS: usize = 5;
+
+/// Characters of a row in the 4x6 font, a full line of the peer list.
+const SMALL_LINE_LEN: usize = 18;
+
+/// Bytes of a line of the peer list: [`SMALL_LINE_LEN`] characters, with a
+/// name of up to [`MAX_NAME_LEN`] bytes padded to 12 characters.
+const PEER_LINE_LEN: usize = SMALL_LINE_LEN + MAX_NAME_LEN;
+
+/// Height of a row in the 4x6 font.
+const SMALL_ROW_HEIGHT: u32 = 6;
+
+/// Rows of the draft in [`chat_compose`].
+const DRAFT_ROWS: usize = 3;
+
+/// A chat message with the name in front, before wrapping.
+const CHAT_LINE_LEN: usize = MAX_NAME_LEN + 2 + MAX_MESSAGE_LEN;
+
+/// Progress of the Wi-Fi connection, as shown by [`wifi_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiStatus {
+    Starting,
+    /// There are no credentials stored for any network.
+    NotConfigured,
+    Connecting,
+    /// Connecting again after `attempt` failures.
+    Retrying {
+        attempt: u32,
+    },
+    /// Associated, waiting for DHCP.
+    WaitingForIp,
+    Connected {
+        ip: [u8; 4],
+    },
+    /// The connection dropped or an attempt failed, the next one starts soon.
+    Lost {
+        retry_in_s: u32,
+    },
+    /// Too many failed attempts, waiting for the user.
+    Offline,
+}
+
+/// "Counter:" with the current value in big digits below it.
+pub fn counter<D>(display: &mut D, value: u32) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+    let (title, rest) = split_top(screen(), 10);
+    Label::new("Counter:", title).draw(display)?;
+    BigNumber::new(value.into(), rest).draw(display)
+}
+
+/// Connection progress of the Wi-Fi example, with the IP address once connected.
+pub fn wifi_status<D>(display: &mut D, status: WifiStatus) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+    let (title, body) = split_top(screen(), 10);
+    Label::new("WiFi example", title).draw(display)?;
+
+    let mut text: heapless::String<32> = heapless::String::new();
+    // the longest is an IP address after "IP:", so this always fits
+    let _ = match status {
+        WifiStatus::Starting => Ok(()),
+        WifiStatus::NotConfigured => write!(text, "no network configured"),
+        WifiStatus::Connecting => write!(text, "connecting..."),
+        WifiStatus::Retrying { attempt } => write!(text, "connecting...\nretry {}", attempt),
+        WifiStatus::WaitingForIp => write!(text, "waiting for IP..."),
+        WifiStatus::Connected { ip } => {
+            write!(
+                text,
+                "Connected.\nIP: {}.{}.{}.{}",
+                ip[0], ip[1], ip[2], ip[3]
+            )
+        }
+        WifiStatus::Lost { retry_in_s } => write!(text, "lost, retry in {}s", retry_in_s),
+        WifiStatus::Offline => write!(text, "offline, press button"),
+    };
+    TextBox::new(&text, body).draw(display)
+}
+
+/// Name and address of the provisioning access point.
+pub fn provisioning<D>(display: &mut D, ap_ssid: &str, ip: [u8; 4]) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+    StatusBar::new("WiFi setup", screen())
+        .icon(&icons::WIFI)
+        .draw(display)?;
+
+    let mut text: heapless::String<64> = heapless::String::new();
+    // an SSID has at most 32 bytes, so this always fits
+    let _ = write!(text, "{}\n{}.{}.{}.{}", ap_ssid, ip[0], ip[1], ip[2], ip[3]);
+    TextBox::new(&text, StatusBar::below(screen())).draw(display)
+}
+
+/// Where [`text`] puts its text, to tell whether it changes over time.
+pub fn text_box(text: &str) -> TextBox<'_> {
+    TextBox::new(text, screen())
+}
+
+/// Free text, e.g. sent over HTTP, wrapped; the pages of longer text take
+/// turns, going by how long it has been shown.
+pub fn text<D>(display: &mut D, text: &str, elapsed_ms: u64) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+    text_box(text).at(elapsed_ms).draw(display)
+}
+
+/// Where [`esp_now_receiver`] puts a message, to tell whether it changes
+/// over time.
+pub fn message_box(message: &str) -> TextBox<'_> {
+    TextBox::new(message, StatusBar::below(screen())).overflow(Overflow::Marquee)
+}
+
+/// Last message received over ESP-NOW, or that none has arrived yet. A
+/// message that does not fit scrolls, going by how long it has been shown.
+pub fn esp_now_receiver<D>(
+    display: &mut D,
+    message: Option<&str>,
+    elapsed_ms: u64,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+    let icon = match message {
+        Some(_) => &icons::ENVELOPE,
+        None => &icons::ANTENNA,
+    };
+    StatusBar::new("ESP-NOW", screen())
+        .icon(icon)
+        .draw(display)?;
+    match message {
+        Some(message) => message_box(message).at(elapsed_ms).draw(display),
+        None => Label::new("waiting...", StatusBar::below(screen())).draw(display),
+    }
+}
+
+/// Pairing progress: searching, then the code to compare with the other board.
+pub fn pairing<D>(display: &mut D, stage: Stage) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+
+    let (code, hint) = match stage {
+        Stage::Idle => return Ok(()),
+        Stage::Searching => {
+            StatusBar::new("Pairing...", screen())
+                .icon(&icons::LOCK)
+                .draw(display)?;
+            return Label::new("hold the\nother button", StatusBar::below(screen()))
+                .align(Align::Center)
+                .valign(VAlign::Middle)
+                .draw(display);
+        }
+        Stage::Comparing { code, .. } => (code, "click = ok"),
+        Stage::Confirmed { code, .. } => (code, "waiting..."),
+    };
+
+    StatusBar::new("Code", screen())
+        .icon(&icons::LOCK)
+        .draw(display)?;
+    let (hint_area, number) = split_bottom(StatusBar::below(screen()), 10);
+    BigNumber::new(code.into(), number)
+        .digits(4)
+        .draw(display)?;
+    Label::new(hint, hint_area)
+        .align(Align::Center)
+        .draw(display)
+}
+
+/// How many of the `peers` are online, and [`PEER_ROWS`] of them starting at
+/// `first`: paired peers marked with `*`, then name and RSSI, or `off`.
+pub fn peers<D>(display: &mut D, now_ms: u64, peers: &[Peer], first: usize) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+
+    let online = peers.iter().filter(|p| p.is_online(now_ms)).count();
+    let mut title: heapless::String<18> = heapless::String::new();
+    // at most 16 peers, so this always fits
+    let _ = write!(
+        title,
+        "{} peer{} online",
+        online,
+        if online == 1 { "" } else { "s" }
+    );
+    let (title_area, rest) = split_top(screen(), 7);
+    Label::new(&title, title_area)
+        .font(&FONT_4X6)
+        .draw(display)?;
+
+    let mut lines: heapless::Vec<heapless::String<PEER_LINE_LEN>, MAX_KNOWN_PEERS> =
+        heapless::Vec::new();
+    for peer in peers.iter().take(MAX_KNOWN_PEERS) {
+        let mut line = heapless::String::new();
+        let marker = if peer.paired { '*' } else { ' ' };
+        // a marker, 12 characters of name, a space and 4 of RSSI, which fit
+        // in PEER_LINE_LEN bytes whatever the name is made of
+        let _ = match peer.rssi.filter(|_| peer.is_online(now_ms)) {
+            Some(rssi) => write!(line, "{}{:<12} {:>4}", marker, peer.name, rssi),
+            None => write!(line, "{}{:<12}  off", marker, peer.name),
+        };
+        let _ = lines.push(line);
+    }
+    let (list, _) = split_top(rest, 6 * PEER_ROWS as u32);
+    ScrollingList::new(&lines, list).first(first).draw(display)
+}
+
+/// The peer messages go to, or that there is none, and the last
+/// [`CHAT_ROWS`] rows of the `newest_first` messages, wrapped at spaces.
+pub fn chat_history<'a, D>(
+    display: &mut D,
+    to: Option<&str>,
+    newest_first: impl Iterator<Item = &'a ChatMessage>,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+    chat_title(display, to)?;
+
+    // the newest row first, the end of each message below its start
+    let mut rows: heapless::Vec<heapless::String<SMALL_LINE_LEN>, CHAT_ROWS> = heapless::Vec::new();
+    for message in newest_first {
+        let mut line: heapless::String<CHAT_LINE_LEN> = heapless::String::new();
+        let from = if message.outgoing {
+            "me"
+        } else {
+            &message.peer
+        };
+        // the name and the text fit by the size of the string
+        let _ = write!(line, "{}: {}", from, message.text);
+        let free = CHAT_ROWS - rows.len();
+        if free == 0 {
+            break;
+        }
+        let mut message_rows = last_rows(&line, free);
+        message_rows.reverse();
+        rows.extend(message_rows);
+    }
+    rows.reverse();
+
+    // the newest message on the lowest row
+    let (list, _) = split_bottom(chat_rows(), SMALL_ROW_HEIGHT * rows.len() as u32);
+    ScrollingList::new(&rows, list).draw(display)
+}
+
+/// The message being written with a cursor at its end, and the character
+/// wheel at `wheel` in brackets between its neighbours.
+pub fn chat_compose<D>(
+    display: &mut D,
+    to: Option<&str>,
+    draft: &str,
+    wheel: usize,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    display.clear(BinaryColor::Off)?;
+    chat_title(display, to)?;
+    let (draft_area, wheel_area) = split_top(
+        StatusBar::below(screen()),
+        SMALL_ROW_HEIGHT * DRAFT_ROWS as u32,
+    );
+
+    let mut line: heapless::String<{ MAX_DRAFT_LEN + 1 }> = heapless::String::new();
+    // a draft always fits
+    let _ = write!(line, "{}_", draft);
+    let rows = last_rows(&line, DRAFT_ROWS);
+    ScrollingList::new(&rows, draft_area).draw(display)?;
+
+    // four characters on either side, a space shows as `_`
+    let mut strip: heapless::String<11> = heapless::String::new();
+    for offset in 0..9 {
+        let c = WHEEL[(wheel + WHEEL.len() + offset - 4) % WHEEL.len()];
+        let c = if c == b' ' { '_' } else { c as char };
+        let _ = match offset {
+            4 => write!(strip, "[{}]", c),
+            _ => write!(strip, "{}", c),
+        };
+    }
+    Label::new(&strip, wheel_area)
+        .align(Align::Center)
+        .valign(VAlign::Middle)
+        .draw(display)
+}
+
+fn chat_title<D>(display: &mut D, to: Option<&str>) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let mut title: heapless::String<SMALL_LINE_LEN> = heapless::String::new();
+    // names are 12 characters at most
+    let _ = match to {
+        Some(name) => write!(title, "to {}", name),
+        None => write!(title, "no paired peer"),
+    };
+    StatusBar::new(&title, screen())
+        .icon(&icons::ENVELOPE)
+        .draw(display)?;
+    Ok(())
+}
+
+/// The [`CHAT_ROWS`] below the chat title.
+fn chat_rows() -> Rectangle {
+    split_top(
+        StatusBar::below(screen()),
+        SMALL_ROW_HEIGHT * CHAT_ROWS as u32,
+    )
+    .0
+}
+
+/// The last `rows` rows of `text` wrapped for the 4x6 font, at most
+/// [`CHAT_ROWS`].
+fn last_rows(
+    text: &str,
+    rows: usize,
+) -> heapless::Vec<heapless::String<SMALL_LINE_LEN>, CHAT_ROWS> {
+    let rows = rows.min(CHAT_ROWS);
+    let skip = wrap(text, SMALL_LINE_LEN).count().saturating_sub(rows);
+    wrap(text, SMALL_LINE_LEN)
+        .skip(skip)
+        .map(|line| {
+            // lines are counted in characters, the row in bytes
+            let mut row = heapless::String::new();
+            for c in line.chars() {
+                if row.push(c).is_err() {
+                    break;
+                }
+            }
+            row
+        })
+        .collect()
+}
+
+/// Width and height of a [`bitmap`], the whole SH1106 framebuffer.
+pub const BITMAP_SIZE: Size = Size::new(128, 64);
+
+/// Bytes of a [`bitmap`], one bit per pixel.
+pub const BITMAP_LEN: usize = (BITMAP_SIZE.width * BITMAP_SIZE.height / 8) as usize;
+
+/// A framebuffer sized image, as pushed with
+/// [`BlobKind::Bitmap`](crate::protocol::BlobKind::Bitmap): rows of 16 bytes,
+/// the leftmost pixel in the most significant bit. It is placed like the
+/// framebuffer, so only the part behind the glass shows.
+pub fn bitmap<D>(display: &mut D, data: &[u8; BITMAP_LEN]) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let image = ImageRaw::<BinaryColor>::new(data, BITMAP_SIZE.width);
+    Image::new(&image, Point::zero() - VISIBLE_OFFSET).draw(display)
+}
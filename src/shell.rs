@@ -0,0 +1,298 @@
+//! Line based debug shell, served over TCP on [`SHELL_PORT`]
+//!
+//! ```text
+//! $ telnet <ip> 2323
+//! > led on
+//! ok
+//! ```
+//!
+//! [`LineBuffer`] assembles lines from the received bytes, [`parse`] turns
+//! them into a [`Command`] and [`execute`] runs it against a [`Device`], which
+//! the firmware implements on top of the peripherals. Tests implement it with
+//! a fake.
+
+use core::fmt::{self, Write};
+
+use crate::status::DeviceInfo;
+
+/// `telnet` falls back to line mode on ports other than 23, which is what we
+/// want.
+pub const SHELL_PORT: u16 = 2323;
+
+pub const PROMPT: &str = "> ";
+
+/// Longest line accepted, longer input is cut off.
+pub const MAX_LINE_LEN: usize = 128;
+
+pub const HELP: &str = "\
+help          this text
+info          MAC, IP, uptime, heap and signal strength
+heap          heap usage
+wifi scan     list the networks in range
+led on|off    switch the LED
+text <msg>    show <msg> on the display
+reboot        restart the device
+exit          close the connection
+";
+
+/// What the shell needs from the firmware.
+pub trait Device {
+    fn info(&mut self) -> DeviceInfo;
+
+    /// Used and free bytes on the heap.
+    fn heap(&mut self) -> (usize, usize);
+
+    /// Report the networks in range to `found` as `(ssid, signal strength)`,
+    /// `false` if the scan failed.
+    fn scan(&mut self, found: &mut dyn FnMut(&str, i8)) -> bool;
+
+    fn set_led(&mut self, on: bool);
+
+    fn draw_text(&mut self, text: &str);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    Help,
+    Info,
+    Heap,
+    WifiScan,
+    Led(bool),
+    Text(&'a str),
+    Reboot,
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError<'a> {
+    /// No such command.
+    Unknown(&'a str),
+    /// The command is known but the arguments are wrong, with the right usage.
+    Usage(&'static str),
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Unknown(command) => {
+                write!(f, "unknown command '{}', try 'help'", command)
+            }
+            ParseError::Usage(usage) => write!(f, "usage: {}", usage),
+        }
+    }
+}
+
+/// Parse one line, `Ok(None)` for a blank line.
+pub fn parse(line: &str) -> Result<Option<Command<'_>>, ParseError<'_>> {
+    let line = line.trim();
+    let (name, args) = match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim_start()),
+        None => (line, ""),
+    };
+
+    let command = match (name, args) {
+        ("", _) => return Ok(None),
+        ("help" | "?", _) => Command::Help,
+        ("info", "") => Command::Info,
+        ("heap", "") => Command::Heap,
+        ("wifi", "scan") => Command::WifiScan,
+        ("wifi", _) => return Err(ParseError::Usage("wifi scan")),
+        ("led", "on") => Command::Led(true),
+        ("led", "off") => Command::Led(false),
+        ("led", _) => return Err(ParseError::Usage("led on|off")),
+        ("text", "") => return Err(ParseError::Usage("text <msg>")),
+        ("text", text) => Command::Text(text),
+        ("reboot", "") => Command::Reboot,
+        ("exit" | "quit", "") => Command::Exit,
+        ("info" | "heap" | "reboot" | "exit" | "quit", _) => {
+            return Err(ParseError::Usage("no arguments"))
+        }
+        (name, _) => return Err(ParseError::Unknown(name)),
+    };
+    Ok(Some(command))
+}
+
+/// What the firmware has to do after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Continue,
+    /// Send the output, then close the connection.
+    Close,
+    /// Send the output, then restart.
+    Reboot,
+}
+
+/// Run `command`, writing its output to `out`.
+pub fn execute<W: Write + ?Sized>(
+    command: Command<'_>,
+    device: &mut dyn Device,
+    out: &mut W,
+) -> Result<Outcome, fmt::Error> {
+    match command {
+        Command::Help => out.write_str(HELP)?,
+        Command::Info => {
+            let info = device.info();
+            let m = info.mac;
+            writeln!(
+                out,
+                "mac       {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                m[0], m[1], m[2], m[3], m[4], m[5]
+            )?;
+            match info.ip {
+                Some([a, b, c, d]) => writeln!(out, "ip        {}.{}.{}.{}", a, b, c, d)?,
+                None => writeln!(out, "ip        -")?,
+            }
+            writeln!(out, "uptime    {} s", info.uptime_ms / 1000)?;
+            writeln!(out, "free heap {} bytes", info.free_heap)?;
+            match info.rssi {
+                Some(rssi) => writeln!(out, "rssi      {} dBm", rssi)?,
+                None => writeln!(out, "rssi      -")?,
+            }
+        }
+        Command::Heap => {
+            let (used, free) = device.heap();
+            writeln!(out, "used {} bytes, free {} bytes", used, free)?;
+        }
+        Command::WifiScan => {
+            let mut result = Ok(());
+            let scanned = device.scan(&mut |ssid, rssi| {
+                if result.is_ok() {
+                    result = writeln!(out, "{:4} dBm  {}", rssi, ssid);
+                }
+            });
+            result?;
+            if !scanned {
+                writeln!(out, "scan failed")?;
+            }
+        }
+        Command::Led(on) => {
+            device.set_led(on);
+            writeln!(out, "ok")?;
+        }
+        Command::Text(text) => {
+            device.draw_text(text);
+            writeln!(out, "ok")?;
+        }
+        Command::Reboot => {
+            writeln!(out, "rebooting")?;
+            return Ok(Outcome::Reboot);
+        }
+        Command::Exit => {
+            writeln!(out, "bye")?;
+            return Ok(Outcome::Close);
+        }
+    }
+    Ok(Outcome::Continue)
+}
+
+/// Parse and run `line`, reporting parse errors to `out`.
+pub fn run_line<W: Write + ?Sized>(
+    line: &str,
+    device: &mut dyn Device,
+    out: &mut W,
+) -> Result<Outcome, fmt::Error> {
+    match parse(line) {
+        Ok(Some(command)) => execute(command, device, out),
+        Ok(None) => Ok(Outcome::Continue),
+        Err(e) => {
+            writeln!(out, "{}", e)?;
+            Ok(Outcome::Continue)
+        }
+    }
+}
+
+const IAC: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Telnet {
+    Data,
+    /// After IAC.
+    Command,
+    /// After IAC WILL/WONT/DO/DONT, the option byte follows.
+    Option,
+}
+
+/// Collects received bytes into lines.
+///
+/// Accepts `\r`, `\n` and `\r\n` line ends, handles backspace and drops telnet
+/// negotiation as well as anything that is not printable ASCII.
+#[derive(Debug)]
+pub struct LineBuffer {
+    line: heapless::String<MAX_LINE_LEN>,
+    complete: bool,
+    /// The last line ended with `\r`, so a `\n` right after it is no new line.
+    after_cr: bool,
+    telnet: Telnet,
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        LineBuffer {
+            line: heapless::String::new(),
+            complete: false,
+            after_cr: false,
+            telnet: Telnet::Data,
+        }
+    }
+
+    /// Add a byte, returns the line once it is complete.
+    pub fn push(&mut self, byte: u8) -> Option<&str> {
+        if self.complete {
+            self.line.clear();
+            self.complete = false;
+        }
+
+        let after_cr = core::mem::replace(&mut self.after_cr, false);
+        match (self.telnet, byte) {
+            (Telnet::Data, b'\n') if after_cr => (),
+            (Telnet::Data, IAC) => self.telnet = Telnet::Command,
+            (Telnet::Command, 251..=254) => self.telnet = Telnet::Option,
+            (Telnet::Command, _) | (Telnet::Option, _) => self.telnet = Telnet::Data,
+            (Telnet::Data, b'\r' | b'\n') => {
+                self.after_cr = byte == b'\r';
+                self.complete = true;
+                return Some(&self.line);
+            }
+            (Telnet::Data, 0x08 | 0x7f) => {
+                self.line.pop();
+            }
+            (Telnet::Data, b' '..=b'~') => {
+                // too long lines are cut off
+                let _ = self.line.push(byte as char);
+            }
+            (Telnet::Data, _) => (),
+        }
+        None
+    }
+
+    /// Forget a partial line.
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.complete = false;
+        self.after_cr = false;
+        self.telnet = Telnet::Data;
+    }
+}
+
+/// Turns `\n` into `\r\n` on the way to a telnet client.
+pub struct CrLf<W>(pub W);
+
+impl<W: Write> Write for CrLf<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.0.write_str(first)?;
+        }
+        for line in lines {
+            self.0.write_str("\r\n")?;
+            self.0.write_str(line)?;
+        }
+        Ok(())
+    }
+}
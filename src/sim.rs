@@ -0,0 +1,457 @@
+//! Host-side simulators
+//!
+//! [`MockDisplay`] records what would have been sent to the SH1106 in a 128x64
+//! framebuffer, so screens can be rendered and checked without a board.
+//! Framebuffers can be written out as PBM or PNG files and compared against
+//! golden images stored in the repository.
+//!
+//! [`MeshNetwork`] runs a mesh [`Router`] per virtual board and carries their
+//! broadcasts to the boards in range, so routing can be tried on any layout.
+
+use std::{
+    format, fs, io,
+    path::{Path, PathBuf},
+    string::String,
+    vec::Vec,
+};
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use crate::app::Mac;
+use crate::crc::crc32;
+use crate::protocol::{
+    mesh::{MeshConfig, Router},
+    Frame, Message,
+};
+
+/// Width of the SH1106 framebuffer.
+pub const WIDTH: usize = 128;
+
+/// Height of the SH1106 framebuffer.
+pub const HEIGHT: usize = 64;
+
+/// Set this environment variable to rewrite golden images instead of comparing
+/// against them.
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// An in-memory stand-in for the sh1106 `GraphicsMode`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MockDisplay {
+    pixels: [[bool; WIDTH]; HEIGHT],
+}
+
+impl Default for MockDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockDisplay {
+    /// A blank display.
+    pub fn new() -> Self {
+        MockDisplay {
+            pixels: [[false; WIDTH]; HEIGHT],
+        }
+    }
+
+    /// Turn all pixels off, like `GraphicsMode::clear`.
+    pub fn clear_buffer(&mut self) {
+        self.pixels = [[false; WIDTH]; HEIGHT];
+    }
+
+    /// The color of the pixel at `point`, or `None` if it is out of bounds.
+    pub fn pixel(&self, point: Point) -> Option<BinaryColor> {
+        let (x, y) = (
+            usize::try_from(point.x).ok()?,
+            usize::try_from(point.y).ok()?,
+        );
+        let row = self.pixels.get(y)?;
+        row.get(x).map(|&on| BinaryColor::from(on))
+    }
+
+    /// Number of pixels that are on.
+    pub fn lit_pixels(&self) -> usize {
+        self.pixels.iter().flatten().filter(|&&on| on).count()
+    }
+
+    /// Number of pixels that differ from `other`.
+    pub fn diff_pixels(&self, other: &MockDisplay) -> usize {
+        self.pixels
+            .iter()
+            .flatten()
+            .zip(other.pixels.iter().flatten())
+            .filter(|(a, b)| a != b)
+            .count()
+    }
+
+    /// Encode the framebuffer as a plain (ASCII) PBM image, lit pixels are 1.
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut out = format!("P1\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+        for row in &self.pixels {
+            let line: Vec<&str> = row.iter().map(|&on| if on { "1" } else { "0" }).collect();
+            out.extend_from_slice(line.join(" ").as_bytes());
+            out.push(b'\n');
+        }
+        out
+    }
+
+    /// Decode a plain (ASCII) PBM image of exactly 128x64 pixels.
+    pub fn from_pbm(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, String::from(msg));
+
+        let text = core::str::from_utf8(data).map_err(|_| invalid("PBM is not ASCII"))?;
+        let mut tokens = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+
+        if tokens.next() != Some("P1") {
+            return Err(invalid("only plain PBM (P1) is supported"));
+        }
+        let width = tokens.next().and_then(|t| t.parse::<usize>().ok());
+        let height = tokens.next().and_then(|t| t.parse::<usize>().ok());
+        if width != Some(WIDTH) || height != Some(HEIGHT) {
+            return Err(invalid("PBM must be 128x64"));
+        }
+
+        // P1 allows the pixels to be written without any separators
+        let mut bits = tokens.flat_map(|t| t.chars());
+        let mut display = MockDisplay::new();
+        for row in display.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel = match bits.next() {
+                    Some('1') => true,
+                    Some('0') => false,
+                    _ => return Err(invalid("truncated or malformed PBM pixel data")),
+                };
+            }
+        }
+
+        Ok(display)
+    }
+
+    /// Encode the framebuffer as an 8-bit grayscale PNG, lit pixels are white.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(HEIGHT * (WIDTH + 1));
+        for row in &self.pixels {
+            // filter type "None"
+            raw.push(0);
+            raw.extend(row.iter().map(|&on| if on { 0xff } else { 0x00 }));
+        }
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+        // bit depth 8, grayscale, deflate, adaptive filtering, no interlace
+        ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+        png_chunk(&mut out, b"IHDR", &ihdr);
+        png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    pub fn save_pbm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_pbm())
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_png())
+    }
+
+    /// Compare the framebuffer against the golden PBM image at `path`.
+    ///
+    /// If [`UPDATE_GOLDEN_ENV`] is set, it is (re)written instead; without it a
+    /// missing golden image is an error, so a deleted one cannot pass. On a
+    /// mismatch, or if it is missing, the actual framebuffer is saved next to
+    /// it as `<name>.actual.png` and `<name>.actual.pbm`.
+    pub fn compare_golden(&self, path: impl AsRef<Path>) -> Result<(), GoldenMismatch> {
+        let path = path.as_ref();
+
+        if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+            self.save_pbm(path).map_err(GoldenMismatch::Io)?;
+            return Ok(());
+        }
+
+        if !path.exists() {
+            let actual = self.save_actual(path)?;
+            return Err(GoldenMismatch::Missing { actual });
+        }
+
+        let golden = fs::read(path)
+            .and_then(|data| MockDisplay::from_pbm(&data))
+            .map_err(GoldenMismatch::Io)?;
+
+        let differing = self.diff_pixels(&golden);
+        if differing == 0 {
+            return Ok(());
+        }
+
+        let actual = self.save_actual(path)?;
+        Err(GoldenMismatch::Pixels { differing, actual })
+    }
+
+    /// Save the framebuffer next to the golden image at `path`, for a look at
+    /// what was drawn instead; returns where the PNG went.
+    fn save_actual(&self, path: &Path) -> Result<PathBuf, GoldenMismatch> {
+        let actual = path.with_extension("actual.png");
+        self.save_png(&actual).map_err(GoldenMismatch::Io)?;
+        self.save_pbm(path.with_extension("actual.pbm"))
+            .map_err(GoldenMismatch::Io)?;
+        Ok(actual)
+    }
+
+    /// Like [`MockDisplay::compare_golden`], but panics on a mismatch.
+    #[track_caller]
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        if let Err(e) = self.compare_golden(path) {
+            panic!("{} does not match: {:?}", path.display(), e);
+        }
+    }
+}
+
+impl core::fmt::Debug for MockDisplay {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for row in &self.pixels {
+            let line: String = row.iter().map(|&on| if on { '#' } else { '.' }).collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl DrawTarget for MockDisplay {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bb = self.bounding_box();
+
+        pixels
+            .into_iter()
+            .filter(|Pixel(pos, _color)| bb.contains(*pos))
+            .for_each(|Pixel(pos, color)| {
+                self.pixels[pos.y as usize][pos.x as usize] = color.is_on();
+            });
+
+        Ok(())
+    }
+}
+
+impl OriginDimensions for MockDisplay {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+/// Why [`MockDisplay::compare_golden`] failed.
+#[derive(Debug)]
+pub enum GoldenMismatch {
+    /// The golden image could not be read or written.
+    Io(io::Error),
+    /// There is no golden image, run with [`UPDATE_GOLDEN_ENV`] to create it;
+    /// the actual image was written to `actual`.
+    Missing { actual: PathBuf },
+    /// The framebuffer differs, the actual image was written to `actual`.
+    Pixels { differing: usize, actual: PathBuf },
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// zlib stream made of uncompressed deflate blocks, which is all a PNG needs.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xffff;
+
+    let mut out = std::vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// A message a [`MeshNetwork`] board received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeshDelivery {
+    /// Index of the receiving board.
+    pub node: usize,
+    pub origin: Mac,
+    pub id: u16,
+    pub hops: u8,
+    /// The encoded frame.
+    pub data: Vec<u8>,
+}
+
+/// Virtual boards running a mesh [`Router`] each, linked by radio range.
+///
+/// Time moves in steps of one millisecond. Every frame a board sends reaches
+/// the boards linked to it, except for the share [`MeshNetwork::set_loss`]
+/// drops.
+pub struct MeshNetwork {
+    routers: Vec<Router>,
+    /// `links[a][b]`: `b` hears `a`.
+    links: Vec<Vec<bool>>,
+    /// Percent of frames lost on each link.
+    loss: u32,
+    rng: u32,
+    now_ms: u64,
+    transmissions: usize,
+    delivered: Vec<MeshDelivery>,
+}
+
+impl MeshNetwork {
+    /// `count` boards, none in range of another yet.
+    pub fn new(count: usize, config: MeshConfig) -> Self {
+        MeshNetwork {
+            routers: (0..count)
+                .map(|n| Router::new(Self::mac(n), config, n as u32 * 7919 + 1))
+                .collect(),
+            links: std::vec![std::vec![false; count]; count],
+            loss: 0,
+            rng: 0x2545_f491,
+            now_ms: 0,
+            transmissions: 0,
+            delivered: Vec::new(),
+        }
+    }
+
+    /// `count` boards in a row, each in range of its neighbours only.
+    pub fn line(count: usize, config: MeshConfig) -> Self {
+        let mut network = Self::new(count, config);
+        for n in 1..count {
+            network.link(n - 1, n);
+        }
+        network
+    }
+
+    /// The MAC of board `node`.
+    pub fn mac(node: usize) -> Mac {
+        [0x02, 0, 0, 0, (node >> 8) as u8, node as u8]
+    }
+
+    /// Put `a` and `b` in range of each other.
+    pub fn link(&mut self, a: usize, b: usize) {
+        self.links[a][b] = true;
+        self.links[b][a] = true;
+    }
+
+    pub fn unlink(&mut self, a: usize, b: usize) {
+        self.links[a][b] = false;
+        self.links[b][a] = false;
+    }
+
+    pub fn set_loss(&mut self, percent: u32) {
+        self.loss = percent;
+    }
+
+    pub fn router(&self, node: usize) -> &Router {
+        &self.routers[node]
+    }
+
+    pub fn router_mut(&mut self, node: usize) -> &mut Router {
+        &mut self.routers[node]
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// Flood `message` from board `node` to `dst`, returns its id.
+    pub fn send(&mut self, node: usize, dst: Mac, message: Message<'_>) -> u16 {
+        let now_ms = self.now_ms;
+        self.routers[node].send(now_ms, dst, message).unwrap()
+    }
+
+    /// Run for `ms` milliseconds.
+    pub fn run(&mut self, ms: u64) {
+        for _ in 0..ms {
+            self.step();
+        }
+    }
+
+    /// Every board sends what is due, then time moves on by a millisecond.
+    pub fn step(&mut self) {
+        let now_ms = self.now_ms;
+        for from in 0..self.routers.len() {
+            while let Some(outgoing) = self.routers[from].poll_transmit(now_ms) {
+                self.transmissions += 1;
+                for to in 0..self.routers.len() {
+                    if self.links[from][to] && !self.lose() {
+                        self.deliver(to, &outgoing.data);
+                    }
+                }
+            }
+        }
+        self.now_ms += 1;
+    }
+
+    /// Frames sent by all boards so far, first sends and relays.
+    pub fn transmissions(&self) -> usize {
+        self.transmissions
+    }
+
+    /// What reached the boards, in order of arrival.
+    pub fn delivered(&self) -> &[MeshDelivery] {
+        &self.delivered
+    }
+
+    fn deliver(&mut self, node: usize, data: &[u8]) {
+        let Ok(Frame {
+            message: Message::Mesh(mesh),
+            ..
+        }) = Frame::decode(data)
+        else {
+            return;
+        };
+        if let Some(delivered) = self.routers[node].receive(self.now_ms, mesh) {
+            self.delivered.push(MeshDelivery {
+                node,
+                origin: delivered.origin,
+                id: delivered.id,
+                hops: delivered.hops,
+                data: delivered.data.to_vec(),
+            });
+        }
+    }
+
+    fn lose(&mut self) -> bool {
+        if self.loss == 0 {
+            return false;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng % 100 < self.loss
+    }
+}
@@ -0,0 +1,111 @@
+//! HTTP API of the connected firmware on port 80
+//!
+//! - `GET /` or `GET /info`: [`DeviceInfo`] as JSON
+//! - `POST /display`: show the body on the OLED, either as plain text or as
+//!   the `text` field of a form
+//!
+//! ```text
+//! curl http://<ip>/info
+//! curl --data-binary 'Hello!' http://<ip>/display
+//! ```
+
+use core::fmt::{self, Write};
+
+use crate::app::Mac;
+use crate::http::{self, form_field, url_decode, Method, Request, Status};
+
+/// Longest text accepted by `POST /display`.
+pub const MAX_TEXT_LEN: usize = 128;
+
+/// What `GET /info` reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub mac: Mac,
+    pub ip: Option<[u8; 4]>,
+    pub uptime_ms: u64,
+    /// Free bytes on the heap.
+    pub free_heap: usize,
+    /// Signal strength of the access point, in dBm.
+    pub rssi: Option<i8>,
+}
+
+/// What the firmware has to do after answering a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Continue,
+    /// Show this text on the display.
+    DrawText(heapless::String<MAX_TEXT_LEN>),
+}
+
+/// Answer `request` into `response`.
+pub fn handle<W: Write>(
+    request: &Request<'_>,
+    info: &DeviceInfo,
+    response: &mut W,
+) -> Result<Outcome, fmt::Error> {
+    match (request.method, request.path) {
+        (Method::Get, "/" | "/info") => {
+            http::write_response_with(response, Status::Ok, "application/json", |w| {
+                write_info(w, info)
+            })?;
+            Ok(Outcome::Continue)
+        }
+        (Method::Post, "/display") => match text(request) {
+            Some(text) => {
+                http::write_response(response, Status::Ok, "application/json", "{\"ok\":true}")?;
+                Ok(Outcome::DrawText(text))
+            }
+            None => {
+                http::write_response(
+                    response,
+                    Status::BadRequest,
+                    "application/json",
+                    "{\"ok\":false,\"error\":\"text must be UTF-8 of at most 128 bytes\"}",
+                )?;
+                Ok(Outcome::Continue)
+            }
+        },
+        (_, "/" | "/info" | "/display") => {
+            http::write_response(response, Status::MethodNotAllowed, "text/plain", "")?;
+            Ok(Outcome::Continue)
+        }
+        _ => {
+            http::write_response(response, Status::NotFound, "text/plain", "")?;
+            Ok(Outcome::Continue)
+        }
+    }
+}
+
+fn text(request: &Request<'_>) -> Option<heapless::String<MAX_TEXT_LEN>> {
+    let is_form = request
+        .header("Content-Type")
+        .is_some_and(|t| t.starts_with("application/x-www-form-urlencoded"));
+    if is_form {
+        url_decode(form_field(request.body, "text")?)
+    } else {
+        let text = core::str::from_utf8(request.body).ok()?;
+        text.try_into().ok()
+    }
+}
+
+fn write_info(w: &mut dyn Write, info: &DeviceInfo) -> fmt::Result {
+    let m = info.mac;
+    write!(
+        w,
+        "{{\"mac\":\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\",",
+        m[0], m[1], m[2], m[3], m[4], m[5]
+    )?;
+    match info.ip {
+        Some([a, b, c, d]) => write!(w, "\"ip\":\"{}.{}.{}.{}\",", a, b, c, d)?,
+        None => w.write_str("\"ip\":null,")?,
+    }
+    write!(
+        w,
+        "\"uptime_ms\":{},\"free_heap\":{},",
+        info.uptime_ms, info.free_heap
+    )?;
+    match info.rssi {
+        Some(rssi) => write!(w, "\"rssi\":{}}}", rssi),
+        None => w.write_str("\"rssi\":null}"),
+    }
+}
@@ -0,0 +1,235 @@
+//! Keeps the Wi-Fi station connected
+//!
+//! [`WifiSupervisor`] watches the link and the IP address and tells the
+//! firmware when to (re)connect:
+//!
+//! ```text
+//! Idle -> Connecting -> Dhcp -> Up
+//!             ^          |      |
+//!             |          v      v
+//!             +--------- Lost <-+
+//! ```
+//!
+//! Failed attempts are retried after an exponentially growing delay. After
+//! [`SupervisorConfig::max_retries`] failures in a row it gives up and waits
+//! for [`WifiSupervisor::start`].
+
+use crate::screens::WifiStatus;
+
+/// Timings and limits of the supervisor, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SupervisorConfig {
+    /// Give up on an association attempt after this long.
+    pub connect_timeout_ms: u32,
+    /// Give up waiting for a DHCP lease after this long.
+    pub dhcp_timeout_ms: u32,
+    /// Delay before the first retry, doubled on every further failure.
+    pub initial_backoff_ms: u32,
+    /// Upper limit of the delay between retries.
+    pub max_backoff_ms: u32,
+    /// Failed attempts in a row before giving up.
+    pub max_retries: u32,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        SupervisorConfig {
+            connect_timeout_ms: 15_000,
+            dhcp_timeout_ms: 20_000,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 300_000,
+            max_retries: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiState {
+    /// Not started yet, or given up after too many failures.
+    Idle {
+        gave_up: bool,
+    },
+    /// Waiting for the association, `attempt` counts from 0.
+    Connecting {
+        attempt: u32,
+        since_ms: u64,
+    },
+    /// Associated, waiting for an address.
+    Dhcp {
+        attempt: u32,
+        since_ms: u64,
+    },
+    Up {
+        ip: [u8; 4],
+    },
+    /// Waiting to retry after a failed attempt or a dropped connection.
+    Lost {
+        attempt: u32,
+        retry_at_ms: u64,
+    },
+}
+
+/// What the firmware observed since the last [`WifiSupervisor::update`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Link {
+    /// The station is associated; `false` if asking the driver failed.
+    pub connected: bool,
+    /// The interface has an address.
+    pub ip: Option<[u8; 4]>,
+}
+
+/// What the firmware has to do with the Wi-Fi controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Connect,
+    Disconnect,
+}
+
+#[derive(Debug)]
+pub struct WifiSupervisor {
+    config: SupervisorConfig,
+    state: WifiState,
+}
+
+impl Default for WifiSupervisor {
+    fn default() -> Self {
+        Self::new(SupervisorConfig::default())
+    }
+}
+
+impl WifiSupervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        WifiSupervisor {
+            config,
+            state: WifiState::Idle { gave_up: false },
+        }
+    }
+
+    pub fn state(&self) -> WifiState {
+        self.state
+    }
+
+    /// Start connecting, also after giving up. Does nothing while the
+    /// supervisor is already running.
+    pub fn start(&mut self, now_ms: u64) -> Option<Command> {
+        match self.state {
+            WifiState::Idle { .. } => {
+                self.state = WifiState::Connecting {
+                    attempt: 0,
+                    since_ms: now_ms,
+                };
+                Some(Command::Connect)
+            }
+            _ => None,
+        }
+    }
+
+    /// Advance to `now_ms` with the current state of the `link`.
+    pub fn update(&mut self, now_ms: u64, link: Link) -> Option<Command> {
+        match self.state {
+            WifiState::Idle { .. } => None,
+            WifiState::Connecting { attempt, since_ms } => {
+                if link.connected {
+                    self.state = WifiState::Dhcp {
+                        attempt,
+                        since_ms: now_ms,
+                    };
+                    self.update(now_ms, link)
+                } else if elapsed(now_ms, since_ms) >= self.config.connect_timeout_ms {
+                    self.failed(now_ms, attempt)
+                } else {
+                    None
+                }
+            }
+            WifiState::Dhcp { attempt, since_ms } => match link {
+                Link {
+                    connected: true,
+                    ip: Some(ip),
+                } => {
+                    self.state = WifiState::Up { ip };
+                    None
+                }
+                Link {
+                    connected: false, ..
+                } => self.failed(now_ms, attempt),
+                _ if elapsed(now_ms, since_ms) >= self.config.dhcp_timeout_ms => {
+                    self.failed(now_ms, attempt)
+                }
+                _ => None,
+            },
+            WifiState::Up { .. } => match link {
+                Link {
+                    connected: true,
+                    ip: Some(new_ip),
+                } => {
+                    // a renewed lease may come with another address
+                    self.state = WifiState::Up { ip: new_ip };
+                    None
+                }
+                // the first retry after a dropout does not wait
+                _ => {
+                    self.state = WifiState::Lost {
+                        attempt: 0,
+                        retry_at_ms: now_ms,
+                    };
+                    Some(Command::Disconnect)
+                }
+            },
+            WifiState::Lost {
+                attempt,
+                retry_at_ms,
+            } => {
+                if now_ms >= retry_at_ms {
+                    self.state = WifiState::Connecting {
+                        attempt,
+                        since_ms: now_ms,
+                    };
+                    Some(Command::Connect)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// What to show on the display.
+    pub fn status(&self, now_ms: u64) -> WifiStatus {
+        match self.state {
+            WifiState::Idle { gave_up: false } => WifiStatus::Starting,
+            WifiState::Idle { gave_up: true } => WifiStatus::Offline,
+            WifiState::Connecting { attempt: 0, .. } => WifiStatus::Connecting,
+            WifiState::Connecting { attempt, .. } => WifiStatus::Retrying { attempt },
+            WifiState::Dhcp { .. } => WifiStatus::WaitingForIp,
+            WifiState::Up { ip } => WifiStatus::Connected { ip },
+            WifiState::Lost { retry_at_ms, .. } => WifiStatus::Lost {
+                retry_in_s: retry_at_ms.saturating_sub(now_ms).div_ceil(1000) as u32,
+            },
+        }
+    }
+
+    /// Delay before retry number `attempt` (counting from 1).
+    pub fn backoff_ms(&self, attempt: u32) -> u32 {
+        let shift = attempt.saturating_sub(1).min(31);
+        self.config
+            .initial_backoff_ms
+            .saturating_mul(1 << shift)
+            .min(self.config.max_backoff_ms)
+    }
+
+    fn failed(&mut self, now_ms: u64, attempt: u32) -> Option<Command> {
+        let attempt = attempt + 1;
+        self.state = if attempt > self.config.max_retries {
+            WifiState::Idle { gave_up: true }
+        } else {
+            WifiState::Lost {
+                attempt,
+                retry_at_ms: now_ms + self.backoff_ms(attempt) as u64,
+            }
+        };
+        Some(Command::Disconnect)
+    }
+}
+
+fn elapsed(now_ms: u64, since_ms: u64) -> u32 {
+    now_ms.saturating_sub(since_ms).min(u32::MAX as u64) as u32
+}
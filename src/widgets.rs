@@ -0,0 +1,874 @@
+//! Widgets that lay themselves out inside a rectangle of the visible area
+//!
+//! A widget is built with the area it may use and what it shows, then drawn
+//! like any other [`Drawable`]. Nothing is drawn outside the area: text
+//! that is too wide ends in [`ELLIPSIS`], lines that do not fit below each
+//! other are left out. [`screen`] and the `split_*` functions cut the
+//! visible area into the rectangles for the widgets:
+//!
+//! ```ignore
+//! let (title, rest) = split_top(screen(), 10);
+//! Label::new("Counter:", title).draw(display)?;
+//! BigNumber::new(42, rest).draw(display)?;
+//! ```
+//!
+//! Text of any length goes into a [`TextBox`] instead, which wraps it at
+//! spaces in the largest of the [`FONTS`] it fits in, and pages or scrolls
+//! through what does not fit even in the smallest.
+
+use core::fmt::Write as _;
+
+use embedded_graphics::{
+    image::{Image, ImageRaw},
+    mono_font::{
+        ascii::{FONT_10X20, FONT_4X6, FONT_5X8, FONT_6X10, FONT_7X13, FONT_9X15},
+        MonoFont, MonoTextStyle,
+    },
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+
+use crate::display::VISIBLE_SIZE;
+
+/// Ends text that was cut off.
+pub const ELLIPSIS: &str = "...";
+
+/// Icons shown by the [`StatusBar`] at most.
+pub const MAX_STATUS_ICONS: usize = 4;
+
+/// The fonts a [`TextBox`] picks from, largest first.
+pub const FONTS: [&MonoFont<'static>; 6] = [
+    &FONT_10X20,
+    &FONT_9X15,
+    &FONT_7X13,
+    &FONT_6X10,
+    &FONT_5X8,
+    &FONT_4X6,
+];
+
+/// How long a [`TextBox`] shows each of its pages.
+pub const PAGE_MS: u64 = 3_000;
+
+/// A [`TextBox`] marquee moves up a pixel this often...
+pub const MARQUEE_STEP_MS: u64 = 50;
+
+/// ...after it rested with the start of the text at the top for this long.
+pub const MARQUEE_PAUSE_MS: u64 = 1_500;
+
+/// The whole visible area, in its own coordinates.
+pub fn screen() -> Rectangle {
+    Rectangle::new(Point::zero(), VISIBLE_SIZE)
+}
+
+/// The top `height` rows of `area`, and the rest.
+pub fn split_top(area: Rectangle, height: u32) -> (Rectangle, Rectangle) {
+    let height = height.min(area.size.height);
+    (
+        Rectangle::new(area.top_left, Size::new(area.size.width, height)),
+        Rectangle::new(
+            area.top_left + Point::new(0, height as i32),
+            Size::new(area.size.width, area.size.height - height),
+        ),
+    )
+}
+
+/// The bottom `height` rows of `area`, and the rest above them.
+pub fn split_bottom(area: Rectangle, height: u32) -> (Rectangle, Rectangle) {
+    let (rest, bottom) = split_top(area, area.size.height.saturating_sub(height));
+    (bottom, rest)
+}
+
+/// The left `width` columns of `area`, and the rest.
+pub fn split_left(area: Rectangle, width: u32) -> (Rectangle, Rectangle) {
+    let width = width.min(area.size.width);
+    (
+        Rectangle::new(area.top_left, Size::new(width, area.size.height)),
+        Rectangle::new(
+            area.top_left + Point::new(width as i32, 0),
+            Size::new(area.size.width - width, area.size.height),
+        ),
+    )
+}
+
+/// Where a line goes between the sides of its area.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Where the lines go between the top and the bottom of their area.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VAlign {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// Pixels from one character of `font` to the next.
+fn advance(font: &MonoFont<'_>) -> u32 {
+    font.character_size.width + font.character_spacing
+}
+
+/// Width of `chars` characters of `font`.
+pub fn text_width(font: &MonoFont<'_>, chars: usize) -> u32 {
+    (chars as u32 * advance(font)).saturating_sub(font.character_spacing)
+}
+
+/// Characters of `font` that fit into `width` pixels.
+pub fn chars_that_fit(font: &MonoFont<'_>, width: u32) -> usize {
+    ((width + font.character_spacing) / advance(font)) as usize
+}
+
+/// `line` cut to at most `max` characters; cut lines end in [`ELLIPSIS`],
+/// which is returned separately as it is not part of `line`.
+pub fn ellipsize(line: &str, max: usize) -> (&str, &'static str) {
+    if line.chars().count() <= max {
+        return (line, "");
+    }
+    let (keep, ellipsis) = match max.checked_sub(ELLIPSIS.len()) {
+        Some(keep) if keep > 0 => (keep, ELLIPSIS),
+        // too narrow for the ellipsis to say anything
+        _ => (max, ""),
+    };
+    let end = line.char_indices().nth(keep).map_or(line.len(), |(i, _)| i);
+    (&line[..end], ellipsis)
+}
+
+/// One or more lines of text, split at `\n`.
+#[derive(Debug, Clone, Copy)]
+pub struct Label<'a> {
+    text: &'a str,
+    area: Rectangle,
+    font: &'a MonoFont<'a>,
+    align: Align,
+    valign: VAlign,
+    inverted: bool,
+}
+
+impl<'a> Label<'a> {
+    /// `text` in the 6x10 font, from the top left of `area`.
+    pub fn new(text: &'a str, area: Rectangle) -> Self {
+        Label {
+            text,
+            area,
+            font: &FONT_6X10,
+            align: Align::Left,
+            valign: VAlign::Top,
+            inverted: false,
+        }
+    }
+
+    pub fn font(self, font: &'a MonoFont<'a>) -> Self {
+        Label { font, ..self }
+    }
+
+    pub fn align(self, align: Align) -> Self {
+        Label { align, ..self }
+    }
+
+    pub fn valign(self, valign: VAlign) -> Self {
+        Label { valign, ..self }
+    }
+
+    /// Dark text on a lit area.
+    pub fn inverted(self) -> Self {
+        Label {
+            inverted: true,
+            ..self
+        }
+    }
+
+    /// Lines that fit below each other.
+    pub fn rows(&self) -> usize {
+        (self.area.size.height / self.font.character_size.height) as usize
+    }
+}
+
+impl Drawable for Label<'_> {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let mut target = target.clipped(&self.area);
+        let color = BinaryColor::from(!self.inverted);
+        if self.inverted {
+            self.area
+                .into_styled(PrimitiveStyle::with_fill(color.invert()))
+                .draw(&mut target)?;
+        }
+        let style = MonoTextStyle::new(self.font, color);
+        let line_height = self.font.character_size.height;
+
+        let lines = self.text.split('\n').count().min(self.rows());
+        let spare = self.area.size.height - lines as u32 * line_height;
+        let top = match self.valign {
+            VAlign::Top => 0,
+            VAlign::Middle => spare / 2,
+            VAlign::Bottom => spare,
+        };
+
+        let max = chars_that_fit(self.font, self.area.size.width);
+        for (row, line) in self.text.split('\n').take(lines).enumerate() {
+            let (line, ellipsis) = ellipsize(line, max);
+            let width = text_width(self.font, line.chars().count() + ellipsis.len());
+            let spare = self.area.size.width.saturating_sub(width);
+            let left = match self.align {
+                Align::Left => 0,
+                Align::Center => spare / 2,
+                Align::Right => spare,
+            };
+            let at = self.area.top_left
+                + Point::new(left as i32, (top + row as u32 * line_height) as i32);
+            let next = Text::with_baseline(line, at, style, Baseline::Top).draw(&mut target)?;
+            Text::with_baseline(ellipsis, next, style, Baseline::Top).draw(&mut target)?;
+        }
+        Ok(())
+    }
+}
+
+/// The lines of `text` wrapped to at most `max` characters, see [`Wrap`].
+pub fn wrap(text: &str, max: usize) -> Wrap<'_> {
+    Wrap {
+        rest: Some(text),
+        max: max.max(1),
+        cut_words: false,
+    }
+}
+
+/// The lines of a text, broken at `\n` and at the last space before they
+/// get too long. The spaces a line is broken at are left out; a word longer
+/// than a whole line is cut.
+#[derive(Debug, Clone)]
+pub struct Wrap<'a> {
+    rest: Option<&'a str>,
+    max: usize,
+    cut_words: bool,
+}
+
+impl Wrap<'_> {
+    /// Whether a word had to be cut so far.
+    pub fn cut_words(&self) -> bool {
+        self.cut_words
+    }
+}
+
+impl<'a> Iterator for Wrap<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest?;
+        let (paragraph, after) = match rest.split_once('\n') {
+            Some((paragraph, after)) => (paragraph, Some(after)),
+            None => (rest, None),
+        };
+        // the first character that does not fit any more
+        let Some((cut, _)) = paragraph.char_indices().nth(self.max) else {
+            self.rest = after;
+            return Some(paragraph);
+        };
+        let space = if paragraph[cut..].starts_with(' ') {
+            Some(cut)
+        } else {
+            paragraph[..cut].rfind(' ')
+        };
+        let (line, next) = match space.filter(|&i| !paragraph[..i].trim_end().is_empty()) {
+            Some(i) => {
+                // the break replaces the spaces, and a `\n` right after them
+                let next = rest[i..].trim_start_matches(' ');
+                (
+                    paragraph[..i].trim_end(),
+                    next.strip_prefix('\n').unwrap_or(next),
+                )
+            }
+            None => {
+                self.cut_words = true;
+                (&paragraph[..cut], &rest[cut..])
+            }
+        };
+        self.rest = Some(next).filter(|next| !next.is_empty());
+        Some(line)
+    }
+}
+
+/// What a [`TextBox`] does with lines that do not fit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Show as many lines as fit at a time, for [`PAGE_MS`] each.
+    #[default]
+    Page,
+    /// Move the lines up a pixel at a time, the start following the end.
+    Marquee,
+}
+
+/// The font a [`TextBox`] picked, and how its text wraps in it.
+#[derive(Debug, Clone, Copy)]
+pub struct TextLayout<'a> {
+    pub font: &'a MonoFont<'a>,
+    /// Characters of a line.
+    pub chars: usize,
+    /// Lines shown at once.
+    pub rows: usize,
+    /// Lines of the wrapped text.
+    pub lines: usize,
+}
+
+impl TextLayout<'_> {
+    pub fn overflows(&self) -> bool {
+        self.lines > self.rows
+    }
+
+    /// Pages of [`TextLayout::rows`] lines.
+    pub fn pages(&self) -> usize {
+        self.lines.div_ceil(self.rows.max(1)).max(1)
+    }
+}
+
+/// Text wrapped into its area in the largest font it fits in.
+///
+/// A font fits if the lines fit below each other without cutting a word,
+/// or, failing that, with cut words. Text that does not fit even in the
+/// smallest font overflows: it is shown a page at a time or as a marquee,
+/// going by the time since it is shown that is given to [`TextBox::at`].
+#[derive(Debug, Clone, Copy)]
+pub struct TextBox<'a> {
+    text: &'a str,
+    area: Rectangle,
+    fonts: &'a [&'a MonoFont<'a>],
+    align: Align,
+    valign: VAlign,
+    overflow: Overflow,
+    elapsed_ms: u64,
+}
+
+impl<'a> TextBox<'a> {
+    /// `text` in the largest of the [`FONTS`] that fits, paged.
+    pub fn new(text: &'a str, area: Rectangle) -> Self {
+        TextBox {
+            text,
+            area,
+            fonts: &FONTS,
+            align: Align::Left,
+            valign: VAlign::Top,
+            overflow: Overflow::Page,
+            elapsed_ms: 0,
+        }
+    }
+
+    /// Pick from `fonts` instead, largest first.
+    pub fn fonts(self, fonts: &'a [&'a MonoFont<'a>]) -> Self {
+        TextBox { fonts, ..self }
+    }
+
+    pub fn align(self, align: Align) -> Self {
+        TextBox { align, ..self }
+    }
+
+    /// Where the lines go if they fit; overflowing text starts at the top.
+    pub fn valign(self, valign: VAlign) -> Self {
+        TextBox { valign, ..self }
+    }
+
+    pub fn overflow(self, overflow: Overflow) -> Self {
+        TextBox { overflow, ..self }
+    }
+
+    /// The text has been shown for `elapsed_ms`.
+    pub fn at(self, elapsed_ms: u64) -> Self {
+        TextBox { elapsed_ms, ..self }
+    }
+
+    pub fn layout(&self) -> TextLayout<'a> {
+        let (width, height) = (self.area.size.width, self.area.size.height);
+        let mut cut_words = None;
+        let mut smallest = None;
+        for &font in self.fonts {
+            let chars = chars_that_fit(font, width);
+            let rows = (height / font.character_size.height) as usize;
+            if chars == 0 || rows == 0 {
+                continue;
+            }
+            let mut lines = wrap(self.text, chars);
+            let layout = TextLayout {
+                font,
+                chars,
+                rows,
+                lines: lines.by_ref().count(),
+            };
+            if !layout.overflows() {
+                if !lines.cut_words() {
+                    return layout;
+                }
+                cut_words = cut_words.or(Some(layout));
+            }
+            smallest = Some(layout);
+        }
+        // too small for any of the fonts, nothing is shown
+        cut_words.or(smallest).unwrap_or(TextLayout {
+            font: &FONT_4X6,
+            chars: 0,
+            rows: 0,
+            lines: 0,
+        })
+    }
+
+    /// The page, or how many pixels the marquee moved, at the time given to
+    /// [`TextBox::at`]; the text looks the same as long as this does.
+    pub fn position(&self) -> u64 {
+        let layout = self.layout();
+        if !layout.overflows() {
+            return 0;
+        }
+        match self.overflow {
+            Overflow::Page => self.elapsed_ms / PAGE_MS % layout.pages() as u64,
+            Overflow::Marquee => {
+                let distance = Self::marquee_distance(&layout) as u64;
+                let cycle = MARQUEE_PAUSE_MS + distance * MARQUEE_STEP_MS;
+                (self.elapsed_ms % cycle).saturating_sub(MARQUEE_PAUSE_MS) / MARQUEE_STEP_MS
+            }
+        }
+    }
+
+    /// Pixels from the start of the text to the start of the copy that
+    /// follows its end, a line further down.
+    fn marquee_distance(layout: &TextLayout<'_>) -> u32 {
+        (layout.lines as u32 + 1) * layout.font.character_size.height
+    }
+
+    /// Draw `line` with its top `y` pixels below the top of the area.
+    fn draw_line<D>(
+        &self,
+        target: &mut D,
+        font: &MonoFont<'_>,
+        line: &str,
+        y: i32,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let width = text_width(font, line.chars().count());
+        let spare = self.area.size.width.saturating_sub(width);
+        let left = match self.align {
+            Align::Left => 0,
+            Align::Center => spare / 2,
+            Align::Right => spare,
+        };
+        let style = MonoTextStyle::new(font, BinaryColor::On);
+        let at = self.area.top_left + Point::new(left as i32, y);
+        Text::with_baseline(line, at, style, Baseline::Top).draw(target)?;
+        Ok(())
+    }
+}
+
+impl Drawable for TextBox<'_> {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let mut target = target.clipped(&self.area);
+        let layout = self.layout();
+        let line_height = layout.font.character_size.height as i32;
+        let lines = wrap(self.text, layout.chars);
+
+        if !layout.overflows() {
+            let spare = self.area.size.height as i32 - layout.lines as i32 * line_height;
+            let top = match self.valign {
+                VAlign::Top => 0,
+                VAlign::Middle => spare / 2,
+                VAlign::Bottom => spare,
+            };
+            for (row, line) in lines.enumerate() {
+                self.draw_line(
+                    &mut target,
+                    layout.font,
+                    line,
+                    top + row as i32 * line_height,
+                )?;
+            }
+            return Ok(());
+        }
+
+        match self.overflow {
+            Overflow::Page => {
+                let first = self.position() as usize * layout.rows;
+                for (row, line) in lines.skip(first).take(layout.rows).enumerate() {
+                    self.draw_line(&mut target, layout.font, line, row as i32 * line_height)?;
+                }
+            }
+            Overflow::Marquee => {
+                let distance = Self::marquee_distance(&layout) as i32;
+                let offset = self.position() as i32;
+                let height = self.area.size.height as i32;
+                for (row, line) in lines.enumerate() {
+                    // the line, and its copy below the end of the text
+                    for y in [0, distance].map(|copy| copy + row as i32 * line_height - offset) {
+                        if y + line_height > 0 && y < height {
+                            self.draw_line(&mut target, layout.font, line, y)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A number as large as fits, centered in its area.
+#[derive(Debug, Clone, Copy)]
+pub struct BigNumber {
+    value: i64,
+    area: Rectangle,
+    digits: usize,
+    align: Align,
+}
+
+impl BigNumber {
+    /// Fonts tried, largest first.
+    const FONTS: [&'static MonoFont<'static>; 3] = [&FONT_10X20, &FONT_6X10, &FONT_4X6];
+
+    pub fn new(value: i64, area: Rectangle) -> Self {
+        BigNumber {
+            value,
+            area,
+            digits: 1,
+            align: Align::Center,
+        }
+    }
+
+    /// Pad with zeros to at least `digits` digits.
+    pub fn digits(self, digits: usize) -> Self {
+        BigNumber { digits, ..self }
+    }
+
+    pub fn align(self, align: Align) -> Self {
+        BigNumber { align, ..self }
+    }
+}
+
+impl Drawable for BigNumber {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let mut text: heapless::String<24> = heapless::String::new();
+        // an i64 has at most 19 digits and a sign, the padding is cut short
+        let _ = write!(text, "{:01$}", self.value, self.digits.min(20));
+        let font = Self::FONTS
+            .into_iter()
+            .find(|font| {
+                text_width(font, text.len()) <= self.area.size.width
+                    && font.character_size.height <= self.area.size.height
+            })
+            .unwrap_or(&FONT_4X6);
+        Label::new(&text, self.area)
+            .font(font)
+            .align(self.align)
+            .valign(VAlign::Middle)
+            .draw(target)
+    }
+}
+
+/// A bar filled to `value` of `max`, with an outline.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressBar {
+    area: Rectangle,
+    value: u32,
+    max: u32,
+}
+
+impl ProgressBar {
+    pub fn new(area: Rectangle, value: u32, max: u32) -> Self {
+        ProgressBar { area, value, max }
+    }
+}
+
+impl Drawable for ProgressBar {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let mut target = target.clipped(&self.area);
+        self.area
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(&mut target)?;
+        let inner = self.area.offset(-1);
+        let filled = match self.max {
+            0 => 0,
+            max => (inner.size.width as u64 * self.value.min(max) as u64 / max as u64) as u32,
+        };
+        Rectangle::new(inner.top_left, Size::new(filled, inner.size.height))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(&mut target)
+    }
+}
+
+/// A small monochrome picture: rows of bytes, the leftmost pixel in the
+/// most significant bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IconData {
+    pub width: u32,
+    pub data: &'static [u8],
+}
+
+impl IconData {
+    pub fn size(&self) -> Size {
+        let row_len = self.width.div_ceil(8);
+        Size::new(self.width, self.data.len() as u32 / row_len)
+    }
+}
+
+/// Icons of 7x7 pixels, for the [`StatusBar`] and next to labels.
+pub mod icons {
+    use super::IconData;
+
+    pub const WIFI: IconData = IconData {
+        width: 7,
+        data: &[0x7c, 0x82, 0x38, 0x44, 0x00, 0x10, 0x00],
+    };
+
+    pub const ANTENNA: IconData = IconData {
+        width: 7,
+        data: &[0x92, 0x54, 0x38, 0x10, 0x10, 0x10, 0x38],
+    };
+
+    pub const LOCK: IconData = IconData {
+        width: 7,
+        data: &[0x38, 0x44, 0x44, 0xfe, 0xee, 0xee, 0xfe],
+    };
+
+    pub const ENVELOPE: IconData = IconData {
+        width: 7,
+        data: &[0xfe, 0xc6, 0xaa, 0x92, 0x82, 0xfe, 0x00],
+    };
+
+    pub const PEER: IconData = IconData {
+        width: 7,
+        data: &[0x38, 0x38, 0x00, 0x7c, 0xfe, 0xfe, 0x00],
+    };
+}
+
+/// An [`IconData`] with its top left corner at a point.
+#[derive(Debug, Clone, Copy)]
+pub struct Icon<'a> {
+    icon: &'a IconData,
+    top_left: Point,
+}
+
+impl<'a> Icon<'a> {
+    pub fn new(icon: &'a IconData, top_left: Point) -> Self {
+        Icon { icon, top_left }
+    }
+}
+
+impl Drawable for Icon<'_> {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let raw = ImageRaw::<BinaryColor>::new(self.icon.data, self.icon.width);
+        Image::new(&raw, self.top_left).draw(target)
+    }
+}
+
+/// Items below each other, one per row, starting at the `first`; the
+/// `selected` one is inverted. With a scrollbar on the right, if asked for,
+/// when there are more items than rows.
+#[derive(Debug, Clone, Copy)]
+pub struct ScrollingList<'a, T> {
+    items: &'a [T],
+    area: Rectangle,
+    font: &'a MonoFont<'a>,
+    first: usize,
+    selected: Option<usize>,
+    scrollbar: bool,
+}
+
+impl<'a, T: AsRef<str>> ScrollingList<'a, T> {
+    /// `items` in the 4x6 font, from the first one.
+    pub fn new(items: &'a [T], area: Rectangle) -> Self {
+        ScrollingList {
+            items,
+            area,
+            font: &FONT_4X6,
+            first: 0,
+            selected: None,
+            scrollbar: false,
+        }
+    }
+
+    pub fn font(self, font: &'a MonoFont<'a>) -> Self {
+        ScrollingList { font, ..self }
+    }
+
+    pub fn first(self, first: usize) -> Self {
+        ScrollingList { first, ..self }
+    }
+
+    /// Mark `selected`, scrolling just as far as needed to show it.
+    pub fn selected(self, selected: usize) -> Self {
+        let rows = self.rows().max(1);
+        let first = self
+            .first
+            .min(selected)
+            .max((selected + 1).saturating_sub(rows));
+        ScrollingList {
+            selected: Some(selected),
+            first,
+            ..self
+        }
+    }
+
+    pub fn scrollbar(self) -> Self {
+        ScrollingList {
+            scrollbar: true,
+            ..self
+        }
+    }
+
+    /// Items shown at once.
+    pub fn rows(&self) -> usize {
+        (self.area.size.height / self.font.character_size.height) as usize
+    }
+}
+
+impl<T: AsRef<str>> Drawable for ScrollingList<'_, T> {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let mut target = target.clipped(&self.area);
+        let rows = self.rows();
+        let mut area = self.area;
+        if self.scrollbar && self.items.len() > rows {
+            let (rest, bar) = split_left(area, area.size.width.saturating_sub(2));
+            area = rest;
+            // the thumb is as long and as far down as the rows shown
+            let (len, height) = (self.items.len() as u32, bar.size.height);
+            // and ends at the bottom of the bar at the latest
+            let top = (height * self.first.min(self.items.len()) as u32 / len)
+                .min(height.saturating_sub(1));
+            let thumb = (height * rows as u32 / len).max(1).min(height - top);
+            let x = bar.top_left.x + 1;
+            Line::new(
+                Point::new(x, bar.top_left.y + top as i32),
+                Point::new(x, bar.top_left.y + (top + thumb) as i32 - 1),
+            )
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(&mut target)?;
+        }
+
+        let row_height = self.font.character_size.height;
+        for (row, (index, item)) in self
+            .items
+            .iter()
+            .enumerate()
+            .skip(self.first)
+            .take(rows)
+            .enumerate()
+        {
+            let row_area = Rectangle::new(
+                area.top_left + Point::new(0, (row as u32 * row_height) as i32),
+                Size::new(area.size.width, row_height),
+            );
+            let label = Label::new(item.as_ref(), row_area).font(self.font);
+            if self.selected == Some(index) {
+                label.inverted().draw(&mut target)?;
+            } else {
+                label.draw(&mut target)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A title in the 4x6 font on the left and icons on the right, over a line.
+#[derive(Debug, Clone)]
+pub struct StatusBar<'a> {
+    title: &'a str,
+    icons: heapless::Vec<&'a IconData, MAX_STATUS_ICONS>,
+    area: Rectangle,
+}
+
+impl<'a> StatusBar<'a> {
+    /// Height of the bar with its line.
+    pub const HEIGHT: u32 = 9;
+
+    /// A bar across the top of `area`.
+    pub fn new(title: &'a str, area: Rectangle) -> Self {
+        StatusBar {
+            title,
+            icons: heapless::Vec::new(),
+            area: split_top(area, Self::HEIGHT).0,
+        }
+    }
+
+    /// Add `icon` left of the ones added before; beyond
+    /// [`MAX_STATUS_ICONS`] they are left out.
+    pub fn icon(mut self, icon: &'a IconData) -> Self {
+        let _ = self.icons.push(icon);
+        self
+    }
+
+    /// What is left of `area` below the bar.
+    pub fn below(area: Rectangle) -> Rectangle {
+        split_top(area, Self::HEIGHT).1
+    }
+}
+
+impl Drawable for StatusBar<'_> {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let mut target = target.clipped(&self.area);
+        let (content, _) = split_top(self.area, Self::HEIGHT - 2);
+        let mut right = content.top_left.x + content.size.width as i32;
+        for icon in &self.icons {
+            let size = icon.size();
+            right -= size.width as i32;
+            Icon::new(icon, Point::new(right, content.top_left.y)).draw(&mut target)?;
+            right -= 1;
+        }
+
+        let title_width = (right - content.top_left.x).max(0) as u32;
+        let (title, _) = split_left(content, title_width);
+        Label::new(self.title, title)
+            .font(&FONT_4X6)
+            .valign(VAlign::Middle)
+            .draw(&mut target)?;
+
+        let y = self.area.top_left.y + Self::HEIGHT as i32 - 1;
+        Line::new(
+            Point::new(self.area.top_left.x, y),
+            Point::new(self.area.top_left.x + self.area.size.width as i32 - 1, y),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(&mut target)
+    }
+}
@@ -0,0 +1,426 @@
+//! The apps driven through their `App` interface on the host.
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use esp32_c3_buddy_like::{
+    app::{
+        chat::{Mode, WHEEL},
+        receiver::{View, GREETING},
+        App, Chat, Counter, Effects, Input, Received, Receiver, Snow, BROADCAST,
+    },
+    button::ButtonEvent,
+    display::{VISIBLE_OFFSET, VISIBLE_SIZE},
+    peers::{PeerTable, ONLINE_MS, SAVE_INTERVAL_MS},
+    protocol::{fragment::split, BlobKind, Frame, Message, Sensor, SensorReading},
+    screens::{self, BITMAP_LEN},
+    sim::MockDisplay,
+    VisibleArea,
+};
+
+const PEER: [u8; 6] = [0x34, 0x85, 0x18, 0x01, 0x02, 0x03];
+const OWN: [u8; 6] = [0x34, 0x85, 0x18, 0x0a, 0x0b, 0x0c];
+
+fn button(event: ButtonEvent) -> Input<'static> {
+    Input {
+        button: Some(event),
+        ..Default::default()
+    }
+}
+
+fn golden(name: &str) -> String {
+    format!("{}/tests/golden/{}.pbm", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[test]
+fn counter_counts_presses() {
+    let mut app = Counter::new();
+
+    assert!(!app.update(0, Input::default()).redraw);
+    assert!(app.update(500, button(ButtonEvent::Pressed)).redraw);
+    assert!(!app.update(530, button(ButtonEvent::Released)).redraw);
+    assert!(!app.update(830, button(ButtonEvent::Click)).redraw);
+    assert!(app.update(900, button(ButtonEvent::Pressed)).redraw);
+    assert!(!app.update(1700, button(ButtonEvent::LongPress(800))).redraw);
+    assert_eq!(app.count(), 2);
+}
+
+#[test]
+fn counter_renders_the_counter_screen() {
+    let mut app = Counter::new();
+    for now in [200, 400, 600] {
+        app.update(now, button(ButtonEvent::Pressed));
+    }
+
+    let mut display = VisibleArea::new(MockDisplay::new());
+    app.render(&mut display).unwrap();
+    assert_eq!(app.count(), 3);
+
+    let mut expected = VisibleArea::new(MockDisplay::new());
+    esp32_c3_buddy_like::screens::counter(&mut expected, 3).unwrap();
+    assert_eq!(display.into_inner(), expected.into_inner());
+}
+
+#[test]
+fn snow_steps_every_100ms_and_stays_on_the_glass() {
+    let mut app = Snow::new(0x1234_5678, VISIBLE_SIZE);
+
+    assert!(app.update(0, Input::default()).redraw);
+    assert!(!app.update(50, Input::default()).redraw);
+
+    for step in 1..500 {
+        assert!(app.update(step * 100, Input::default()).redraw);
+        for flake in app.flakes() {
+            assert!(flake.x >= 0 && flake.x <= VISIBLE_SIZE.width as i32);
+            assert!(flake.y >= 0 && flake.y <= VISIBLE_SIZE.height as i32);
+        }
+    }
+}
+
+#[test]
+fn snow_is_deterministic_for_a_seed() {
+    let mut app = Snow::new(42, VISIBLE_SIZE);
+    for step in 0..20 {
+        app.update(step * 100, Input::default());
+    }
+
+    let mut display = VisibleArea::new(MockDisplay::new());
+    app.render(&mut display).unwrap();
+    display.into_inner().assert_golden(golden("snow_seed_42"));
+}
+
+fn receive(app: &mut impl App, dst: [u8; 6], data: &[u8]) -> Effects {
+    app.update(
+        0,
+        Input {
+            received: Some(Received {
+                src: PEER,
+                dst,
+                data,
+            }),
+            ..Default::default()
+        },
+    )
+}
+
+fn frame(seq: u16, message: Message<'_>) -> Vec<u8> {
+    Frame::new(seq, message).to_vec().unwrap().to_vec()
+}
+
+#[test]
+fn receiver_shows_message_and_greets_broadcasters() {
+    let mut app = Receiver::new();
+
+    let effects = receive(&mut app, BROADCAST, &frame(1, Message::Counter(117)));
+
+    assert_eq!(app.message(), Some("117"));
+    assert!(effects.redraw);
+    assert_eq!(effects.add_peer, Some(PEER));
+    let outgoing = effects.send.unwrap();
+    assert_eq!(outgoing.dst, PEER);
+    assert_eq!(
+        Frame::decode(&outgoing.data).unwrap(),
+        Frame::new(1, Message::Text(GREETING))
+    );
+
+    // the next reply has the next sequence number
+    let effects = receive(&mut app, BROADCAST, &frame(2, Message::Text("again")));
+    assert_eq!(Frame::decode(&effects.send.unwrap().data).unwrap().seq, 2);
+}
+
+#[test]
+fn receiver_does_not_answer_unicast() {
+    let mut app = Receiver::new();
+
+    let effects = receive(&mut app, OWN, &frame(7, Message::Text("Hello Peer")));
+
+    assert_eq!(app.message(), Some("Hello Peer"));
+    assert!(effects.redraw);
+    assert_eq!(effects.add_peer, None);
+    assert_eq!(effects.send, None);
+
+    assert!(!app.update(10, Input::default()).redraw);
+}
+
+#[test]
+fn receiver_scrolls_long_messages() {
+    let mut app = Receiver::new();
+    let text = "a message much too long for the screen of the board, \
+                even in the smallest of the fonts, so it moves up by itself";
+    assert!(receive(&mut app, OWN, &frame(1, Message::Text(text))).redraw);
+
+    // it rests at the start first, then moves a pixel at a time
+    assert!(!app.update(1000, Input::default()).redraw);
+    assert!(app.update(1600, Input::default()).redraw);
+    assert!(!app.update(1610, Input::default()).redraw);
+    assert!(app.update(1650, Input::default()).redraw);
+
+    // a short one stays where it is
+    assert!(receive(&mut app, OWN, &frame(2, Message::Text("short"))).redraw);
+    assert!(!app.update(1700, Input::default()).redraw);
+    assert!(!app.update(5000, Input::default()).redraw);
+}
+
+#[test]
+fn receiver_shows_sensor_readings() {
+    let mut app = Receiver::new();
+    let reading = SensorReading {
+        sensor: Sensor::Temperature,
+        value: -2_500,
+    };
+    receive(&mut app, OWN, &frame(1, Message::SensorReading(reading)));
+    assert_eq!(app.message(), Some("temperature: -2.500 C"));
+}
+
+#[test]
+fn receiver_answers_pings() {
+    let mut app = Receiver::new();
+
+    let effects = receive(&mut app, OWN, &frame(3, Message::Ping(0xdead_beef)));
+
+    assert!(!effects.redraw);
+    assert_eq!(app.message(), None);
+    assert_eq!(effects.add_peer, Some(PEER));
+    let pong = effects.send.unwrap();
+    let pong = Frame::decode(&pong.data).unwrap();
+    assert_eq!(pong.message, Message::Pong(0xdead_beef));
+}
+
+#[test]
+fn receiver_rejects_invalid_frames() {
+    let mut app = Receiver::new();
+    receive(&mut app, OWN, &frame(1, Message::Text("first")));
+
+    // what the examples used to send
+    let effects = receive(&mut app, BROADCAST, b"117");
+    assert_eq!(effects, Effects::default());
+
+    let mut corrupted = frame(2, Message::Text("second"));
+    corrupted[8] ^= 0x20;
+    assert_eq!(receive(&mut app, OWN, &corrupted), Effects::default());
+
+    assert_eq!(app.message(), Some("first"));
+    assert_eq!(app.rejected(), 2);
+}
+
+#[test]
+fn receiver_shows_pushed_bitmaps() {
+    let mut app = Receiver::new();
+
+    // one pixel in the top left corner of the glass, one off it
+    let mut bitmap = [0u8; BITMAP_LEN];
+    bitmap[12 * 16 + 28 / 8] = 0x80 >> (28 % 8);
+    bitmap[0] = 0x80;
+
+    let fragments: Vec<_> = split(1, BlobKind::Bitmap, &bitmap).unwrap().collect();
+    assert_eq!(fragments.len(), 5);
+    for (seq, fragment) in fragments.iter().enumerate().rev() {
+        let effects = receive(
+            &mut app,
+            OWN,
+            &frame(seq as u16, Message::Fragment(*fragment)),
+        );
+        // only the last one completes the bitmap
+        assert_eq!(effects.redraw, seq == 0);
+    }
+    assert_eq!(app.bitmap(), Some(&bitmap));
+
+    let mut display = VisibleArea::new(MockDisplay::new());
+    app.render(&mut display).unwrap();
+    let display = display.into_inner();
+    assert_eq!(display.lit_pixels(), 1);
+    assert_eq!(display.pixel(VISIBLE_OFFSET), Some(BinaryColor::On));
+
+    // a message takes over the screen again
+    receive(&mut app, OWN, &frame(9, Message::Text("hi")));
+    assert_eq!(app.bitmap(), None);
+
+    // other blobs are ignored
+    let config = split(2, BlobKind::Config, b"x=1").unwrap().next().unwrap();
+    let effects = receive(&mut app, OWN, &frame(10, Message::Fragment(config)));
+    assert!(!effects.redraw);
+    assert_eq!(app.message(), Some("hi"));
+}
+
+#[test]
+fn receiver_lists_peers() {
+    let mut table = PeerTable::new();
+    for n in 0..7 {
+        table.set_paired([0, 0, 0, 0, 0, n], n == 0, n == 0);
+    }
+    // as loaded from flash
+    table.take_changed(0);
+    let mut app = Receiver::with_peers(table);
+    assert!(!app.take_peers_changed(0));
+    app.peers_mut().seen(0, PEER, -42);
+    assert!(!app.take_peers_changed(0));
+    assert!(app.take_peers_changed(SAVE_INTERVAL_MS));
+
+    let effects = app.update(0, button(ButtonEvent::DoubleClick));
+    assert!(effects.redraw);
+    assert_eq!(app.view(), View::Peers { first: 0 });
+    let mut display = VisibleArea::new(MockDisplay::new());
+    app.render(&mut display).unwrap();
+    let mut expected = VisibleArea::new(MockDisplay::new());
+    screens::peers(&mut expected, 0, app.peers().peers(), 0).unwrap();
+    assert_eq!(display.into_inner().diff_pixels(&expected.into_inner()), 0);
+
+    // nothing changed
+    assert!(!app.update(10, Input::default()).redraw);
+    // what arrives is not shown, but the peer is heard from
+    app.peers_mut().seen(20, PEER, -50);
+    assert!(receive(&mut app, OWN, &frame(1, Message::Text("hi"))).redraw);
+    assert!(!receive(&mut app, OWN, &frame(2, Message::Text("ho"))).redraw);
+    assert_eq!(app.message(), Some("ho"));
+    // the peer goes offline
+    assert!(app.update(ONLINE_MS + 20, Input::default()).redraw);
+    assert!(!app.update(ONLINE_MS + 30, Input::default()).redraw);
+
+    // 8 peers, 5 rows: a click scrolls down until the last page
+    for first in [1, 2, 3, 0] {
+        assert!(app.update(0, button(ButtonEvent::Click)).redraw);
+        assert_eq!(app.view(), View::Peers { first });
+    }
+
+    assert!(app.update(0, button(ButtonEvent::DoubleClick)).redraw);
+    assert_eq!(app.view(), View::Received);
+    // clicks do nothing here
+    assert!(!app.update(0, button(ButtonEvent::Click)).redraw);
+    assert_eq!(app.view(), View::Received);
+}
+
+fn paired_table(macs: &[[u8; 6]]) -> PeerTable {
+    let mut table = PeerTable::new();
+    for &mac in macs {
+        table.set_paired(mac, true, true);
+    }
+    table
+}
+
+/// Click round the wheel to `c` and take it.
+fn type_char(app: &mut Chat, c: u8) {
+    let Mode::Compose { wheel, .. } = *app.mode() else {
+        panic!("not composing");
+    };
+    let target = WHEEL.iter().position(|&w| w == c).unwrap();
+    for _ in 0..(target + WHEEL.len() - wheel) % WHEEL.len() {
+        assert!(app.update(0, button(ButtonEvent::Click)).redraw);
+    }
+    assert!(app.update(0, button(ButtonEvent::DoubleClick)).redraw);
+}
+
+#[test]
+fn chat_stamps_messages_with_peer_names() {
+    let mut table = paired_table(&[PEER]);
+    table.rename(&PEER, "alice").unwrap();
+    let mut app = Chat::with_peers(table);
+    assert_eq!(app.selected(), Some(PEER));
+
+    assert!(receive(&mut app, OWN, &frame(1, Message::Text("hello"))).redraw);
+    // only text is chat
+    assert!(!receive(&mut app, OWN, &frame(2, Message::Ping(7))).redraw);
+    let stranger = [0x34, 0x85, 0x18, 0x01, 0xbe, 0xef];
+    let data = frame(1, Message::Text("who?"));
+    let input = Input {
+        received: Some(Received {
+            src: stranger,
+            dst: OWN,
+            data: &data,
+        }),
+        ..Default::default()
+    };
+    assert!(app.update(0, input).redraw);
+
+    let history: Vec<_> = app
+        .history()
+        .map(|m| (m.peer.as_str(), m.outgoing, m.text.as_str()))
+        .collect();
+    assert_eq!(
+        history,
+        [("alice", false, "hello"), ("buddy-beef", false, "who?")]
+    );
+}
+
+#[test]
+fn chat_composes_and_sends_to_the_selected_peer() {
+    let mut app = Chat::with_peers(paired_table(&[PEER]));
+    assert!(app.update(0, button(ButtonEvent::DoubleClick)).redraw);
+    for &c in b"HIX<" {
+        type_char(&mut app, c);
+    }
+    type_char(&mut app, b'!');
+    assert_eq!(
+        *app.mode(),
+        Mode::Compose {
+            draft: "HI!".try_into().unwrap(),
+            wheel: WHEEL.len() - 2,
+        }
+    );
+
+    let effects = app.update(0, button(ButtonEvent::LongPress(1000)));
+    assert!(effects.redraw);
+    let sent = effects.send.unwrap();
+    assert_eq!(sent.dst, PEER);
+    assert_eq!(
+        Frame::decode(&sent.data).unwrap().message,
+        Message::Text("HI!")
+    );
+    assert_eq!(*app.mode(), Mode::History { scroll: 0 });
+    let last = app.history().last().unwrap();
+    assert!(last.outgoing);
+    assert_eq!(last.text, "HI!");
+
+    // an empty message is not sent
+    app.update(0, button(ButtonEvent::DoubleClick));
+    assert_eq!(
+        app.update(0, button(ButtonEvent::LongPress(1000))).send,
+        None
+    );
+    assert_eq!(app.history().count(), 1);
+}
+
+#[test]
+fn chat_without_paired_peers_sends_nothing() {
+    let mut app = Chat::new();
+    assert_eq!(app.selected(), None);
+    app.update(0, button(ButtonEvent::DoubleClick));
+    type_char(&mut app, b'E');
+    assert_eq!(
+        app.update(0, button(ButtonEvent::LongPress(1000))).send,
+        None
+    );
+    assert_eq!(app.history().count(), 0);
+}
+
+#[test]
+fn chat_long_press_picks_the_next_paired_peer() {
+    let other = [0x34, 0x85, 0x18, 0x01, 0x02, 0x04];
+    let mut table = paired_table(&[PEER, other]);
+    table.seen(0, [0, 0, 0, 0, 0, 1], -40);
+    let mut app = Chat::with_peers(table);
+    // the most recently paired first, the unpaired peer is skipped
+    for selected in [other, PEER, other] {
+        assert_eq!(app.selected(), Some(selected));
+        assert!(app.update(0, button(ButtonEvent::LongPress(1000))).redraw);
+    }
+}
+
+#[test]
+fn chat_scrolls_back_and_returns_to_the_latest_message() {
+    let mut app = Chat::with_peers(paired_table(&[PEER]));
+    for seq in 1..=3 {
+        receive(&mut app, OWN, &frame(seq, Message::Text("hi")));
+    }
+    for scroll in [1, 2, 0] {
+        assert!(app.update(0, button(ButtonEvent::Click)).redraw);
+        assert_eq!(*app.mode(), Mode::History { scroll });
+    }
+    app.update(0, button(ButtonEvent::Click));
+    receive(&mut app, OWN, &frame(4, Message::Text("new")));
+    assert_eq!(*app.mode(), Mode::History { scroll: 0 });
+
+    let mut display = VisibleArea::new(MockDisplay::new());
+    app.render(&mut display).unwrap();
+    let mut expected = VisibleArea::new(MockDisplay::new());
+    let peer_name = app.peers().get(&PEER).unwrap().name.clone();
+    screens::chat_history(&mut expected, Some(&peer_name), app.history().rev()).unwrap();
+    assert_eq!(display.into_inner().diff_pixels(&expected.into_inner()), 0);
+}
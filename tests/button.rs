@@ -0,0 +1,190 @@
+//! Debouncing and gesture detection of the button driver.
+
+use esp32_c3_buddy_like::button::{Button, ButtonConfig, ButtonEvent};
+
+const CONFIG: ButtonConfig = ButtonConfig {
+    debounce_ms: 20,
+    double_click_ms: 300,
+    long_press_ms: 800,
+};
+
+/// Poll `button` every millisecond from `from` to `to` (exclusive) with `down`.
+fn hold(button: &mut Button, from: u64, to: u64, down: bool) {
+    for now in from..to {
+        button.poll(now, down);
+    }
+}
+
+fn events(button: &mut Button) -> Vec<ButtonEvent> {
+    std::iter::from_fn(|| button.pop()).collect()
+}
+
+/// Contact bounce: the level flips every millisecond for `ms`.
+fn bounce(button: &mut Button, from: u64, ms: u64) {
+    for now in from..from + ms {
+        button.poll(now, now % 2 == 0);
+    }
+}
+
+#[test]
+fn bounces_are_filtered() {
+    let mut button = Button::new(CONFIG);
+
+    bounce(&mut button, 0, 15);
+    hold(&mut button, 15, 100, true);
+    bounce(&mut button, 100, 15);
+    hold(&mut button, 115, 200, false);
+
+    assert_eq!(
+        events(&mut button),
+        [ButtonEvent::Pressed, ButtonEvent::Released]
+    );
+
+    hold(&mut button, 200, 600, false);
+    assert_eq!(events(&mut button), [ButtonEvent::Click]);
+}
+
+#[test]
+fn glitches_shorter_than_debounce_are_ignored() {
+    let mut button = Button::new(CONFIG);
+
+    hold(&mut button, 0, 10, true);
+    hold(&mut button, 10, 1000, false);
+
+    assert_eq!(events(&mut button), []);
+    assert!(!button.is_down());
+}
+
+#[test]
+fn double_click() {
+    let mut button = Button::new(CONFIG);
+
+    hold(&mut button, 0, 80, true);
+    hold(&mut button, 80, 200, false);
+    hold(&mut button, 200, 280, true);
+    hold(&mut button, 280, 1000, false);
+
+    assert_eq!(
+        events(&mut button),
+        [
+            ButtonEvent::Pressed,
+            ButtonEvent::Released,
+            ButtonEvent::Pressed,
+            ButtonEvent::Released,
+            ButtonEvent::DoubleClick,
+        ]
+    );
+}
+
+#[test]
+fn two_slow_clicks_are_two_clicks() {
+    let mut button = Button::new(CONFIG);
+
+    hold(&mut button, 0, 80, true);
+    hold(&mut button, 80, 600, false);
+    hold(&mut button, 600, 680, true);
+    hold(&mut button, 680, 1200, false);
+
+    assert_eq!(
+        events(&mut button),
+        [
+            ButtonEvent::Pressed,
+            ButtonEvent::Released,
+            ButtonEvent::Click,
+            ButtonEvent::Pressed,
+            ButtonEvent::Released,
+            ButtonEvent::Click,
+        ]
+    );
+}
+
+#[test]
+fn long_press_is_reported_while_held_and_is_not_a_click() {
+    let mut button = Button::new(CONFIG);
+
+    hold(&mut button, 0, 900, true);
+    assert_eq!(
+        events(&mut button),
+        [ButtonEvent::Pressed, ButtonEvent::LongPress(800)]
+    );
+
+    hold(&mut button, 900, 2000, true);
+    assert_eq!(events(&mut button), []);
+
+    hold(&mut button, 2000, 3000, false);
+    assert_eq!(events(&mut button), [ButtonEvent::Released]);
+}
+
+#[test]
+fn interrupt_path_uses_edge_timestamps() {
+    let mut button = Button::new(CONFIG);
+
+    // bouncy falling edge at 100, the main loop only runs every 50ms
+    for (now, down) in [(100, true), (101, false), (102, true)] {
+        button.on_edge(now, down);
+    }
+    button.update(110);
+    assert_eq!(events(&mut button), []);
+    button.update(150);
+    assert_eq!(events(&mut button), [ButtonEvent::Pressed]);
+
+    button.on_edge(1000, false);
+    button.update(1050);
+    // held from 102 until the rising edge at 1000
+    assert_eq!(
+        events(&mut button),
+        [ButtonEvent::LongPress(898), ButtonEvent::Released]
+    );
+}
+
+#[test]
+fn click_without_double_click_window_is_immediate() {
+    let mut button = Button::new(ButtonConfig {
+        double_click_ms: 0,
+        ..CONFIG
+    });
+
+    hold(&mut button, 0, 50, true);
+    hold(&mut button, 50, 80, false);
+
+    assert_eq!(
+        events(&mut button),
+        [
+            ButtonEvent::Pressed,
+            ButtonEvent::Released,
+            ButtonEvent::Click
+        ]
+    );
+}
+
+#[test]
+fn deadlines_between_edges() {
+    let mut button = Button::new(CONFIG);
+    assert_eq!(button.next_deadline(), None);
+
+    // only the edges and the deadlines, as an async task sees them
+    button.on_edge(100, true);
+    assert_eq!(button.next_deadline(), Some(120));
+    button.update(120);
+    assert_eq!(events(&mut button), [ButtonEvent::Pressed]);
+    // held from 100
+    assert_eq!(button.next_deadline(), Some(900));
+
+    button.on_edge(250, false);
+    assert_eq!(button.next_deadline(), Some(270));
+    button.update(270);
+    assert_eq!(events(&mut button), [ButtonEvent::Released]);
+    assert_eq!(button.next_deadline(), Some(550));
+    button.update(550);
+    assert_eq!(events(&mut button), [ButtonEvent::Click]);
+    assert_eq!(button.next_deadline(), None);
+
+    button.on_edge(1000, true);
+    button.update(1020);
+    button.update(button.next_deadline().unwrap());
+    assert_eq!(
+        events(&mut button),
+        [ButtonEvent::Pressed, ButtonEvent::LongPress(800)]
+    );
+    assert_eq!(button.next_deadline(), None);
+}
@@ -0,0 +1,171 @@
+//! Wi-Fi credentials store.
+
+use embedded_storage::{ReadStorage, Storage};
+use esp32_c3_buddy_like::config::{
+    ConfigStore, CredentialsError, DecodeError, StoreError, WifiConfig, MAX_NETWORKS,
+    MAX_RECORD_LEN,
+};
+
+/// Flash in RAM, erased to `0xff`.
+struct RamFlash(Vec<u8>);
+
+impl RamFlash {
+    fn new() -> Self {
+        RamFlash(vec![0xff; 4096])
+    }
+}
+
+impl ReadStorage for RamFlash {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(self.0.get(offset..offset + bytes.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Storage for RamFlash {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        self.0
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(())?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+fn config(networks: &[(&str, &str)]) -> WifiConfig {
+    let mut config = WifiConfig::default();
+    // added in reverse, so the first one ends up first
+    for (ssid, password) in networks.iter().rev() {
+        config.add(ssid, password).unwrap();
+    }
+    config
+}
+
+fn ssids(config: &WifiConfig) -> Vec<&str> {
+    config.networks().iter().map(|n| n.ssid.as_str()).collect()
+}
+
+#[test]
+fn erased_flash_is_empty() {
+    let mut store = ConfigStore::with_offset(RamFlash::new(), 0);
+    assert!(store.load().unwrap().is_empty());
+}
+
+#[test]
+fn save_and_load() {
+    let saved = config(&[("home", "secret"), ("office", ""), ("phone", "hotspot")]);
+
+    let mut store = ConfigStore::with_offset(RamFlash::new(), 1024);
+    store.save(&saved).unwrap();
+    let mut store = ConfigStore::with_offset(store.into_inner(), 1024);
+
+    assert_eq!(store.load().unwrap(), saved);
+}
+
+#[test]
+fn longest_credentials_fit() {
+    let ssid = "s".repeat(32);
+    let password = "p".repeat(64);
+    let mut saved = WifiConfig::default();
+    for i in 0..MAX_NETWORKS {
+        saved
+            .add(&format!("{}{}", i, &ssid[1..]), &password)
+            .unwrap();
+    }
+
+    let mut buf = [0; MAX_RECORD_LEN];
+    let len = saved.encode(&mut buf);
+    assert_eq!(len, MAX_RECORD_LEN);
+    assert_eq!(WifiConfig::decode(&buf).unwrap(), saved);
+}
+
+#[test]
+fn corrupt_records_are_rejected() {
+    let mut buf = [0; MAX_RECORD_LEN];
+    let len = config(&[("home", "secret")]).encode(&mut buf);
+
+    let mut flipped = buf;
+    flipped[8] ^= 0x01;
+    assert_eq!(WifiConfig::decode(&flipped), Err(DecodeError::Corrupt));
+
+    let mut bad_crc = buf;
+    bad_crc[len - 1] ^= 0x80;
+    assert_eq!(WifiConfig::decode(&bad_crc), Err(DecodeError::Corrupt));
+
+    assert_eq!(
+        WifiConfig::decode(&buf[..len - 1]),
+        Err(DecodeError::Corrupt)
+    );
+    assert_eq!(WifiConfig::decode(&[0; 16]), Err(DecodeError::Corrupt));
+
+    let mut newer = buf;
+    newer[4] = 2;
+    assert_eq!(
+        WifiConfig::decode(&newer),
+        Err(DecodeError::UnsupportedVersion(2))
+    );
+}
+
+#[test]
+fn store_reports_corruption() {
+    let mut flash = RamFlash::new();
+    flash.0[..4].copy_from_slice(b"junk");
+
+    let mut store = ConfigStore::with_offset(flash, 0);
+    assert!(matches!(
+        store.load(),
+        Err(StoreError::Decode(DecodeError::Corrupt))
+    ));
+}
+
+#[test]
+fn add_replaces_and_evicts() {
+    let mut config = config(&[("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")]);
+
+    config.add("c", "new").unwrap();
+    assert_eq!(ssids(&config), ["c", "a", "b", "d"]);
+    assert_eq!(config.networks()[0].password, "new");
+
+    config.add("e", "5").unwrap();
+    assert_eq!(ssids(&config), ["e", "c", "a", "b"]);
+
+    assert!(config.remove("a"));
+    assert!(!config.remove("a"));
+    assert_eq!(ssids(&config), ["e", "c", "b"]);
+}
+
+#[test]
+fn invalid_credentials() {
+    let mut config = WifiConfig::default();
+    assert_eq!(config.add("", "pw"), Err(CredentialsError::EmptySsid));
+    assert_eq!(
+        config.add(&"s".repeat(33), "pw"),
+        Err(CredentialsError::SsidTooLong)
+    );
+    assert_eq!(
+        config.add("home", &"p".repeat(65)),
+        Err(CredentialsError::PasswordTooLong)
+    );
+    assert!(config.is_empty());
+}
+
+#[test]
+fn select_strongest_visible() {
+    let config = config(&[("home", "1"), ("office", "2"), ("phone", "3")]);
+
+    let visible = [("neighbour", -30), ("phone", -70), ("office", -50)];
+    assert_eq!(config.select(visible).unwrap().ssid, "office");
+
+    // nothing stored is visible, try the most recent one (may be hidden)
+    assert_eq!(config.select([("neighbour", -30)]).unwrap().ssid, "home");
+
+    assert!(WifiConfig::default().select(visible).is_none());
+}
@@ -0,0 +1,354 @@
+//! The serial console: line editing, command lookup and the built-in commands.
+
+use esp32_c3_buddy_like::{
+    config::WifiConfig,
+    console::{self, Args, Command, Device, Error, LineEditor, Peer, Registry},
+    gateway::{Collector, GatewayStatus, Transport},
+};
+
+#[derive(Default)]
+struct Fake {
+    wifi_config: WifiConfig,
+    saved: usize,
+    save_fails: bool,
+    peers: Vec<Peer>,
+    led: Option<bool>,
+    text: Option<String>,
+    collector: Option<Collector>,
+    channel: Option<u8>,
+}
+
+impl Device for Fake {
+    fn wifi_config(&mut self) -> &mut WifiConfig {
+        &mut self.wifi_config
+    }
+
+    fn save_wifi_config(&mut self) -> bool {
+        self.saved += 1;
+        !self.save_fails
+    }
+
+    fn peers(&mut self, found: &mut dyn FnMut(&Peer)) {
+        self.peers.iter().for_each(found);
+    }
+
+    fn set_led(&mut self, on: bool) {
+        self.led = Some(on);
+    }
+
+    fn draw_text(&mut self, text: &str) {
+        self.text = Some(text.to_string());
+    }
+
+    fn gateway(&mut self) -> GatewayStatus {
+        GatewayStatus {
+            collector: self.collector,
+            channel: self.channel,
+            forwarded: 12,
+            dropped: 1,
+        }
+    }
+
+    fn set_collector(&mut self, collector: Option<Collector>) -> bool {
+        self.collector = collector;
+        self.saved += 1;
+        !self.save_fails
+    }
+}
+
+fn run(device: &mut Fake, line: &str) -> String {
+    let mut out = String::new();
+    Registry::new(&console::builtins())
+        .dispatch(line, device, &mut out)
+        .unwrap();
+    out
+}
+
+#[test]
+fn args() {
+    let words: Vec<_> = Args::new("  a \"b c\"  d\"e ").collect();
+    assert_eq!(words, ["a", "b c", "d\"e"]);
+    let words: Vec<_> = Args::new("\"\" \"open quote").collect();
+    assert_eq!(words, ["", "open quote"]);
+
+    let mut args = Args::new("one  two three ");
+    assert_eq!(args.word(), Ok("one"));
+    assert_eq!(args.rest(), "two three");
+    assert_eq!(args.end(), Err(Error::Usage));
+    assert_eq!(Args::new("  ").end(), Ok(()));
+}
+
+#[test]
+fn find() {
+    let builtins = console::builtins::<Fake>();
+    let registry = Registry::new(&builtins);
+    let (command, args) = registry.find("wifi  set home pw").unwrap();
+    assert_eq!(command.name, "wifi set");
+    assert_eq!(args.rest(), "home pw");
+    assert_eq!(registry.find("led on").unwrap().0.name, "led");
+    assert!(registry.find("wifi").is_none());
+    assert!(registry.find("wifiset").is_none());
+    assert!(registry.find("").is_none());
+}
+
+#[test]
+fn longest_name_wins() {
+    struct Calls(Vec<&'static str>);
+    let commands: &[Command<Calls>] = &[
+        Command {
+            name: "wifi",
+            args: "",
+            help: "",
+            run: |calls, _, _| {
+                calls.0.push("wifi");
+                Ok(())
+            },
+        },
+        Command {
+            name: "wifi scan",
+            args: "",
+            help: "",
+            run: |calls, _, _| {
+                calls.0.push("wifi scan");
+                Ok(())
+            },
+        },
+    ];
+    let registry = Registry::new(commands);
+    let mut calls = Calls(Vec::new());
+    let mut out = String::new();
+    for line in ["wifi scan", "wifi", "wifi other"] {
+        registry.dispatch(line, &mut calls, &mut out).unwrap();
+    }
+    assert_eq!(calls.0, ["wifi scan", "wifi", "wifi"]);
+}
+
+#[test]
+fn wifi_commands() {
+    let mut device = Fake::default();
+    assert_eq!(run(&mut device, "wifi list"), "no networks stored\n");
+
+    assert_eq!(
+        run(&mut device, "wifi set \"My Network\" \"pass word\""),
+        "saved, used after the next reboot\n"
+    );
+    assert_eq!(
+        run(&mut device, "wifi set open"),
+        "saved, used after the next reboot\n"
+    );
+    assert_eq!(device.saved, 2);
+    let networks = device.wifi_config.networks();
+    assert_eq!(networks[0].ssid, "open");
+    assert_eq!(networks[0].password, "");
+    assert_eq!(networks[1].ssid, "My Network");
+    assert_eq!(networks[1].password, "pass word");
+    assert_eq!(run(&mut device, "wifi list"), "open\nMy Network\n");
+
+    assert_eq!(
+        run(&mut device, "wifi forget nope"),
+        "'nope' is not stored\n"
+    );
+    assert_eq!(device.saved, 2);
+    run(&mut device, "wifi forget open");
+    assert_eq!(run(&mut device, "wifi list"), "My Network\n");
+
+    assert_eq!(
+        run(&mut device, &format!("wifi set {}", "x".repeat(33))),
+        "invalid credentials: SsidTooLong\n"
+    );
+
+    device.save_fails = true;
+    assert_eq!(
+        run(&mut device, "wifi set home pw"),
+        "error saving the configuration\n"
+    );
+}
+
+#[test]
+fn peers() {
+    let mut device = Fake::default();
+    assert_eq!(run(&mut device, "peers"), "no peers\n");
+
+    device.peers = vec![
+        Peer {
+            mac: [0xff; 6],
+            channel: None,
+            encrypted: false,
+        },
+        Peer {
+            mac: [0x34, 0x85, 0x18, 0x0a, 0xbc, 0xde],
+            channel: Some(11),
+            encrypted: true,
+        },
+    ];
+    assert_eq!(
+        run(&mut device, "peers"),
+        "ff:ff:ff:ff:ff:ff  channel -   plain\n\
+         34:85:18:0a:bc:de  channel 11  encrypted\n"
+    );
+}
+
+#[test]
+fn gateway_commands() {
+    let mut device = Fake::default();
+    assert_eq!(
+        run(&mut device, "gateway"),
+        "not forwarding\n\
+         channel unknown, not connected\n\
+         12 forwarded, 1 dropped\n"
+    );
+
+    assert_eq!(
+        run(&mut device, "gateway set tcp://192.168.1.10:9000"),
+        "saved\n"
+    );
+    assert_eq!(
+        device.collector,
+        Some(Collector {
+            transport: Transport::Tcp,
+            ip: [192, 168, 1, 10],
+            port: 9000,
+        })
+    );
+    device.channel = Some(6);
+    assert_eq!(
+        run(&mut device, "gateway"),
+        "forwarding to tcp://192.168.1.10:9000\n\
+         channel 6\n\
+         12 forwarded, 1 dropped\n"
+    );
+
+    assert_eq!(
+        run(&mut device, "gateway set http://example.com"),
+        "invalid collector: Transport\n"
+    );
+    assert_eq!(run(&mut device, "gateway off"), "saved\n");
+    assert_eq!(device.collector, None);
+    assert_eq!(device.saved, 2);
+    assert_eq!(
+        run(&mut device, "gateway set"),
+        "usage: gateway set udp|tcp://<ip>:<port>\n"
+    );
+}
+
+#[test]
+fn led_and_text() {
+    let mut device = Fake::default();
+    assert_eq!(run(&mut device, "led on"), "ok\n");
+    assert_eq!(device.led, Some(true));
+    run(&mut device, "led off");
+    assert_eq!(device.led, Some(false));
+
+    assert_eq!(run(&mut device, "text  Hello  there "), "ok\n");
+    assert_eq!(device.text.as_deref(), Some("Hello  there"));
+}
+
+#[test]
+fn errors() {
+    let mut device = Fake::default();
+    assert_eq!(
+        run(&mut device, "blink"),
+        "unknown command 'blink', try 'help'\n"
+    );
+    assert_eq!(run(&mut device, "led"), "usage: led on|off\n");
+    assert_eq!(run(&mut device, "led dim"), "usage: led on|off\n");
+    assert_eq!(run(&mut device, "led on now"), "usage: led on|off\n");
+    assert_eq!(run(&mut device, "text"), "usage: text <msg>\n");
+    assert_eq!(run(&mut device, "peers all"), "usage: peers\n");
+    assert_eq!(
+        run(&mut device, "wifi set"),
+        "usage: wifi set <ssid> [<password>]\n"
+    );
+    assert_eq!(
+        run(&mut device, "wifi"),
+        "usage: wifi set <ssid> [<password>]\n\
+         usage: wifi forget <ssid>\n\
+         usage: wifi list\n"
+    );
+    assert_eq!(run(&mut device, "   "), "");
+    assert_eq!(device.led, None);
+}
+
+#[test]
+fn help() {
+    let out = run(&mut Fake::default(), "help");
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), console::builtins::<Fake>().len() + 1);
+    assert_eq!(lines[0], "wifi set <ssid> [<password>]  store a network");
+    assert_eq!(lines[3], "peers                   show the ESP-NOW peers");
+    assert_eq!(lines[9], "help                    this text");
+    assert_eq!(run(&mut Fake::default(), "?"), out);
+}
+
+/// Feed `input`, returns the finished lines and the echo.
+fn edit(editor: &mut LineEditor, input: &[u8]) -> (Vec<String>, String) {
+    let mut echo = String::new();
+    let lines = input
+        .iter()
+        .filter_map(|&b| editor.push(b, &mut echo).map(String::from))
+        .collect();
+    (lines, echo)
+}
+
+#[test]
+fn echo() {
+    let mut editor = LineEditor::new();
+    assert_eq!(
+        edit(&mut editor, b"led on\r"),
+        (vec!["led on".into()], "led on\r\n".into())
+    );
+    // the \n after a \r is no extra line
+    assert_eq!(
+        edit(&mut editor, b"\npeers\n"),
+        (vec!["peers".into()], "peers\r\n".into())
+    );
+}
+
+#[test]
+fn backspace() {
+    let mut editor = LineEditor::new();
+    let (lines, echo) = edit(&mut editor, b"lex\x7fd\x08\x08ed on\r");
+    assert_eq!(lines, ["led on"]);
+    assert_eq!(echo, "lex\x08 \x08d\x08 \x08\x08 \x08ed on\r\n");
+
+    // nothing to delete
+    assert_eq!(edit(&mut editor, b"\x7f").1, "");
+}
+
+#[test]
+fn cancel_and_kill() {
+    let mut editor = LineEditor::new();
+    assert_eq!(
+        edit(&mut editor, b"wifi\x03"),
+        (vec![String::new()], "wifi^C\r\n".into())
+    );
+    let (lines, echo) = edit(&mut editor, b"ab\x15peers\r");
+    assert_eq!(lines, ["peers"]);
+    assert_eq!(echo, "ab\x08 \x08\x08 \x08peers\r\n");
+}
+
+#[test]
+fn history() {
+    let mut editor = LineEditor::new();
+    edit(&mut editor, b"led on\r");
+    // an empty line does not replace the previous one
+    edit(&mut editor, b"\r");
+
+    let (lines, echo) = edit(&mut editor, b"x\x1b[A\r");
+    assert_eq!(lines, ["led on"]);
+    assert_eq!(echo, "x\x08 \x08led on\r\n");
+
+    // other escape sequences are swallowed
+    let (lines, echo) = edit(&mut editor, b"a\x1b[D\x1b[3~\x1bOb\r");
+    assert_eq!(lines, ["ab"]);
+    assert_eq!(echo, "ab\r\n");
+}
+
+#[test]
+fn long_lines() {
+    let mut editor = LineEditor::new();
+    let input = "x".repeat(console::MAX_LINE_LEN + 2) + "\r";
+    let (lines, echo) = edit(&mut editor, input.as_bytes());
+    assert_eq!(lines, ["x".repeat(console::MAX_LINE_LEN)]);
+    assert!(echo.ends_with("x\x07\x07\r\n"));
+}
@@ -0,0 +1,292 @@
+//! Splitting blobs into fragments and putting them back together.
+
+use esp32_c3_buddy_like::{
+    app::{Mac, BROADCAST},
+    protocol::{
+        fragment::{split, Blob, Reassembler, ReassemblyError},
+        reliable::{Event, Reliable, ReliableConfig},
+        BlobKind, EncodeError, Fragment, Frame, Message, FRAGMENT_DATA_LEN, MAX_BLOB_LEN,
+    },
+};
+
+const A: Mac = [0xa; 6];
+const B: Mac = [0xb; 6];
+
+fn blob(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+/// Feed all `fragments` from `src`, returns the blob if one completed.
+fn feed(
+    reassembler: &mut Reassembler,
+    now: u64,
+    src: Mac,
+    fragments: &[Fragment<'_>],
+) -> Option<(BlobKind, Vec<u8>)> {
+    let mut complete = None;
+    for fragment in fragments {
+        if let Some(blob) = reassembler.receive(now, src, *fragment).unwrap() {
+            assert_eq!(blob.src, src);
+            assert!(complete.is_none(), "completed twice");
+            complete = Some((blob.kind, blob.data.to_vec()));
+        }
+    }
+    complete
+}
+
+#[test]
+fn split_sizes() {
+    for (len, count) in [
+        (0, 1),
+        (1, 1),
+        (FRAGMENT_DATA_LEN, 1),
+        (FRAGMENT_DATA_LEN + 1, 2),
+        (1024, 5),
+        (MAX_BLOB_LEN, 256),
+    ] {
+        let data = blob(len);
+        let fragments: Vec<_> = split(9, BlobKind::Log, &data).unwrap().collect();
+        assert_eq!(fragments.len(), count, "{} bytes", len);
+        assert_eq!(split(9, BlobKind::Log, &data).unwrap().len(), count);
+
+        let mut joined = Vec::new();
+        for (index, fragment) in fragments.iter().enumerate() {
+            assert_eq!(fragment.index as usize, index);
+            assert_eq!(fragment.blob_len as usize, len);
+            assert!(fragment.is_valid());
+            // every fragment fits into a frame
+            Frame::new(0, Message::Fragment(*fragment))
+                .to_vec()
+                .unwrap();
+            joined.extend_from_slice(fragment.data);
+        }
+        assert_eq!(joined, data);
+    }
+
+    assert!(matches!(
+        split(9, BlobKind::Log, &blob(MAX_BLOB_LEN + 1)),
+        Err(EncodeError::TooLong)
+    ));
+}
+
+#[test]
+fn in_any_order() {
+    let data = blob(1024);
+    let mut fragments: Vec<_> = split(1, BlobKind::Bitmap, &data).unwrap().collect();
+    fragments.swap(0, 4);
+    fragments.swap(1, 2);
+    // repeats change nothing
+    fragments.insert(2, fragments[0]);
+
+    let mut reassembler = Reassembler::new(1_000);
+    assert_eq!(
+        feed(&mut reassembler, 0, A, &fragments),
+        Some((BlobKind::Bitmap, data))
+    );
+    assert_eq!(reassembler.in_progress(), 0);
+}
+
+#[test]
+fn small_blobs() {
+    let mut reassembler = Reassembler::new(1_000);
+    for len in [0, 1, 100] {
+        let data = blob(len);
+        let fragments: Vec<_> = split(len as u16, BlobKind::Config, &data)
+            .unwrap()
+            .collect();
+        assert_eq!(
+            feed(&mut reassembler, 0, A, &fragments),
+            Some((BlobKind::Config, data))
+        );
+    }
+}
+
+#[test]
+fn interleaved_senders() {
+    let one = blob(600);
+    let two: Vec<u8> = blob(500).iter().map(|b| !b).collect();
+    // the same transfer id from two senders
+    let from_a: Vec<_> = split(5, BlobKind::Log, &one).unwrap().collect();
+    let from_b: Vec<_> = split(5, BlobKind::Log, &two).unwrap().collect();
+
+    let mut reassembler: Reassembler = Reassembler::new(1_000);
+    let mut done = Vec::new();
+    for i in 0..3 {
+        for (src, fragments) in [(A, &from_a), (B, &from_b)] {
+            if let Some(Blob { src, data, .. }) = reassembler.receive(0, src, fragments[i]).unwrap()
+            {
+                done.push((src, data.to_vec()));
+            }
+        }
+    }
+    assert_eq!(done, [(A, one), (B, two)]);
+}
+
+#[test]
+fn stalled_blobs_time_out() {
+    let data = blob(1024);
+    let fragments: Vec<_> = split(1, BlobKind::Bitmap, &data).unwrap().collect();
+
+    let mut reassembler = Reassembler::new(500);
+    assert_eq!(feed(&mut reassembler, 0, A, &fragments[..2]), None);
+    // each fragment restarts the clock
+    assert_eq!(feed(&mut reassembler, 400, A, &fragments[2..3]), None);
+    assert_eq!(reassembler.expire(800), 0);
+    assert_eq!(reassembler.in_progress(), 1);
+
+    assert_eq!(reassembler.expire(900), 1);
+    assert_eq!(reassembler.in_progress(), 0);
+    assert_eq!(reassembler.expired(), 1);
+
+    // the rest alone is not enough
+    assert_eq!(feed(&mut reassembler, 1_000, A, &fragments[3..]), None);
+    // but a complete repeat is
+    assert_eq!(
+        feed(&mut reassembler, 1_100, A, &fragments),
+        Some((BlobKind::Bitmap, data))
+    );
+}
+
+#[test]
+fn pool_is_bounded() {
+    let data = blob(1024);
+    let first = |transfer| {
+        split(transfer, BlobKind::Bitmap, &data)
+            .unwrap()
+            .next()
+            .unwrap()
+    };
+
+    let mut reassembler: Reassembler = Reassembler::new(500);
+    reassembler.receive(0, A, first(1)).unwrap();
+    reassembler.receive(0, A, first(2)).unwrap();
+    assert_eq!(
+        reassembler.receive(0, A, first(3)),
+        Err(ReassemblyError::PoolFull)
+    );
+    assert_eq!(
+        reassembler.receive(0, B, first(1)),
+        Err(ReassemblyError::PoolFull)
+    );
+
+    // room again once the others time out
+    assert_eq!(reassembler.receive(600, B, first(1)), Ok(None));
+    assert_eq!(reassembler.in_progress(), 1);
+
+    let big = blob(1025);
+    let fragment = split(4, BlobKind::Log, &big).unwrap().next().unwrap();
+    assert_eq!(
+        reassembler.receive(600, A, fragment),
+        Err(ReassemblyError::TooLarge)
+    );
+
+    // a larger pool takes it
+    let mut reassembler: Reassembler<1, 2048> = Reassembler::new(500);
+    let fragments: Vec<_> = split(4, BlobKind::Log, &big).unwrap().collect();
+    let mut complete = None;
+    for fragment in fragments {
+        if let Some(blob) = reassembler.receive(0, A, fragment).unwrap() {
+            complete = Some(blob.data.to_vec());
+        }
+    }
+    assert_eq!(complete, Some(big));
+}
+
+#[test]
+fn mismatched_fragments() {
+    let data = blob(600);
+    let fragments: Vec<_> = split(1, BlobKind::Config, &data).unwrap().collect();
+
+    let mut reassembler = Reassembler::new(500);
+    reassembler.receive(0, A, fragments[0]).unwrap();
+
+    // same transfer, different blob
+    let other = Fragment {
+        blob_len: 601,
+        ..fragments[1]
+    };
+    assert_eq!(
+        reassembler.receive(0, A, other),
+        Err(ReassemblyError::Mismatch)
+    );
+    let other = Fragment {
+        kind: BlobKind::Log,
+        ..fragments[1]
+    };
+    assert_eq!(
+        reassembler.receive(0, A, other),
+        Err(ReassemblyError::Mismatch)
+    );
+
+    // data that does not fit the index
+    let other = Fragment {
+        data: &data[..10],
+        ..fragments[1]
+    };
+    assert_eq!(
+        reassembler.receive(0, A, other),
+        Err(ReassemblyError::Mismatch)
+    );
+
+    // none of it got in the way
+    assert_eq!(
+        feed(&mut reassembler, 0, A, &fragments[1..]),
+        Some((BlobKind::Config, data))
+    );
+}
+
+/// A bitmap pushed over a lossy link, with retries from the reliable layer.
+#[test]
+fn bitmap_over_a_noisy_channel() {
+    let data = blob(1024);
+    let config = ReliableConfig {
+        ack_timeout_ms: 50,
+        max_retries: 10,
+    };
+    let mut a = Reliable::new(config, 1);
+    let mut b = Reliable::new(config, 2);
+    let mut reassembler: Reassembler = Reassembler::new(2_000);
+
+    for fragment in split(7, BlobKind::Bitmap, &data).unwrap() {
+        a.send(0, B, Message::Fragment(fragment)).unwrap();
+    }
+
+    let mut rng = 0x1234_5678u32;
+    let mut lose = move || {
+        rng ^= rng << 13;
+        rng ^= rng >> 17;
+        rng ^= rng << 5;
+        rng % 10 < 3
+    };
+    let mut received = None;
+    for now in (0..2_000).step_by(10) {
+        while let Some(outgoing) = a.poll_transmit(now) {
+            if lose() {
+                continue;
+            }
+            let Some(frame) = b.receive(now, A, outgoing.dst, &outgoing.data) else {
+                continue;
+            };
+            let Message::Fragment(fragment) = frame.message else {
+                panic!("unexpected {:?}", frame.message);
+            };
+            if let Some(blob) = reassembler.receive(now, A, fragment).unwrap() {
+                assert!(received.is_none());
+                received = Some(blob.data.to_vec());
+            }
+        }
+        while let Some(ack) = b.poll_transmit(now) {
+            if !lose() {
+                assert_ne!(ack.dst, BROADCAST);
+                a.receive(now, B, ack.dst, &ack.data);
+            }
+        }
+    }
+
+    assert_eq!(received, Some(data));
+    let events: Vec<_> = std::iter::from_fn(|| a.pop_event()).collect();
+    assert_eq!(events.len(), 5);
+    assert!(events
+        .iter()
+        .all(|e| matches!(e, Event::Delivered { dst: B, .. })));
+}
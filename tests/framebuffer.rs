@@ -0,0 +1,245 @@
+//! The framebuffer that flushes only the changed columns, its async flush
+//! and its stats.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use embassy_futures::{block_on, join::join, yield_now};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use esp32_c3_buddy_like::{
+    framebuffer::{
+        AsyncPageWriter, BufferedDisplay, Flushed, FrameBuffer, FrameStats, PageWriter, Span,
+        HEIGHT, PAGES, WIDTH,
+    },
+    screens,
+    sim::MockDisplay,
+    VisibleArea,
+};
+
+/// Remembers the writes, and fails the one to `fail_page`.
+#[derive(Default)]
+struct Recorder {
+    writes: Vec<(u8, u8, Vec<u8>)>,
+    fail_page: Option<u8>,
+}
+
+impl PageWriter for Recorder {
+    type Error = u8;
+
+    fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), u8> {
+        if self.fail_page == Some(page) {
+            return Err(page);
+        }
+        self.writes.push((page, column, data.to_vec()));
+        Ok(())
+    }
+}
+
+/// Like the I2C interrupt, lets other tasks run before a write is done.
+impl AsyncPageWriter for Recorder {
+    type Error = u8;
+
+    async fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), u8> {
+        yield_now().await;
+        PageWriter::write_page(self, page, column, data)
+    }
+}
+
+fn clock() -> u64 {
+    static NOW_US: AtomicU64 = AtomicU64::new(0);
+    NOW_US.fetch_add(1000, Ordering::Relaxed)
+}
+
+fn pixel(frame: &mut FrameBuffer, x: i32, y: i32, color: BinaryColor) {
+    Pixel(Point::new(x, y), color).draw(frame).unwrap();
+}
+
+#[test]
+fn first_flush_sends_everything() {
+    let mut frame = FrameBuffer::new();
+    let mut recorder = Recorder::default();
+    assert_eq!(
+        frame.flush(&mut recorder),
+        Ok(Flushed {
+            pages: PAGES as u8,
+            bytes: WIDTH * PAGES,
+        })
+    );
+    assert!(recorder
+        .writes
+        .iter()
+        .enumerate()
+        .all(|(page, (p, column, data))| *p as usize == page
+            && *column == 0
+            && data.len() == WIDTH));
+
+    assert_eq!(frame.flush(&mut recorder), Ok(Flushed::default()));
+    assert_eq!(recorder.writes.len(), PAGES);
+
+    frame.invalidate();
+    assert_eq!(frame.flush(&mut recorder).unwrap().pages, PAGES as u8);
+}
+
+#[test]
+fn only_changed_columns() {
+    let mut frame = FrameBuffer::new();
+    frame.flush(&mut Recorder::default()).unwrap();
+
+    // the same picture again is nothing to send
+    frame.clear(BinaryColor::Off).unwrap();
+    assert_eq!(frame.dirty(0), None);
+
+    pixel(&mut frame, 30, 13, BinaryColor::On);
+    pixel(&mut frame, 40, 14, BinaryColor::On);
+    pixel(&mut frame, 99, 63, BinaryColor::On);
+    assert_eq!(frame.pixel(Point::new(30, 13)), Some(BinaryColor::On));
+    assert_eq!(frame.pixel(Point::new(30, 12)), Some(BinaryColor::Off));
+    assert_eq!(frame.pixel(Point::new(WIDTH as i32, 0)), None);
+    assert_eq!(
+        frame.dirty(1),
+        Some(Span {
+            page: 1,
+            start: 30,
+            end: 41,
+        })
+    );
+
+    let mut recorder = Recorder::default();
+    assert_eq!(
+        frame.flush(&mut recorder),
+        Ok(Flushed {
+            pages: 2,
+            bytes: 12
+        })
+    );
+    assert_eq!((recorder.writes[0].0, recorder.writes[0].1), (1, 30));
+    assert_eq!(recorder.writes[0].2[0], 1 << 5);
+    assert_eq!(recorder.writes[0].2[10], 1 << 6);
+    assert_eq!(recorder.writes[1], (7, 99, vec![1 << 7]));
+
+    // turning a pixel back off is a change too
+    pixel(&mut frame, 99, 63, BinaryColor::Off);
+    assert_eq!(
+        frame.dirty(7),
+        Some(Span {
+            page: 7,
+            start: 99,
+            end: 100,
+        })
+    );
+}
+
+#[test]
+fn failed_pages_stay_dirty() {
+    let mut frame = FrameBuffer::new();
+    frame.flush(&mut Recorder::default()).unwrap();
+    for y in [0, HEIGHT as i32 - 1] {
+        pixel(&mut frame, 5, y, BinaryColor::On);
+    }
+
+    let mut recorder = Recorder {
+        fail_page: Some(7),
+        ..Default::default()
+    };
+    assert_eq!(frame.flush(&mut recorder), Err(7));
+    assert_eq!(frame.dirty(0), None);
+    assert!(frame.dirty(7).is_some());
+
+    recorder.fail_page = None;
+    assert_eq!(
+        frame.flush(&mut recorder),
+        Ok(Flushed { pages: 1, bytes: 1 })
+    );
+}
+
+#[test]
+fn drawing_during_an_async_flush() {
+    let mut display = BufferedDisplay::new(Recorder::default(), clock);
+    let mut front = FrameBuffer::new();
+    pixel(&mut front, 10, 20, BinaryColor::On);
+    display.frame_mut().copy_frame(&front);
+    block_on(display.flush_async()).unwrap();
+    assert_eq!(display.writer().writes.len(), PAGES);
+
+    // the next frame is drawn while the last one is on its way
+    pixel(&mut front, 10, 20, BinaryColor::Off);
+    display.frame_mut().copy_frame(&front);
+    let (flushed, ()) = block_on(join(display.flush_async(), async {
+        pixel(&mut front, 11, 20, BinaryColor::On);
+    }));
+    assert_eq!(flushed, Ok(Flushed { pages: 1, bytes: 1 }));
+    assert_eq!(display.writer().writes.last(), Some(&(2, 10, vec![0])));
+
+    display.frame_mut().copy_frame(&front);
+    block_on(display.flush_async()).unwrap();
+    assert_eq!(display.writer().writes.last(), Some(&(2, 11, vec![1 << 4])));
+    assert_eq!(display.stats().frames, 3);
+
+    display.writer_mut().fail_page = Some(2);
+    pixel(display.frame_mut(), 0, 0, BinaryColor::On);
+    pixel(display.frame_mut(), 0, 16, BinaryColor::On);
+    assert_eq!(block_on(display.flush_async()), Err(2));
+    assert_eq!(display.frame().dirty(0), None);
+}
+
+#[test]
+fn counter_sends_its_digits() {
+    let mut display = VisibleArea::new(BufferedDisplay::new(Recorder::default(), clock));
+    screens::counter(&mut display, 41).unwrap();
+    display.flush().unwrap();
+
+    // the same layout as the pixels the screens are checked with
+    let mut mock = VisibleArea::new(MockDisplay::new());
+    screens::counter(&mut mock, 41).unwrap();
+    for y in 0..HEIGHT as i32 {
+        for x in 0..WIDTH as i32 {
+            let point = Point::new(x, y);
+            assert_eq!(
+                display.inner().frame().pixel(point),
+                mock.inner().pixel(point)
+            );
+        }
+    }
+
+    screens::counter(&mut display, 42).unwrap();
+    let flushed = display.flush().unwrap();
+    assert!(flushed.pages > 0);
+    assert!(flushed.bytes < 72 * 5 / 2, "{:?}", flushed);
+
+    let stats = display.inner().stats();
+    assert_eq!((stats.frames, stats.unchanged), (2, 0));
+    display.flush().unwrap();
+    assert_eq!(display.inner().stats().unchanged, 1);
+}
+
+#[test]
+fn stats() {
+    let mut stats = FrameStats::default();
+    let frame = Flushed {
+        pages: 1,
+        bytes: 10,
+    };
+    stats.record(0, 2000, frame);
+    assert_eq!(
+        (stats.frames, stats.last_us, stats.average_us, stats.max_us),
+        (1, 2000, 2000, 2000)
+    );
+
+    stats.record(50_000, 60_000, frame);
+    assert_eq!(
+        (stats.last_us, stats.max_us, stats.period_us),
+        (10_000, 10_000, 50_000)
+    );
+    assert_eq!(stats.average_us, 3000);
+    assert_eq!(stats.fps(), 20);
+    assert_eq!(stats.bytes, 20);
+
+    stats.record(60_000, 60_000, Flushed::default());
+    assert_eq!((stats.frames, stats.unchanged), (2, 1));
+
+    stats.record(150_000, 150_500, frame);
+    assert_eq!(stats.period_us, 50_000 + (100_000 - 50_000) / 8);
+    assert_eq!(
+        stats.to_string(),
+        "17 fps, flush 0.5 ms (avg 2.6, max 10.0), 3 frames, 1 unchanged"
+    );
+}
@@ -0,0 +1,283 @@
+//! The ESP-NOW gateway: JSON lines, the queue, the TCP uplink and the stored
+//! collector.
+
+use embedded_storage::{ReadStorage, Storage};
+use esp32_c3_buddy_like::{
+    app::{BROADCAST, MAX_PAYLOAD},
+    config::DecodeError,
+    gateway::{
+        self, parse_json, Collector, CollectorStore, Forwarded, Gateway, LineError, ParseError,
+        TcpUplink, Transport, UplinkCommand, CONNECT_TIMEOUT_MS, MAX_LINE_LEN, MAX_QUEUED_LINES,
+        RETRY_MS,
+    },
+};
+
+const SRC: [u8; 6] = [0x34, 0x85, 0x18, 0x01, 0x02, 0x03];
+
+fn frame(ts_ms: u64, data: &[u8]) -> Forwarded<'_> {
+    Forwarded {
+        ts_ms,
+        src: SRC,
+        dst: BROADCAST,
+        rssi: -61,
+        channel: 6,
+        data,
+    }
+}
+
+#[test]
+fn json_lines() {
+    let mut gateway = Gateway::new();
+    gateway.forward(&frame(81234, &[0x01, 0x00, 0x07, 0xab]));
+    assert_eq!(
+        gateway.next_line(),
+        Some(
+            "{\"ts_ms\":81234,\"src\":\"34:85:18:01:02:03\",\"dst\":\"ff:ff:ff:ff:ff:ff\",\
+             \"rssi\":-61,\"channel\":6,\"data\":\"010007ab\"}\n"
+        )
+    );
+}
+
+#[test]
+fn lines_parse_back() {
+    let payload = [0x01, 0x00, 0x07, 0xab];
+    let mut gateway = Gateway::new();
+    gateway.forward(&frame(81234, &payload));
+    let mut data = [0; MAX_PAYLOAD];
+    assert_eq!(
+        parse_json(gateway.next_line().unwrap(), &mut data),
+        Ok(frame(81234, &payload))
+    );
+
+    // any order, spaces and unknown fields
+    let line = r#" { "data": "", "gateway": "kitchen", "channel": 1, "rssi": -90,
+        "dst": "34:85:18:01:02:03", "src": "ff:ff:ff:ff:ff:ff", "ts_ms": 7 } "#;
+    let mut data = [0; MAX_PAYLOAD];
+    let parsed = parse_json(line, &mut data).unwrap();
+    assert_eq!((parsed.ts_ms, parsed.rssi, parsed.channel), (7, -90, 1));
+    assert_eq!((parsed.src, parsed.dst), (BROADCAST, SRC));
+    assert!(parsed.data.is_empty());
+}
+
+#[test]
+fn broken_lines() {
+    let line = "{\"ts_ms\":1,\"src\":\"34:85:18:01:02:03\",\"dst\":\"ff:ff:ff:ff:ff:ff\",\
+                \"rssi\":-61,\"channel\":6,\"data\":\"0a\"}";
+    let mut data = [0; MAX_PAYLOAD];
+    assert!(parse_json(line, &mut data).is_ok());
+
+    for (broken, error) in [
+        ("", LineError::Syntax),
+        ("ts_ms=1", LineError::Syntax),
+        ("{\"ts_ms\":1,}", LineError::Syntax),
+        ("{\"ts_ms\" 1}", LineError::Syntax),
+        ("{\"ts_ms\":1 \"rssi\":2}", LineError::Syntax),
+        ("{\"ts_ms\":true}", LineError::Syntax),
+        ("{\"ts_ms\":\"a\\\"b\"}", LineError::Syntax),
+        ("{\"ts_ms\":1}", LineError::Missing("data")),
+        ("{\"data\":\"\"}", LineError::Missing("ts_ms")),
+        ("{\"ts_ms\":-1,\"data\":\"\"}", LineError::Invalid("ts_ms")),
+        ("{\"rssi\":-129,\"data\":\"\"}", LineError::Invalid("rssi")),
+        (
+            "{\"channel\":\"6\",\"data\":\"\"}",
+            LineError::Invalid("channel"),
+        ),
+        (
+            "{\"src\":\"34:85:18:01:02\",\"data\":\"\"}",
+            LineError::Invalid("src"),
+        ),
+        (
+            "{\"dst\":\"34:85:18:01:02:0g\",\"data\":\"\"}",
+            LineError::Invalid("dst"),
+        ),
+        ("{\"data\":\"abc\"}", LineError::Invalid("data")),
+        ("{\"data\":\"zz\"}", LineError::Invalid("data")),
+    ] {
+        assert_eq!(parse_json(broken, &mut data), Err(error), "{}", broken);
+    }
+
+    let too_long = format!("{{\"data\":\"{}\"}}", "00".repeat(MAX_PAYLOAD + 1));
+    assert_eq!(
+        parse_json(&too_long, &mut data),
+        Err(LineError::Invalid("data"))
+    );
+}
+
+#[test]
+fn longest_line_fits() {
+    let data = [0xff; MAX_PAYLOAD];
+    let longest = Forwarded {
+        ts_ms: u64::MAX,
+        rssi: -128,
+        channel: 255,
+        ..frame(0, &data)
+    };
+    let mut gateway = Gateway::new();
+    gateway.forward(&longest);
+    let line = gateway.next_line().unwrap();
+    assert!(line.len() <= MAX_LINE_LEN);
+    assert!(line.ends_with("ff\"}\n"));
+
+    // more than fits into an ESP-NOW frame
+    gateway.forward(&frame(0, &[0; MAX_PAYLOAD + 1]));
+    assert_eq!(gateway.pending(), 1);
+    assert_eq!(gateway.dropped(), 1);
+}
+
+#[test]
+fn queue_drops_the_oldest() {
+    let mut gateway = Gateway::new();
+    assert_eq!(gateway.next_line(), None);
+    for ts in 0..MAX_QUEUED_LINES as u64 + 2 {
+        gateway.forward(&frame(ts, &[]));
+    }
+    assert_eq!(gateway.pending(), MAX_QUEUED_LINES);
+    assert_eq!(gateway.dropped(), 2);
+    assert!(gateway.next_line().unwrap().starts_with("{\"ts_ms\":2,"));
+
+    // a line stays until it is sent
+    assert!(gateway.next_line().unwrap().starts_with("{\"ts_ms\":2,"));
+    gateway.sent();
+    assert!(gateway.next_line().unwrap().starts_with("{\"ts_ms\":3,"));
+    assert_eq!(gateway.forwarded(), 1);
+
+    while gateway.next_line().is_some() {
+        gateway.sent();
+    }
+    // nothing left to count
+    gateway.sent();
+    assert_eq!(gateway.forwarded(), MAX_QUEUED_LINES as u32);
+}
+
+#[test]
+fn collectors() {
+    let collector = Collector::parse("udp://192.168.1.10:9000").unwrap();
+    assert_eq!(
+        collector,
+        Collector {
+            transport: Transport::Udp,
+            ip: [192, 168, 1, 10],
+            port: 9000,
+        }
+    );
+    assert_eq!(collector.to_string(), "udp://192.168.1.10:9000");
+    assert_eq!(
+        Collector::parse("tcp://10.0.0.1:1").unwrap().to_string(),
+        "tcp://10.0.0.1:1"
+    );
+
+    for (s, error) in [
+        ("192.168.1.10:9000", ParseError::Transport),
+        ("http://192.168.1.10:9000", ParseError::Transport),
+        ("udp://192.168.1.10", ParseError::Port),
+        ("udp://192.168.1.10:0", ParseError::Port),
+        ("udp://192.168.1.10:65536", ParseError::Port),
+        ("udp://collector.lan:9000", ParseError::Address),
+        ("udp://192.168.1:9000", ParseError::Address),
+        ("udp://192.168.1.10.1:9000", ParseError::Address),
+        ("udp://192.168.1.256:9000", ParseError::Address),
+    ] {
+        assert_eq!(Collector::parse(s), Err(error), "{}", s);
+    }
+}
+
+#[test]
+fn uplink_connects_and_retries() {
+    let mut uplink = TcpUplink::new();
+    assert_eq!(uplink.update(0, false), Some(UplinkCommand::Open));
+    assert_eq!(uplink.update(10, false), None);
+    assert!(!uplink.is_connected());
+    assert_eq!(uplink.update(20, true), None);
+    assert!(uplink.is_connected());
+
+    // the collector went away
+    assert_eq!(uplink.update(1000, false), Some(UplinkCommand::Close));
+    assert!(!uplink.is_connected());
+    assert_eq!(uplink.update(1000 + RETRY_MS - 1, false), None);
+    assert_eq!(
+        uplink.update(1000 + RETRY_MS, false),
+        Some(UplinkCommand::Open)
+    );
+
+    // nobody answers
+    let opened = 1000 + RETRY_MS;
+    assert_eq!(uplink.update(opened + CONNECT_TIMEOUT_MS - 1, false), None);
+    assert_eq!(
+        uplink.update(opened + CONNECT_TIMEOUT_MS, false),
+        Some(UplinkCommand::Close)
+    );
+
+    // a failed write
+    let mut uplink = TcpUplink::new();
+    uplink.update(0, false);
+    uplink.update(0, true);
+    assert_eq!(uplink.failed(50), UplinkCommand::Close);
+    assert_eq!(uplink.update(50, true), None);
+    assert_eq!(
+        uplink.update(50 + RETRY_MS, false),
+        Some(UplinkCommand::Open)
+    );
+}
+
+#[test]
+fn record_round_trip() {
+    assert_eq!(gateway::decode(&gateway::encode(None)), Ok(None));
+    let collector = Collector::parse("tcp://172.16.0.2:5140").ok();
+    let record = gateway::encode(collector);
+    assert_eq!(gateway::decode(&record), Ok(collector));
+
+    assert_eq!(gateway::decode(&[0xff; 16]), Ok(None));
+    let mut corrupt = record;
+    corrupt[8] ^= 1;
+    assert_eq!(gateway::decode(&corrupt), Err(DecodeError::Corrupt));
+    let mut newer = record;
+    newer[4] = 2;
+    assert_eq!(
+        gateway::decode(&newer),
+        Err(DecodeError::UnsupportedVersion(2))
+    );
+    assert_eq!(gateway::decode(&record[..8]), Err(DecodeError::Corrupt));
+}
+
+/// Flash in RAM, erased to `0xff`.
+struct RamFlash(Vec<u8>);
+
+impl ReadStorage for RamFlash {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(self.0.get(offset..offset + bytes.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Storage for RamFlash {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        self.0
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(())?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn store() {
+    let mut store = CollectorStore::with_offset(RamFlash(vec![0xff; 8192]), 4096);
+    assert_eq!(store.load().unwrap(), None);
+
+    let collector = Collector::parse("udp://192.168.1.10:9000").ok();
+    store.save(collector).unwrap();
+    assert_eq!(store.load().unwrap(), collector);
+    store.save(None).unwrap();
+    assert_eq!(store.load().unwrap(), None);
+
+    let flash = store.into_inner();
+    assert!(flash.0[..4096].iter().all(|&b| b == 0xff));
+}
@@ -0,0 +1,108 @@
+//! Request parsing and response writing of the HTTP helpers.
+
+use esp32_c3_buddy_like::http::{
+    self, form_field, url_decode, Html, Method, ParseError, Request, Status,
+};
+
+#[test]
+fn parse_get() {
+    let request =
+        Request::parse(b"GET /info?verbose=1 HTTP/1.1\r\nHost: buddy\r\nAccept: */*\r\n\r\n")
+            .unwrap();
+    assert_eq!(request.method, Method::Get);
+    assert_eq!(request.path, "/info");
+    assert_eq!(request.query, Some("verbose=1"));
+    assert_eq!(request.header("host"), Some("buddy"));
+    assert_eq!(request.header("Accept"), Some("*/*"));
+    assert_eq!(request.header("Content-Type"), None);
+    assert!(request.body.is_empty());
+}
+
+#[test]
+fn parse_waits_for_the_body() {
+    let full = b"POST /save HTTP/1.1\r\nContent-Length: 11\r\n\r\nssid=a&b=cd";
+    for len in 0..full.len() {
+        assert_eq!(
+            Request::parse(&full[..len]).unwrap_err(),
+            ParseError::Incomplete,
+            "{} bytes",
+            len
+        );
+    }
+
+    let request = Request::parse(full).unwrap();
+    assert_eq!(request.method, Method::Post);
+    assert_eq!(request.body, b"ssid=a&b=cd");
+}
+
+#[test]
+fn parse_rejects_garbage() {
+    for request in [
+        &b"\x16\x03\x01\x02\x00\x01\r\n\r\n"[..],
+        b"GET\r\n\r\n",
+        b"GET index.html HTTP/1.1\r\n\r\n",
+        b"GET / SPDY/3\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+        // more than fits into the buffer, or into a usize on the board
+        b"POST / HTTP/1.1\r\nContent-Length: 1024\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: 4294967295\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n",
+    ] {
+        assert_eq!(
+            Request::parse(request).unwrap_err(),
+            ParseError::Invalid,
+            "{:?}",
+            String::from_utf8_lossy(request)
+        );
+    }
+}
+
+#[test]
+fn form_fields() {
+    let form = b"ssid=My+Net&password=p%26ss%3Dw%C3%B6rd&empty=&flag";
+    assert_eq!(form_field(form, "ssid"), Some(&b"My+Net"[..]));
+    assert_eq!(form_field(form, "empty"), Some(&b""[..]));
+    assert_eq!(form_field(form, "flag"), Some(&b""[..]));
+    assert_eq!(form_field(form, "missing"), None);
+
+    let password: heapless::String<16> = url_decode(form_field(form, "password").unwrap()).unwrap();
+    assert_eq!(password, "p&ss=wörd");
+    let ssid: heapless::String<16> = url_decode(b"My+Net").unwrap();
+    assert_eq!(ssid, "My Net");
+
+    assert_eq!(url_decode::<16>(b"%zz"), None);
+    assert_eq!(url_decode::<16>(b"%4"), None);
+    assert_eq!(url_decode::<16>(b"%ff"), None);
+    assert_eq!(url_decode::<4>(b"12345"), None);
+}
+
+#[test]
+fn html_escaping() {
+    assert_eq!(
+        Html("<b>\"Tom & Jerry's\"</b>").to_string(),
+        "&lt;b&gt;&quot;Tom &amp; Jerry&#39;s&quot;&lt;/b&gt;"
+    );
+}
+
+#[test]
+fn responses() {
+    let mut response = String::new();
+    http::write_response(&mut response, Status::NotFound, "text/plain", "nope").unwrap();
+    assert_eq!(
+        response,
+        "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\
+         Connection: close\r\n\r\nnope"
+    );
+
+    let mut streamed = String::new();
+    http::write_response_with(&mut streamed, Status::NotFound, "text/plain", |w| {
+        w.write_str("no")?;
+        w.write_str("pe")
+    })
+    .unwrap();
+    assert_eq!(streamed, response);
+
+    let mut redirect = String::new();
+    http::write_redirect(&mut redirect, "http://192.168.4.1/").unwrap();
+    assert!(redirect.starts_with("HTTP/1.1 302 Found\r\nLocation: http://192.168.4.1/\r\n"));
+}
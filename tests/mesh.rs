@@ -0,0 +1,344 @@
+//! Flooding through the mesh, on one router and on virtual boards.
+
+use esp32_c3_buddy_like::{
+    app::{Mac, BROADCAST},
+    pairing::PairedPeer,
+    protocol::{
+        mesh::{MeshConfig, Router, SendError, MAX_QUEUED, SEEN_LEN, TAG_LEN},
+        EncodeError, Frame, Mesh, Message, MAX_MESH_DATA_LEN,
+    },
+    sim::MeshNetwork,
+};
+
+const A: Mac = [0xa; 6];
+const B: Mac = [0xb; 6];
+const C: Mac = [0xc; 6];
+
+/// The mesh message in a frame sent by a router.
+fn mesh(data: &[u8]) -> Mesh<'_> {
+    match Frame::decode(data).unwrap().message {
+        Message::Mesh(mesh) => mesh,
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+fn no_jitter() -> MeshConfig {
+    MeshConfig {
+        max_jitter_ms: 0,
+        ..Default::default()
+    }
+}
+
+/// Which boards got the message `id` from `origin`, and after how many hops.
+fn reached(network: &MeshNetwork, origin: usize, id: u16) -> Vec<(usize, u8)> {
+    let mut reached: Vec<_> = network
+        .delivered()
+        .iter()
+        .filter(|d| d.origin == MeshNetwork::mac(origin) && d.id == id)
+        .map(|d| (d.node, d.hops))
+        .collect();
+    reached.sort();
+    reached
+}
+
+#[test]
+fn send_wraps_a_frame() {
+    let mut router = Router::new(A, MeshConfig::default(), 5);
+    let id = router.send(0, BROADCAST, Message::Counter(7)).unwrap();
+
+    let outgoing = router.poll_transmit(0).unwrap();
+    assert_eq!(outgoing.dst, BROADCAST);
+    let mesh = mesh(&outgoing.data);
+    assert_eq!(
+        (mesh.origin, mesh.id, mesh.ttl, mesh.hops, mesh.dst),
+        (A, id, 4, 0, BROADCAST)
+    );
+    assert_eq!(
+        Frame::decode(mesh.data),
+        Ok(Frame::new(id, Message::Counter(7)))
+    );
+    assert_eq!(router.poll_transmit(100), None);
+
+    // the next message has the next id
+    assert_eq!(
+        router.send(0, C, Message::Counter(8)),
+        Ok(id.wrapping_add(1))
+    );
+}
+
+#[test]
+fn relays_new_messages_once() {
+    let mut a = Router::new(A, no_jitter(), 1);
+    let mut b = Router::new(B, no_jitter(), 2);
+    a.send(0, BROADCAST, Message::Text("hi")).unwrap();
+    let sent = a.poll_transmit(0).unwrap();
+
+    let delivered = b.receive(0, mesh(&sent.data)).unwrap();
+    assert_eq!(
+        (delivered.origin, delivered.dst, delivered.hops),
+        (A, BROADCAST, 0)
+    );
+    assert_eq!(
+        Frame::decode(delivered.data).unwrap().message,
+        Message::Text("hi")
+    );
+    let relayed = b.poll_transmit(0).unwrap();
+    let relay = mesh(&relayed.data);
+    assert_eq!((relay.origin, relay.ttl, relay.hops), (A, 3, 1));
+    assert_eq!(relay.data, delivered.data);
+
+    // a second copy is dropped, and so is the echo of the origin's own
+    assert_eq!(b.receive(0, mesh(&sent.data)), None);
+    assert_eq!(b.poll_transmit(0), None);
+    assert_eq!(a.receive(0, relay), None);
+    assert_eq!(a.poll_transmit(0), None);
+    assert_eq!((b.relayed(), b.duplicates()), (1, 1));
+    assert_eq!(a.duplicates(), 1);
+}
+
+#[test]
+fn unicast_and_ttl() {
+    let mut a = Router::new(A, no_jitter(), 1);
+    let mut b = Router::new(B, no_jitter(), 2);
+    let mut c = Router::new(C, no_jitter(), 3);
+
+    // for C: B passes it on, C keeps it
+    a.send(0, C, Message::Ping(1)).unwrap();
+    let sent = a.poll_transmit(0).unwrap();
+    assert_eq!(b.receive(0, mesh(&sent.data)), None);
+    let relayed = b.poll_transmit(0).unwrap();
+    let delivered = c.receive(0, mesh(&relayed.data)).unwrap();
+    assert_eq!((delivered.origin, delivered.dst, delivered.hops), (A, C, 1));
+    assert_eq!(c.poll_transmit(0), None);
+
+    // the last hop delivers, but does not relay
+    let last = Mesh {
+        id: 99,
+        ttl: 0,
+        ..mesh(&sent.data)
+    };
+    let last = Mesh {
+        dst: BROADCAST,
+        ..last
+    };
+    assert!(b.receive(0, last).is_some());
+    assert_eq!(b.poll_transmit(0), None);
+}
+
+#[test]
+fn spoofed_origin_is_rejected() {
+    // A and B are paired, C is not
+    let key = PairedPeer {
+        mac: A,
+        lmk: [7; 16],
+    }
+    .mesh_key();
+    let mut a = Router::new(A, no_jitter(), 1);
+    let mut b = Router::new(B, no_jitter(), 2);
+    let mut c = Router::new(C, no_jitter(), 3);
+
+    // C claims to be A, without a tag, with one of its own and with a
+    // genuine one taken from another message
+    a.send_authenticated(0, B, Message::Ping(1), &key).unwrap();
+    let genuine = a.poll_transmit(0).unwrap();
+    let genuine = mesh(&genuine.data);
+    let frame = Frame::new(7, Message::Text("open")).to_vec().unwrap();
+    let mut forged_tag = frame.clone();
+    forged_tag.extend_from_slice(&[0; TAG_LEN]).unwrap();
+    let mut copied_tag = frame.clone();
+    copied_tag
+        .extend_from_slice(&genuine.data[genuine.data.len() - TAG_LEN..])
+        .unwrap();
+    let forged = |id, data| Mesh {
+        origin: A,
+        id,
+        ttl: 2,
+        hops: 0,
+        dst: B,
+        data,
+    };
+    for (id, data) in [(10, &frame[..]), (11, &forged_tag), (12, &copied_tag)] {
+        let delivered = b.receive(0, forged(id, data)).unwrap();
+        assert_eq!(delivered.origin, A);
+        assert_eq!(delivered.authenticated(&key), None, "id {}", id);
+    }
+    // nor does moving a genuine message to another id help
+    let moved = b.receive(0, Mesh { id: 13, ..genuine }).unwrap();
+    assert_eq!(moved.authenticated(&key), None);
+
+    // the genuine one is taken, but only with A's key
+    let delivered = b.receive(0, genuine).unwrap();
+    let data = delivered.authenticated(&key).unwrap();
+    assert_eq!(Frame::decode(data).unwrap().message, Message::Ping(1));
+    assert_eq!(delivered.authenticated(&[1; 32]), None);
+
+    // boards on the way relay what they cannot check
+    c.send_authenticated(0, B, Message::Ping(2), &[1; 32])
+        .unwrap();
+    let sent = c.poll_transmit(0).unwrap();
+    assert_eq!(a.receive(0, mesh(&sent.data)), None);
+    assert_eq!(
+        mesh(&a.poll_transmit(0).unwrap().data).data,
+        mesh(&sent.data).data
+    );
+}
+
+#[test]
+fn relays_wait_a_little() {
+    let config = MeshConfig {
+        max_jitter_ms: 20,
+        ..Default::default()
+    };
+    let mut a = Router::new(A, config, 1);
+    let mut b = Router::new(B, config, 2);
+    // the first send goes out right away
+    for _ in 0..MAX_QUEUED {
+        a.send(0, BROADCAST, Message::Counter(1)).unwrap();
+    }
+    assert_eq!(
+        a.send(0, BROADCAST, Message::Counter(1)),
+        Err(SendError::QueueFull)
+    );
+
+    let mut due = Vec::new();
+    while let Some(sent) = a.poll_transmit(0) {
+        b.receive(0, mesh(&sent.data)).unwrap();
+    }
+    for now in 0..=20 {
+        while b.poll_transmit(now).is_some() {
+            due.push(now);
+        }
+    }
+    assert_eq!(due.len(), MAX_QUEUED);
+    assert!(due.iter().any(|&now| now > 0), "{:?}", due);
+}
+
+#[test]
+fn copies_are_forgotten_after_a_while() {
+    let mut b = Router::new(B, no_jitter(), 2);
+    let message = |id| Mesh {
+        origin: A,
+        id,
+        ttl: 0,
+        hops: 0,
+        dst: BROADCAST,
+        data: b"",
+    };
+    assert!(b.receive(0, message(0)).is_some());
+    for id in 1..SEEN_LEN as u16 {
+        assert!(b.receive(0, message(id)).is_some());
+    }
+    assert!(b.receive(0, message(0)).is_none());
+    assert!(b.receive(0, message(SEEN_LEN as u16)).is_some());
+    // pushed out by now
+    assert!(b.receive(0, message(0)).is_some());
+}
+
+#[test]
+fn too_long() {
+    let mut a = Router::new(A, MeshConfig::default(), 1);
+    // a frame adds 11 bytes to the text
+    let text = "x".repeat(MAX_MESH_DATA_LEN - 11);
+    a.send(0, BROADCAST, Message::Text(&text)).unwrap();
+    let text = "x".repeat(MAX_MESH_DATA_LEN - 10);
+    assert_eq!(
+        a.send(0, BROADCAST, Message::Text(&text)),
+        Err(SendError::Encode(EncodeError::TooLong))
+    );
+}
+
+#[test]
+fn across_a_building() {
+    // seven boards in a row, each only in range of the next
+    let mut network = MeshNetwork::line(7, MeshConfig::default());
+    let id = network.send(0, BROADCAST, Message::Text("hello"));
+    network.run(200);
+
+    // four relays: up to five boards away
+    assert_eq!(
+        reached(&network, 0, id),
+        [(1, 0), (2, 1), (3, 2), (4, 3), (5, 4)]
+    );
+    // the sender and every relay once, the last board in reach stops
+    assert_eq!(network.transmissions(), 5);
+
+    let delivered = network.delivered().last().unwrap();
+    assert_eq!(
+        Frame::decode(&delivered.data).unwrap().message,
+        Message::Text("hello")
+    );
+
+    // a longer TTL reaches the end
+    let config = MeshConfig {
+        ttl: 6,
+        ..Default::default()
+    };
+    let mut network = MeshNetwork::line(7, config);
+    let id = network.send(6, MeshNetwork::mac(0), Message::Counter(1));
+    network.run(200);
+    assert_eq!(reached(&network, 6, id), [(0, 5)]);
+}
+
+#[test]
+fn every_board_hears_it_once() {
+    // all in range of each other
+    let mut network = MeshNetwork::new(5, MeshConfig::default());
+    for a in 0..5 {
+        for b in a + 1..5 {
+            network.link(a, b);
+        }
+    }
+    let id = network.send(2, BROADCAST, Message::Counter(3));
+    network.run(200);
+
+    assert_eq!(reached(&network, 2, id), [(0, 0), (1, 0), (3, 0), (4, 0)]);
+    assert_eq!(network.transmissions(), 5);
+    let duplicates: u32 = (0..5).map(|n| network.router(n).duplicates()).sum();
+    // each of the five sends is heard by four boards, only once it is new
+    assert_eq!(duplicates, 5 * 4 - 4);
+}
+
+#[test]
+fn around_a_broken_link() {
+    // a 4x4 grid of boards, each in range of those next to it
+    let config = MeshConfig {
+        ttl: 8,
+        ..Default::default()
+    };
+    let mut network = MeshNetwork::new(16, config);
+    for row in 0..4 {
+        for col in 0..4 {
+            let n = row * 4 + col;
+            if col < 3 {
+                network.link(n, n + 1);
+            }
+            if row < 3 {
+                network.link(n, n + 4);
+            }
+        }
+    }
+    network.unlink(5, 6);
+    network.unlink(9, 10);
+    network.set_loss(10);
+
+    // corner to corner, 6 hops at best
+    let mut ids = Vec::new();
+    for n in 0..20 {
+        ids.push(network.send(0, MeshNetwork::mac(15), Message::Counter(n)));
+        network.run(100);
+    }
+    let mut arrived = 0;
+    for id in ids {
+        match reached(&network, 0, id)[..] {
+            [] => {}
+            [(15, hops)] => {
+                assert!(hops >= 5);
+                arrived += 1;
+            }
+            ref other => panic!("message {} reached {:?}", id, other),
+        }
+    }
+    // flooding only makes up for losses with other paths
+    assert!(arrived >= 18, "only {} of 20 arrived", arrived);
+    assert!(network.delivered().iter().all(|d| d.node == 15));
+}
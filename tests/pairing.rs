@@ -0,0 +1,391 @@
+//! Pairing two simulated boards, and storing the result.
+
+use embedded_storage::{ReadStorage, Storage};
+use esp32_c3_buddy_like::{
+    app::{Mac, Outgoing, BROADCAST},
+    config::{DecodeError, StoreError},
+    pairing::{
+        PairedPeer, PairedPeers, Pairing, PairingError, PairingEvent, PairingStore, Stage,
+        MAX_PAIRED, MAX_PAIRED_RECORD_LEN, PAIRING_TIMEOUT_MS,
+    },
+    protocol::{Frame, Message},
+};
+
+const A: Mac = [0x34, 0x85, 0x18, 0, 0, 0xa];
+const B: Mac = [0x34, 0x85, 0x18, 0, 0, 0xb];
+const M: Mac = [0x34, 0x85, 0x18, 0, 0, 0xee];
+
+struct Board {
+    mac: Mac,
+    pairing: Pairing,
+}
+
+impl Board {
+    fn new(mac: Mac) -> Self {
+        Board {
+            mac,
+            pairing: Pairing::new(mac),
+        }
+    }
+
+    /// Enter pairing mode with keys made from `seed`.
+    fn start(&mut self, now: u64, seed: u8) {
+        self.pairing.start(now, [seed; 32], [seed ^ 0x55; 16]);
+    }
+
+    fn code(&self) -> Option<u16> {
+        match self.pairing.stage() {
+            Stage::Comparing { code, .. } | Stage::Confirmed { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
+    fn events(&mut self) -> Vec<PairingEvent> {
+        std::iter::from_fn(|| self.pairing.pop_event()).collect()
+    }
+
+    fn deliver(&mut self, now: u64, src: Mac, outgoing: &Outgoing) {
+        if outgoing.dst == self.mac || outgoing.dst == BROADCAST {
+            let frame = Frame::decode(&outgoing.data).unwrap();
+            assert!(self.pairing.receive(now, src, &frame.message));
+        }
+    }
+}
+
+/// Let `boards` talk from `from` to `to` in 10 ms steps; `lose` drops
+/// transmissions.
+fn run(boards: &mut [Board], from: u64, to: u64, lose: &mut dyn FnMut() -> bool) {
+    for now in (from..to).step_by(10) {
+        for i in 0..boards.len() {
+            let src = boards[i].mac;
+            while let Some(outgoing) = boards[i].pairing.poll_transmit(now) {
+                for j in (0..boards.len()).filter(|&j| j != i) {
+                    if !lose() {
+                        boards[j].deliver(now, src, &outgoing);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn lossless() -> bool {
+    false
+}
+
+fn paired(events: &[PairingEvent]) -> PairedPeer {
+    match events {
+        [PairingEvent::Paired(peer)] => *peer,
+        events => panic!("not paired: {:?}", events),
+    }
+}
+
+#[test]
+fn two_boards_pair() {
+    let mut boards = [Board::new(A), Board::new(B)];
+    assert_eq!(boards[0].pairing.stage(), Stage::Idle);
+    boards[0].start(0, 1);
+    assert_eq!(boards[0].pairing.stage(), Stage::Searching);
+    // the other user is a bit slower
+    run(&mut boards, 0, 2_000, &mut lossless);
+    boards[1].start(2_000, 2);
+    run(&mut boards, 2_000, 3_000, &mut lossless);
+
+    let code = boards[0].code().unwrap();
+    assert_eq!(boards[1].code(), Some(code));
+    assert!(code < 10_000);
+    assert_eq!(
+        boards[0].pairing.stage(),
+        Stage::Comparing { peer: B, code }
+    );
+
+    assert!(boards[0].pairing.confirm(3_000));
+    assert!(!boards[0].pairing.confirm(3_000));
+    run(&mut boards, 3_000, 10_000, &mut lossless);
+    assert_eq!(
+        boards[0].pairing.stage(),
+        Stage::Confirmed { peer: B, code }
+    );
+    assert!(boards[1].pairing.confirm(10_000));
+    run(&mut boards, 10_000, 15_000, &mut lossless);
+
+    let a = paired(&boards[0].events());
+    let b = paired(&boards[1].events());
+    assert_eq!((a.mac, b.mac), (B, A));
+    assert_eq!(a.lmk, b.lmk);
+    assert!(!boards[0].pairing.is_active());
+    assert_eq!(boards[1].pairing.stage(), Stage::Idle);
+
+    // the key depends on the random input
+    let mut again = [Board::new(A), Board::new(B)];
+    again[0].start(0, 3);
+    again[1].start(0, 2);
+    run(&mut again, 0, 1_000, &mut lossless);
+    again[0].pairing.confirm(1_000);
+    again[1].pairing.confirm(1_000);
+    run(&mut again, 1_000, 5_000, &mut lossless);
+    assert_ne!(paired(&again[0].events()).lmk, a.lmk);
+}
+
+#[test]
+fn pairs_over_a_lossy_channel() {
+    for seed in 1..20u32 {
+        let mut boards = [Board::new(A), Board::new(B)];
+        boards[0].start(0, seed as u8);
+        boards[1].start(0, !seed as u8);
+
+        let mut rng = seed.wrapping_mul(0x9e37_79b9);
+        let mut lose = move || {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            rng % 10 < 4
+        };
+        run(&mut boards, 0, 5_000, &mut lose);
+        assert_eq!(boards[0].code(), boards[1].code(), "seed {}", seed);
+        boards[0].pairing.confirm(5_000);
+        boards[1].pairing.confirm(5_000);
+        run(&mut boards, 5_000, 20_000, &mut lose);
+
+        let a = paired(&boards[0].events());
+        let b = paired(&boards[1].events());
+        assert_eq!(a.lmk, b.lmk, "seed {}", seed);
+    }
+}
+
+#[test]
+fn times_out_without_a_peer() {
+    let mut board = Board::new(A);
+    board.start(1_000, 1);
+    let mut sent = 0;
+    for now in (1_000..1_000 + PAIRING_TIMEOUT_MS + 100).step_by(10) {
+        while let Some(outgoing) = board.pairing.poll_transmit(now) {
+            assert_eq!(outgoing.dst, BROADCAST);
+            let frame = Frame::decode(&outgoing.data).unwrap();
+            assert!(matches!(frame.message, Message::PairCommit { .. }));
+            sent += 1;
+        }
+    }
+    assert_eq!(sent, 100);
+    assert_eq!(
+        board.events(),
+        [PairingEvent::Failed(PairingError::TimedOut)]
+    );
+    assert!(!board.pairing.is_active());
+}
+
+#[test]
+fn rejected_code() {
+    let mut boards = [Board::new(A), Board::new(B)];
+    boards[0].start(0, 1);
+    boards[1].start(0, 2);
+    run(&mut boards, 0, 1_000, &mut lossless);
+    assert!(boards[0].pairing.confirm(1_000));
+    boards[1].pairing.cancel();
+    assert_eq!(
+        boards[1].events(),
+        [PairingEvent::Failed(PairingError::Cancelled)]
+    );
+
+    run(
+        &mut boards,
+        1_000,
+        PAIRING_TIMEOUT_MS + 1_000,
+        &mut lossless,
+    );
+    assert_eq!(
+        boards[0].events(),
+        [PairingEvent::Failed(PairingError::TimedOut)]
+    );
+    // cancelling when not pairing does nothing
+    boards[1].pairing.cancel();
+    assert_eq!(boards[1].events(), []);
+}
+
+#[test]
+fn man_in_the_middle_shows_other_codes() {
+    // M pairs with A and B separately, relaying nothing it cannot make up
+    let mut to_a = [Board::new(A), Board::new(M)];
+    let mut to_b = [Board::new(B), Board::new(M)];
+    let mut mismatches = 0;
+    for seed in 0..20u8 {
+        to_a[0].start(0, seed);
+        to_a[1].start(0, seed.wrapping_add(100));
+        to_b[0].start(0, seed.wrapping_add(50));
+        to_b[1].start(0, seed.wrapping_add(150));
+        run(&mut to_a, 0, 1_000, &mut lossless);
+        run(&mut to_b, 0, 1_000, &mut lossless);
+        if to_a[0].code().unwrap() != to_b[0].code().unwrap() {
+            mismatches += 1;
+        }
+    }
+    assert_eq!(mismatches, 20);
+}
+
+#[test]
+fn bad_reveal_and_confirmation() {
+    let mut boards = [Board::new(A), Board::new(B)];
+    boards[0].start(0, 1);
+    boards[1].start(0, 2);
+    // A hears B's commit, but then a nonce that does not match it
+    while boards[1].pairing.poll_transmit(0).is_some() {}
+    let commit = boards[1].pairing.poll_transmit(300).unwrap();
+    boards[0].deliver(300, B, &commit);
+    let reveal = Message::PairReveal { nonce: [0; 16] };
+    assert!(boards[0].pairing.receive(300, B, &reveal));
+    assert_eq!(
+        boards[0].events(),
+        [PairingEvent::Failed(PairingError::BadPeer)]
+    );
+
+    let mut boards = [Board::new(A), Board::new(B)];
+    boards[0].start(0, 1);
+    boards[1].start(0, 2);
+    run(&mut boards, 0, 1_000, &mut lossless);
+    assert!(boards[0].code().is_some());
+    let confirm = Message::PairConfirm { tag: [0; 16] };
+    // from someone else it is ignored
+    assert!(boards[0].pairing.receive(1_000, M, &confirm));
+    assert_eq!(boards[0].events(), []);
+    assert!(boards[0].pairing.receive(1_000, B, &confirm));
+    assert_eq!(
+        boards[0].events(),
+        [PairingEvent::Failed(PairingError::BadPeer)]
+    );
+}
+
+#[test]
+fn sticks_to_the_first_peer() {
+    let mut boards = [Board::new(A), Board::new(B), Board::new(M)];
+    boards[0].start(0, 1);
+    boards[1].start(0, 2);
+    run(&mut boards[..2], 0, 1_000, &mut lossless);
+    let code = boards[0].code().unwrap();
+
+    // a third board starts pairing nearby
+    boards[2].start(1_000, 3);
+    run(&mut boards, 1_000, 2_000, &mut lossless);
+    assert_eq!(
+        boards[0].pairing.stage(),
+        Stage::Comparing { peer: B, code }
+    );
+    assert_eq!(boards[1].code(), Some(code));
+}
+
+#[test]
+fn other_messages_pass() {
+    let mut pairing = Pairing::new(A);
+    assert!(!pairing.receive(0, B, &Message::Counter(1)));
+    // pairing messages are used up even when not pairing
+    assert!(pairing.receive(0, B, &Message::PairReveal { nonce: [1; 16] }));
+    assert_eq!(pairing.poll_transmit(0), None);
+    assert_eq!(pairing.pop_event(), None);
+}
+
+fn peer(n: u8) -> PairedPeer {
+    PairedPeer {
+        mac: [0x34, 0x85, 0x18, 0, 0, n],
+        lmk: [n; 16],
+    }
+}
+
+#[test]
+fn paired_peers() {
+    let mut peers = PairedPeers::default();
+    for n in 0..MAX_PAIRED as u8 {
+        assert_eq!(peers.add(peer(n)), None);
+    }
+    assert_eq!(peers.peers()[0], peer(MAX_PAIRED as u8 - 1));
+    // pairing again moves to the front, with the new key
+    let renewed = PairedPeer {
+        lmk: [0xaa; 16],
+        ..peer(3)
+    };
+    assert_eq!(peers.add(renewed), None);
+    assert_eq!(peers.peers()[0], renewed);
+    assert_eq!(peers.get(&peer(3).mac), Some(&renewed));
+    // when full the oldest goes
+    assert_eq!(peers.add(peer(100)), Some(peer(0)));
+    assert!(!peers.contains(&peer(0).mac));
+    assert!(peers.contains(&peer(100).mac));
+
+    assert!(peers.remove(&peer(100).mac));
+    assert!(!peers.remove(&peer(100).mac));
+    assert_eq!(peers.peers().len(), MAX_PAIRED - 1);
+}
+
+#[test]
+fn paired_peers_encoding() {
+    let mut peers = PairedPeers::default();
+    peers.add(peer(1));
+    peers.add(peer(2));
+
+    let mut buf = [0; MAX_PAIRED_RECORD_LEN];
+    let len = peers.encode(&mut buf);
+    assert_eq!(len, 6 + 2 * 22 + 4);
+    assert_eq!(&buf[..6], b"BPAR\x01\x02");
+    assert_eq!(PairedPeers::decode(&buf[..len]), Ok(peers.clone()));
+
+    assert_eq!(
+        PairedPeers::decode(&[0xff; MAX_PAIRED_RECORD_LEN]),
+        Ok(PairedPeers::default())
+    );
+    let mut corrupt = buf;
+    corrupt[10] ^= 1;
+    assert_eq!(PairedPeers::decode(&corrupt), Err(DecodeError::Corrupt));
+    assert_eq!(
+        PairedPeers::decode(&buf[..len - 1]),
+        Err(DecodeError::Corrupt)
+    );
+    let mut newer = buf;
+    newer[4] = 2;
+    assert_eq!(
+        PairedPeers::decode(&newer),
+        Err(DecodeError::UnsupportedVersion(2))
+    );
+}
+
+/// Flash in RAM, erased to `0xff`.
+struct RamFlash(Vec<u8>);
+
+impl ReadStorage for RamFlash {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(self.0.get(offset..offset + bytes.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Storage for RamFlash {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        self.0
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(())?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn pairing_store() {
+    let mut store = PairingStore::with_offset(RamFlash(vec![0xff; 8192]), 4096);
+    assert_eq!(store.load().unwrap(), PairedPeers::default());
+
+    let mut peers = PairedPeers::default();
+    peers.add(peer(7));
+    store.save(&peers).unwrap();
+    assert_eq!(store.load().unwrap(), peers);
+
+    let flash = store.into_inner();
+    assert!(flash.0[..4096].iter().all(|&b| b == 0xff));
+
+    let mut store = PairingStore::with_offset(RamFlash(vec![0; 100]), 4096);
+    assert!(matches!(store.load(), Err(StoreError::Storage(()))));
+}
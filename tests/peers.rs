@@ -0,0 +1,254 @@
+//! The peer table: eviction, names and persistence.
+
+use embedded_storage::{ReadStorage, Storage};
+use esp32_c3_buddy_like::{
+    app::Mac,
+    config::{DecodeError, StoreError},
+    peers::{
+        Peer, PeerStore, PeerTable, RenameError, MAX_KNOWN_PEERS, MAX_PEERS_RECORD_LEN, ONLINE_MS,
+        SAVE_INTERVAL_MS,
+    },
+};
+
+fn mac(n: u8) -> Mac {
+    [0x34, 0x85, 0x18, 0, 0xa1, n]
+}
+
+fn macs(table: &PeerTable) -> Vec<u8> {
+    table.peers().iter().map(|p| p.mac[5]).collect()
+}
+
+#[test]
+fn new_peers() {
+    let mut table = PeerTable::new();
+    assert!(table.is_empty());
+
+    assert_eq!(table.seen(100, mac(1), -40), None);
+    let peer = table.get(&mac(1)).unwrap();
+    assert_eq!(peer.name, "buddy-a101");
+    assert_eq!(peer.last_seen_ms, Some(100));
+    assert_eq!(peer.rssi, Some(-40));
+    assert!(!peer.paired && !peer.encrypted);
+    // saved, but not right after boot
+    assert!(!table.take_changed(100));
+    assert!(table.take_changed(SAVE_INTERVAL_MS));
+
+    // heard from again, nothing to save
+    table.seen(200, mac(1), -70);
+    let peer = table.get(&mac(1)).unwrap();
+    assert_eq!((peer.last_seen_ms, peer.rssi), (Some(200), Some(-70)));
+    assert!(!table.take_changed(3 * SAVE_INTERVAL_MS));
+}
+
+#[test]
+fn traffic_is_saved_at_most_every_interval() {
+    let mut table = PeerTable::new();
+    table.set_paired(mac(0), true, true);
+    assert!(table.take_changed(0));
+
+    // boards making up MACs, one after the other
+    let mut saves = 0;
+    for n in 0..=240u64 {
+        let now = n * 30_000;
+        table.seen(now, [2, 0, 0, 0, (n >> 8) as u8, n as u8], -80);
+        saves += table.take_changed(now) as u64;
+    }
+    assert_eq!(saves, 240 * 30_000 / SAVE_INTERVAL_MS);
+
+    // a different order is saved too, for eviction after a reboot
+    table.seen(7_300_000, mac(0), -50);
+    assert!(!table.take_changed(7_300_000));
+    assert!(table.take_changed(7_200_000 + SAVE_INTERVAL_MS));
+    assert_eq!(table.peers()[0].mac, mac(0));
+
+    // while what the user does is saved right away
+    table.rename(&mac(0), "kitchen").unwrap();
+    assert!(table.take_changed(7_200_000 + SAVE_INTERVAL_MS + 1));
+}
+
+#[test]
+fn online() {
+    let mut table = PeerTable::new();
+    table.seen(0, mac(1), -40);
+    table.seen(10_000, mac(2), -50);
+    table.seen(20_000, mac(3), -60);
+    table.set_paired(mac(4), true, true);
+
+    assert_eq!(table.online_count(20_000), 3);
+    assert_eq!(table.online_count(ONLINE_MS), 2);
+    let online: Vec<_> = table.online(ONLINE_MS + 10_000).map(|p| p.mac[5]).collect();
+    assert_eq!(online, [3]);
+    assert_eq!(table.online_count(ONLINE_MS + 20_000), 0);
+}
+
+#[test]
+fn least_recently_seen_go_first() {
+    let mut table = PeerTable::new();
+    for n in 0..MAX_KNOWN_PEERS as u8 {
+        assert_eq!(table.seen(n as u64, mac(n), -50), None);
+    }
+    assert_eq!(macs(&table)[..3], [15, 14, 13]);
+
+    // hearing from 0 again saves it
+    table.seen(100, mac(0), -50);
+    let evicted = table.seen(101, mac(100), -50).unwrap();
+    assert_eq!(evicted.mac, mac(1));
+    assert_eq!(table.peers().len(), MAX_KNOWN_PEERS);
+    assert_eq!(macs(&table)[..2], [100, 0]);
+    assert_eq!(table.get(&mac(1)), None);
+}
+
+#[test]
+fn paired_peers_stay() {
+    let mut table = PeerTable::new();
+    table.set_paired(mac(0), true, true);
+    for n in 1..MAX_KNOWN_PEERS as u8 {
+        table.seen(n as u64, mac(n), -50);
+    }
+
+    // 0 is the oldest, but paired
+    assert_eq!(table.seen(100, mac(100), -50).unwrap().mac, mac(1));
+    assert!(table.get(&mac(0)).unwrap().paired);
+
+    // unless there is nothing else
+    let mut table = PeerTable::new();
+    for n in 0..MAX_KNOWN_PEERS as u8 {
+        table.set_paired(mac(n), true, false);
+    }
+    assert_eq!(table.seen(0, mac(100), -50).unwrap().mac, mac(0));
+}
+
+#[test]
+fn pairing_and_names() {
+    let mut table = PeerTable::new();
+    table.seen(0, mac(1), -50);
+    table.take_changed(0);
+
+    table.set_paired(mac(1), true, true);
+    let peer = table.get(&mac(1)).unwrap();
+    assert!(peer.paired && peer.encrypted);
+    // still heard from
+    assert_eq!(peer.rssi, Some(-50));
+    assert!(table.take_changed(0));
+    table.set_paired(mac(1), true, true);
+    assert!(!table.take_changed(0));
+
+    table.rename(&mac(1), "kitchen").unwrap();
+    assert_eq!(table.get(&mac(1)).unwrap().name, "kitchen");
+    assert!(table.take_changed(0));
+    assert_eq!(
+        table.rename(&mac(1), "a very long name"),
+        Err(RenameError::TooLong)
+    );
+    assert_eq!(table.rename(&mac(1), ""), Err(RenameError::Empty));
+    assert_eq!(table.rename(&mac(2), "hall"), Err(RenameError::UnknownPeer));
+    assert!(!table.take_changed(0));
+
+    assert_eq!(table.remove(&mac(1)).unwrap().name, "kitchen");
+    assert_eq!(table.remove(&mac(1)), None);
+    assert!(table.take_changed(0));
+}
+
+#[test]
+fn encoding() {
+    let mut table = PeerTable::new();
+    table.seen(5, mac(1), -50);
+    table.set_paired(mac(2), true, true);
+    table.seen(7, mac(3), -60);
+    table.rename(&mac(3), "kitchen ü").unwrap();
+
+    let mut buf = [0; MAX_PEERS_RECORD_LEN];
+    let len = table.encode(&mut buf);
+    assert_eq!(&buf[..6], b"BPER\x01\x03");
+    let decoded = PeerTable::decode(&buf[..len]).unwrap();
+
+    // the order and all but the timestamps and RSSI survive
+    assert_eq!(macs(&decoded), [3, 2, 1]);
+    let expected: Vec<_> = table
+        .peers()
+        .iter()
+        .map(|p| Peer {
+            last_seen_ms: None,
+            rssi: None,
+            ..p.clone()
+        })
+        .collect();
+    assert_eq!(decoded.peers(), &expected[..]);
+    assert_eq!(decoded.online_count(7), 0);
+
+    // a full table fits
+    let mut full = PeerTable::new();
+    for n in 0..MAX_KNOWN_PEERS as u8 {
+        full.seen(0, mac(n), -50);
+    }
+    assert_eq!(full.encode(&mut buf), MAX_PEERS_RECORD_LEN);
+    assert_eq!(
+        PeerTable::decode(&buf).unwrap().peers().len(),
+        MAX_KNOWN_PEERS
+    );
+
+    assert_eq!(
+        PeerTable::decode(&[0xff; MAX_PEERS_RECORD_LEN]),
+        Ok(PeerTable::default())
+    );
+    let len = table.encode(&mut buf);
+    let mut broken = buf;
+    broken[10] ^= 1;
+    assert_eq!(PeerTable::decode(&broken[..len]), Err(DecodeError::Corrupt));
+    assert_eq!(
+        PeerTable::decode(&buf[..len - 1]),
+        Err(DecodeError::Corrupt)
+    );
+    let mut newer = buf;
+    newer[4] = 2;
+    assert_eq!(
+        PeerTable::decode(&newer),
+        Err(DecodeError::UnsupportedVersion(2))
+    );
+}
+
+/// Flash in RAM, erased to `0xff`.
+struct RamFlash(Vec<u8>);
+
+impl ReadStorage for RamFlash {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(self.0.get(offset..offset + bytes.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Storage for RamFlash {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        self.0
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(())?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn peer_store() {
+    let mut store = PeerStore::with_offset(RamFlash(vec![0xff; 8192]), 4096);
+    assert_eq!(store.load().unwrap(), PeerTable::default());
+
+    let mut table = PeerTable::new();
+    table.set_paired(mac(7), true, true);
+    table.take_changed(0);
+    store.save(&table).unwrap();
+    assert_eq!(store.load().unwrap(), table);
+
+    let flash = store.into_inner();
+    assert!(flash.0[..4096].iter().all(|&b| b == 0xff));
+
+    let mut store = PeerStore::with_offset(RamFlash(vec![0; 100]), 4096);
+    assert!(matches!(store.load(), Err(StoreError::Storage(()))));
+}
@@ -0,0 +1,408 @@
+//! The ESP-NOW frame format: round trips, the exact layout and what happens
+//! to broken or random input.
+
+use esp32_c3_buddy_like::{
+    crc::crc32,
+    protocol::{
+        BlobKind, Command, DecodeError, EncodeError, Fragment, Frame, Mesh, Message, Sensor,
+        SensorReading, FRAGMENT_DATA_LEN, MAX_BLOB_LEN, MAX_FRAME_LEN, MAX_MESH_DATA_LEN,
+        MAX_MESSAGE_LEN,
+    },
+};
+
+fn encode(frame: &Frame<'_>) -> Vec<u8> {
+    frame.to_vec().unwrap().to_vec()
+}
+
+fn messages() -> Vec<Message<'static>> {
+    vec![
+        Message::Text(""),
+        Message::Text("Hello Peer"),
+        Message::Text("grüße"),
+        Message::Counter(0),
+        Message::Counter(u32::MAX),
+        Message::Ping(1),
+        Message::Pong(0x1234_5678),
+        Message::SensorReading(SensorReading {
+            sensor: Sensor::Temperature,
+            value: 21_500,
+        }),
+        Message::SensorReading(SensorReading {
+            sensor: Sensor::Other(200),
+            value: i32::MIN,
+        }),
+        Message::Command(Command::Led(true)),
+        Message::Command(Command::Led(false)),
+        Message::Command(Command::Identify),
+        Message::Command(Command::Reboot),
+        Message::Ack {
+            seq: 0xbeef,
+            broadcast: true,
+        },
+        Message::Fragment(Fragment {
+            transfer: 3,
+            kind: BlobKind::Bitmap,
+            index: 1,
+            blob_len: 300,
+            data: &[0x55; 300 - FRAGMENT_DATA_LEN],
+        }),
+        Message::Fragment(Fragment {
+            transfer: 0xffff,
+            kind: BlobKind::Other(42),
+            index: 0,
+            blob_len: 0,
+            data: &[],
+        }),
+        Message::PairCommit {
+            public_key: [1; 32],
+            commitment: [2; 32],
+        },
+        Message::PairReveal { nonce: [3; 16] },
+        Message::PairConfirm { tag: [4; 16] },
+        Message::Mesh(Mesh {
+            origin: [1, 2, 3, 4, 5, 6],
+            id: 0x0102,
+            ttl: 3,
+            hops: 1,
+            dst: [0xff; 6],
+            data: b"BD\x01\x02",
+        }),
+    ]
+}
+
+#[test]
+fn round_trip() {
+    for (seq, message) in [0, 1, 0x1234, u16::MAX]
+        .into_iter()
+        .zip(messages().into_iter().cycle())
+    {
+        let frame = Frame::new(seq, message);
+        assert_eq!(Frame::decode(&encode(&frame)), Ok(frame));
+    }
+    for message in messages() {
+        let frame = Frame::new(7, message);
+        assert_eq!(Frame::decode(&encode(&frame)), Ok(frame));
+    }
+}
+
+#[test]
+fn layout() {
+    let bytes = encode(&Frame::new(0x0102, Message::Counter(0x0a0b_0c0d)));
+    assert_eq!(&bytes[..11], b"BD\x01\x02\x02\x01\x04\x0d\x0c\x0b\x0a");
+    assert_eq!(bytes[11..], crc32(&bytes[..11]).to_le_bytes());
+
+    let bytes = encode(&Frame::new(
+        0,
+        Message::SensorReading(SensorReading {
+            sensor: Sensor::Voltage,
+            value: -1,
+        }),
+    ));
+    assert_eq!(&bytes[3..12], b"\x05\x00\x00\x05\x02\xff\xff\xff\xff");
+
+    let frame = Frame {
+        ack_requested: true,
+        ..Frame::new(
+            0x0102,
+            Message::Ack {
+                seq: 0x0304,
+                broadcast: false,
+            },
+        )
+    };
+    let bytes = encode(&frame);
+    assert_eq!(&bytes[3..10], b"\x87\x02\x01\x03\x04\x03\x00");
+    assert_eq!(Frame::decode(&bytes), Ok(frame));
+
+    let bytes = encode(&Frame::new(
+        0,
+        Message::Fragment(Fragment {
+            transfer: 0x0102,
+            kind: BlobKind::Log,
+            index: 0,
+            blob_len: 2,
+            data: b"ok",
+        }),
+    ));
+    assert_eq!(&bytes[3..15], b"\x08\x00\x00\x08\x02\x01\x02\x00\x02\x00ok");
+
+    let bytes = encode(&Frame::new(
+        0,
+        Message::Mesh(Mesh {
+            origin: [1, 2, 3, 4, 5, 6],
+            id: 0x0102,
+            ttl: 3,
+            hops: 1,
+            dst: [7; 6],
+            data: b"in",
+        }),
+    ));
+    assert_eq!(
+        &bytes[3..25],
+        b"\x0c\x00\x00\x12\x01\x02\x03\x04\x05\x06\x02\x01\x03\x01\x07\x07\x07\x07\x07\x07in"
+    );
+
+    let bytes = encode(&Frame::new(0, Message::Text("hi")));
+    assert_eq!(&bytes[3..9], b"\x01\x00\x00\x02hi");
+    assert_eq!(bytes.len(), 13);
+}
+
+#[test]
+fn longest_text() {
+    let text = "x".repeat(MAX_MESSAGE_LEN);
+    let bytes = encode(&Frame::new(1, Message::Text(&text)));
+    assert_eq!(bytes.len(), MAX_FRAME_LEN);
+    assert_eq!(Frame::decode(&bytes).unwrap().message, Message::Text(&text));
+
+    let text = "x".repeat(MAX_MESSAGE_LEN + 1);
+    assert_eq!(
+        Frame::new(1, Message::Text(&text)).to_vec(),
+        Err(EncodeError::TooLong)
+    );
+    // nor is it taken from others, even with a good checksum
+    for len in [MAX_MESSAGE_LEN + 1, 255] {
+        let mut bytes = encode(&Frame::new(1, Message::Text("")));
+        bytes.truncate(7);
+        bytes[6] = len as u8;
+        bytes.extend(std::iter::repeat(b'x').take(len));
+        bytes.extend([0; 4]);
+        reseal(&mut bytes);
+        assert_eq!(Frame::decode(&bytes), Err(DecodeError::BadLength));
+    }
+
+    let data = [7; FRAGMENT_DATA_LEN];
+    let fragment = Fragment {
+        transfer: 1,
+        kind: BlobKind::Config,
+        index: 0,
+        blob_len: MAX_BLOB_LEN as u16,
+        data: &data,
+    };
+    let bytes = encode(&Frame::new(1, Message::Fragment(fragment)));
+    assert_eq!(bytes.len(), MAX_FRAME_LEN);
+
+    // data that does not fit index and blob length
+    for (index, blob_len, len) in [
+        (0, 300, FRAGMENT_DATA_LEN - 1),
+        (1, 300, 300 - FRAGMENT_DATA_LEN + 1),
+        (2, 300, 0),
+        (0, MAX_BLOB_LEN as u16 + 1, FRAGMENT_DATA_LEN),
+    ] {
+        let fragment = Fragment {
+            index,
+            blob_len,
+            data: &data[..len],
+            ..fragment
+        };
+        assert_eq!(
+            Frame::new(1, Message::Fragment(fragment)).to_vec(),
+            Err(EncodeError::BadFragment)
+        );
+    }
+
+    let data = [7; MAX_MESH_DATA_LEN + 1];
+    let mesh = Mesh {
+        origin: [1; 6],
+        id: 1,
+        ttl: 1,
+        hops: 0,
+        dst: [2; 6],
+        data: &data[..MAX_MESH_DATA_LEN],
+    };
+    let bytes = encode(&Frame::new(1, Message::Mesh(mesh)));
+    assert_eq!(bytes.len(), MAX_FRAME_LEN);
+    let mesh = Mesh {
+        data: &data,
+        ..mesh
+    };
+    assert_eq!(
+        Frame::new(1, Message::Mesh(mesh)).to_vec(),
+        Err(EncodeError::TooLong)
+    );
+}
+
+/// Fix up the checksum after tampering with a frame.
+fn reseal(bytes: &mut [u8]) {
+    let len = bytes.len() - 4;
+    let crc = crc32(&bytes[..len]);
+    bytes[len..].copy_from_slice(&crc.to_le_bytes());
+}
+
+#[test]
+fn decode_errors() {
+    let good = encode(&Frame::new(1, Message::Counter(5)));
+
+    assert_eq!(Frame::decode(&[]), Err(DecodeError::TooShort));
+    assert_eq!(Frame::decode(&good[..10]), Err(DecodeError::TooShort));
+    assert_eq!(Frame::decode(b"117"), Err(DecodeError::TooShort));
+    assert_eq!(
+        Frame::decode(b"Hello Peer, how are you?"),
+        Err(DecodeError::BadMagic)
+    );
+
+    let mut bytes = good.clone();
+    bytes[2] = 2;
+    assert_eq!(
+        Frame::decode(&bytes),
+        Err(DecodeError::UnsupportedVersion(2))
+    );
+
+    assert_eq!(Frame::decode(&good[..14]), Err(DecodeError::BadLength));
+    let mut longer = good.clone();
+    longer.push(0);
+    assert_eq!(Frame::decode(&longer), Err(DecodeError::BadLength));
+
+    let mut bytes = good.clone();
+    bytes[8] ^= 1;
+    assert_eq!(Frame::decode(&bytes), Err(DecodeError::BadChecksum));
+
+    let mut bytes = good.clone();
+    bytes[3] = 99;
+    reseal(&mut bytes);
+    assert_eq!(Frame::decode(&bytes), Err(DecodeError::UnknownType(99)));
+
+    // a counter of the wrong size
+    let mut bytes = encode(&Frame::new(1, Message::Text("abc")));
+    bytes[3] = 2;
+    reseal(&mut bytes);
+    assert_eq!(Frame::decode(&bytes), Err(DecodeError::BadPayload));
+
+    // a mesh frame too long to relay
+    let mut bytes = encode(&Frame::new(1, Message::Text(&"x".repeat(MAX_MESSAGE_LEN))));
+    bytes[3] = 12;
+    reseal(&mut bytes);
+    assert_eq!(
+        Frame::decode(&bytes),
+        Ok(Frame::new(
+            1,
+            Message::Mesh(Mesh {
+                origin: *b"xxxxxx",
+                id: 0x7878,
+                ttl: b'x',
+                hops: b'x',
+                dst: *b"xxxxxx",
+                data: &[b'x'; MAX_MESH_DATA_LEN],
+            })
+        ))
+    );
+    let mut longer = bytes[..bytes.len() - 4].to_vec();
+    longer.extend_from_slice(b"x\0\0\0\0");
+    longer[6] += 1;
+    reseal(&mut longer);
+    assert_eq!(Frame::decode(&longer), Err(DecodeError::BadLength));
+
+    // text that is not UTF-8
+    let mut bytes = encode(&Frame::new(1, Message::Text("abc")));
+    bytes[7] = 0xff;
+    reseal(&mut bytes);
+    assert_eq!(Frame::decode(&bytes), Err(DecodeError::BadPayload));
+
+    // a fragment claiming more data than it has
+    let fragment = Fragment {
+        transfer: 1,
+        kind: BlobKind::Bitmap,
+        index: 0,
+        blob_len: 3,
+        data: b"abc",
+    };
+    let mut bytes = encode(&Frame::new(1, Message::Fragment(fragment)));
+    bytes[11] = 4;
+    reseal(&mut bytes);
+    assert_eq!(Frame::decode(&bytes), Err(DecodeError::BadPayload));
+    // or too short for its header
+    let mut bytes = encode(&Frame::new(1, Message::Text("abcde")));
+    bytes[3] = 8;
+    reseal(&mut bytes);
+    assert_eq!(Frame::decode(&bytes), Err(DecodeError::BadPayload));
+
+    // an unknown command
+    let mut bytes = encode(&Frame::new(1, Message::Command(Command::Led(true))));
+    bytes[8] = 2;
+    reseal(&mut bytes);
+    assert_eq!(Frame::decode(&bytes), Err(DecodeError::BadPayload));
+}
+
+#[test]
+fn every_bit_flip_is_detected() {
+    for message in messages() {
+        let good = encode(&Frame::new(0x4242, message));
+        for bit in 0..good.len() * 8 {
+            let mut bytes = good.clone();
+            bytes[bit / 8] ^= 1 << (bit % 8);
+            assert!(Frame::decode(&bytes).is_err(), "{:?} bit {}", message, bit);
+        }
+    }
+}
+
+#[test]
+fn sensor_reading_display() {
+    let reading = |sensor, value| SensorReading { sensor, value }.to_string();
+    assert_eq!(
+        reading(Sensor::Temperature, 21_500),
+        "temperature: 21.500 C"
+    );
+    assert_eq!(reading(Sensor::Temperature, -50), "temperature: -0.050 C");
+    assert_eq!(reading(Sensor::Humidity, 45_000), "humidity: 45.000 %");
+    assert_eq!(reading(Sensor::Voltage, 3_300), "voltage: 3.300 V");
+    assert_eq!(reading(Sensor::Other(9), -3), "sensor 9: -3");
+}
+
+/// xorshift32, so the fuzz tests are reproducible.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+#[test]
+fn fuzz_random_bytes() {
+    let mut rng = Rng(0x1234_5678);
+    for _ in 0..20_000 {
+        let len = rng.next() as usize % (MAX_FRAME_LEN + 10);
+        let mut bytes = rng.bytes(len);
+        // get past the first checks now and then
+        if rng.next() % 2 == 0 && len >= 11 {
+            bytes[..3].copy_from_slice(b"BD\x01");
+            bytes[3] = (1 + rng.next() as u8 % 12) | (rng.next() as u8 & 0x80);
+            bytes[6] = (len - 11) as u8;
+            reseal(&mut bytes);
+        }
+        if let Ok(frame) = Frame::decode(&bytes) {
+            // whatever decodes encodes to the same bytes
+            assert_eq!(encode(&frame), bytes);
+        }
+    }
+}
+
+#[test]
+fn fuzz_round_trip() {
+    let mut rng = Rng(42);
+    for _ in 0..5_000 {
+        let text: String = (0..rng.next() as usize % 60)
+            .map(|_| char::from_u32(rng.next() % 0x800).unwrap_or('?'))
+            .collect();
+        let value = rng.next();
+        let message = match value % 6 {
+            0 => Message::Text(&text),
+            1 => Message::Counter(value),
+            2 => Message::Ping(value),
+            3 => Message::Pong(value),
+            4 => Message::SensorReading(SensorReading {
+                sensor: Sensor::Other(3 + value as u8 % 200),
+                value: value as i32,
+            }),
+            _ => Message::Command(Command::Led(value % 2 == 0)),
+        };
+        let frame = Frame::new(rng.next() as u16, message);
+        assert_eq!(Frame::decode(&encode(&frame)), Ok(frame));
+    }
+}
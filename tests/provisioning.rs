@@ -0,0 +1,284 @@
+//! Captive portal: the setup page, DHCP and DNS.
+
+use esp32_c3_buddy_like::{
+    config::Credentials,
+    http::Request,
+    provisioning::{
+        dhcp::{DhcpServer, MAX_LEASES, REPLY_LEN},
+        dns::{self, MAX_MESSAGE_LEN},
+        portal::{self, Outcome},
+        AP_IP,
+    },
+};
+
+const NETWORKS: &[(&str, i8)] = &[("home", -40), ("<script>", -80)];
+
+fn get(path: &str) -> (String, Outcome) {
+    let raw = format!("GET {} HTTP/1.1\r\nHost: example.com\r\n\r\n", path);
+    let mut response = String::new();
+    let outcome = portal::handle(
+        &Request::parse(raw.as_bytes()).unwrap(),
+        NETWORKS,
+        &mut response,
+    )
+    .unwrap();
+    (response, outcome)
+}
+
+fn post(path: &str, body: &str) -> (String, Outcome) {
+    let raw = format!(
+        "POST {} HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+         Content-Length: {}\r\n\r\n{}",
+        path,
+        body.len(),
+        body
+    );
+    let mut response = String::new();
+    let outcome = portal::handle(
+        &Request::parse(raw.as_bytes()).unwrap(),
+        NETWORKS,
+        &mut response,
+    )
+    .unwrap();
+    (response, outcome)
+}
+
+fn content_length(response: &str) -> usize {
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let length = head
+        .lines()
+        .find_map(|l| l.strip_prefix("Content-Length: "))
+        .unwrap();
+    assert_eq!(length.parse::<usize>().unwrap(), body.len());
+    body.len()
+}
+
+#[test]
+fn form_lists_the_scan() {
+    let (response, outcome) = get("/");
+    assert_eq!(outcome, Outcome::Continue);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(content_length(&response) > 0);
+    assert!(response.contains("value=\"home\" checked> home (-40 dBm)"));
+    assert!(response.contains("&lt;script&gt; (-80 dBm)"));
+    assert!(!response.contains("<script>"));
+}
+
+#[test]
+fn other_pages_redirect_to_the_form() {
+    let (response, outcome) = get("/generate_204");
+    assert_eq!(outcome, Outcome::Continue);
+    assert!(response.starts_with("HTTP/1.1 302 Found\r\nLocation: http://192.168.4.1/\r\n"));
+}
+
+#[test]
+fn save_chosen_network() {
+    let (response, outcome) = post("/save", "ssid=home&other=&password=se+cr%3Det");
+    assert_eq!(
+        outcome,
+        Outcome::Save(Credentials {
+            ssid: "home".try_into().unwrap(),
+            password: "se cr=et".try_into().unwrap(),
+        })
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    content_length(&response);
+
+    // a network typed in wins over the selection
+    let (_, outcome) = post("/save", "ssid=home&other=Hidden+Net&password=");
+    assert_eq!(
+        outcome,
+        Outcome::Save(Credentials {
+            ssid: "Hidden Net".try_into().unwrap(),
+            password: "".try_into().unwrap(),
+        })
+    );
+}
+
+#[test]
+fn invalid_form() {
+    for body in [
+        "other=&password=x".to_string(),
+        format!("ssid={}&password=x", "s".repeat(33)),
+        format!("ssid=home&password={}", "p".repeat(65)),
+    ] {
+        let (response, outcome) = post("/save", &body);
+        assert_eq!(outcome, Outcome::Continue, "{}", body);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        content_length(&response);
+    }
+
+    let (response, _) = post("/", "");
+    assert!(response.starts_with("HTTP/1.1 405 "));
+}
+
+/// A DHCP message from `mac` with the given options.
+fn dhcp_request(mac: [u8; 6], options: &[u8]) -> Vec<u8> {
+    let mut message = vec![0; 240];
+    message[0] = 1;
+    message[1] = 1;
+    message[2] = 6;
+    message[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+    message[10] = 0x80;
+    message[28..34].copy_from_slice(&mac);
+    message[236..240].copy_from_slice(&[99, 130, 83, 99]);
+    message.extend_from_slice(options);
+    message.push(255);
+    message
+}
+
+fn reply_option(reply: &[u8], code: u8) -> Option<Vec<u8>> {
+    let mut options = &reply[240..];
+    while options[0] != 255 {
+        let len = options[1] as usize;
+        if options[0] == code {
+            return Some(options[2..2 + len].to_vec());
+        }
+        options = &options[2 + len..];
+    }
+    None
+}
+
+#[test]
+fn dhcp_discover_and_request() {
+    let mut server = DhcpServer::new(AP_IP);
+    let mac = [2, 0, 0, 0, 0, 1];
+    let mut reply = [0; REPLY_LEN];
+
+    let len = server
+        .handle(&dhcp_request(mac, &[53, 1, 1]), &mut reply)
+        .unwrap();
+    assert_eq!(len, REPLY_LEN);
+    assert_eq!(reply[0], 2);
+    assert_eq!(reply[4..8], [0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(reply[10], 0x80);
+    assert_eq!(reply[16..20], [192, 168, 4, 2]);
+    assert_eq!(reply[28..34], mac);
+    assert_eq!(reply_option(&reply, 53), Some(vec![2]));
+    assert_eq!(reply_option(&reply, 54), Some(AP_IP.to_vec()));
+    assert_eq!(reply_option(&reply, 1), Some(vec![255, 255, 255, 0]));
+    assert_eq!(reply_option(&reply, 3), Some(AP_IP.to_vec()));
+    assert_eq!(reply_option(&reply, 6), Some(AP_IP.to_vec()));
+
+    let request = dhcp_request(
+        mac,
+        &[53, 1, 3, 50, 4, 192, 168, 4, 2, 54, 4, 192, 168, 4, 1],
+    );
+    server.handle(&request, &mut reply).unwrap();
+    assert_eq!(reply_option(&reply, 53), Some(vec![5]));
+    assert_eq!(reply[16..20], [192, 168, 4, 2]);
+    assert_eq!(server.lease(&mac), Some([192, 168, 4, 2]));
+
+    // a second client gets the next address
+    server
+        .handle(&dhcp_request([2, 0, 0, 0, 0, 2], &[53, 1, 1]), &mut reply)
+        .unwrap();
+    assert_eq!(reply[16..20], [192, 168, 4, 3]);
+}
+
+#[test]
+fn dhcp_wrong_requests() {
+    let mut server = DhcpServer::new(AP_IP);
+    let mac = [2, 0, 0, 0, 0, 1];
+    let mut reply = [0; REPLY_LEN];
+
+    // an address we never offered
+    let request = dhcp_request(mac, &[53, 1, 3, 50, 4, 10, 0, 0, 7]);
+    server.handle(&request, &mut reply).unwrap();
+    assert_eq!(reply_option(&reply, 53), Some(vec![6]));
+    assert_eq!(reply[16..20], [0; 4]);
+
+    // the client chose another server
+    server
+        .handle(&dhcp_request(mac, &[53, 1, 1]), &mut reply)
+        .unwrap();
+    let request = dhcp_request(mac, &[53, 1, 3, 50, 4, 192, 168, 4, 2, 54, 4, 10, 0, 0, 1]);
+    assert_eq!(server.handle(&request, &mut reply), None);
+
+    // not DHCP at all
+    assert_eq!(server.handle(&[0; 100], &mut reply), None);
+    let mut reply_message = dhcp_request(mac, &[53, 1, 2]);
+    reply_message[0] = 2;
+    assert_eq!(server.handle(&reply_message, &mut reply), None);
+}
+
+#[test]
+fn dhcp_pool_reuses_the_oldest_lease() {
+    let mut server = DhcpServer::new(AP_IP);
+    let mut reply = [0; REPLY_LEN];
+    let mac = |i: u8| [2, 0, 0, 0, 0, i];
+
+    for i in 0..MAX_LEASES as u8 {
+        server
+            .handle(&dhcp_request(mac(i), &[53, 1, 1]), &mut reply)
+            .unwrap();
+        assert_eq!(reply[16..20], [192, 168, 4, 2 + i]);
+    }
+    // seen again, so the second client is the oldest now
+    server
+        .handle(&dhcp_request(mac(0), &[53, 1, 1]), &mut reply)
+        .unwrap();
+    assert_eq!(reply[16..20], [192, 168, 4, 2]);
+
+    server
+        .handle(&dhcp_request(mac(100), &[53, 1, 1]), &mut reply)
+        .unwrap();
+    assert_eq!(reply[16..20], [192, 168, 4, 3]);
+    assert_eq!(server.lease(&mac(1)), None);
+    assert_eq!(server.lease(&mac(0)), Some([192, 168, 4, 2]));
+}
+
+/// A query for `name` with type `qtype`.
+fn dns_query(name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&[0, 1]);
+    query
+}
+
+#[test]
+fn dns_answers_everything_with_the_access_point() {
+    let query = dns_query("connectivitycheck.gstatic.com", 1);
+    let mut reply = [0; MAX_MESSAGE_LEN];
+    let len = dns::answer(&query, AP_IP, &mut reply).unwrap();
+
+    assert_eq!(len, query.len() + 16);
+    assert_eq!(reply[..2], [0x12, 0x34]);
+    assert_eq!(reply[2..4], [0x81, 0x80]);
+    assert_eq!(reply[4..8], [0, 1, 0, 1]);
+    assert_eq!(reply[12..query.len()], query[12..]);
+    let answer = &reply[query.len()..len];
+    assert_eq!(answer[..2], [0xc0, 12]);
+    assert_eq!(answer[2..6], [0, 1, 0, 1]);
+    assert_eq!(answer[10..12], [0, 4]);
+    assert_eq!(answer[12..], AP_IP);
+}
+
+#[test]
+fn dns_no_ipv6() {
+    let query = dns_query("example.com", 28);
+    let mut reply = [0; MAX_MESSAGE_LEN];
+    let len = dns::answer(&query, AP_IP, &mut reply).unwrap();
+    assert_eq!(len, query.len());
+    assert_eq!(reply[6..8], [0, 0]);
+}
+
+#[test]
+fn dns_ignores_garbage() {
+    let mut reply = [0; MAX_MESSAGE_LEN];
+    let mut response = dns_query("example.com", 1);
+    response[2] |= 0x80;
+    assert_eq!(dns::answer(&response, AP_IP, &mut reply), None);
+
+    let truncated = dns_query("example.com", 1);
+    assert_eq!(
+        dns::answer(&truncated[..truncated.len() - 3], AP_IP, &mut reply),
+        None
+    );
+    assert_eq!(dns::answer(&[0; 5], AP_IP, &mut reply), None);
+}
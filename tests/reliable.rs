@@ -0,0 +1,337 @@
+//! Reliable ESP-NOW delivery between simulated nodes.
+
+use esp32_c3_buddy_like::{
+    app::{Mac, Outgoing, BROADCAST},
+    protocol::{
+        reliable::{Event, Reliable, ReliableConfig, SendError, MAX_PENDING},
+        Frame, Message,
+    },
+};
+
+const A: Mac = [0xa; 6];
+const B: Mac = [0xb; 6];
+const C: Mac = [0xc; 6];
+
+const CONFIG: ReliableConfig = ReliableConfig {
+    ack_timeout_ms: 100,
+    max_retries: 3,
+};
+
+struct Node {
+    mac: Mac,
+    reliable: Reliable,
+    /// What the application got.
+    received: Vec<(Mac, u32)>,
+}
+
+impl Node {
+    fn new(mac: Mac, seed: u32) -> Self {
+        Node {
+            mac,
+            reliable: Reliable::new(CONFIG, seed),
+            received: Vec::new(),
+        }
+    }
+
+    fn transmit(&mut self, now: u64) -> Vec<Outgoing> {
+        std::iter::from_fn(|| self.reliable.poll_transmit(now)).collect()
+    }
+
+    fn receive(&mut self, now: u64, src: Mac, outgoing: &Outgoing) {
+        if outgoing.dst != self.mac && outgoing.dst != BROADCAST {
+            return;
+        }
+        if let Some(frame) = self
+            .reliable
+            .receive(now, src, outgoing.dst, &outgoing.data)
+        {
+            match frame.message {
+                Message::Counter(value) => self.received.push((src, value)),
+                message => panic!("unexpected {:?}", message),
+            }
+        }
+    }
+
+    fn events(&mut self) -> Vec<Event> {
+        std::iter::from_fn(|| self.reliable.pop_event()).collect()
+    }
+}
+
+/// Run `nodes` from `from` to `to` in 10 ms steps, `lose` decides whether a
+/// transmission `(src, dst, n)` gets lost, `n` counting all transmissions.
+fn run(nodes: &mut [Node], from: u64, to: u64, lose: &mut dyn FnMut(Mac, Mac, usize) -> bool) {
+    let mut n = 0;
+    for now in (from..to).step_by(10) {
+        for i in 0..nodes.len() {
+            let src = nodes[i].mac;
+            for outgoing in nodes[i].transmit(now) {
+                for j in (0..nodes.len()).filter(|&j| j != i) {
+                    n += 1;
+                    if !lose(src, nodes[j].mac, n) {
+                        nodes[j].receive(now, src, &outgoing);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn lossless(_: Mac, _: Mac, _: usize) -> bool {
+    false
+}
+
+#[test]
+fn unicast() {
+    let mut nodes = [Node::new(A, 1), Node::new(B, 2)];
+    let seq = nodes[0].reliable.send(0, B, Message::Counter(7)).unwrap();
+    assert_eq!(nodes[0].reliable.pending(), 1);
+
+    run(&mut nodes, 0, 50, &mut lossless);
+    assert_eq!(nodes[1].received, [(A, 7)]);
+    assert_eq!(nodes[0].events(), [Event::Delivered { dst: B, seq }]);
+    assert_eq!(nodes[0].reliable.pending(), 0);
+    // B has nothing to report, ACKs are no frames of their own
+    assert_eq!(nodes[1].events(), []);
+}
+
+#[test]
+fn lost_frame_is_sent_again() {
+    let mut nodes = [Node::new(A, 1), Node::new(B, 2)];
+    let seq = nodes[0].reliable.send(0, B, Message::Counter(7)).unwrap();
+
+    let mut sent = Vec::new();
+    run(&mut nodes, 0, 300, &mut |src, _, _| {
+        sent.push(src);
+        // the first frame from A gets lost
+        src == A && sent.iter().filter(|&&s| s == A).count() == 1
+    });
+    // once lost, once delivered, once acknowledged
+    assert_eq!(sent, [A, A, B]);
+    assert_eq!(nodes[1].received, [(A, 7)]);
+    assert_eq!(nodes[0].events(), [Event::Delivered { dst: B, seq }]);
+}
+
+#[test]
+fn lost_ack_does_not_duplicate() {
+    let mut nodes = [Node::new(A, 1), Node::new(B, 2)];
+    let seq = nodes[0].reliable.send(0, B, Message::Counter(7)).unwrap();
+
+    let mut acks = 0;
+    run(&mut nodes, 0, 300, &mut |src, _, _| {
+        if src == B {
+            acks += 1;
+        }
+        src == B && acks == 1
+    });
+    // the repeat was acknowledged but not passed on
+    assert_eq!(acks, 2);
+    assert_eq!(nodes[1].received, [(A, 7)]);
+    assert_eq!(nodes[0].events(), [Event::Delivered { dst: B, seq }]);
+}
+
+#[test]
+fn gives_up() {
+    let mut a = Node::new(A, 1);
+    let seq = a.reliable.send(0, B, Message::Counter(7)).unwrap();
+
+    // the first try and three retries, 100 ms apart
+    let mut sent = Vec::new();
+    for now in (0..1_000).step_by(10) {
+        sent.extend(a.transmit(now).into_iter().map(|_| now));
+    }
+    assert_eq!(sent, [0, 100, 200, 300]);
+    assert_eq!(a.events(), [Event::Failed { dst: B, seq }]);
+    assert_eq!(a.reliable.pending(), 0);
+
+    // a late ACK changes nothing
+    let ack = Frame::new(
+        0,
+        Message::Ack {
+            seq,
+            broadcast: false,
+        },
+    );
+    a.reliable.receive(1_000, B, A, &ack.to_vec().unwrap());
+    assert_eq!(a.events(), []);
+}
+
+#[test]
+fn broadcast_waits_for_every_peer() {
+    let mut nodes = [Node::new(A, 1), Node::new(B, 2), Node::new(C, 3)];
+    let seq = nodes[0]
+        .reliable
+        .broadcast(0, Message::Counter(9), &[B, C])
+        .unwrap();
+
+    // C misses the first broadcast
+    let mut first = true;
+    run(&mut nodes, 0, 300, &mut |src, dst, _| {
+        let lose = src == A && dst == C && first;
+        if src == A && dst == C {
+            first = false;
+        }
+        lose
+    });
+    assert_eq!(nodes[1].received, [(A, 9)]);
+    assert_eq!(nodes[2].received, [(A, 9)]);
+    assert_eq!(
+        nodes[0].events(),
+        [Event::Delivered {
+            dst: BROADCAST,
+            seq
+        }]
+    );
+}
+
+#[test]
+fn broadcast_fails_if_a_peer_is_missing() {
+    let mut nodes = [Node::new(A, 1), Node::new(B, 2)];
+    let seq = nodes[0]
+        .reliable
+        .broadcast(0, Message::Counter(9), &[B, C])
+        .unwrap();
+
+    run(&mut nodes, 0, 1_000, &mut lossless);
+    assert_eq!(nodes[1].received, [(A, 9)]);
+    assert_eq!(
+        nodes[0].events(),
+        [Event::Failed {
+            dst: BROADCAST,
+            seq
+        }]
+    );
+}
+
+#[test]
+fn broadcast_to_nobody() {
+    let mut a = Node::new(A, 1);
+    let seq = a.reliable.broadcast(0, Message::Counter(1), &[]).unwrap();
+    assert_eq!(a.transmit(0).len(), 1);
+    assert_eq!(
+        a.events(),
+        [Event::Delivered {
+            dst: BROADCAST,
+            seq
+        }]
+    );
+    assert_eq!(a.transmit(500).len(), 0);
+}
+
+#[test]
+fn sequence_numbers_per_destination() {
+    let mut a = Node::new(A, 1);
+    let b1 = a.reliable.send(0, B, Message::Counter(1)).unwrap();
+    let c1 = a.reliable.send(0, C, Message::Counter(1)).unwrap();
+    let b2 = a.reliable.send(0, B, Message::Counter(2)).unwrap();
+    let c2 = a.reliable.send(0, C, Message::Counter(2)).unwrap();
+    assert_eq!(b2, b1.wrapping_add(1));
+    assert_eq!(c2, c1.wrapping_add(1));
+    assert_ne!(b1, c1);
+
+    let frames: Vec<_> = a
+        .transmit(0)
+        .into_iter()
+        .map(|o| {
+            let frame = Frame::decode(&o.data).unwrap();
+            assert!(frame.ack_requested);
+            (o.dst, frame.seq)
+        })
+        .collect();
+    assert_eq!(frames.len(), 4);
+    for expected in [(B, b1), (C, c1), (B, b2), (C, c2)] {
+        assert!(frames.contains(&expected));
+    }
+}
+
+#[test]
+fn limits() {
+    let mut a = Node::new(A, 1);
+    for i in 0..MAX_PENDING {
+        a.reliable.send(0, B, Message::Counter(i as u32)).unwrap();
+    }
+    assert_eq!(
+        a.reliable.send(0, B, Message::Counter(0)),
+        Err(SendError::QueueFull)
+    );
+
+    let mut a = Node::new(A, 1);
+    assert_eq!(
+        a.reliable.broadcast(0, Message::Counter(0), &[B; 17]),
+        Err(SendError::TooManyPeers)
+    );
+    let text = "x".repeat(300);
+    assert!(matches!(
+        a.reliable.send(0, B, Message::Text(&text)),
+        Err(SendError::Encode(_))
+    ));
+    assert_eq!(a.reliable.pending(), 0);
+}
+
+#[test]
+fn plain_and_invalid_frames() {
+    let mut b = Reliable::new(CONFIG, 2);
+
+    // frames without an ACK request are passed on every time, unanswered
+    let plain = Frame::new(5, Message::Counter(1)).to_vec().unwrap();
+    assert!(b.receive(0, A, B, &plain).is_some());
+    assert!(b.receive(0, A, B, &plain).is_some());
+    assert_eq!(b.poll_transmit(0), None);
+
+    assert!(b.receive(0, A, B, b"117").is_none());
+    assert_eq!(b.poll_transmit(0), None);
+}
+
+#[test]
+fn restarted_sender_gets_through() {
+    let mut b = Node::new(B, 2);
+    for seed in [1, 1_000, 123_456] {
+        // a fresh start, as after a reboot of A
+        let mut a = Node::new(A, seed);
+        a.reliable.send(0, B, Message::Counter(seed)).unwrap();
+        for outgoing in a.transmit(0) {
+            b.receive(0, A, &outgoing);
+        }
+    }
+    assert_eq!(b.received, [(A, 1), (A, 1_000), (A, 123_456)]);
+}
+
+#[test]
+fn noisy_channel() {
+    let mut nodes = [Node::new(A, 11), Node::new(B, 22)];
+    let mut rng = 0x9e37_79b9u32;
+    let mut lose = |_: Mac, _: Mac, _: usize| {
+        // xorshift32, 30% loss in both directions
+        rng ^= rng << 13;
+        rng ^= rng >> 17;
+        rng ^= rng << 5;
+        rng % 10 < 3
+    };
+
+    let mut now = 0;
+    let mut delivered = 0;
+    let mut failed = 0;
+    for value in 0..200 {
+        nodes[0]
+            .reliable
+            .send(now, B, Message::Counter(value))
+            .unwrap();
+        run(&mut nodes, now, now + 1_000, &mut lose);
+        now += 1_000;
+        for event in nodes[0].events() {
+            match event {
+                Event::Delivered { .. } => delivered += 1,
+                Event::Failed { .. } => failed += 1,
+            }
+        }
+    }
+
+    // 4 tries with 51% round trip loss each fail about 6% of the time
+    assert_eq!(delivered + failed, 200);
+    assert!(failed < 25, "{} failed", failed);
+    // every frame arrived at most once, and at least the delivered ones
+    let values: Vec<u32> = nodes[1].received.iter().map(|&(_, v)| v).collect();
+    let mut unique = values.clone();
+    unique.dedup();
+    assert_eq!(values, unique);
+    assert!(values.len() >= delivered);
+}
@@ -0,0 +1,177 @@
+//! Golden image tests for the screens, run with `cargo host-test`.
+//!
+//! Set `UPDATE_GOLDEN=1` to regenerate the images in `tests/golden` after an
+//! intentional change.
+
+use esp32_c3_buddy_like::{
+    app::chat::ChatMessage,
+    pairing::Stage,
+    peers::PeerTable,
+    screens::{self, WifiStatus},
+    sim::{GoldenMismatch, MockDisplay, UPDATE_GOLDEN_ENV},
+    VisibleArea,
+};
+
+fn golden(name: &str) -> String {
+    format!("{}/tests/golden/{}.pbm", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn render(draw: impl FnOnce(&mut VisibleArea<MockDisplay>)) -> MockDisplay {
+    let mut display = VisibleArea::new(MockDisplay::new());
+    draw(&mut display);
+    display.into_inner()
+}
+
+#[test]
+fn counter() {
+    render(|d| screens::counter(d, 0).unwrap()).assert_golden(golden("counter_0"));
+    render(|d| screens::counter(d, 42).unwrap()).assert_golden(golden("counter_42"));
+}
+
+#[test]
+fn wifi_status() {
+    render(|d| screens::wifi_status(d, WifiStatus::Connecting).unwrap())
+        .assert_golden(golden("wifi_connecting"));
+    render(|d| {
+        screens::wifi_status(
+            d,
+            WifiStatus::Connected {
+                ip: [192, 168, 100, 200],
+            },
+        )
+        .unwrap()
+    })
+    .assert_golden(golden("wifi_connected"));
+    render(|d| screens::wifi_status(d, WifiStatus::Lost { retry_in_s: 16 }).unwrap())
+        .assert_golden(golden("wifi_lost"));
+    render(|d| screens::wifi_status(d, WifiStatus::Offline).unwrap())
+        .assert_golden(golden("wifi_offline"));
+}
+
+#[test]
+fn provisioning() {
+    render(|d| screens::provisioning(d, "buddy-setup", [192, 168, 4, 1]).unwrap())
+        .assert_golden(golden("provisioning"));
+}
+
+#[test]
+fn text() {
+    render(|d| screens::text(d, "Hello from\nthe dev box", 0).unwrap())
+        .assert_golden(golden("text"));
+}
+
+#[test]
+fn esp_now_receiver() {
+    render(|d| screens::esp_now_receiver(d, None, 0).unwrap())
+        .assert_golden(golden("receiver_waiting"));
+    render(|d| screens::esp_now_receiver(d, Some("117"), 0).unwrap())
+        .assert_golden(golden("receiver_message"));
+}
+
+#[test]
+fn nothing_is_drawn_outside_the_visible_area() {
+    let display = render(|d| screens::esp_now_receiver(d, None, 0).unwrap());
+    for y in 0..64 {
+        for x in 0..128 {
+            let visible = (28..100).contains(&x) && (12..52).contains(&y);
+            if !visible {
+                assert_eq!(
+                    display.pixel(embedded_graphics::prelude::Point::new(x, y)),
+                    Some(embedded_graphics::pixelcolor::BinaryColor::Off),
+                    "pixel ({}, {}) is outside the glass",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn pbm_round_trip() {
+    let display = render(|d| screens::counter(d, 1234).unwrap());
+    let decoded = MockDisplay::from_pbm(&display.to_pbm()).unwrap();
+    assert_eq!(display.diff_pixels(&decoded), 0);
+    assert!(display.lit_pixels() > 0);
+}
+
+#[test]
+fn pairing() {
+    render(|d| screens::pairing(d, Stage::Searching).unwrap())
+        .assert_golden(golden("pairing_searching"));
+    let peer = [1, 2, 3, 4, 5, 6];
+    render(|d| screens::pairing(d, Stage::Comparing { peer, code: 42 }).unwrap())
+        .assert_golden(golden("pairing_code"));
+    render(|d| screens::pairing(d, Stage::Confirmed { peer, code: 9876 }).unwrap())
+        .assert_golden(golden("pairing_confirmed"));
+}
+
+#[test]
+fn peers() {
+    let mut table = PeerTable::new();
+    for n in 1..=7u8 {
+        table.seen(n as u64 * 10_000, [0, 0, 0, 0, 0xa1, n], -30 - 10 * n as i8);
+    }
+    table.set_paired([0, 0, 0, 0, 0xa1, 4], true, true);
+    table.rename(&[0, 0, 0, 0, 0xa1, 6], "kitchen").unwrap();
+
+    render(|d| screens::peers(d, 70_000, &[], 0).unwrap()).assert_golden(golden("peers_none"));
+    // 1 to 4 are offline by now
+    render(|d| screens::peers(d, 70_000, table.peers(), 0).unwrap())
+        .assert_golden(golden("peers_online"));
+    render(|d| screens::peers(d, 70_000, table.peers(), 2).unwrap())
+        .assert_golden(golden("peers_scrolled"));
+
+    // names as long as they get, or of wider characters, still leave room
+    // for the RSSI
+    let mut table = PeerTable::new();
+    for (n, name) in ["kitchen-door", "küchenuhr", "ääääää"].iter().enumerate() {
+        let mac = [0, 0, 0, 0, 0xa2, n as u8];
+        table.seen(0, mac, -128);
+        table.rename(&mac, name).unwrap();
+    }
+    render(|d| screens::peers(d, 0, table.peers(), 0).unwrap())
+        .assert_golden(golden("peers_long_names"));
+}
+
+fn chat_message(peer: &str, outgoing: bool, text: &str) -> ChatMessage {
+    ChatMessage {
+        peer: peer.try_into().unwrap(),
+        outgoing,
+        text: text.try_into().unwrap(),
+    }
+}
+
+#[test]
+fn chat() {
+    let history = [
+        chat_message("alice", false, "scrolled away"),
+        chat_message("alice", false, "lunch at noon? the usual place"),
+        chat_message("alice", true, "SURE"),
+        chat_message("buddy-0203", false, "hi"),
+    ];
+    render(|d| screens::chat_history(d, None, [].iter()).unwrap())
+        .assert_golden(golden("chat_empty"));
+    render(|d| screens::chat_history(d, Some("alice"), history.iter().rev()).unwrap())
+        .assert_golden(golden("chat_history"));
+    render(|d| screens::chat_compose(d, Some("alice"), "SEE YOU", 2).unwrap())
+        .assert_golden(golden("chat_compose"));
+}
+
+#[test]
+fn a_missing_golden_image_fails() {
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        // creating it is what was asked for
+        return;
+    }
+    let path = std::env::temp_dir().join(format!("missing-{}.pbm", std::process::id()));
+    let result = MockDisplay::new().compare_golden(&path);
+    assert!(
+        matches!(result, Err(GoldenMismatch::Missing { .. })),
+        "{:?}",
+        result
+    );
+    assert!(!path.exists());
+    std::fs::remove_file(path.with_extension("actual.png")).unwrap();
+    std::fs::remove_file(path.with_extension("actual.pbm")).unwrap();
+}
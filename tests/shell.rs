@@ -0,0 +1,214 @@
+//! The TCP debug shell.
+
+use esp32_c3_buddy_like::{
+    shell::{self, Command, CrLf, Device, LineBuffer, Outcome, ParseError},
+    status::DeviceInfo,
+};
+
+#[derive(Default)]
+struct Fake {
+    led: Option<bool>,
+    text: Option<String>,
+    scan_fails: bool,
+}
+
+impl Device for Fake {
+    fn info(&mut self) -> DeviceInfo {
+        DeviceInfo {
+            mac: [0x34, 0x85, 0x18, 0x0a, 0xbc, 0xde],
+            ip: Some([192, 168, 1, 20]),
+            uptime_ms: 123_456,
+            free_heap: 65_000,
+            rssi: None,
+        }
+    }
+
+    fn heap(&mut self) -> (usize, usize) {
+        (7_000, 65_000)
+    }
+
+    fn scan(&mut self, found: &mut dyn FnMut(&str, i8)) -> bool {
+        if self.scan_fails {
+            return false;
+        }
+        found("home", -48);
+        found("neighbour", -81);
+        true
+    }
+
+    fn set_led(&mut self, on: bool) {
+        self.led = Some(on);
+    }
+
+    fn draw_text(&mut self, text: &str) {
+        self.text = Some(text.to_string());
+    }
+}
+
+fn run(device: &mut Fake, line: &str) -> (String, Outcome) {
+    let mut out = String::new();
+    let outcome = shell::run_line(line, device, &mut out).unwrap();
+    (out, outcome)
+}
+
+#[test]
+fn parse() {
+    assert_eq!(shell::parse(""), Ok(None));
+    assert_eq!(shell::parse("   "), Ok(None));
+    assert_eq!(shell::parse("help"), Ok(Some(Command::Help)));
+    assert_eq!(shell::parse("?"), Ok(Some(Command::Help)));
+    assert_eq!(shell::parse(" info "), Ok(Some(Command::Info)));
+    assert_eq!(shell::parse("heap"), Ok(Some(Command::Heap)));
+    assert_eq!(shell::parse("wifi  scan"), Ok(Some(Command::WifiScan)));
+    assert_eq!(shell::parse("led on"), Ok(Some(Command::Led(true))));
+    assert_eq!(shell::parse("led off"), Ok(Some(Command::Led(false))));
+    assert_eq!(
+        shell::parse("text  Hello there "),
+        Ok(Some(Command::Text("Hello there")))
+    );
+    assert_eq!(shell::parse("reboot"), Ok(Some(Command::Reboot)));
+    assert_eq!(shell::parse("exit"), Ok(Some(Command::Exit)));
+    assert_eq!(shell::parse("quit"), Ok(Some(Command::Exit)));
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(shell::parse("blink"), Err(ParseError::Unknown("blink")));
+    assert_eq!(shell::parse("LED on"), Err(ParseError::Unknown("LED")));
+    assert_eq!(shell::parse("led"), Err(ParseError::Usage("led on|off")));
+    assert_eq!(
+        shell::parse("led dim"),
+        Err(ParseError::Usage("led on|off"))
+    );
+    assert_eq!(shell::parse("wifi"), Err(ParseError::Usage("wifi scan")));
+    assert_eq!(shell::parse("text"), Err(ParseError::Usage("text <msg>")));
+    assert_eq!(
+        shell::parse("reboot now"),
+        Err(ParseError::Usage("no arguments"))
+    );
+}
+
+#[test]
+fn commands() {
+    let mut device = Fake::default();
+
+    let (out, outcome) = run(&mut device, "info");
+    assert_eq!(outcome, Outcome::Continue);
+    assert_eq!(
+        out,
+        "mac       34:85:18:0a:bc:de\n\
+         ip        192.168.1.20\n\
+         uptime    123 s\n\
+         free heap 65000 bytes\n\
+         rssi      -\n"
+    );
+
+    let (out, _) = run(&mut device, "heap");
+    assert_eq!(out, "used 7000 bytes, free 65000 bytes\n");
+
+    let (out, _) = run(&mut device, "wifi scan");
+    assert_eq!(out, " -48 dBm  home\n -81 dBm  neighbour\n");
+
+    let (out, _) = run(&mut device, "led on");
+    assert_eq!(out, "ok\n");
+    assert_eq!(device.led, Some(true));
+    run(&mut device, "led off");
+    assert_eq!(device.led, Some(false));
+
+    run(&mut device, "text Hi!");
+    assert_eq!(device.text.as_deref(), Some("Hi!"));
+
+    let (out, _) = run(&mut device, "help");
+    assert!(out.starts_with("help "));
+}
+
+#[test]
+fn failed_scan() {
+    let mut device = Fake {
+        scan_fails: true,
+        ..Fake::default()
+    };
+    assert_eq!(run(&mut device, "wifi scan").0, "scan failed\n");
+}
+
+#[test]
+fn outcomes() {
+    let mut device = Fake::default();
+    assert_eq!(run(&mut device, "exit"), ("bye\n".into(), Outcome::Close));
+    assert_eq!(
+        run(&mut device, "reboot"),
+        ("rebooting\n".into(), Outcome::Reboot)
+    );
+    assert_eq!(
+        run(&mut device, "blink"),
+        (
+            "unknown command 'blink', try 'help'\n".into(),
+            Outcome::Continue
+        )
+    );
+    assert_eq!(
+        run(&mut device, "led"),
+        ("usage: led on|off\n".into(), Outcome::Continue)
+    );
+    assert_eq!(run(&mut device, ""), (String::new(), Outcome::Continue));
+}
+
+fn lines(buffer: &mut LineBuffer, input: &[u8]) -> Vec<String> {
+    input
+        .iter()
+        .filter_map(|&b| buffer.push(b).map(String::from))
+        .collect()
+}
+
+#[test]
+fn line_ends() {
+    let mut buffer = LineBuffer::new();
+    assert_eq!(
+        lines(&mut buffer, b"info\r\nheap\nled on\r"),
+        ["info", "heap", "led on"]
+    );
+    // an empty line is still a line
+    assert_eq!(lines(&mut buffer, b"\r\n\r\n"), ["", ""]);
+    // a line split across reads
+    assert_eq!(lines(&mut buffer, b"wifi "), Vec::<String>::new());
+    assert_eq!(lines(&mut buffer, b"scan\r\n"), ["wifi scan"]);
+}
+
+#[test]
+fn line_editing() {
+    let mut buffer = LineBuffer::new();
+    assert_eq!(lines(&mut buffer, b"lex\x08d\x7f\x7fed on\n"), ["led on"]);
+    // backspace on an empty line and control characters are ignored
+    assert_eq!(lines(&mut buffer, b"\x08\x1bheap\t\n"), ["heap"]);
+    // non-ASCII is dropped
+    assert_eq!(lines(&mut buffer, "text h\u{e9}\n".as_bytes()), ["text h"]);
+
+    let long = "x".repeat(200) + "\n";
+    assert_eq!(
+        lines(&mut buffer, long.as_bytes()),
+        ["x".repeat(shell::MAX_LINE_LEN)]
+    );
+
+    lines(&mut buffer, b"partial");
+    buffer.clear();
+    assert_eq!(lines(&mut buffer, b"info\n"), ["info"]);
+}
+
+#[test]
+fn telnet_negotiation() {
+    let mut buffer = LineBuffer::new();
+    // IAC DO SUPPRESS-GO-AHEAD, IAC WILL ECHO, IAC NOP
+    let input = b"\xff\xfd\x03he\xff\xfb\x01ap\xff\xf1\r\n";
+    assert_eq!(lines(&mut buffer, input), ["heap"]);
+}
+
+#[test]
+fn crlf() {
+    let mut out = CrLf(String::new());
+    shell::run_line("heap", &mut Fake::default(), &mut out).unwrap();
+    assert_eq!(out.0, "used 7000 bytes, free 65000 bytes\r\n");
+
+    let mut out = CrLf(String::new());
+    shell::run_line("wifi scan", &mut Fake::default(), &mut out).unwrap();
+    assert_eq!(out.0, " -48 dBm  home\r\n -81 dBm  neighbour\r\n");
+}
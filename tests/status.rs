@@ -0,0 +1,98 @@
+//! The HTTP API of the connected firmware.
+
+use esp32_c3_buddy_like::{
+    http::Request,
+    status::{self, DeviceInfo, Outcome},
+};
+
+const INFO: DeviceInfo = DeviceInfo {
+    mac: [0x34, 0x85, 0x18, 0x0a, 0xbc, 0xde],
+    ip: Some([192, 168, 1, 20]),
+    uptime_ms: 123_456,
+    free_heap: 65_000,
+    rssi: Some(-61),
+};
+
+fn request(raw: &str, info: &DeviceInfo) -> (String, Outcome) {
+    let mut response = String::new();
+    let outcome = status::handle(
+        &Request::parse(raw.as_bytes()).unwrap(),
+        info,
+        &mut response,
+    )
+    .unwrap();
+    (response, outcome)
+}
+
+fn post(content_type: &str, body: &str) -> (String, Outcome) {
+    let raw = format!(
+        "POST /display HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    );
+    request(&raw, &INFO)
+}
+
+fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").unwrap().1
+}
+
+#[test]
+fn info_as_json() {
+    let (response, outcome) = request("GET /info HTTP/1.1\r\n\r\n", &INFO);
+    assert_eq!(outcome, Outcome::Continue);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"));
+    assert_eq!(
+        body(&response),
+        "{\"mac\":\"34:85:18:0a:bc:de\",\"ip\":\"192.168.1.20\",\"uptime_ms\":123456,\
+         \"free_heap\":65000,\"rssi\":-61}"
+    );
+
+    let unknown = DeviceInfo {
+        ip: None,
+        rssi: None,
+        ..INFO
+    };
+    let (response, _) = request("GET / HTTP/1.1\r\n\r\n", &unknown);
+    assert!(body(&response).contains("\"ip\":null,"));
+    assert!(body(&response).ends_with("\"rssi\":null}"));
+}
+
+#[test]
+fn draw_text() {
+    let (response, outcome) = post("text/plain", "Hello\nthere");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert_eq!(
+        outcome,
+        Outcome::DrawText("Hello\nthere".try_into().unwrap())
+    );
+
+    let (_, outcome) = post("application/x-www-form-urlencoded", "text=Hi+%21");
+    assert_eq!(outcome, Outcome::DrawText("Hi !".try_into().unwrap()));
+}
+
+#[test]
+fn draw_text_errors() {
+    for (content_type, body) in [
+        ("text/plain", "x".repeat(129)),
+        ("application/x-www-form-urlencoded", "other=1".to_string()),
+        ("application/x-www-form-urlencoded", "text=%ff".to_string()),
+    ] {
+        let (response, outcome) = post(content_type, &body);
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            body
+        );
+        assert_eq!(outcome, Outcome::Continue);
+    }
+}
+
+#[test]
+fn unknown_requests() {
+    let (response, _) = request("GET /display HTTP/1.1\r\n\r\n", &INFO);
+    assert!(response.starts_with("HTTP/1.1 405 "));
+    let (response, _) = request("GET /favicon.ico HTTP/1.1\r\n\r\n", &INFO);
+    assert!(response.starts_with("HTTP/1.1 404 "));
+}
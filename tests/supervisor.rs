@@ -0,0 +1,179 @@
+//! Reconnect logic of the Wi-Fi supervisor.
+
+use esp32_c3_buddy_like::{
+    screens::WifiStatus,
+    supervisor::{Command, Link, SupervisorConfig, WifiState, WifiSupervisor},
+};
+
+const CONFIG: SupervisorConfig = SupervisorConfig {
+    connect_timeout_ms: 1_000,
+    dhcp_timeout_ms: 2_000,
+    initial_backoff_ms: 100,
+    max_backoff_ms: 1_000,
+    max_retries: 5,
+};
+
+const IP: [u8; 4] = [192, 168, 1, 20];
+
+const DOWN: Link = Link {
+    connected: false,
+    ip: None,
+};
+const ASSOCIATED: Link = Link {
+    connected: true,
+    ip: None,
+};
+const UP: Link = Link {
+    connected: true,
+    ip: Some(IP),
+};
+
+/// Update every millisecond from `from` to `to` (exclusive), returning the
+/// commands with their time.
+fn run(supervisor: &mut WifiSupervisor, from: u64, to: u64, link: Link) -> Vec<(u64, Command)> {
+    (from..to)
+        .filter_map(|now| supervisor.update(now, link).map(|c| (now, c)))
+        .collect()
+}
+
+#[test]
+fn connects() {
+    let mut supervisor = WifiSupervisor::new(CONFIG);
+    assert_eq!(supervisor.status(0), WifiStatus::Starting);
+    assert_eq!(run(&mut supervisor, 0, 10, DOWN), []);
+
+    assert_eq!(supervisor.start(10), Some(Command::Connect));
+    assert_eq!(supervisor.start(11), None);
+    assert_eq!(supervisor.status(11), WifiStatus::Connecting);
+
+    assert_eq!(run(&mut supervisor, 11, 500, DOWN), []);
+    assert_eq!(run(&mut supervisor, 500, 800, ASSOCIATED), []);
+    assert_eq!(supervisor.status(800), WifiStatus::WaitingForIp);
+
+    assert_eq!(run(&mut supervisor, 800, 900, UP), []);
+    assert_eq!(supervisor.state(), WifiState::Up { ip: IP });
+    assert_eq!(supervisor.status(900), WifiStatus::Connected { ip: IP });
+}
+
+#[test]
+fn retries_with_exponential_backoff() {
+    let mut supervisor = WifiSupervisor::new(CONFIG);
+    supervisor.start(0);
+
+    // the connect timeout, then 100, 200, 400, 800 and 1000 ms of backoff
+    let commands = run(&mut supervisor, 0, 20_000, DOWN);
+    assert_eq!(
+        commands,
+        [
+            (1_000, Command::Disconnect),
+            (1_100, Command::Connect),
+            (2_100, Command::Disconnect),
+            (2_300, Command::Connect),
+            (3_300, Command::Disconnect),
+            (3_700, Command::Connect),
+            (4_700, Command::Disconnect),
+            (5_500, Command::Connect),
+            (6_500, Command::Disconnect),
+            (7_500, Command::Connect),
+            (8_500, Command::Disconnect),
+        ]
+    );
+    assert_eq!(supervisor.state(), WifiState::Idle { gave_up: true });
+    assert_eq!(supervisor.status(20_000), WifiStatus::Offline);
+
+    // a button press starts over
+    assert_eq!(supervisor.start(20_000), Some(Command::Connect));
+    assert_eq!(supervisor.status(20_000), WifiStatus::Connecting);
+}
+
+#[test]
+fn shows_the_retry() {
+    let mut supervisor = WifiSupervisor::new(CONFIG);
+    supervisor.start(0);
+    run(&mut supervisor, 0, 2_200, DOWN);
+
+    // second failure at 2100, retry at 2300
+    assert_eq!(supervisor.status(2_200), WifiStatus::Lost { retry_in_s: 1 });
+    run(&mut supervisor, 2_200, 2_400, DOWN);
+    assert_eq!(
+        supervisor.status(2_400),
+        WifiStatus::Retrying { attempt: 2 }
+    );
+}
+
+#[test]
+fn backoff_is_capped() {
+    let supervisor = WifiSupervisor::new(SupervisorConfig {
+        max_retries: u32::MAX,
+        ..CONFIG
+    });
+    assert_eq!(supervisor.backoff_ms(1), 100);
+    assert_eq!(supervisor.backoff_ms(4), 800);
+    assert_eq!(supervisor.backoff_ms(5), 1_000);
+    assert_eq!(supervisor.backoff_ms(1_000), 1_000);
+}
+
+#[test]
+fn dhcp_timeout() {
+    let mut supervisor = WifiSupervisor::new(CONFIG);
+    supervisor.start(0);
+    assert_eq!(
+        run(&mut supervisor, 0, 2_100, ASSOCIATED),
+        [(2_000, Command::Disconnect)]
+    );
+    assert_eq!(supervisor.status(2_000), WifiStatus::Lost { retry_in_s: 1 });
+}
+
+#[test]
+fn recovers_from_a_dropout() {
+    let mut supervisor = WifiSupervisor::new(CONFIG);
+    supervisor.start(0);
+    run(&mut supervisor, 0, 10, UP);
+    assert_eq!(supervisor.state(), WifiState::Up { ip: IP });
+
+    // the access point reboots
+    let commands = run(&mut supervisor, 10, 5_000, DOWN);
+    assert_eq!(
+        commands[..4],
+        [
+            (10, Command::Disconnect),
+            (11, Command::Connect),
+            (1_011, Command::Disconnect),
+            (1_111, Command::Connect),
+        ]
+    );
+
+    // and is back once the next retry (at 5511) comes
+    assert_eq!(
+        run(&mut supervisor, 5_000, 5_600, UP),
+        [(5_511, Command::Connect)]
+    );
+    assert_eq!(supervisor.state(), WifiState::Up { ip: IP });
+
+    // the retries start from the beginning again
+    let commands = run(&mut supervisor, 5_600, 6_800, DOWN);
+    assert_eq!(
+        commands,
+        [
+            (5_600, Command::Disconnect),
+            (5_601, Command::Connect),
+            (6_601, Command::Disconnect),
+            (6_701, Command::Connect),
+        ]
+    );
+}
+
+#[test]
+fn new_address() {
+    let mut supervisor = WifiSupervisor::new(CONFIG);
+    supervisor.start(0);
+    run(&mut supervisor, 0, 10, UP);
+
+    let ip = [192, 168, 1, 21];
+    let link = Link {
+        connected: true,
+        ip: Some(ip),
+    };
+    assert_eq!(run(&mut supervisor, 10, 20, link), []);
+    assert_eq!(supervisor.status(20), WifiStatus::Connected { ip });
+}
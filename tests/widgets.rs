@@ -0,0 +1,347 @@
+//! Layout, alignment, clipping, ellipsis and wrapping of the widgets.
+
+use embedded_graphics::{
+    mono_font::ascii::{FONT_10X20, FONT_4X6, FONT_6X10, FONT_7X13},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::Rectangle,
+};
+use esp32_c3_buddy_like::{
+    sim::MockDisplay,
+    widgets::{
+        chars_that_fit, ellipsize, icons, screen, split_bottom, split_left, split_top, text_width,
+        wrap, Align, BigNumber, Icon, Label, Overflow, ProgressBar, ScrollingList, StatusBar,
+        TextBox, VAlign, MARQUEE_PAUSE_MS, MARQUEE_STEP_MS, PAGE_MS,
+    },
+};
+
+fn draw(widget: impl Drawable<Color = BinaryColor>) -> MockDisplay {
+    let mut display = MockDisplay::new();
+    widget.draw(&mut display).ok();
+    display
+}
+
+/// The smallest rectangle around the lit pixels.
+fn lit(display: &MockDisplay) -> Option<Rectangle> {
+    let points: Vec<Point> = (0..64)
+        .flat_map(|y| (0..128).map(move |x| Point::new(x, y)))
+        .filter(|&p| display.pixel(p) == Some(BinaryColor::On))
+        .collect();
+    let min_x = points.iter().map(|p| p.x).min()?;
+    let min_y = points.iter().map(|p| p.y).min()?;
+    let max_x = points.iter().map(|p| p.x).max()?;
+    let max_y = points.iter().map(|p| p.y).max()?;
+    Some(Rectangle::with_corners(
+        Point::new(min_x, min_y),
+        Point::new(max_x, max_y),
+    ))
+}
+
+fn inside(inner: Rectangle, outer: Rectangle) -> bool {
+    outer.contains(inner.top_left) && outer.contains(inner.bottom_right().unwrap())
+}
+
+#[test]
+fn splits() {
+    assert_eq!(screen(), Rectangle::new(Point::zero(), Size::new(72, 40)));
+    let (top, rest) = split_top(screen(), 10);
+    assert_eq!(top, Rectangle::new(Point::zero(), Size::new(72, 10)));
+    assert_eq!(rest, Rectangle::new(Point::new(0, 10), Size::new(72, 30)));
+    let (bottom, rest) = split_bottom(screen(), 6);
+    assert_eq!(bottom, Rectangle::new(Point::new(0, 34), Size::new(72, 6)));
+    assert_eq!(rest.size, Size::new(72, 34));
+    let (left, rest) = split_left(screen(), 100);
+    assert_eq!((left, rest.size), (screen(), Size::new(0, 40)));
+}
+
+#[test]
+fn text_metrics() {
+    assert_eq!(text_width(&FONT_6X10, 12), 72);
+    assert_eq!(chars_that_fit(&FONT_6X10, 72), 12);
+    assert_eq!(chars_that_fit(&FONT_4X6, 72), 18);
+    assert_eq!(chars_that_fit(&FONT_6X10, 5), 0);
+
+    assert_eq!(ellipsize("kitchen", 7), ("kitchen", ""));
+    assert_eq!(ellipsize("living room", 7), ("livi", "..."));
+    assert_eq!(ellipsize("living room", 3), ("liv", ""));
+    assert_eq!(ellipsize("grüße aus köln", 6), ("grü", "..."));
+}
+
+#[test]
+fn labels_align_and_clip() {
+    let area = Rectangle::new(Point::new(10, 10), Size::new(60, 20));
+    let left = lit(&draw(Label::new("ab", area))).unwrap();
+    assert_eq!(left.top_left.x, 10);
+
+    let right = lit(&draw(Label::new("ab", area).align(Align::Right))).unwrap();
+    assert!(right.bottom_right().unwrap().x >= 68);
+
+    let centered = lit(&draw(
+        Label::new("ab", area)
+            .align(Align::Center)
+            .valign(VAlign::Middle),
+    ))
+    .unwrap();
+    // the text is 12x10 in the middle of 60x20
+    assert!((34..=36).contains(&centered.top_left.x), "{:?}", centered);
+    assert!(centered.top_left.y >= 15);
+
+    let bottom = lit(&draw(Label::new("ab", area).valign(VAlign::Bottom))).unwrap();
+    assert!(bottom.top_left.y >= 20);
+
+    // three lines into room for two, each too long
+    let long = "a line that goes on\nand on\nand on";
+    let display = draw(Label::new(long, area));
+    assert!(inside(lit(&display).unwrap(), area));
+    assert_eq!(
+        draw(Label::new("a line ...\nand on", area)).diff_pixels(&display),
+        0
+    );
+
+    let inverted = draw(Label::new("ab", area).inverted());
+    assert_eq!(lit(&inverted), Some(area));
+    assert_eq!(
+        inverted.lit_pixels(),
+        60 * 20 - draw(Label::new("ab", area)).lit_pixels()
+    );
+}
+
+#[test]
+fn big_numbers_shrink_to_fit() {
+    let area = Rectangle::new(Point::new(0, 10), Size::new(72, 30));
+    let small = lit(&draw(BigNumber::new(7, area))).unwrap();
+    assert!(small.size.height > 10, "{:?}", small);
+    assert!(inside(small, area));
+
+    // eight digits are too wide for the 10x20 font
+    let wide = lit(&draw(BigNumber::new(12_345_678, area))).unwrap();
+    assert!(wide.size.height <= 10, "{:?}", wide);
+    assert!(inside(wide, area));
+
+    let padded = Label::new("0042", area)
+        .font(&FONT_10X20)
+        .align(Align::Center)
+        .valign(VAlign::Middle);
+    assert_eq!(
+        draw(BigNumber::new(42, area).digits(4)).diff_pixels(&draw(padded)),
+        0
+    );
+}
+
+#[test]
+fn progress_bars() {
+    let area = Rectangle::new(Point::new(0, 0), Size::new(22, 5));
+    // the outline
+    let empty = draw(ProgressBar::new(area, 0, 10));
+    assert_eq!(empty.lit_pixels(), 2 * 22 + 2 * 3);
+    // half of the 20x3 inside
+    let half = draw(ProgressBar::new(area, 5, 10));
+    assert_eq!(half.lit_pixels() - empty.lit_pixels(), 10 * 3);
+    let full = draw(ProgressBar::new(area, 99, 10));
+    assert_eq!(full.lit_pixels(), 22 * 5);
+    assert_eq!(draw(ProgressBar::new(area, 3, 0)).diff_pixels(&empty), 0);
+}
+
+#[test]
+fn icons() {
+    assert_eq!(icons::WIFI.size(), Size::new(7, 7));
+    let display = draw(Icon::new(&icons::LOCK, Point::new(3, 4)));
+    assert_eq!(
+        lit(&display),
+        Some(Rectangle::new(Point::new(3, 4), Size::new(7, 7)))
+    );
+}
+
+#[test]
+fn scrolling_lists() {
+    let items = ["one", "two", "three", "four", "five", "six"];
+    let area = Rectangle::new(Point::new(0, 7), Size::new(72, 18));
+    let list = ScrollingList::new(&items, area);
+    assert_eq!(list.rows(), 3);
+
+    let rows = |labels: &[&str]| {
+        let mut display = MockDisplay::new();
+        for (row, label) in labels.iter().enumerate() {
+            let row_area = Rectangle::new(Point::new(0, 7 + 6 * row as i32), Size::new(72, 6));
+            Label::new(label, row_area)
+                .font(&FONT_4X6)
+                .draw(&mut display)
+                .unwrap();
+        }
+        display
+    };
+    assert_eq!(draw(list).diff_pixels(&rows(&["one", "two", "three"])), 0);
+    assert_eq!(draw(list.first(4)).diff_pixels(&rows(&["five", "six"])), 0);
+
+    // the selection scrolls into view and is inverted
+    let selected = draw(list.selected(4));
+    let inverted = Rectangle::new(Point::new(0, 19), Size::new(72, 6));
+    assert_eq!(selected.pixel(Point::new(71, 19)), Some(BinaryColor::On));
+    assert_eq!(selected.pixel(Point::new(71, 13)), Some(BinaryColor::Off));
+    assert!(inside(lit(&selected).unwrap(), area));
+    assert!(inside(inverted, lit(&selected).unwrap()));
+
+    // the scrollbar is on the right, as far down as the rows shown
+    let bar = draw(list.first(3).scrollbar());
+    assert_eq!(bar.pixel(Point::new(71, 7)), Some(BinaryColor::Off));
+    assert_eq!(bar.pixel(Point::new(71, 16)), Some(BinaryColor::On));
+    assert_eq!(bar.pixel(Point::new(71, 24)), Some(BinaryColor::On));
+    // and stops at the bottom when scrolled further than a page from it
+    let end = draw(list.first(5).scrollbar());
+    assert!(inside(lit(&end).unwrap(), area));
+    assert_eq!(end.pixel(Point::new(71, 24)), Some(BinaryColor::On));
+    assert_eq!(end.pixel(Point::new(71, 25)), Some(BinaryColor::Off));
+    // no room for one when everything fits
+    let few = ScrollingList::new(&items[..2], area).scrollbar();
+    assert_eq!(draw(few).pixel(Point::new(71, 7)), Some(BinaryColor::Off));
+}
+
+#[test]
+fn status_bars() {
+    let bar = StatusBar::new("a very long title", screen())
+        .icon(&icons::WIFI)
+        .icon(&icons::LOCK);
+    let display = draw(bar);
+    assert!(inside(
+        lit(&display).unwrap(),
+        split_top(screen(), StatusBar::HEIGHT).0
+    ));
+    // the line under it
+    assert_eq!(display.pixel(Point::new(0, 8)), Some(BinaryColor::On));
+    assert_eq!(display.pixel(Point::new(71, 8)), Some(BinaryColor::On));
+    // the icons from the right, the title cut off before them
+    let wifi = draw(Icon::new(&icons::WIFI, Point::new(65, 0)));
+    let lock = draw(Icon::new(&icons::LOCK, Point::new(57, 0)));
+    for y in 0..7 {
+        for x in 57..72 {
+            let p = Point::new(x, y);
+            let icon =
+                wifi.pixel(p) == Some(BinaryColor::On) || lock.pixel(p) == Some(BinaryColor::On);
+            assert_eq!(display.pixel(p) == Some(BinaryColor::On), icon, "{:?}", p);
+        }
+    }
+    // icons wider than the bar are cut off at its left edge
+    let narrow = Rectangle::new(Point::new(20, 10), Size::new(10, 20));
+    let display = draw(
+        StatusBar::new("title", narrow)
+            .icon(&icons::WIFI)
+            .icon(&icons::LOCK),
+    );
+    assert!(inside(lit(&display).unwrap(), narrow));
+    assert_eq!(
+        StatusBar::below(screen()),
+        Rectangle::new(Point::new(0, 9), Size::new(72, 31))
+    );
+}
+
+#[test]
+fn wrapping() {
+    let lines = |text, max| wrap(text, max).collect::<Vec<_>>();
+    assert_eq!(lines("kitchen light", 13), ["kitchen light"]);
+    assert_eq!(lines("kitchen light is on", 13), ["kitchen light", "is on"]);
+    // the spaces at the break are left out, a `\n` after them too
+    assert_eq!(lines("one  two\nthree", 5), ["one", "two", "three"]);
+    assert_eq!(lines("one \n\ntwo", 3), ["one", "", "two"]);
+    assert_eq!(lines("  indented", 10), ["  indented"]);
+
+    let mut cut = wrap("192.168.100.200", 12);
+    assert_eq!(cut.by_ref().collect::<Vec<_>>(), ["192.168.100.", "200"]);
+    assert!(cut.cut_words());
+    let mut whole = wrap("a few short words", 5);
+    assert_eq!(whole.by_ref().count(), 3);
+    assert!(!whole.cut_words());
+
+    assert_eq!(lines("grüße aus köln", 5), ["grüße", "aus", "köln"]);
+    assert_eq!(lines("", 5), [""]);
+}
+
+#[test]
+fn text_boxes_pick_the_largest_font_that_fits() {
+    let area = Rectangle::new(Point::new(0, 10), Size::new(72, 30));
+    let font = |text| TextBox::new(text, area).layout().font.character_size;
+
+    assert_eq!(font("117"), FONT_10X20.character_size);
+    assert_eq!(font("connecting"), FONT_7X13.character_size);
+    assert_eq!(font("lost, retry in 16s"), FONT_6X10.character_size);
+    // cutting the address would not fit either, so it wraps at the space
+    assert_eq!(
+        font("Connected.\nIP: 192.168.100.200"),
+        FONT_4X6.character_size
+    );
+
+    let layout = TextBox::new("Hello from\nthe dev box", screen()).layout();
+    assert_eq!((layout.chars, layout.rows, layout.lines), (10, 3, 3));
+    assert!(!layout.overflows());
+
+    let display = draw(TextBox::new("Hello from the dev box", area).align(Align::Center));
+    // three lines of the 7x13 font do not fit, two of the 6x10 one do
+    let expected = Label::new("Hello from\nthe dev box", area)
+        .font(&FONT_6X10)
+        .align(Align::Center);
+    assert_eq!(display.diff_pixels(&draw(expected)), 0);
+}
+
+/// Too long for five rows of 18 characters, ten lines of them.
+const LONG: &str = "one two three four five six seven eight nine ten eleven twelve \
+                    thirteen fourteen fifteen sixteen seventeen eighteen nineteen twenty";
+
+#[test]
+fn overflowing_text_is_paged() {
+    let text_box = TextBox::new(LONG, screen());
+    let layout = text_box.layout();
+    assert_eq!(layout.font.character_size, FONT_4X6.character_size);
+    assert!(layout.overflows());
+    assert_eq!((layout.rows, layout.pages()), (6, 2));
+
+    let pages: Vec<Vec<&str>> = wrap(LONG, layout.chars)
+        .collect::<Vec<_>>()
+        .chunks(layout.rows)
+        .map(<[&str]>::to_vec)
+        .collect();
+    let page = |lines: &[&str]| draw(Label::new(&lines.join("\n"), screen()).font(&FONT_4X6));
+
+    assert_eq!(draw(text_box).diff_pixels(&page(&pages[0])), 0);
+    assert_eq!(
+        draw(text_box.at(PAGE_MS - 1)).diff_pixels(&page(&pages[0])),
+        0
+    );
+    assert_eq!(text_box.at(PAGE_MS).position(), 1);
+    assert_eq!(draw(text_box.at(PAGE_MS)).diff_pixels(&page(&pages[1])), 0);
+    // and around again
+    assert_eq!(text_box.at(2 * PAGE_MS).position(), 0);
+}
+
+#[test]
+fn overflowing_text_scrolls_as_a_marquee() {
+    let area = Rectangle::new(Point::new(0, 9), Size::new(72, 31));
+    let text_box = TextBox::new(LONG, area).overflow(Overflow::Marquee);
+    let start = draw(text_box);
+    assert!(inside(lit(&start).unwrap(), area));
+    assert_eq!(
+        draw(text_box.at(MARQUEE_PAUSE_MS - 1)).diff_pixels(&start),
+        0
+    );
+
+    // a pixel every step
+    let moved = |steps| text_box.at(MARQUEE_PAUSE_MS + steps * MARQUEE_STEP_MS);
+    assert_eq!(moved(1).position(), 1);
+    let one_up = draw(moved(1));
+    for y in 9..39 {
+        for x in 0..72 {
+            let (above, below) = (Point::new(x, y), Point::new(x, y + 1));
+            assert_eq!(one_up.pixel(above), start.pixel(below), "{:?}", above);
+        }
+    }
+    assert!(inside(lit(&draw(moved(37))).unwrap(), area));
+
+    // the start follows the end a line further down, and stops at the top
+    let layout = text_box.layout();
+    let distance = (layout.lines as u64 + 1) * 6;
+    assert_eq!(moved(distance - 1).position(), distance - 1);
+    assert_eq!(moved(distance).position(), 0);
+    assert_eq!(draw(moved(distance)).diff_pixels(&start), 0);
+
+    // nothing moves if it fits
+    let short = TextBox::new("short", area).overflow(Overflow::Marquee);
+    assert_eq!(short.at(MARQUEE_PAUSE_MS + 1000).position(), 0);
+}
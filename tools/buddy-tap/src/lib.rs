@@ -0,0 +1,214 @@
+//! Decoding what the ESP-NOW gateway forwards
+//!
+//! The gateway sends one JSON line per ESP-NOW frame it hears, see
+//! [`gateway`](esp32_c3_buddy_like::gateway). A [`Decoder`] parses a line
+//! with the firmware's own [`parse_json`], decodes the payload with the
+//! firmware's [`Frame::decode`] and describes it in one line of text.
+//! Fragments are put back together, so a complete blob is reported once its
+//! last fragment is in.
+//!
+//! Captures are the JSON lines as they arrived, one per line, so a recorded
+//! session is replayed by feeding its lines to a new [`Decoder`].
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead};
+
+use esp32_c3_buddy_like::{
+    app::{Mac, BROADCAST, MAX_PAYLOAD},
+    gateway::{parse_json, Forwarded, LineError},
+    protocol::{fragment::Reassembler, mesh::split_tag, BlobKind, Frame, Message},
+};
+
+/// Fragments that go this long without the next one are dropped, in gateway
+/// time.
+pub const BLOB_TIMEOUT_MS: u64 = 10_000;
+
+/// Largest blob put back together, a few bitmaps.
+pub const MAX_BLOB_LEN: usize = 8192;
+
+/// What a [`Decoder`] has seen so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub lines: u32,
+    /// Lines that are not what the gateway writes.
+    pub bad_lines: u32,
+    /// Payloads that are not frames, e.g. from other ESP-NOW devices.
+    pub bad_frames: u32,
+    pub blobs: u32,
+}
+
+pub struct Decoder {
+    reassembler: Box<Reassembler<4, MAX_BLOB_LEN>>,
+    stats: Stats,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            reassembler: Box::new(Reassembler::new(BLOB_TIMEOUT_MS)),
+            stats: Stats::default(),
+        }
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Describe a line from the gateway, or why it makes no sense.
+    pub fn decode_line(&mut self, line: &str) -> Result<String, LineError> {
+        self.stats.lines += 1;
+        let mut data = [0; MAX_PAYLOAD];
+        match parse_json(line, &mut data) {
+            Ok(forwarded) => Ok(self.decode(&forwarded)),
+            Err(e) => {
+                self.stats.bad_lines += 1;
+                Err(e)
+            }
+        }
+    }
+
+    /// Describe one forwarded frame:
+    ///
+    /// ```text
+    ///     81.234 34:85:18:01:02:03 -> broadcast          -61 dBm ch 6  #7 text "hi"
+    /// ```
+    pub fn decode(&mut self, forwarded: &Forwarded<'_>) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{:>6}.{:03} {} -> {:<17} {:>4} dBm ch {:<2} ",
+            forwarded.ts_ms / 1000,
+            forwarded.ts_ms % 1000,
+            mac(&forwarded.src),
+            address(&forwarded.dst),
+            forwarded.rssi,
+            forwarded.channel
+        );
+        self.describe(&mut out, forwarded.ts_ms, forwarded.src, forwarded.data);
+        out
+    }
+
+    /// Describe the frame in `data`, sent by `src`.
+    fn describe(&mut self, out: &mut String, now_ms: u64, src: Mac, data: &[u8]) {
+        let frame = match Frame::decode(data) {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.stats.bad_frames += 1;
+                let _ = write!(out, "not a frame ({:?}), {} bytes", e, data.len());
+                return;
+            }
+        };
+        let _ = write!(out, "#{}", frame.seq);
+        if frame.ack_requested {
+            out.push_str(" ack?");
+        }
+        out.push(' ');
+
+        let _ = match frame.message {
+            Message::Text(text) => write!(out, "text {:?}", text),
+            Message::Counter(value) => write!(out, "counter {}", value),
+            Message::Ping(value) => write!(out, "ping {}", value),
+            Message::Pong(value) => write!(out, "pong {}", value),
+            Message::SensorReading(reading) => write!(out, "{}", reading),
+            Message::Command(command) => write!(out, "command {:?}", command),
+            Message::Ack { seq, broadcast } => {
+                let what = if broadcast { " of a broadcast" } else { "" };
+                write!(out, "ack #{}{}", seq, what)
+            }
+            Message::Fragment(fragment) => {
+                let _ = write!(
+                    out,
+                    "fragment {}/{} of {} transfer {}, {} bytes",
+                    fragment.index as usize + 1,
+                    fragment.count(),
+                    kind(fragment.kind),
+                    fragment.transfer,
+                    fragment.blob_len
+                );
+                match self.reassembler.receive(now_ms, src, fragment) {
+                    Ok(Some(blob)) => {
+                        self.stats.blobs += 1;
+                        let _ = write!(out, ", complete");
+                        if blob.kind == BlobKind::Log {
+                            let _ = write!(out, ": {:?}", String::from_utf8_lossy(blob.data));
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(e) => {
+                        let _ = write!(out, ", dropped: {:?}", e);
+                    }
+                }
+                Ok(())
+            }
+            Message::PairCommit { .. } => write!(out, "pairing: commit"),
+            Message::PairReveal { .. } => write!(out, "pairing: reveal"),
+            Message::PairConfirm { .. } => write!(out, "pairing: confirm"),
+            Message::Mesh(mesh) => {
+                let _ = write!(
+                    out,
+                    "mesh {} -> {} id {} ttl {} hops {}: ",
+                    mac(&mesh.origin),
+                    address(&mesh.dst),
+                    mesh.id,
+                    mesh.ttl,
+                    mesh.hops
+                );
+                let (frame, tag) = split_tag(mesh.data);
+                if tag.is_some() {
+                    let _ = write!(out, "tagged ");
+                }
+                self.describe(out, now_ms, mesh.origin, frame);
+                Ok(())
+            }
+        };
+    }
+}
+
+/// Decode every line of a capture, calling `each` with the line and what
+/// [`Decoder::decode_line`] made of it. Returns the decoder's [`Stats`].
+pub fn replay<R: BufRead>(
+    capture: R,
+    mut each: impl FnMut(&str, Result<String, LineError>),
+) -> io::Result<Stats> {
+    let mut decoder = Decoder::new();
+    for line in capture.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let decoded = decoder.decode_line(&line);
+        each(&line, decoded);
+    }
+    Ok(decoder.stats())
+}
+
+pub fn mac(mac: &Mac) -> String {
+    let m = mac;
+    format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        m[0], m[1], m[2], m[3], m[4], m[5]
+    )
+}
+
+fn address(dst: &Mac) -> String {
+    if *dst == BROADCAST {
+        "broadcast".to_string()
+    } else {
+        mac(dst)
+    }
+}
+
+fn kind(kind: BlobKind) -> String {
+    match kind {
+        BlobKind::Bitmap => "bitmap".to_string(),
+        BlobKind::Config => "config".to_string(),
+        BlobKind::Log => "log".to_string(),
+        BlobKind::Other(id) => format!("blob kind {}", id),
+    }
+}
@@ -0,0 +1,194 @@
+//! `buddy-tap`: listen to the ESP-NOW gateway and print what it forwards
+//!
+//! ```text
+//! cargo tap listen udp://0.0.0.0:9000 --record session.jsonl
+//! cargo tap replay session.jsonl --realtime --to udp://127.0.0.1:9001
+//! ```
+//!
+//! `listen` is the collector the gateway sends to: it binds the UDP port, or
+//! accepts the gateway's TCP connections, and prints every frame decoded.
+//! `--record` also appends the lines as they came to a file. `replay` decodes
+//! a recording again, optionally at the pace it was recorded and sending the
+//! lines on to another collector, e.g. a second `buddy-tap listen`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::process::ExitCode;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use buddy_tap::{replay, Decoder};
+use esp32_c3_buddy_like::gateway::{parse_json, Collector, Transport, MAX_LINE_LEN};
+
+const USAGE: &str = "\
+usage: buddy-tap listen udp|tcp://<ip>:<port> [--record <file>]
+       buddy-tap replay <file> [--realtime] [--to udp://<ip>:<port>]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["listen", address, rest @ ..] => parse_listen(address, rest)
+            .and_then(|(collector, record)| listen(collector, record).map_err(|e| e.to_string())),
+        ["replay", file, rest @ ..] => parse_replay(rest)
+            .and_then(|(realtime, to)| run_replay(file, realtime, to).map_err(|e| e.to_string())),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn collector(address: &str) -> Result<Collector, String> {
+    Collector::parse(address).map_err(|e| format!("invalid address {}: {:?}", address, e))
+}
+
+fn parse_listen<'a>(
+    address: &str,
+    rest: &[&'a str],
+) -> Result<(Collector, Option<&'a str>), String> {
+    let record = match rest {
+        [] => None,
+        ["--record", file] => Some(*file),
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok((collector(address)?, record))
+}
+
+fn parse_replay(mut rest: &[&str]) -> Result<(bool, Option<Collector>), String> {
+    let mut realtime = false;
+    let mut to = None;
+    loop {
+        match rest {
+            [] => return Ok((realtime, to)),
+            ["--realtime", more @ ..] => {
+                realtime = true;
+                rest = more;
+            }
+            ["--to", address, more @ ..] => {
+                let collector = collector(address)?;
+                if collector.transport != Transport::Udp {
+                    return Err("replay sends over UDP only".to_string());
+                }
+                to = Some(collector);
+                rest = more;
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+}
+
+fn socket_addr(collector: Collector) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::from(collector.ip), collector.port))
+}
+
+/// Print every line the gateway sends to `collector`, and append it to
+/// `record` if given.
+fn listen(collector: Collector, record: Option<&str>) -> io::Result<()> {
+    let mut record = match record {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    let lines = receive(collector)?;
+    eprintln!("listening on {}", collector);
+
+    let mut decoder = Decoder::new();
+    for line in lines {
+        if let Some(file) = &mut record {
+            writeln!(file, "{}", line)?;
+            file.flush()?;
+        }
+        match decoder.decode_line(&line) {
+            Ok(decoded) => println!("{}", decoded),
+            Err(e) => println!("unreadable line ({:?}): {}", e, line),
+        }
+    }
+    Ok(())
+}
+
+/// The lines arriving on `collector`, from any number of gateways.
+fn receive(collector: Collector) -> io::Result<mpsc::Receiver<String>> {
+    let (tx, rx) = mpsc::channel();
+    let address = socket_addr(collector);
+    match collector.transport {
+        Transport::Udp => {
+            let socket = UdpSocket::bind(address)?;
+            thread::spawn(move || {
+                let mut buf = [0u8; 2 * MAX_LINE_LEN];
+                while let Ok((len, _)) = socket.recv_from(&mut buf) {
+                    // one line per datagram, but take several just as well
+                    for line in String::from_utf8_lossy(&buf[..len]).lines() {
+                        if !line.trim().is_empty() && tx.send(line.to_string()).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        Transport::Tcp => {
+            let listener = TcpListener::bind(address)?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let tx = tx.clone();
+                    if let Ok(peer) = stream.peer_addr() {
+                        eprintln!("gateway {} connected", peer);
+                    }
+                    thread::spawn(move || {
+                        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                            if tx.send(line).is_err() {
+                                return;
+                            }
+                        }
+                    });
+                }
+            });
+        }
+    }
+    Ok(rx)
+}
+
+/// Decode the recording in `path`; with `realtime` wait between the lines as
+/// long as the gateway did, and send them on to `to` if given.
+fn run_replay(path: &str, realtime: bool, to: Option<Collector>) -> io::Result<()> {
+    let socket = match to {
+        Some(_) => Some(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?),
+        None => None,
+    };
+    let mut last_ts_ms = None;
+    let mut error = Ok(());
+
+    let stats = replay(BufReader::new(File::open(path)?), |line, decoded| {
+        if realtime {
+            let mut data = [0; esp32_c3_buddy_like::app::MAX_PAYLOAD];
+            if let Ok(forwarded) = parse_json(line, &mut data) {
+                // the gateway's clock starts over when it reboots
+                if let Some(delay) = last_ts_ms.and_then(|last| forwarded.ts_ms.checked_sub(last)) {
+                    thread::sleep(Duration::from_millis(delay));
+                }
+                last_ts_ms = Some(forwarded.ts_ms);
+            }
+        }
+        match decoded {
+            Ok(decoded) => println!("{}", decoded),
+            Err(e) => println!("unreadable line ({:?}): {}", e, line),
+        }
+        if let (Some(socket), Some(to)) = (&socket, to) {
+            if let Err(e) = socket.send_to(format!("{}\n", line).as_bytes(), socket_addr(to)) {
+                error = Err(e);
+            }
+        }
+    })?;
+    error?;
+
+    eprintln!(
+        "{} lines, {} unreadable, {} not frames, {} blobs",
+        stats.lines, stats.bad_lines, stats.bad_frames, stats.blobs
+    );
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! Decoding gateway lines made by the firmware's own encoders, and replaying a
+//! recorded session.
+
+use std::fs::File;
+use std::io::BufReader;
+
+use buddy_tap::{replay, Decoder, Stats};
+use esp32_c3_buddy_like::{
+    app::{Mac, BROADCAST},
+    gateway::{Forwarded, Gateway, LineError},
+    protocol::{fragment, BlobKind, Frame, Mesh, Message, Sensor, SensorReading},
+};
+
+const A: Mac = [0x34, 0x85, 0x18, 0x01, 0x02, 0x03];
+const B: Mac = [0x34, 0x85, 0x18, 0x0a, 0x0b, 0x0c];
+
+/// The line the gateway writes for `data`, sent by `src` to `dst`.
+fn line(ts_ms: u64, src: Mac, dst: Mac, data: &[u8]) -> String {
+    let mut gateway = Gateway::new();
+    gateway.forward(&Forwarded {
+        ts_ms,
+        src,
+        dst,
+        rssi: -61,
+        channel: 6,
+        data,
+    });
+    gateway.next_line().unwrap().to_string()
+}
+
+fn frame(seq: u16, message: Message<'_>) -> Vec<u8> {
+    Frame::new(seq, message).to_vec().unwrap().to_vec()
+}
+
+#[test]
+fn messages() {
+    let mut decoder = Decoder::new();
+    assert_eq!(
+        decoder.decode_line(&line(81234, A, BROADCAST, &frame(7, Message::Text("hi")))),
+        Ok(
+            "    81.234 34:85:18:01:02:03 -> broadcast          -61 dBm ch 6  #7 text \"hi\""
+                .to_string()
+        )
+    );
+
+    let reading = Message::SensorReading(SensorReading {
+        sensor: Sensor::Temperature,
+        value: 21_500,
+    });
+    let mut acked = Frame::new(8, reading);
+    acked.ack_requested = true;
+    let decoded = decoder
+        .decode_line(&line(5, A, B, &acked.to_vec().unwrap()))
+        .unwrap();
+    assert_eq!(
+        decoded,
+        "     0.005 34:85:18:01:02:03 -> 34:85:18:0a:0b:0c  -61 dBm ch 6  \
+         #8 ack? temperature: 21.500 C"
+    );
+
+    let decoded = decoder
+        .decode_line(&line(
+            5,
+            B,
+            A,
+            &frame(
+                1,
+                Message::Ack {
+                    seq: 8,
+                    broadcast: false,
+                },
+            ),
+        ))
+        .unwrap();
+    assert!(decoded.ends_with("#1 ack #8"), "{}", decoded);
+
+    assert_eq!(
+        decoder.stats(),
+        Stats {
+            lines: 3,
+            ..Stats::default()
+        }
+    );
+}
+
+#[test]
+fn mesh_is_unwrapped() {
+    let inner = frame(3, Message::Counter(42));
+    let mesh = Message::Mesh(Mesh {
+        origin: B,
+        id: 3,
+        ttl: 2,
+        hops: 1,
+        dst: BROADCAST,
+        data: &inner,
+    });
+    let decoded = Decoder::new()
+        .decode_line(&line(0, A, BROADCAST, &frame(9, mesh)))
+        .unwrap();
+    assert!(
+        decoded
+            .ends_with("#9 mesh 34:85:18:0a:0b:0c -> broadcast id 3 ttl 2 hops 1: #3 counter 42"),
+        "{}",
+        decoded
+    );
+}
+
+#[test]
+fn fragments_are_put_together() {
+    let log = "boot ok\n".repeat(60);
+    let fragments: Vec<_> = fragment::split(4, BlobKind::Log, log.as_bytes())
+        .unwrap()
+        .collect();
+    assert!(fragments.len() > 1);
+
+    let mut decoder = Decoder::new();
+    let mut decoded = Vec::new();
+    // in any order
+    for (seq, fragment) in fragments.iter().enumerate().rev() {
+        let data = frame(seq as u16, Message::Fragment(*fragment));
+        decoded.push(decoder.decode_line(&line(100, A, B, &data)).unwrap());
+    }
+    assert!(
+        decoded[0].contains(&format!(
+            "fragment {}/{} of log transfer 4, 480 bytes",
+            fragments.len(),
+            fragments.len()
+        )),
+        "{}",
+        decoded[0]
+    );
+    assert!(!decoded[0].contains("complete"));
+    let last = decoded.last().unwrap();
+    assert!(
+        last.ends_with(&format!(", complete: {:?}", log)),
+        "{}",
+        last
+    );
+    assert_eq!(decoder.stats().blobs, 1);
+}
+
+#[test]
+fn foreign_payloads_and_bad_lines() {
+    let mut decoder = Decoder::new();
+    let decoded = decoder
+        .decode_line(&line(0, A, BROADCAST, &[0xde, 0xad]))
+        .unwrap();
+    assert!(decoded.contains("not a frame"), "{}", decoded);
+    assert_eq!(
+        decoder.decode_line("{\"ts_ms\":1}"),
+        Err(LineError::Missing("data"))
+    );
+    assert_eq!(decoder.decode_line("garbage"), Err(LineError::Syntax));
+    assert_eq!(
+        decoder.stats(),
+        Stats {
+            lines: 3,
+            bad_lines: 2,
+            bad_frames: 1,
+            blobs: 0,
+        }
+    );
+}
+
+#[test]
+fn replays_a_capture() {
+    let capture = File::open(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/captures/session.jsonl"
+    ))
+    .unwrap();
+    let mut decoded = Vec::new();
+    let stats = replay(BufReader::new(capture), |_, line| decoded.push(line)).unwrap();
+    assert_eq!(
+        stats,
+        Stats {
+            lines: 8,
+            bad_lines: 1,
+            bad_frames: 1,
+            blobs: 1,
+        }
+    );
+    assert!(decoded[0].as_ref().unwrap().ends_with("text \"hello\""));
+    assert_eq!(decoded[6], Err(LineError::Syntax));
+}